@@ -14,6 +14,7 @@ import {
     FeaturedMessage,
     PollDisplay,
     SuperchatDisplay,
+    DonationGoal,
     DonationMatter,
     Container,
 } from './components/elements';
@@ -212,12 +213,14 @@ function App() {
 
         const originalElement = localLayout.elements[selectedElement];
         
-        // Generate unique ID
-        let newId = `${selectedElement}-copy`;
-        let counter = 1;
+        // Generate unique ID as another instance of the same type (the overlay and
+        // server resolve the element type by stripping a -N suffix)
+        const baseType = selectedElement.replace(/-\d+$/, '');
+        let counter = 2;
+        let newId = `${baseType}-${counter}`;
         while (localLayout.elements[newId]) {
-            newId = `${selectedElement}-copy-${counter}`;
             counter++;
+            newId = `${baseType}-${counter}`;
         }
 
         // Create duplicate with slight offset
@@ -648,6 +651,10 @@ function App() {
                 case 'superchat':
                     newElement.position = { y: '0vh' };
                     break;
+                case 'goal':
+                    newElement.position = { y: '0vh', right: '0vw' };
+                    newElement.size = { width: '25vw' };
+                    break;
             }
         } else {
             // When dropped, still apply element-specific size/style defaults
@@ -695,6 +702,7 @@ function App() {
                     FeaturedMessage,
                     PollDisplay,
                     SuperchatDisplay,
+                    DonationGoal,
                     DonationMatter,
                 }}
             >
@@ -425,6 +425,7 @@ export function EditorCanvas({
             featured: { width: 600, height: 150 },
             poll: { width: 300, height: 200 },
             superchat: { width: 300, height: 150 },
+            goal: { width: 480, height: 80 },
         };
 
         const defaults = defaultSizesMap[baseId] || { width: 200, height: 100 };
@@ -1286,6 +1287,7 @@ export function EditorCanvas({
         featured: { width: 600, height: 100 },
         poll: { width: 300, height: 150 },
         superchat: { width: 300, height: 100 },
+        goal: { width: 480, height: 80 },
         matter: { width: '100%', height: '100%' },
     };
 
@@ -1407,6 +1409,7 @@ export function EditorCanvas({
             featured: 'Featured',
             poll: 'Poll',
             superchat: 'Superchat',
+            goal: 'Goal',
             matter: 'Donation Matter',
         };
         return names[baseId] || elementId;
@@ -1631,6 +1634,16 @@ export function EditorCanvas({
                     </div>
                 );
                 break;
+            case 'goal':
+                // Matches overlay structure: .element--goal.goal-ui
+                content = (
+                    <div className="element--goal goal-ui">
+                        <div className="goal-label">Donation Goal</div>
+                        <div className="goal-bar"><div className="goal-fill" style={{ width: '65%' }} /></div>
+                        <div className="goal-amount">$65.00 / $100.00</div>
+                    </div>
+                );
+                break;
             case 'matter':
                 // Preview for physics-based donation visualization
                 content = (
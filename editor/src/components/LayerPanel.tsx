@@ -20,6 +20,7 @@ function getDisplayName(elementId: string, config: ElementConfig): string {
         featured: 'Featured',
         poll: 'Poll',
         superchat: 'Superchat',
+        goal: 'Goal',
         matter: 'Donation Matter',
     };
     return names[baseId] || elementId;
@@ -36,6 +37,7 @@ function getElementIcon(elementId: string): string {
         featured: '⭐',
         poll: '📊',
         superchat: '💰',
+        goal: '🎯',
         matter: '💥',
     };
     return icons[baseId] || '📦';
@@ -201,6 +201,7 @@ function getDefaultDisplayName(elementId: string): string {
         featured: 'Featured Message',
         poll: 'Poll Display',
         superchat: 'Superchat Display',
+        goal: 'Donation Goal',
     };
     const suffix = elementId.match(/-(\d+)$/)?.[1];
     const baseName = names[baseId] || baseId.charAt(0).toUpperCase() + baseId.slice(1);
@@ -74,6 +74,13 @@ export function Toolbox({ onAddElement }: ToolboxProps) {
                 onAddElement={onAddElement}
             />
 
+            <ToolboxItem
+                label="Donation Goal"
+                icon="🎯"
+                elementId="goal"
+                onAddElement={onAddElement}
+            />
+
             <ToolboxItem
                 label="Donation Matter"
                 icon="💥"
@@ -194,6 +194,31 @@ SuperchatDisplay.craft = {
     displayName: 'Superchat Display',
 };
 
+// Donation Goal element
+export function DonationGoal({ style }: { style?: React.CSSProperties }) {
+    const { connectors: { connect, drag }, selected } = useNode((state) => ({
+        selected: state.events.selected,
+    }));
+
+    return (
+        <div
+            ref={(ref) => ref && connect(drag(ref))}
+            style={style}
+            className={`element-wrapper ${selected ? 'selected' : ''}`}
+        >
+            <span className="element-label">Donation Goal</span>
+            <div className="preview-goal">
+                <strong>Goal Label</strong>
+                <div>$65.00 / $100.00</div>
+            </div>
+        </div>
+    );
+}
+
+DonationGoal.craft = {
+    displayName: 'Donation Goal',
+};
+
 // Donation Matter element (for background physics visualization)
 export function DonationMatter({ style }: { style?: React.CSSProperties }) {
     const { connectors: { connect, drag }, selected } = useNode((state) => ({
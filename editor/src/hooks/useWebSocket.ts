@@ -1,5 +1,5 @@
 import { useState, useEffect, useRef, useCallback } from 'react';
-import { Layout, LayoutListResponse } from '../types/layout';
+import { ElementConfig, Layout, LayoutErrorsResponse, LayoutHistoryResponse, LayoutListResponse } from '../types/layout';
 
 interface WebSocketMessage {
     tag: string;
@@ -11,10 +11,14 @@ export function useWebSocket() {
     const [connected, setConnected] = useState(false);
     const [currentLayout, setCurrentLayout] = useState<Layout | null>(null);
     const [layoutList, setLayoutList] = useState<LayoutListResponse | null>(null);
+    const [layoutErrors, setLayoutErrors] = useState<LayoutErrorsResponse | null>(null);
+    const [layoutHistory, setLayoutHistory] = useState<LayoutHistoryResponse | null>(null);
 
     const connect = useCallback(() => {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        const wsUrl = `${protocol}//${window.location.host}/chat.ws`;
+        // Layout writes need the ingest socket; open the editor as /editor?token=... when INGEST_TOKEN is set
+        const token = new URLSearchParams(window.location.search).get('token') ?? '';
+        const wsUrl = `${protocol}//${window.location.host}/ingest.ws?token=${encodeURIComponent(token)}`;
 
         console.log('[Editor] Connecting to WebSocket:', wsUrl);
         const ws = new WebSocket(wsUrl);
@@ -37,10 +41,24 @@ export function useWebSocket() {
                         console.log('[Editor] Received layout update:', message.name);
                         setCurrentLayout(message as Layout);
                         break;
+                    case 'layout_element_update':
+                        setCurrentLayout(prev =>
+                            prev && prev.name === message.layoutName
+                                ? { ...prev, elements: { ...prev.elements, [message.elementId]: message.config } }
+                                : prev
+                        );
+                        break;
                     case 'layout_list':
                         console.log('[Editor] Received layout list:', message);
                         setLayoutList(message as LayoutListResponse);
                         break;
+                    case 'layout_history':
+                        setLayoutHistory(message as LayoutHistoryResponse);
+                        break;
+                    case 'layout_errors':
+                        console.warn('[Editor] Layout errors:', message.name, message.errors);
+                        setLayoutErrors(message as LayoutErrorsResponse);
+                        break;
                     default:
                         // Ignore chat messages and other tags in editor
                         break;
@@ -80,20 +98,57 @@ export function useWebSocket() {
         }
     }, []);
 
-    const switchLayout = useCallback((name: string) => {
+    // Show unsaved edits on overlays; the saved file is untouched
+    const previewLayout = useCallback((layout: Layout) => {
+        if (wsRef.current?.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({ preview_layout: { layout } }));
+        }
+    }, []);
+
+    // Without an output, switches the default "overlay" output
+    const switchLayout = useCallback((name: string, output?: string) => {
         if (wsRef.current?.readyState === WebSocket.OPEN) {
-            wsRef.current.send(JSON.stringify({ switch_layout: name }));
+            const switch_layout = output ? { name, output } : name;
+            wsRef.current.send(JSON.stringify({ switch_layout }));
         }
     }, []);
 
     const saveLayout = useCallback((name: string, layout: Layout) => {
         if (wsRef.current?.readyState === WebSocket.OPEN) {
+            setLayoutErrors(null);
             wsRef.current.send(JSON.stringify({
                 save_layout: { name, layout }
             }));
         }
     }, []);
 
+    const updateElement = useCallback((name: string, elementId: string, config: ElementConfig) => {
+        if (wsRef.current?.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({
+                layout_element_update: { name, element_id: elementId, config }
+            }));
+        }
+    }, []);
+
+    const requestLayoutHistory = useCallback((name: string) => {
+        if (wsRef.current?.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({ list_layout_history: name }));
+        }
+    }, []);
+
+    const restoreLayoutVersion = useCallback((name: string, version: number) => {
+        if (wsRef.current?.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({ restore_layout_version: { name, version } }));
+        }
+    }, []);
+
+    // Overwrite a layout with the factory default (the old version goes to history)
+    const resetLayout = useCallback((name: string) => {
+        if (wsRef.current?.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({ reset_layout: { name } }));
+        }
+    }, []);
+
     const deleteLayout = useCallback((name: string) => {
         if (wsRef.current?.readyState === WebSocket.OPEN) {
             wsRef.current.send(JSON.stringify({ delete_layout: name }));
@@ -110,9 +165,16 @@ export function useWebSocket() {
         connected,
         currentLayout,
         layoutList,
+        layoutErrors,
+        layoutHistory,
         sendLayoutUpdate,
+        previewLayout,
         switchLayout,
         saveLayout,
+        updateElement,
+        requestLayoutHistory,
+        restoreLayoutVersion,
+        resetLayout,
         deleteLayout,
         requestLayouts,
     };
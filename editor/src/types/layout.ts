@@ -95,6 +95,7 @@ export interface Style {
     zIndex?: number;
     customCss?: string;      // SCSS source
     compiledCss?: string;    // Compiled CSS (set by server)
+    compiledCssHash?: string;    // Hash of the customCss it was compiled from (set by server)
 }
 
 // Anchor point for auto-sized elements (which corner/edge to anchor from)
@@ -154,6 +155,7 @@ export interface MessageStyle {
     fontSize: string;
     backgroundColor?: string;
     textColor?: string;
+    firstTimeColor?: string;     // Highlight for a user's first message
 
     // Display options
     showAvatars?: boolean;       // Default: true
@@ -167,6 +169,24 @@ export interface MessageStyle {
     showModBadge?: boolean;      // Default: true
     showVerifiedBadge?: boolean; // Default: true
     showSubBadge?: boolean;      // Default: true
+    // Images replacing the built-in badge icons, keyed by badge type ("owner", "mod", ...)
+    badgeImages?: Record<string, string>;
+
+    // Keyed by platform name (case-insensitive), e.g. "youtube"
+    platformOverrides?: Record<string, PlatformStyle>;
+}
+
+// Per-platform overrides of MessageStyle; unset fields fall back to the base style
+export interface PlatformStyle {
+    backgroundColor?: string;
+    textColor?: string;
+    firstTimeColor?: string;
+    showOwnerBadge?: boolean;
+    showStaffBadge?: boolean;
+    showModBadge?: boolean;
+    showVerifiedBadge?: boolean;
+    showSubBadge?: boolean;
+    badgeImages?: Record<string, string>;
 }
 
 export interface Layout {
@@ -179,6 +199,25 @@ export interface Layout {
 export interface LayoutListResponse {
     layouts: string[];
     active: string;
+    outputs: Record<string, string>; // output name -> active layout
+}
+
+/** A prior saved version of a layout, newest first in LayoutHistoryResponse */
+export interface LayoutVersion {
+    version: number;
+    savedAt: number;    // Unix seconds when it was replaced
+}
+
+export interface LayoutHistoryResponse {
+    name: string;
+    versions: LayoutVersion[];
+}
+
+/** Sent when a layout save is rejected (saved: false) or its SCSS failed to compile */
+export interface LayoutErrorsResponse {
+    name: string;
+    saved: boolean;
+    errors: string[];
 }
 
 export const defaultElementConfig = (): ElementConfig => ({
@@ -240,7 +279,7 @@ export const defaultMessageStyle = (): MessageStyle => ({
 
 export const defaultLayout = (): Layout => ({
     name: 'default',
-    version: 1,
+    version: 2,
     elements: {
         chat: {
             enabled: true,
@@ -286,6 +325,12 @@ export const defaultLayout = (): Layout => ({
             size: {},
             style: {},
         },
+        goal: {
+            enabled: true,
+            position: { y: '0vh', right: '0vw' },
+            size: { width: '25vw' },
+            style: {},
+        },
     },
     messageStyle: defaultMessageStyle(),
 });
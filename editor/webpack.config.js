@@ -36,7 +36,7 @@ module.exports = {
         port: 3000,
         proxy: [
             {
-                context: ['/chat.ws'],
+                context: ['/chat.ws', '/ingest.ws'],
                 target: 'ws://127.0.0.1:1350',
                 ws: true,
             },
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Where the mapping is read from unless `ALERT_SOUNDS` names another file
+pub const DEFAULT_PATH: &str = "alert_sounds.json";
+
+/// Sound clips overlays play for paid messages, keyed by `Message::get_paid_tier()`.
+/// Names are files in `public/sounds/`, e.g.
+/// `{"default": "chime.mp3", "tiers": {"50": "fanfare.mp3", "100": "airhorn.mp3"}}`
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertSounds {
+    /// Played for tiers without a sound of their own
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub tiers: HashMap<u8, String>,
+}
+
+impl AlertSounds {
+    pub fn path_from_env() -> String {
+        dotenvy::var("ALERT_SOUNDS").unwrap_or_else(|_| DEFAULT_PATH.to_string())
+    }
+
+    /// Read the mapping; a missing file means no alert sounds
+    pub fn load(path: &str) -> Result<Self> {
+        if !Path::new(path).exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        Self::parse(&content).with_context(|| format!("Invalid alert sounds in {}", path))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let sounds: Self = serde_json::from_str(content)?;
+        for name in sounds.default.iter().chain(sounds.tiers.values()) {
+            if !is_valid_sound_name(name) {
+                bail!("{:?} is not a file name in public/sounds/", name);
+            }
+        }
+        Ok(sounds)
+    }
+
+    /// The clip for a paid tier, falling back to the default
+    pub fn sound_for(&self, tier: u8) -> Option<&str> {
+        self.tiers
+            .get(&tier)
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Plain file names only, so a name can't point outside the sounds directory
+fn is_valid_sound_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sound_for_tier() {
+        let sounds = AlertSounds::parse(
+            r#"{"default": "chime.mp3", "tiers": {"50": "fanfare.mp3", "100": "airhorn.ogg"}}"#,
+        )
+        .unwrap();
+        assert_eq!(sounds.sound_for(100), Some("airhorn.ogg"));
+        assert_eq!(sounds.sound_for(50), Some("fanfare.mp3"));
+        assert_eq!(sounds.sound_for(5), Some("chime.mp3"));
+
+        let no_default = AlertSounds::parse(r#"{"tiers": {"100": "airhorn.ogg"}}"#).unwrap();
+        assert_eq!(no_default.sound_for(5), None);
+        assert_eq!(AlertSounds::default().sound_for(100), None);
+    }
+
+    #[test]
+    fn test_rejects_paths() {
+        for name in ["../secret.mp3", "sub/dir.mp3", ".hidden", "", "a b.mp3"] {
+            let json = serde_json::json!({ "default": name }).to_string();
+            assert!(AlertSounds::parse(&json).is_err(), "{:?}", name);
+        }
+        assert!(AlertSounds::parse(r#"{"tiers": {"1000": "x.mp3"}}"#).is_err());
+        assert!(AlertSounds::parse(r#"{"tier": {}}"#).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, bail, Result};
+use std::time::Duration;
+
+use crate::platforms::replay::ReplayConfig;
+
+/// Command line flags. Everything else is configured through the environment.
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    /// `--replay <file>`, `--replay-interval <secs>` and `--replay-loop`
+    pub replay: Option<ReplayConfig>,
+    /// `--record <file>`
+    pub record: Option<String>,
+}
+
+impl Args {
+    /// Read the command line (without the program name)
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut replay_path = None;
+        let mut interval = None;
+        let mut looping = false;
+        let mut record = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--replay" => {
+                    replay_path = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--replay needs a file"))?,
+                    );
+                }
+                "--replay-interval" => {
+                    let secs = args
+                        .next()
+                        .and_then(|secs| secs.parse::<f64>().ok())
+                        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                        .ok_or_else(|| anyhow!("--replay-interval needs a number of seconds"))?;
+                    interval = Some(Duration::from_secs_f64(secs));
+                }
+                "--replay-loop" => looping = true,
+                "--record" => {
+                    record = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--record needs a file"))?,
+                    );
+                }
+                other => bail!("unknown argument {:?}", other),
+            }
+        }
+
+        let replay = match replay_path {
+            Some(path) => Some(ReplayConfig {
+                path,
+                interval,
+                looping,
+            }),
+            None if interval.is_some() || looping => {
+                bail!("--replay-interval and --replay-loop need --replay")
+            }
+            None => None,
+        };
+        if replay
+            .as_ref()
+            .zip(record.as_ref())
+            .is_some_and(|(replay, record)| replay.path == *record)
+        {
+            bail!("--replay and --record can't use the same file");
+        }
+        Ok(Self { replay, record })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+        assert_eq!(
+            parse(&[
+                "--replay-loop",
+                "--replay",
+                "chat.ndjson",
+                "--replay-interval",
+                "0.5",
+                "--record",
+                "session.ndjson",
+            ])
+            .unwrap(),
+            Args {
+                replay: Some(ReplayConfig {
+                    path: "chat.ndjson".to_string(),
+                    interval: Some(Duration::from_millis(500)),
+                    looping: true,
+                }),
+                record: Some("session.ndjson".to_string()),
+            }
+        );
+        assert!(parse(&["--replay"]).is_err());
+        assert!(parse(&["--record"]).is_err());
+        assert!(parse(&["--replay", "a", "--replay-interval", "-1"]).is_err());
+        assert!(parse(&["--replay-loop"]).is_err());
+        assert!(parse(&["--replay", "a", "--record", "a"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use tracing::field::{Field, Visit};
+use tracing_subscriber::field::{MakeVisitor, RecordFields, VisitOutput};
+use tracing_subscriber::fmt::format::{DefaultFields, DefaultVisitor, Writer};
+use tracing_subscriber::fmt::FormatFields;
+
+// ANSI escape sequences for console output.
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_BOLD_GREEN: &str = "\x1b[1;32m";
+
+/// Events with `highlight = true` have their message shown in bold green, e.g. paid chat
+const HIGHLIGHT_FIELD: &str = "highlight";
+
+/// Whether console output should be colored.
+/// Respects NO_COLOR (https://no-color.org) and only colors interactive terminals.
+pub fn console_supports_color() -> bool {
+    static SUPPORTS_COLOR: OnceLock<bool> = OnceLock::new();
+    *SUPPORTS_COLOR.get_or_init(|| {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        !no_color && std::io::stdout().is_terminal()
+    })
+}
+
+/// Formats fields like `DefaultFields`, but colors the message of highlighted events when
+/// the writer takes ANSI codes. The message is still escaped by `DefaultFields`, so chat
+/// text can't smuggle its own escape sequences in, and the `highlight` field isn't shown.
+pub struct ConsoleFields;
+
+impl<'writer> FormatFields<'writer> for ConsoleFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut highlight = FindHighlight(false);
+        fields.record(&mut highlight);
+        let color = highlight.0 && writer.has_ansi_escapes();
+
+        if color {
+            writer.write_str(ANSI_BOLD_GREEN)?;
+        }
+        let mut visitor = SkipHighlight(DefaultFields::new().make_visitor(writer.by_ref()));
+        fields.record(&mut visitor);
+        visitor.0.finish()?;
+        if color {
+            writer.write_str(ANSI_RESET)?;
+        }
+        Ok(())
+    }
+}
+
+struct FindHighlight(bool);
+
+impl Visit for FindHighlight {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == HIGHLIGHT_FIELD {
+            self.0 = value;
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// Passes every field but `highlight` on to the default visitor
+struct SkipHighlight<'a>(DefaultVisitor<'a>);
+
+impl Visit for SkipHighlight<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.record_str(field, value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0.record_error(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() != HIGHLIGHT_FIELD {
+            self.0.record_debug(field, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::info;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What the subscriber main() sets up writes for `log`
+    fn formatted(ansi: bool, log: impl FnOnce()) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(ansi)
+            .fmt_fields(ConsoleFields)
+            .finish();
+        tracing::subscriber::with_default(subscriber, log);
+        let bytes = buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_highlight_reaches_the_terminal() {
+        let line = formatted(true, || info!(highlight = true, "[youtube] ($5) donor: hi"));
+        assert!(
+            line.contains(&format!(
+                "{ANSI_BOLD_GREEN}[youtube] ($5) donor: hi{ANSI_RESET}"
+            )),
+            "{line:?}"
+        );
+        assert!(!line.contains("highlight"));
+
+        let line = formatted(true, || info!(highlight = false, "[youtube] viewer: hi"));
+        assert!(!line.contains(ANSI_BOLD_GREEN));
+        assert!(line.contains("[youtube] viewer: hi"));
+
+        // Escapes typed into chat are still neutralized
+        let line = formatted(true, || info!(highlight = true, "{}", "\x1b[2Jgone"));
+        assert!(line.contains("\\x1b[2Jgone"), "{line:?}");
+    }
+
+    #[test]
+    fn test_no_color_without_ansi() {
+        let line = formatted(false, || {
+            info!(highlight = true, "[youtube] ($5) donor: hi")
+        });
+        assert!(!line.contains('\x1b'), "{line:?}");
+        assert!(line.contains("[youtube] ($5) donor: hi"));
+        assert!(!line.contains("highlight"));
+
+        // Other fields are formatted as usual
+        let line = formatted(false, || info!(count = 3, "done"));
+        assert!(line.contains("done count=3"), "{line:?}");
+    }
+}
@@ -0,0 +1,206 @@
+use tracing::warn;
+
+/// What user-authored CSS may reference once compiled.
+/// The default only permits relative URLs and inline images.
+#[derive(Debug, Clone, Default)]
+pub struct CssPolicy {
+    /// Absolute URL prefixes (e.g. "https://fonts.gstatic.com/") that `url(...)` may load
+    pub url_allowlist: Vec<String>,
+}
+
+impl CssPolicy {
+    /// Read `CSS_URL_ALLOWLIST` (comma separated URL prefixes)
+    pub fn from_env() -> Self {
+        let url_allowlist = dotenvy::var("CSS_URL_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|prefix| prefix.trim().to_ascii_lowercase())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        Self { url_allowlist }
+    }
+
+    /// Relative URLs stay on the overlay's origin; anything absolute must be allowlisted
+    fn allows_url(&self, url: &str) -> bool {
+        if url.starts_with("data:image/") {
+            return true;
+        }
+        let is_absolute = url.starts_with("//")
+            || url
+                .split_once(':')
+                .is_some_and(|(scheme, _)| !scheme.contains('/'));
+        !is_absolute
+            || self
+                .url_allowlist
+                .iter()
+                .any(|prefix| url.starts_with(prefix.as_str()))
+    }
+}
+
+/// Strip constructs from compiled CSS that can load off-origin resources or run script:
+/// `@import`, `url(...)` outside the policy, `expression()`, `javascript:`, `behavior`
+/// and `-moz-binding`. Everything else passes through untouched.
+pub fn sanitize_css(css: &str, policy: &CssPolicy) -> String {
+    let mut kept = Vec::new();
+
+    for (body, terminator) in split_statements(css) {
+        // Selectors and at-rule preludes ("&:hover {", "@media ... {") carry no values
+        if terminator == Some('{') {
+            kept.push(format!("{} {{", body.trim()));
+            continue;
+        }
+
+        let body = body.trim();
+        if !body.is_empty() {
+            if is_unsafe(body, policy) {
+                warn!("Removed unsafe CSS: {}", body);
+            } else if terminator == Some(';') {
+                kept.push(format!("{};", body));
+            } else {
+                kept.push(body.to_string());
+            }
+        }
+        if terminator == Some('}') {
+            kept.push("}".to_string());
+        }
+    }
+
+    kept.join(" ")
+}
+
+/// Split CSS into (text, terminator) pieces at `;`, `{` and `}`, ignoring those inside
+/// quotes or parentheses. The final piece has no terminator.
+fn split_statements(css: &str) -> Vec<(&str, Option<char>)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in css.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (_, '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, ';' | '{' | '}') if depth == 0 => {
+                pieces.push((&css[start..i], Some(c)));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    pieces.push((&css[start..], None));
+    pieces
+}
+
+/// Check a declaration or at-statement against the policy
+fn is_unsafe(statement: &str, policy: &CssPolicy) -> bool {
+    // Escapes (`u\rl(`, `\65xpression(`) can spell any keyword, so rather than decode
+    // them, only allow them in plain `content` strings
+    if statement.contains('\\') && !is_plain_content(statement) {
+        return true;
+    }
+
+    let normalized = statement.to_ascii_lowercase();
+    if normalized.starts_with("@import") {
+        return true;
+    }
+    if normalized.contains("expression(") || normalized.contains("javascript:") {
+        return true;
+    }
+    // image-set() takes bare strings as URLs, which url_arguments doesn't see
+    if normalized.contains("image-set(") {
+        return true;
+    }
+
+    let property = normalized.split(':').next().unwrap_or_default().trim();
+    if property.ends_with("behavior") || property == "-moz-binding" {
+        return true;
+    }
+
+    // As a statement, so the iterator borrowing `normalized` is dropped before it
+    return url_arguments(&normalized).any(|url| !policy.allows_url(url));
+}
+
+/// `content: "\f101"` (icon fonts) is the common legitimate use of escapes
+fn is_plain_content(statement: &str) -> bool {
+    statement.split_once(':').is_some_and(|(property, value)| {
+        property.trim().eq_ignore_ascii_case("content") && !value.contains('(')
+    })
+}
+
+/// Arguments of every `url(...)` in `css`, with whitespace and quotes removed
+fn url_arguments(css: &str) -> impl Iterator<Item = &str> {
+    css.match_indices("url(").filter_map(move |(i, m)| {
+        let rest = &css[i + m.len()..];
+        let end = rest.find(')')?;
+        Some(rest[..end].trim().trim_matches(|c| c == '"' || c == '\''))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removes_import() {
+        let css = "@import url(https://evil.example/x.css); color: red; margin: 1px;";
+        assert_eq!(
+            sanitize_css(css, &CssPolicy::default()),
+            "color: red; margin: 1px;"
+        );
+        assert_eq!(
+            sanitize_css("@IMPORT 'x.css'; color: red", &CssPolicy::default()),
+            "color: red"
+        );
+    }
+
+    #[test]
+    fn test_passes_safe_css_through() {
+        let css = "color: red; font-family: \"Comic Sans MS\", sans-serif; background: url(/static/bg.png); content: \"\\f101\";";
+        assert_eq!(sanitize_css(css, &CssPolicy::default()), css);
+
+        let nested = "color: red; &:hover { color: blue; }";
+        assert_eq!(sanitize_css(nested, &CssPolicy::default()), nested);
+    }
+
+    #[test]
+    fn test_removes_off_origin_urls() {
+        let css = "background: url('https://evil.example/track.png'); color: red;";
+        assert_eq!(sanitize_css(css, &CssPolicy::default()), "color: red;");
+        let css = "background: url(//evil.example/track.png); color: red;";
+        assert_eq!(sanitize_css(css, &CssPolicy::default()), "color: red;");
+
+        let policy = CssPolicy {
+            url_allowlist: vec!["https://fonts.gstatic.com/".to_string()],
+        };
+        let css = "src: url(https://fonts.gstatic.com/a.woff2); background: url(https://evil.example/b.png);";
+        assert_eq!(
+            sanitize_css(css, &policy),
+            "src: url(https://fonts.gstatic.com/a.woff2);"
+        );
+    }
+
+    #[test]
+    fn test_removes_script_hooks() {
+        for css in [
+            "width: expression(alert(1))",
+            "behavior: url(x.htc)",
+            "-ms-behavior: url(x.htc)",
+            "-moz-binding: url(x.xml#xss)",
+            "background: url(javascript:alert(1))",
+            "background: u\\rl(https://evil.example/x.png)",
+            "width: \\65xpression(alert(1))",
+            "background: image-set(\"https://evil.example/x.png\" 1x)",
+        ] {
+            assert_eq!(sanitize_css(css, &CssPolicy::default()), "", "{}", css);
+        }
+    }
+}
@@ -1,11 +1,36 @@
-use crate::message::Message;
+use crate::goal::Goal;
+use crate::message::{canonical_platform, Message};
+use crate::poll::Poll;
 use anyhow::{Context, Result};
 use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+/// Columns selected when reading a full paid message row, in `row_to_message` order.
+const PAID_MESSAGE_COLUMNS: &str =
+    "id, platform, sent_at, received_at, message, emojis, username, avatar,
+     amount, currency, is_verified, is_sub, is_mod, is_owner, is_staff, attachments,
+     is_first_time";
+
+/// An identity whose messages are dropped on arrival
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Ban {
+    /// Canonical platform slug
+    pub platform: String,
+    /// Lowercased, so matching ignores case
+    pub username: String,
+    pub banned_at: i64,
+}
+
+/// Key a ban is stored and looked up by: bans are per platform and ignore case
+fn ban_key(platform: &str, username: &str) -> (String, String) {
+    (canonical_platform(platform), username.trim().to_lowercase())
+}
+
 /// Database wrapper for storing paid messages (superchats)
 #[derive(Clone)]
 pub struct Database {
@@ -27,6 +52,16 @@ impl Database {
         let conn = Connection::open(&db_path)
             .with_context(|| format!("Failed to open database at {:?}", db_path))?;
 
+        Self::from_connection(conn)
+    }
+
+    /// Open a throwaway in-memory database
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
         let db = Self {
             conn: Arc::new(Mutex::new(conn)),
         };
@@ -72,7 +107,68 @@ impl Database {
                 is_sub INTEGER NOT NULL DEFAULT 0,
                 is_mod INTEGER NOT NULL DEFAULT 0,
                 is_owner INTEGER NOT NULL DEFAULT 0,
-                is_staff INTEGER NOT NULL DEFAULT 0
+                is_staff INTEGER NOT NULL DEFAULT 0,
+                attachments TEXT NOT NULL DEFAULT '[]',
+                is_first_time INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        // Columns added after the initial schema
+        Self::add_column_if_missing(
+            &conn,
+            "paid_messages",
+            "attachments",
+            "TEXT NOT NULL DEFAULT '[]'",
+        )?;
+        Self::add_column_if_missing(
+            &conn,
+            "paid_messages",
+            "is_first_time",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+
+        // The donation goal, kept across restarts; at most one row
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS goal (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                label TEXT NOT NULL,
+                target_usd REAL NOT NULL
+            )",
+            [],
+        )?;
+
+        // The layout each output shows, kept across restarts
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS active_layouts (
+                output TEXT PRIMARY KEY,
+                name TEXT NOT NULL
+            )",
+            [],
+        )?;
+
+        // Final results of ended polls
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS polls (
+                id TEXT PRIMARY KEY,
+                question TEXT NOT NULL,
+                options TEXT NOT NULL,
+                multi_vote INTEGER NOT NULL DEFAULT 0,
+                total_votes INTEGER NOT NULL,
+                participants INTEGER NOT NULL,
+                started_at INTEGER NOT NULL,
+                ended_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Banned identities, matched per platform and case-insensitively
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS banned (
+                platform TEXT NOT NULL,
+                username TEXT NOT NULL,
+                banned_at INTEGER NOT NULL,
+                PRIMARY KEY (platform, username)
             )",
             [],
         )?;
@@ -87,20 +183,46 @@ impl Database {
         Ok(())
     }
 
+    /// Add a column to an existing table, for databases created by older versions
+    fn add_column_if_missing(
+        conn: &Connection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|r| r.ok())
+            .any(|name| name == column);
+
+        if !exists {
+            info!("Migrating {}: adding column {}", table, column);
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+
     /// Insert or update a paid message
     pub fn upsert_paid_message(&self, msg: &Message) -> Result<()> {
         let conn = self.conn.lock().unwrap();
 
         let emojis_json = serde_json::to_string(&msg.emojis)?;
+        let attachments_json = serde_json::to_string(&msg.attachments)?;
 
         conn.execute(
-            "INSERT OR REPLACE INTO paid_messages
-             (id, platform, sent_at, received_at, message, emojis, username, avatar,
-              amount, currency, is_verified, is_sub, is_mod, is_owner, is_staff)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            &format!(
+                "INSERT OR REPLACE INTO paid_messages ({})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                PAID_MESSAGE_COLUMNS
+            ),
             params![
                 msg.id.to_string(),
-                msg.platform,
+                canonical_platform(&msg.platform),
                 msg.sent_at,
                 msg.received_at,
                 msg.message,
@@ -114,6 +236,8 @@ impl Database {
                 msg.is_mod as i32,
                 msg.is_owner as i32,
                 msg.is_staff as i32,
+                attachments_json,
+                msg.is_first_time as i32,
             ],
         )?;
 
@@ -125,15 +249,12 @@ impl Database {
     pub fn get_paid_message(&self, id: &Uuid) -> Result<Option<Message>> {
         let conn = self.conn.lock().unwrap();
 
-        let mut stmt = conn.prepare(
-            "SELECT id, platform, sent_at, received_at, message, emojis, username, avatar,
-                    amount, currency, is_verified, is_sub, is_mod, is_owner, is_staff
-             FROM paid_messages WHERE id = ?1"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM paid_messages WHERE id = ?1",
+            PAID_MESSAGE_COLUMNS
+        ))?;
 
-        let result = stmt.query_row(params![id.to_string()], |row| {
-            Ok(Self::row_to_message(row)?)
-        });
+        let result = stmt.query_row(params![id.to_string()], Self::row_to_message);
 
         match result {
             Ok(msg) => Ok(Some(msg)),
@@ -142,8 +263,8 @@ impl Database {
         }
     }
 
-    /// Get all paid messages from the last N hours
-    pub fn get_paid_messages_since_hours(&self, hours: u32) -> Result<Vec<Message>> {
+    /// Get paid messages from the last N hours worth at least `min_usd`
+    pub fn get_paid_messages_above_amount(&self, min_usd: f64, hours: u32) -> Result<Vec<Message>> {
         let conn = self.conn.lock().unwrap();
 
         // Calculate cutoff time in milliseconds
@@ -153,19 +274,17 @@ impl Database {
             .as_millis() as i64;
         let cutoff_ms = now_ms - (hours as i64 * 60 * 60 * 1000);
 
-        let mut stmt = conn.prepare(
-            "SELECT id, platform, sent_at, received_at, message, emojis, username, avatar,
-                    amount, currency, is_verified, is_sub, is_mod, is_owner, is_staff
-             FROM paid_messages
-             WHERE received_at >= ?1
-             ORDER BY received_at ASC"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM paid_messages
+             WHERE received_at >= ?1 AND amount >= ?2
+             ORDER BY received_at ASC",
+            PAID_MESSAGE_COLUMNS
+        ))?;
 
-        let messages = stmt.query_map(params![cutoff_ms], |row| {
-            Self::row_to_message(row)
-        })?
-        .filter_map(|r| r.ok())
-        .collect();
+        let messages = stmt
+            .query_map(params![cutoff_ms, min_usd], Self::row_to_message)?
+            .filter_map(|r| r.ok())
+            .collect();
 
         Ok(messages)
     }
@@ -174,12 +293,10 @@ impl Database {
     pub fn get_all_paid_messages(&self) -> Result<Vec<Message>> {
         let conn = self.conn.lock().unwrap();
 
-        let mut stmt = conn.prepare(
-            "SELECT id, platform, sent_at, received_at, message, emojis, username, avatar,
-                    amount, currency, is_verified, is_sub, is_mod, is_owner, is_staff
-             FROM paid_messages
-             ORDER BY received_at ASC"
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM paid_messages ORDER BY received_at ASC",
+            PAID_MESSAGE_COLUMNS
+        ))?;
 
         let messages = stmt.query_map([], |row| {
             Self::row_to_message(row)
@@ -224,19 +341,213 @@ impl Database {
         Ok(rows_deleted)
     }
 
+    /// Delete paid messages received in the last N hours (the current session)
+    pub fn delete_paid_messages_since_hours(&self, hours: u32) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let cutoff_ms = now_ms - (hours as i64 * 60 * 60 * 1000);
+
+        let rows_deleted = conn.execute(
+            "DELETE FROM paid_messages WHERE received_at >= ?1",
+            params![cutoff_ms],
+        )?;
+
+        info!(
+            "Purged {} paid messages from the current session",
+            rows_deleted
+        );
+        Ok(rows_deleted)
+    }
+
+    /// Ban a user on one platform. Returns false if they were already banned.
+    pub fn ban_user(&self, platform: &str, username: &str) -> Result<bool> {
+        let (platform, username) = ban_key(platform, username);
+        if username.is_empty() {
+            anyhow::bail!("username is empty");
+        }
+        let banned_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        let conn = self.conn.lock().unwrap();
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO banned (platform, username, banned_at) VALUES (?1, ?2, ?3)",
+            params![platform, username, banned_at],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Lift a ban. Returns false if the user wasn't banned.
+    pub fn unban_user(&self, platform: &str, username: &str) -> Result<bool> {
+        let (platform, username) = ban_key(platform, username);
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM banned WHERE platform = ?1 AND username = ?2",
+            params![platform, username],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    pub fn is_banned(&self, platform: &str, username: &str) -> Result<bool> {
+        let (platform, username) = ban_key(platform, username);
+        let conn = self.conn.lock().unwrap();
+        let banned = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM banned WHERE platform = ?1 AND username = ?2)",
+            params![platform, username],
+            |row| row.get(0),
+        )?;
+        Ok(banned)
+    }
+
+    /// Every ban, newest first
+    pub fn list_bans(&self) -> Result<Vec<Ban>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT platform, username, banned_at FROM banned ORDER BY banned_at DESC, platform, username",
+        )?;
+        let bans = stmt
+            .query_map([], |row| {
+                Ok(Ban {
+                    platform: row.get(0)?,
+                    username: row.get(1)?,
+                    banned_at: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(bans)
+    }
+
+    /// Save the result of an ended poll; options are stored as JSON `[{text, votes}]`
+    pub fn save_poll(&self, poll: &Poll) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT OR REPLACE INTO polls
+                (id, question, options, multi_vote, total_votes, participants, started_at, ended_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                poll.id.to_string(),
+                poll.question,
+                serde_json::to_string(&poll.options)?,
+                poll.multi_vote as i32,
+                poll.total_votes,
+                poll.participants,
+                poll.started_at,
+                poll.ended_at.unwrap_or(poll.started_at),
+            ],
+        )?;
+
+        debug!("Saved poll {} to database", poll.id);
+        Ok(())
+    }
+
+    /// Store the donation goal, replacing any previous one
+    pub fn save_goal(&self, goal: &Goal) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT OR REPLACE INTO goal (id, label, target_usd) VALUES (1, ?1, ?2)",
+            params![goal.label, goal.target_usd],
+        )?;
+
+        debug!("Saved donation goal to database");
+        Ok(())
+    }
+
+    /// The stored donation goal, if one is set
+    pub fn load_goal(&self) -> Result<Option<Goal>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            "SELECT label, target_usd FROM goal WHERE id = 1",
+            [],
+            |row| {
+                Ok(Goal {
+                    label: row.get(0)?,
+                    target_usd: row.get(1)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(goal) => Ok(Some(goal)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn delete_goal(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM goal", [])?;
+        Ok(())
+    }
+
+    /// Store the layout an output shows, replacing its previous one
+    pub fn save_active_layout(&self, output: &str, name: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT OR REPLACE INTO active_layouts (output, name) VALUES (?1, ?2)",
+            params![output, name],
+        )?;
+
+        debug!("Saved active layout {} for output {}", name, output);
+        Ok(())
+    }
+
+    /// The stored layout of every output that has been switched
+    pub fn load_active_layouts(&self) -> Result<HashMap<String, String>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare("SELECT output, name FROM active_layouts")?;
+        let layouts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(layouts)
+    }
+
+    /// Check the connection is usable with a trivial query
+    pub fn ping(&self) -> Result<()> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Database connection lock poisoned"))?;
+        conn.query_row("SELECT 1", [], |row| row.get::<_, i32>(0))?;
+        Ok(())
+    }
+
+    /// Writes are committed as they happen, so this waits out any in-flight write
+    /// and runs `PRAGMA optimize`, which SQLite recommends before closing.
+    pub fn flush(&self) -> Result<()> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Database connection lock poisoned"))?;
+        conn.execute_batch("PRAGMA optimize;")?;
+        Ok(())
+    }
+
     /// Convert a database row to a Message
     fn row_to_message(row: &rusqlite::Row) -> rusqlite::Result<Message> {
         let id_str: String = row.get(0)?;
         let emojis_json: String = row.get(5)?;
+        let attachments_json: String = row.get(15)?;
 
         Ok(Message {
             id: Uuid::parse_str(&id_str).unwrap_or_else(|_| Uuid::new_v4()),
-            platform: row.get(1)?,
+            // Rows saved before platforms were canonicalized
+            platform: canonical_platform(&row.get::<_, String>(1)?),
             sent_at: row.get(2)?,
             received_at: row.get(3)?,
             is_placeholder: false,
             message: row.get(4)?,
             emojis: serde_json::from_str(&emojis_json).unwrap_or_default(),
+            attachments: serde_json::from_str(&attachments_json).unwrap_or_default(),
             username: row.get(6)?,
             avatar: row.get(7)?,
             amount: row.get(8)?,
@@ -246,6 +557,253 @@ impl Database {
             is_mod: row.get::<_, i32>(12)? != 0,
             is_owner: row.get::<_, i32>(13)? != 0,
             is_staff: row.get::<_, i32>(14)? != 0,
+            is_first_time: row.get::<_, i32>(16)? != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Attachment;
+
+    fn paid_message() -> Message {
+        Message {
+            amount: 10.0,
+            currency: "USD".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ping() {
+        let db = Database::open_in_memory().unwrap();
+        db.ping().unwrap();
+
+        // A panic while holding the connection poisons it
+        let poisoner = db.clone();
+        let _ = std::thread::spawn(move || {
+            let _conn = poisoner.conn.lock().unwrap();
+            panic!("poison");
         })
+        .join();
+        assert!(db.ping().is_err());
+    }
+
+    #[test]
+    fn test_attachments_roundtrip() {
+        let db = Database::open_in_memory().unwrap();
+        let mut msg = paid_message();
+        msg.attachments = vec![
+            Attachment {
+                url: "https://files.kick.com/images/a.webp".to_string(),
+                mime_type: "image/webp".to_string(),
+                width: Some(320),
+                height: Some(240),
+            },
+            Attachment {
+                url: "https://cdn.discordapp.com/attachments/b.gif".to_string(),
+                mime_type: "image/gif".to_string(),
+                width: None,
+                height: None,
+            },
+        ];
+
+        db.upsert_paid_message(&msg).unwrap();
+        let loaded = db.get_paid_message(&msg.id).unwrap().unwrap();
+        assert_eq!(loaded.attachments, msg.attachments);
+    }
+
+    #[test]
+    fn test_first_time_roundtrip() {
+        let db = Database::open_in_memory().unwrap();
+        let first = Message {
+            is_first_time: true,
+            ..paid_message()
+        };
+        let regular = paid_message();
+
+        db.upsert_paid_message(&first).unwrap();
+        db.upsert_paid_message(&regular).unwrap();
+        assert!(
+            db.get_paid_message(&first.id)
+                .unwrap()
+                .unwrap()
+                .is_first_time
+        );
+        assert!(
+            !db.get_paid_message(&regular.id)
+                .unwrap()
+                .unwrap()
+                .is_first_time
+        );
+    }
+
+    #[test]
+    fn test_no_attachments_roundtrip() {
+        let db = Database::open_in_memory().unwrap();
+        let msg = paid_message();
+
+        db.upsert_paid_message(&msg).unwrap();
+        let loaded = db.get_paid_message(&msg.id).unwrap().unwrap();
+        assert!(loaded.attachments.is_empty());
+    }
+
+    #[test]
+    fn test_platform_is_canonical() {
+        let db = Database::open_in_memory().unwrap();
+        let msg = Message {
+            platform: "YouTube".to_string(),
+            ..paid_message()
+        };
+
+        db.upsert_paid_message(&msg).unwrap();
+        let stored: String = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT platform FROM paid_messages", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, "youtube");
+        let loaded = db.get_paid_message(&msg.id).unwrap().unwrap();
+        assert_eq!(loaded.platform, "youtube");
+    }
+
+    #[test]
+    fn test_save_poll() {
+        let db = Database::open_in_memory().unwrap();
+        let mut poll = Poll::new("Q?", &["a".to_string(), "b".to_string()], false).unwrap();
+        poll.vote(Some("alice"), &[2]);
+        poll.end();
+
+        db.save_poll(&poll).unwrap();
+        let (options, total): (String, u32) = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT options, total_votes FROM polls", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(
+            options,
+            r#"[{"text":"a","votes":0},{"text":"b","votes":1}]"#
+        );
+        assert_eq!(total, 1);
+    }
+
+    #[test]
+    fn test_bans() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(db.ban_user("Kick", "Spammer").unwrap());
+        assert!(!db.ban_user("kick", "SPAMMER").unwrap(), "already banned");
+        assert!(db.ban_user("kick", "  ").is_err());
+
+        assert!(db.is_banned("kick", "spammer").unwrap());
+        assert!(db.is_banned("Kick", "SpAmMeR").unwrap());
+        // The same name on another platform is someone else
+        assert!(!db.is_banned("youtube", "spammer").unwrap());
+
+        let bans = db.list_bans().unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(
+            (bans[0].platform.as_str(), bans[0].username.as_str()),
+            ("kick", "spammer")
+        );
+
+        assert!(db.unban_user("KICK", "Spammer").unwrap());
+        assert!(!db.unban_user("kick", "spammer").unwrap());
+        assert!(!db.is_banned("kick", "spammer").unwrap());
+        assert!(db.list_bans().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_paid_messages_above_amount() {
+        let db = Database::open_in_memory().unwrap();
+        for amount in [2.0, 10.0, 50.0] {
+            db.upsert_paid_message(&Message {
+                amount,
+                ..paid_message()
+            })
+            .unwrap();
+        }
+
+        // They may share a received_at millisecond, leaving their order unspecified
+        let amounts = |min_usd| {
+            let mut amounts: Vec<f64> = db
+                .get_paid_messages_above_amount(min_usd, 24)
+                .unwrap()
+                .iter()
+                .map(|msg| msg.amount)
+                .collect();
+            amounts.sort_by(f64::total_cmp);
+            amounts
+        };
+        assert_eq!(amounts(0.0), [2.0, 10.0, 50.0]);
+        assert_eq!(amounts(10.0), [10.0, 50.0]);
+        assert!(amounts(100.0).is_empty());
+    }
+
+    #[test]
+    fn test_goal_round_trip() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(db.load_goal().unwrap(), None);
+
+        db.save_goal(&Goal::new("Old", 50.0).unwrap()).unwrap();
+        let goal = Goal::new("New mic", 250.0).unwrap();
+        db.save_goal(&goal).unwrap();
+        assert_eq!(db.load_goal().unwrap(), Some(goal));
+
+        db.delete_goal().unwrap();
+        assert_eq!(db.load_goal().unwrap(), None);
+    }
+
+    #[test]
+    fn test_active_layouts_round_trip() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(db.load_active_layouts().unwrap().is_empty());
+
+        db.save_active_layout("overlay", "alt").unwrap();
+        db.save_active_layout("overlay", "stream").unwrap();
+        db.save_active_layout("vertical", "portrait").unwrap();
+        assert_eq!(
+            db.load_active_layouts().unwrap(),
+            HashMap::from([
+                ("overlay".to_string(), "stream".to_string()),
+                ("vertical".to_string(), "portrait".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_attachments_column_migration() {
+        // Schema as created by versions before attachments existed
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE paid_messages (
+                id TEXT PRIMARY KEY,
+                platform TEXT NOT NULL,
+                sent_at INTEGER NOT NULL,
+                received_at INTEGER NOT NULL,
+                message TEXT NOT NULL,
+                emojis TEXT NOT NULL,
+                username TEXT NOT NULL,
+                avatar TEXT NOT NULL,
+                amount REAL NOT NULL,
+                currency TEXT NOT NULL,
+                is_verified INTEGER NOT NULL DEFAULT 0,
+                is_sub INTEGER NOT NULL DEFAULT 0,
+                is_mod INTEGER NOT NULL DEFAULT 0,
+                is_owner INTEGER NOT NULL DEFAULT 0,
+                is_staff INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )
+        .unwrap();
+
+        let db = Database::from_connection(conn).unwrap();
+        let msg = paid_message();
+        db.upsert_paid_message(&msg).unwrap();
+        assert!(db.get_paid_message(&msg.id).unwrap().is_some());
     }
 }
@@ -1,6 +1,8 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use quick_xml::events::Event;
@@ -8,27 +10,74 @@ use quick_xml::reader::Reader;
 use tracing::{error, warn};
 
 const RATES_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+/// A missing currency is warned about at most once in this long
+const MISSING_WARNING_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct ExchangeRates {
     rates: HashMap<String, f64>,
+    /// The last currency looked up and its rate (None if missing), so a burst of
+    /// messages in one currency only looks it up once
+    last: RefCell<Option<(String, Option<f64>)>>,
+    /// When each missing currency was last warned about
+    warned: RefCell<HashMap<String, Instant>>,
 }
 
 impl ExchangeRates {
+    fn new(rates: HashMap<String, f64>) -> Self {
+        Self {
+            rates,
+            last: RefCell::new(None),
+            warned: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Build from rates already expressed as (XYZ->USD)
+    #[cfg(test)]
+    pub fn from_rates(rates: HashMap<String, f64>) -> Self {
+        Self::new(rates)
+    }
+
     pub fn get_usd(&self, currency: &str, amount: &f64) -> f64 {
         // Probably a bit quicker.
         if currency == "USD" {
             return *amount;
         }
 
-        match self.rates.get(currency) {
+        match self.rate(currency) {
             // Note: Rates are stored as (XYZ->USD), not (USD->XYZ).
             Some(rate) => amount * rate,
             None => {
-                warn!(currency = %currency, "Exchange rate not found");
+                if self.should_warn(currency, Instant::now()) {
+                    warn!(currency = %currency, "Exchange rate not found");
+                }
                 0.0
             }
         }
     }
+
+    fn rate(&self, currency: &str) -> Option<f64> {
+        let mut last = self.last.borrow_mut();
+        if let Some((cached, rate)) = last.as_ref() {
+            if cached == currency {
+                return *rate;
+            }
+        }
+        let rate = self.rates.get(currency).copied();
+        *last = Some((currency.to_string(), rate));
+        rate
+    }
+
+    /// Whether to log a missing currency, recording the warning if so
+    fn should_warn(&self, currency: &str, now: Instant) -> bool {
+        let mut warned = self.warned.borrow_mut();
+        match warned.get(currency) {
+            Some(at) if now.duration_since(*at) < MISSING_WARNING_INTERVAL => false,
+            _ => {
+                warned.insert(currency.to_string(), now);
+                true
+            }
+        }
+    }
 }
 
 fn parse_xml(body: &str) -> Result<ExchangeRates> {
@@ -40,27 +89,24 @@ fn parse_xml(body: &str) -> Result<ExchangeRates> {
     loop {
         match r.read_event_into(&mut buf) {
             Ok(Event::Eof) => break,
-            Ok(Event::Empty(e)) => match e.name().as_ref() {
-                b"Cube" => {
-                    let mut c = String::new();
-                    let mut v = String::new();
-
-                    for a in e.attributes().into_iter() {
-                        let attr = a.unwrap();
-
-                        match attr.key.as_ref() {
-                            b"currency" => c = String::from_utf8(attr.value.to_vec())?,
-                            b"rate" => v = String::from_utf8(attr.value.to_vec())?,
-                            _ => (),
-                        }
-                    }
-                    assert_ne!(c, v);
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"Cube" => {
+                let mut c = String::new();
+                let mut v = String::new();
+
+                for a in e.attributes() {
+                    let attr = a.unwrap();
 
-                    let rate: f64 = v.parse()?;
-                    rates.insert(c, rate);
+                    match attr.key.as_ref() {
+                        b"currency" => c = String::from_utf8(attr.value.to_vec())?,
+                        b"rate" => v = String::from_utf8(attr.value.to_vec())?,
+                        _ => (),
+                    }
                 }
-                _ => (),
-            },
+                assert_ne!(c, v);
+
+                let rate: f64 = v.parse()?;
+                rates.insert(c, rate);
+            }
             Err(e) => return Err(anyhow!(e)),
             _ => (),
         }
@@ -82,12 +128,13 @@ fn parse_xml(body: &str) -> Result<ExchangeRates> {
     // $1 USD == $1 USD. Redundant placeholder for safety.
     rates.insert(String::from("USD"), 1.0);
 
-    Ok(ExchangeRates { rates })
+    Ok(ExchangeRates::new(rates))
 }
 
 pub async fn fetch_exchange_rates() -> Result<ExchangeRates> {
     let mut f = OpenOptions::new()
         .create(true)
+        .truncate(false)
         .read(true)
         .write(true)
         .open("exchange_rates.xml")
@@ -102,13 +149,10 @@ pub async fn fetch_exchange_rates() -> Result<ExchangeRates> {
         // Check for XML subject text.
         if text.contains("Reference rates") {
             // Parses the XML response into an ExchangeRates.
-            match parse_xml(&text) {
-                Ok(r) => {
-                    f.write_all(text.as_bytes())
-                        .expect("Failed to write exchange write backup to file.");
-                    return Ok(r);
-                }
-                Err(_) => (),
+            if let Ok(r) = parse_xml(&text) {
+                f.write_all(text.as_bytes())
+                    .expect("Failed to write exchange write backup to file.");
+                return Ok(r);
             }
         }
     }
@@ -118,3 +162,37 @@ pub async fn fetch_exchange_rates() -> Result<ExchangeRates> {
     f.read_to_string(&mut text)?;
     parse_xml(&text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_usd_remembers_last_rate() {
+        let rates = ExchangeRates::from_rates(HashMap::from([
+            ("EUR".to_string(), 1.1),
+            ("GBP".to_string(), 1.25),
+        ]));
+        assert_eq!(rates.get_usd("EUR", &10.0), 11.0);
+        assert_eq!(rates.get_usd("EUR", &20.0), 22.0);
+        assert_eq!(rates.get_usd("GBP", &4.0), 5.0);
+        assert_eq!(rates.get_usd("USD", &3.0), 3.0);
+        assert_eq!(rates.get_usd("XYZ", &3.0), 0.0);
+        assert_eq!(rates.get_usd("EUR", &1.0), 1.1);
+    }
+
+    #[test]
+    fn test_missing_currency_warns_once_a_minute() {
+        let rates = ExchangeRates::from_rates(HashMap::new());
+        let start = Instant::now();
+        assert!(rates.should_warn("XYZ", start));
+        assert!(!rates.should_warn("XYZ", start + Duration::from_secs(1)));
+        assert!(rates.should_warn("ABC", start + Duration::from_secs(1)));
+        assert!(!rates.should_warn("XYZ", start + Duration::from_secs(59)));
+        assert!(rates.should_warn("XYZ", start + MISSING_WARNING_INTERVAL));
+
+        // Conversions record the warning too
+        rates.get_usd("QQQ", &1.0);
+        assert!(!rates.should_warn("QQQ", Instant::now()));
+    }
+}
@@ -1,4 +1,4 @@
-import type { ChatMessage, WebSocketMessage, ViewerCounts } from '../types';
+import type { ChatMessage, WebSocketMessage, ViewerCounts, ServerStats, GoalUpdate } from '../types';
 
 // ============================================================================
 // DOM Elements
@@ -7,6 +7,13 @@ import type { ChatMessage, WebSocketMessage, ViewerCounts } from '../types';
 const chatHistory = document.querySelector<HTMLElement>("#chat-history");
 const donationHistory = document.querySelector<HTMLElement>("#donation-history");
 const connectionStatus = document.querySelector<HTMLElement>("#connection-status");
+const serverStats = document.querySelector<HTMLElement>("#server-stats");
+const goalStatus = document.querySelector<HTMLElement>("#goal-status");
+const pendingMessages = document.querySelector<HTMLElement>("#pending-messages");
+const moderatedToggle = document.querySelector<HTMLInputElement>("#moderated");
+const slowModeInput = document.querySelector<HTMLInputElement>("#slow-mode-secs");
+const searchQuery = document.querySelector<HTMLInputElement>("#chat-search-query");
+const searchResults = document.querySelector<HTMLElement>("#search-results");
 
 // ============================================================================
 // State
@@ -152,7 +159,7 @@ class DashboardChatMessage implements ChatMessage {
     received_at: number;
     message: string;
     html: string;
-    emojis: string[];
+    emojis: ChatMessage["emojis"];
     username: string;
     avatar: string;
     amount: number;
@@ -191,7 +198,9 @@ class DashboardChatMessage implements ChatMessage {
 // ============================================================================
 
 const wsProtocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-const wsUrl = `${wsProtocol}//${window.location.host}/chat.ws`;
+// Featuring messages needs the ingest socket; open as /dashboard?token=... when INGEST_TOKEN is set
+const ingestToken = new URLSearchParams(window.location.search).get('token') ?? '';
+const wsUrl = `${wsProtocol}//${window.location.host}/ingest.ws?token=${encodeURIComponent(ingestToken)}`;
 
 let socket: WebSocket | null = null;
 
@@ -223,6 +232,10 @@ function updateConnectionStatus(connected: boolean): void {
 
             // Request message history
             socket?.send(JSON.stringify({ request_messages: true }));
+            socket?.send(JSON.stringify({ request_server_stats: true }));
+            socket?.send(JSON.stringify({ request_goal: true }));
+            pendingMessages?.replaceChildren();
+            socket?.send(JSON.stringify({ request_moderation: true }));
         });
 
         socket.addEventListener("message", (event: MessageEvent) => {
@@ -233,6 +246,12 @@ function updateConnectionStatus(connected: boolean): void {
                 case "chat_message":
                     handleMessage(message as ChatMessage);
                     break;
+                case "update_message":
+                    handleUpdateMessage(message as ChatMessage);
+                    break;
+                case "edit_error":
+                    alert(`Could not edit message: ${message}`);
+                    break;
                 case "feature_message":
                     // Server now sends full ChatMessage data or null
                     // Extract the ID for dashboard styling
@@ -242,6 +261,39 @@ function updateConnectionStatus(connected: boolean): void {
                 case "viewers":
                     handleViewers(message as ViewerCounts);
                     break;
+                case "server_stats":
+                    handleServerStats(message as ServerStats);
+                    break;
+                case "clear_all":
+                    handleClearAll();
+                    break;
+                case "poll_error":
+                    alert(`Could not start poll: ${message}`);
+                    break;
+                case "poll_update":
+                    // Shown on overlays
+                    break;
+                case "goal_update":
+                    handleGoalUpdate(message as GoalUpdate | null);
+                    break;
+                case "goal_error":
+                    alert(`Could not set goal: ${message}`);
+                    break;
+                case "moderation":
+                    if (moderatedToggle) moderatedToggle.checked = message as boolean;
+                    break;
+                case "slow_mode":
+                    if (slowModeInput) slowModeInput.valueAsNumber = message as number;
+                    break;
+                case "pending_message":
+                    handlePendingMessage(message as ChatMessage);
+                    break;
+                case "search_results":
+                    handleSearchResults(message as ChatMessage[]);
+                    break;
+                case "pending_removed":
+                    document.querySelector(`.pending-message[data-id="${message as string}"]`)?.remove();
+                    break;
                 default:
                     console.log("Unknown tag:", data.tag);
                     break;
@@ -325,7 +377,6 @@ function onPollCreate(): void {
     const multipleChoice = document.getElementById("multiplechoice") as HTMLInputElement | null;
     const pollQuestion = document.getElementById("pollquestion") as HTMLInputElement | null;
 
-    const pollType = multipleChoice?.checked ? "multipoll" : "poll";
     const pollOptions = document.querySelectorAll<HTMLInputElement>(".poll-option");
     const options: string[] = [];
     const questionText = pollQuestion?.value || "";
@@ -346,14 +397,52 @@ function onPollCreate(): void {
         return;
     }
 
-    const pollCommand = `!${pollType} ${questionText}; ${options.join("; ")}`;
-    sendSimpleMessage(pollCommand);
+    socket?.send(JSON.stringify({
+        start_poll: {
+            question: questionText,
+            options,
+            multi_vote: multipleChoice?.checked ?? false,
+        },
+    }));
 
     clearPoll();
 }
 
 function onPollEnd(): void {
-    sendSimpleMessage("!endpoll", true);
+    socket?.send(JSON.stringify({ end_poll: true }));
+}
+
+// ============================================================================
+// Donation Goal Functions
+// ============================================================================
+
+function handleGoalUpdate(goal: GoalUpdate | null): void {
+    if (!goalStatus) return;
+    goalStatus.textContent = goal
+        ? `${goal.label || "Goal"}: $${goal.current.toFixed(2)} / $${goal.target.toFixed(2)}`
+        : "No goal set";
+}
+
+function onGoalSet(): void {
+    const label = document.getElementById("goal-label") as HTMLInputElement | null;
+    const target = document.getElementById("goal-target") as HTMLInputElement | null;
+    const targetValue = parseFloat(target?.value || "");
+
+    if (!(targetValue > 0)) {
+        alert("You need a goal target above zero.");
+        return;
+    }
+
+    socket?.send(JSON.stringify({
+        set_goal: {
+            label: label?.value || "",
+            target: targetValue,
+        },
+    }));
+}
+
+function onGoalReset(): void {
+    socket?.send(JSON.stringify({ reset_goal: true }));
 }
 
 // ============================================================================
@@ -520,12 +609,34 @@ function handleMessage(message: ChatMessage): HTMLElement | null {
     return null;
 }
 
+// A corrected message replaces its copy in place, keeping its position in the history
+function handleUpdateMessage(message: ChatMessage): void {
+    const existingEl = document.getElementById(message.id);
+    if (!existingEl) return;
+
+    const template = document.createElement("template");
+    template.innerHTML = message.html.trim();
+    const updatedEl = template.content.firstElementChild as HTMLElement | null;
+    if (!updatedEl) return;
+
+    existingEl.replaceWith(updatedEl);
+    updatedEl.addEventListener("click", onClickMessage);
+    if (message.amount > 0) {
+        addTimestampToSuperchat(updatedEl, Math.floor(message.sent_at / 1000));
+    }
+    if (featuredMessageIds.has(message.id)) {
+        updatedEl.classList.add("msg--was-featured");
+    }
+}
+
 function addTimestampToSuperchat(el: HTMLElement, timestamp: number): void {
     // Create timestamp element
     const timeEl = document.createElement("span");
     timeEl.className = "superchat-time";
     timeEl.dataset.timestamp = timestamp.toString();
     timeEl.textContent = formatRelativeTime(timestamp);
+    // The exact time, in the server's DISPLAY_TZ
+    if (el.dataset.sentAtDisplay) timeEl.title = el.dataset.sentAtDisplay;
 
     // Insert after the amount element (on the second row)
     const amountEl = el.querySelector(".msg-amount");
@@ -539,7 +650,117 @@ function addTimestampToSuperchat(el: HTMLElement, timestamp: number): void {
 }
 
 function handleViewers(_message: ViewerCounts): void {
-    // Could display viewer counts in dashboard header
+    // Totals arrive (throttled) as server_stats
+}
+
+// ============================================================================
+// Moderation Functions
+// ============================================================================
+
+// Messages held in moderated mode wait above the chat until approved or rejected
+function handlePendingMessage(message: ChatMessage): void {
+    if (!pendingMessages || pendingMessages.querySelector(`[data-id="${message.id}"]`)) return;
+
+    const wrapper = document.createElement("div");
+    wrapper.className = "pending-message";
+    wrapper.dataset.id = message.id;
+    wrapper.innerHTML = message.html;
+
+    const actions = document.createElement("div");
+    actions.className = "pending-actions";
+    for (const [label, className, command] of [
+        ["Approve", "pending-approve", "approve_message"],
+        ["Reject", "pending-reject", "reject_message"],
+    ]) {
+        const button = document.createElement("button");
+        button.textContent = label;
+        button.className = className;
+        button.addEventListener("click", () => {
+            socket?.send(JSON.stringify({ [command]: { id: message.id } }));
+        });
+        actions.appendChild(button);
+    }
+    wrapper.appendChild(actions);
+    pendingMessages.appendChild(wrapper);
+}
+
+// ============================================================================
+// Search Functions
+// ============================================================================
+
+// Searches the server's session buffer, so it finds messages the dashboard never saw
+function searchChat(): void {
+    const query = searchQuery?.value.trim() ?? "";
+    if (!query) {
+        searchResults?.replaceChildren();
+        return;
+    }
+    socket?.send(JSON.stringify({ search_recent: query }));
+}
+
+function onSearchInput(value: string): void {
+    if (!value.trim()) searchResults?.replaceChildren();
+}
+
+function handleSearchResults(messages: ChatMessage[]): void {
+    if (!searchResults || !searchQuery?.value.trim()) return;
+
+    const summary = document.createElement("div");
+    summary.className = "search-summary";
+    summary.textContent = `${messages.length} match${messages.length === 1 ? "" : "es"}`;
+    searchResults.replaceChildren(summary);
+    for (const message of messages) {
+        const wrapper = document.createElement("div");
+        wrapper.innerHTML = message.html;
+        // The same message may be in the chat history, which owns the id
+        wrapper.firstElementChild?.removeAttribute("id");
+        searchResults.appendChild(wrapper);
+    }
+}
+
+function setModerated(enabled: boolean): void {
+    socket?.send(JSON.stringify({ set_moderated: enabled }));
+}
+
+function setSlowMode(secs: number): void {
+    if (!Number.isFinite(secs) || secs < 0) return;
+    socket?.send(JSON.stringify({ set_slow_mode: Math.floor(secs) }));
+}
+
+function handleClearAll(): void {
+    pendingMessages?.replaceChildren();
+    chatHistory?.replaceChildren();
+    donationHistory?.replaceChildren();
+    featuredMessageIds.clear();
+}
+
+function clearMessages(): void {
+    if (!confirm("Clear all chat from the dashboard and overlays?")) return;
+    const purge_db = confirm("Also delete this session's superchats from the database?");
+    socket?.send(JSON.stringify({ clear_messages: { purge_db } }));
+}
+
+// Paid test messages are stored and counted like real ones, so the amount is asked for
+function sendTestMessage(): void {
+    const input = prompt("Amount in USD (leave empty for a plain chat message):", "");
+    if (input === null) return;
+    const amount = input.trim() === "" ? 0 : Number(input);
+    if (!Number.isFinite(amount) || amount < 0) {
+        alert(`Not an amount: ${input}`);
+        return;
+    }
+    const message = amount > 0 ? "This is a test donation." : "This is a test message.";
+    socket?.send(JSON.stringify({ test_message: { amount, currency: "USD", message } }));
+}
+
+function handleServerStats(stats: ServerStats): void {
+    if (!serverStats) return;
+
+    serverStats.textContent = `${stats.clients} clients · ${stats.viewers_total} viewers`;
+    serverStats.title = Object.entries(stats.viewers_by_platform)
+        .sort(([a], [b]) => a.localeCompare(b))
+        .map(([platform, viewers]) => `${platform}: ${viewers}`)
+        .join("\n");
 }
 
 // ============================================================================
@@ -584,8 +805,16 @@ declare global {
         onPollOptionChange: typeof onPollOptionChange;
         onPollCreate: typeof onPollCreate;
         onPollEnd: typeof onPollEnd;
+        onGoalSet: typeof onGoalSet;
+        onGoalReset: typeof onGoalReset;
+        setModerated: typeof setModerated;
+        setSlowMode: typeof setSlowMode;
+        searchChat: typeof searchChat;
+        onSearchInput: typeof onSearchInput;
         sendPaidMessage: typeof sendPaidMessage;
         sendSimpleMessage: typeof sendSimpleMessage;
+        clearMessages: typeof clearMessages;
+        sendTestMessage: typeof sendTestMessage;
     }
 }
 
@@ -594,5 +823,13 @@ window.onPollOptionType = onPollOptionType;
 window.onPollOptionChange = onPollOptionChange;
 window.onPollCreate = onPollCreate;
 window.onPollEnd = onPollEnd;
+window.onGoalSet = onGoalSet;
+window.onGoalReset = onGoalReset;
+window.setModerated = setModerated;
+window.setSlowMode = setSlowMode;
+window.searchChat = searchChat;
+window.onSearchInput = onSearchInput;
 window.sendPaidMessage = sendPaidMessage;
 window.sendSimpleMessage = sendSimpleMessage;
+window.clearMessages = clearMessages;
+window.sendTestMessage = sendTestMessage;
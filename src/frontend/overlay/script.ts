@@ -1,5 +1,6 @@
 import type {
     Layout,
+    LayoutElementDelta,
     ElementConfig,
     ChatMessage,
     WebSocketMessage,
@@ -7,6 +8,9 @@ import type {
     BadgeSettings,
     LiveBadgeOptions,
     ChatOptions,
+    TtsAudio,
+    Poll,
+    GoalUpdate,
 } from '../types';
 import { DonationMatter, DonationMatterConfig } from '../background/DonationMatter';
 
@@ -187,11 +191,15 @@ const reconnect = (): boolean => {
 const bindWebsocketEvents = (): void => {
     socket.addEventListener("open", () => {
         console.log("[SNEED] Connection established.");
-        // Subscribe to specific layout if set, otherwise request active layout
+        // Subscribe to specific layout if set, then to an output, otherwise request active layout
         const layoutName = window.LAYOUT_NAME;
+        const output = window.LAYOUT_OUTPUT;
         if (layoutName) {
             console.log("[SNEED] Subscribing to layout:", layoutName);
             socket.send(JSON.stringify({ subscribe_layout: layoutName }));
+        } else if (output) {
+            console.log("[SNEED] Subscribing to output:", output);
+            socket.send(JSON.stringify({ subscribe_layout: { output } }));
         } else {
             socket.send(JSON.stringify({ request_layout: true }));
         }
@@ -199,6 +207,8 @@ const bindWebsocketEvents = (): void => {
         // Request recent messages to sync state (for featuring messages that arrived before we connected)
         console.log("[SNEED] Requesting recent messages");
         socket.send(JSON.stringify({ request_messages: true }));
+        socket.send(JSON.stringify({ request_poll: true }));
+        socket.send(JSON.stringify({ request_goal: true }));
     });
 
     socket.addEventListener("message", (event: MessageEvent) => {
@@ -211,6 +221,12 @@ const bindWebsocketEvents = (): void => {
                 console.log("[SNEED] Received message:", chatMsg.id, "containers:", chat_containers.length);
                 handle_message(chatMsg);
                 break;
+            case "replay_message":
+                handle_replay_message(message as ChatMessage);
+                break;
+            case "update_message":
+                handle_update_message(message as ChatMessage);
+                break;
             case "feature_message":
                 console.log("[SNEED] Received feature_message event:", message);
                 // Message is now full ChatMessage data or null (for unfeaturing)
@@ -222,9 +238,28 @@ const bindWebsocketEvents = (): void => {
             case "layout_update":
                 apply_layout(message as Layout);
                 break;
+            case "layout_element_update":
+                apply_layout_element(message as LayoutElementDelta);
+                break;
             case "layout_list":
                 console.log("[SNEED] Available layouts:", message);
                 break;
+            case "clear_all":
+                handle_clear_all();
+                break;
+            case "tts_audio":
+                queue_tts(message as TtsAudio);
+                break;
+            case "poll_update":
+                handle_poll_update(message as Poll);
+                break;
+            case "goal_update":
+                handle_goal_update(message as GoalUpdate | null);
+                break;
+            case "server_shutdown":
+                // The close frame follows; the close handler reconnects
+                console.log("[SNEED] Server is shutting down:", message);
+                break;
             default:
                 console.log("Unknown tag:", data.tag);
                 break;
@@ -269,9 +304,37 @@ function start_text_updates(): void {
 }
 start_text_updates();
 
+// Merge a single element change into the current layout and re-apply it
+function apply_layout_element(delta: LayoutElementDelta): void {
+    if (!current_layout || current_layout.name !== delta.layoutName) return;
+    apply_layout({
+        ...current_layout,
+        elements: { ...current_layout.elements, [delta.elementId]: delta.config },
+    });
+}
+
+// Replace the layout's @font-face rules. The server validates fonts on save; the same
+// checks here keep a hand-edited layout from breaking out of the rule.
+function apply_layout_fonts(layout: Layout): void {
+    const rules = (layout.fonts ?? [])
+        .filter(font => font.name.trim() && !/["\\;{}<>\r\n]/.test(font.name))
+        .filter(font => /^https?:\/\//i.test(font.url) && !/[\s"'()\\<>]/.test(font.url))
+        .map(font => `@font-face { font-family: "${font.name.trim()}"; src: url("${font.url}"); font-display: block; }`);
+
+    let style = document.getElementById('layout-fonts');
+    if (!style) {
+        if (rules.length === 0) return;
+        style = document.createElement('style');
+        style.id = 'layout-fonts';
+        document.head.appendChild(style);
+    }
+    style.textContent = rules.join('\n');
+}
+
 function apply_layout(layout: Layout): void {
     console.log("[SNEED] Applying layout:", layout.name);
     current_layout = layout;
+    apply_layout_fonts(layout);
 
     if (!elements_container) {
         console.error("[SNEED] Elements container not found!");
@@ -346,6 +409,7 @@ function apply_layout(layout: Layout): void {
                         showVerifiedBadge: chatOpts.showVerifiedBadge ?? ms.showVerifiedBadge ?? true,
                         showSubBadge: chatOpts.showSubBadge ?? ms.showSubBadge ?? true,
                     };
+                    regroup_messages(chatInfo.container, chatInfo.options);
                 }
             }
         } else {
@@ -422,6 +486,7 @@ function apply_layout(layout: Layout): void {
         if (ms.fontSize) root.style.setProperty('--message-font-size', ms.fontSize);
         if (ms.backgroundColor) root.style.setProperty('--message-bg', ms.backgroundColor);
         if (ms.textColor) root.style.setProperty('--message-color', ms.textColor);
+        if (ms.firstTimeColor) root.style.setProperty('--message-first-color', ms.firstTimeColor);
 
         // Store default badge visibility settings (can be overridden per-element)
         window.badgeSettings = {
@@ -494,6 +559,11 @@ function create_element_for_type(elementId: string, baseType: string, config: El
             el.className += ' superchat-ui';
             break;
 
+        case 'goal':
+            el.className += ' goal-ui';
+            render_goal(el);
+            break;
+
         case 'matter':
             // Matter element is a container for the physics canvas
             // DonationMatter will be initialized after the element is added to DOM
@@ -635,24 +705,35 @@ function apply_element_config(el: HTMLElement | null, config: ElementConfig | un
         el.style.top = 'auto';
         el.style.bottom = 'auto';
 
-        if (pos.x !== null && pos.x !== undefined) {
-            el.style.left = formatPos(pos.x);
-        }
-        if (pos.y !== null && pos.y !== undefined) {
-            el.style.top = formatPos(pos.y);
-        }
-        if (pos.right !== null && pos.right !== undefined) {
-            el.style.right = formatPos(pos.right);
-        }
-        if (pos.bottom !== null && pos.bottom !== undefined) {
-            el.style.bottom = formatPos(pos.bottom);
+        // Same rules as ElementConfig::computed_style on the server
+        const anchor = config.autoSize ? config.anchor : undefined;
+        if (anchor) {
+            const fromRight = anchor.endsWith('right');
+            const fromBottom = anchor.startsWith('bottom');
+            if (fromRight) el.style.right = formatPos(pos.right ?? 0);
+            else el.style.left = formatPos(pos.x ?? 0);
+            if (fromBottom) el.style.bottom = formatPos(pos.bottom ?? 0);
+            else el.style.top = formatPos(pos.y ?? 0);
+        } else {
+            if (pos.x !== null && pos.x !== undefined) {
+                el.style.left = formatPos(pos.x);
+            }
+            if (pos.y !== null && pos.y !== undefined) {
+                el.style.top = formatPos(pos.y);
+            }
+            if (pos.right !== null && pos.right !== undefined) {
+                el.style.right = formatPos(pos.right);
+            }
+            if (pos.bottom !== null && pos.bottom !== undefined) {
+                el.style.bottom = formatPos(pos.bottom);
+            }
         }
     }
 
     // Handle sizing (skip explicit width/height for autoSize elements)
     if (config.size) {
         const size = config.size;
-        const isAutoSize = (config as { autoSize?: boolean }).autoSize === true;
+        const isAutoSize = config.autoSize === true;
 
         // Only apply explicit width/height if not auto-sized
         if (!isAutoSize) {
@@ -780,6 +861,68 @@ function handle_feature_message(message: ChatMessage | null): void {
     }
 }
 
+/**
+ * Wipe every chat container and the featured message (start of a fresh stream).
+ */
+function handle_clear_all(): void {
+    chat_containers.forEach(({ container }) => container.replaceChildren());
+    handle_feature_message(null);
+    console.log("[SNEED] Cleared all messages");
+}
+
+// ============================================================================
+// Spoken donation alerts
+// ============================================================================
+
+const tts_queue: TtsAudio[] = [];
+let tts_playing = false;
+
+/**
+ * Queue a spoken alert; alerts play one after another so they never overlap.
+ */
+// Tier chimes play straight away, over any TTS alert that is speaking
+function play_alert_sound(name: string): void {
+    const audio = new Audio(`/static/sounds/${encodeURIComponent(name)}`);
+    audio.play().catch((err) => {
+        console.log("[SNEED] Could not play alert sound:", name, err);
+    });
+}
+
+function queue_tts(alert: TtsAudio): void {
+    tts_queue.push(alert);
+    play_next_tts();
+}
+
+function play_next_tts(): void {
+    if (tts_playing) {
+        return;
+    }
+    const alert = tts_queue.shift();
+    if (!alert) {
+        return;
+    }
+
+    tts_playing = true;
+    const audio = new Audio(alert.audio);
+    let finished = false;
+    const done = (): void => {
+        // Both the error event and the play() rejection can report one failure
+        if (finished) {
+            return;
+        }
+        finished = true;
+        tts_playing = false;
+        play_next_tts();
+    };
+    audio.addEventListener("ended", done, { once: true });
+    audio.addEventListener("error", done, { once: true });
+    audio.play().catch((err) => {
+        // Browser sources allow autoplay; a normal tab may refuse until clicked
+        console.log("[SNEED] Could not play TTS alert:", alert.id, err);
+        done();
+    });
+}
+
 // Check if a pending feature can be applied (called after layout is applied)
 function checkPendingFeature(): void {
     if (pendingFeatureMessage) {
@@ -800,6 +943,9 @@ function handle_message(message: ChatMessage): HTMLElement | null {
 
     // Premium messages (superchats) bypass the buffer for immediate display
     if (message.amount > 0) {
+        if (message.alert_sound) {
+            play_alert_sound(message.alert_sound);
+        }
         processMessageImmediate(message);
         return null;
     }
@@ -809,6 +955,112 @@ function handle_message(message: ChatMessage): HTMLElement | null {
     return null;
 }
 
+// Show a stored message again as if it just arrived, replacing any copy still on screen
+function handle_replay_message(message: ChatMessage): void {
+    chat_containers.forEach((_, i) => {
+        document.getElementById(i === 0 ? message.id : `${message.id}-${i}`)?.remove();
+    });
+    if (message.alert_sound) {
+        play_alert_sound(message.alert_sound);
+    }
+    processMessageImmediate(message);
+    chat_containers.forEach((_, i) => {
+        document.getElementById(i === 0 ? message.id : `${message.id}-${i}`)?.classList.add('msg--replay');
+    });
+}
+
+// Re-render every on-screen copy of a corrected message where it stands, without
+// replaying its alert
+function handle_update_message(message: ChatMessage): void {
+    chat_containers.forEach(({ options }, i) => {
+        const instanceId = i === 0 ? message.id : `${message.id}-${i}`;
+        const existingEl = document.getElementById(instanceId);
+        if (!existingEl) return;
+
+        let messageHtml = message.html;
+        if (options.showUsernames === false) {
+            messageHtml = messageHtml.replace('class="msg', 'class="msg msg--hide-username');
+        }
+        const template = document.createElement("template");
+        template.innerHTML = messageHtml.replace(`id="${message.id}"`, `id="${instanceId}"`).trim();
+        const updatedEl = template.content.firstElementChild as HTMLElement | null;
+        if (!updatedEl) return;
+
+        existingEl.replaceWith(updatedEl);
+        filter_badges_with_options(updatedEl, options);
+        apply_platform_style(updatedEl, message.platform);
+    });
+}
+
+// Swap built-in badge icons for the layout's badgeImages, with the platform's entries
+// taking precedence over the base style's
+function apply_badge_images(messageEl: HTMLElement, platform: string): void {
+    const ms = current_layout?.messageStyle;
+    if (!ms) return;
+    const overrides = ms.platformOverrides ?? {};
+    const key = Object.keys(overrides).find(k => k.toLowerCase() === platform.toLowerCase());
+    const images: Record<string, string> = { ...ms.badgeImages, ...(key ? overrides[key].badgeImages : {}) };
+
+    for (const [type, url] of Object.entries(images)) {
+        messageEl.querySelectorAll<HTMLElement>(`.msg-badge--${type}`).forEach(badge => {
+            const img = document.createElement('img');
+            img.className = 'badge';
+            img.src = url;
+            img.alt = badge.title;
+            badge.replaceChildren(img);
+        });
+    }
+}
+
+// Apply the layout's messageStyle.platformOverrides entry for this message's platform
+function apply_platform_style(messageEl: HTMLElement, platform: string): void {
+    apply_badge_images(messageEl, platform);
+    const overrides = current_layout?.messageStyle?.platformOverrides;
+    if (!overrides || !platform) return;
+
+    const key = Object.keys(overrides).find(k => k.toLowerCase() === platform.toLowerCase());
+    if (!key) return;
+    const ps = overrides[key];
+
+    if (ps.backgroundColor) messageEl.style.setProperty('--message-bg', ps.backgroundColor);
+    if (ps.textColor) messageEl.style.setProperty('--message-color', ps.textColor);
+    if (ps.firstTimeColor) messageEl.style.setProperty('--message-first-color', ps.firstTimeColor);
+
+    const badges: Record<string, boolean | undefined> = {
+        owner: ps.showOwnerBadge,
+        staff: ps.showStaffBadge,
+        mod: ps.showModBadge,
+        verified: ps.showVerifiedBadge,
+        sub: ps.showSubBadge,
+    };
+    for (const [type, show] of Object.entries(badges)) {
+        if (show !== false) continue;
+        messageEl.querySelectorAll<HTMLElement>(`.msg-badge--${type}`).forEach(badge => {
+            badge.style.display = 'none';
+        });
+    }
+}
+
+// Whether two messages are an unpaid run from the same user (paid messages stand alone)
+function continues_run(a: HTMLElement, b: HTMLElement): boolean {
+    const author = a.dataset.author;
+    return author !== undefined && author === b.dataset.author &&
+        !a.classList.contains('msg--t') && !b.classList.contains('msg--t');
+}
+
+// In condensed chats, show the avatar and name once per run from the same user: on the
+// oldest message when the chat grows from the bottom, on the newest when it grows from the top.
+// Mirrors render_messages in message.rs.
+function regroup_messages(container: HTMLElement, options: ChatOptions): void {
+    const messages = Array.from(container.children) as HTMLElement[];
+    const topFirst = options.direction === 'top';
+    messages.forEach((el, i) => {
+        const neighbor = topFirst ? messages[i + 1] : messages[i - 1];
+        const grouped = options.condensedMode === true && neighbor !== undefined && continues_run(el, neighbor);
+        el.classList.toggle('msg--grouped', grouped);
+    });
+}
+
 // Filter badges based on per-element options
 function filter_badges_with_options(messageEl: HTMLElement, options: ChatOptions): void {
     const badges = messageEl.querySelectorAll('.msg-badge');
@@ -870,6 +1122,7 @@ function processMessageImmediate(message: ChatMessage): HTMLElement | null {
         if (insertedEl) {
             // Use per-element badge options
             filter_badges_with_options(insertedEl, options);
+            apply_platform_style(insertedEl, message.platform);
 
             if (i === 0) {
                 firstEl = insertedEl;
@@ -880,8 +1133,8 @@ function processMessageImmediate(message: ChatMessage): HTMLElement | null {
         }
 
         // Remove old messages from beginning (oldest first in DOM)
-        // Keep limit low (150) to prevent memory issues in long-running overlay tabs
-        const maxMessages = 150;
+        // The layout's maxMessages keeps memory bounded in long-running overlay tabs
+        const maxMessages = current_layout?.messageStyle?.maxMessages ?? 100;
         if (container.children.length > maxMessages) {
             const toRemove: HTMLElement[] = [];
             for (let i = 0; i < container.children.length - maxMessages; i++) {
@@ -897,6 +1150,9 @@ function processMessageImmediate(message: ChatMessage): HTMLElement | null {
             }
         }
 
+        // After trimming, so a run whose first message was removed gets its name back
+        regroup_messages(container, options);
+
         // Auto-scroll to show newest messages
         const chatSection = container.parentElement;
         if (chatSection) {
@@ -939,16 +1195,17 @@ window.livestream_viewers = {};
 
 function calculate_viewer_count(viewers: ViewerCounts, options: LiveBadgeOptions | undefined): number {
     const mode = options?.platformMode || 'all';
-    const platforms = options?.platforms || [];
+    // The server keys counts by lowercase platform slug; layouts may name them either way
+    const platforms = (options?.platforms || []).map(p => p.toLowerCase());
 
     let total = 0;
     for (const [platform, count] of Object.entries(viewers)) {
         const platformCount = typeof count === 'number' ? count : parseInt(count as string, 10) || 0;
         if (mode === 'all') {
             total += platformCount;
-        } else if (mode === 'include' && platforms.includes(platform)) {
+        } else if (mode === 'include' && platforms.includes(platform.toLowerCase())) {
             total += platformCount;
-        } else if (mode === 'exclude' && !platforms.includes(platform)) {
+        } else if (mode === 'exclude' && !platforms.includes(platform.toLowerCase())) {
             total += platformCount;
         }
     }
@@ -1001,142 +1258,123 @@ function recalculate_premium_positions(): void {
 }
 
 // ============================================================================
-// Poll System
+// Donation Goal
 // ============================================================================
 
-let active_poll: Poll | null = null;
-const poll_ui = document.getElementById("poll-ui");
-const superchat_ui = document.getElementById("superchat-ui");
+// Sent by the server whenever the goal or the session total changes; null when no goal is set
+let current_goal: GoalUpdate | null = null;
 
-class Poll {
-    question: string;
-    options: string[];
-    votes: number[];
-    voters: string[];
-    multi_vote: boolean;
-    total_votes: number;
-
-    constructor(question: string, multi_vote: boolean, options: string[]) {
-        this.question = question;
-        this.options = options;
-        this.votes = new Array(options.length).fill(0);
-        this.voters = [];
-        this.multi_vote = multi_vote;
-        this.total_votes = 0;
-
-        this.update();
-        if (poll_ui) {
-            poll_ui.style.display = "block";
-            poll_ui.classList.remove("fade-out");
-            poll_ui.classList.add("fade-in");
-        }
-        if (superchat_ui) {
-            superchat_ui.classList.add("slide-down");
-        }
+function render_goal(container: HTMLElement): void {
+    if (current_goal === null) {
+        container.style.display = "none";
+        container.innerHTML = "";
+        return;
     }
 
-    end_poll(): void {
-        const participants = this.voters.length;
-        let html = `<strong>${this.question}</strong><br><small>${participants} participants</small><ul>`;
-        let winning_option = 0;
-
-        for (let i = 0; i < this.options.length; i++) {
-            if (this.votes[i] > this.votes[winning_option]) {
-                winning_option = i;
-            }
-        }
-
-        for (let i = 0; i < this.options.length; i++) {
-            let percentage = 0;
-            if (this.total_votes > 0) {
-                percentage = (this.votes[i] / this.total_votes) * 100;
-            }
-            const percentStr = percentage.toFixed(2);
-            if (i === winning_option) {
-                html += `<li><strong>!vote ${i + 1}: ${this.options[i]} - ${this.votes[i]} (${percentStr}%)</strong></li>`;
-            } else {
-                html += `<li>!vote ${i + 1}: ${this.options[i]} - ${this.votes[i]} (${percentStr}%)</li>`;
-            }
-        }
+    const { label, target, current } = current_goal;
+    const percentage = Math.min(100, (current / target) * 100);
+    container.style.display = "";
+    container.classList.toggle("goal-complete", current >= target);
+    container.innerHTML = `
+        ${label ? `<div class="goal-label">${escape_text(label)}</div>` : ''}
+        <div class="goal-bar"><div class="goal-fill" style="width: ${percentage.toFixed(2)}%"></div></div>
+        <div class="goal-amount">$${current.toFixed(2)} / $${target.toFixed(2)}</div>
+    `;
+}
 
-        if (poll_ui) {
-            poll_ui.innerHTML = html;
-            setTimeout(() => {
-                poll_ui.classList.remove("fade-in");
-                poll_ui.classList.add("fade-out");
-                setTimeout(() => { poll_ui.style.display = "none"; }, 500);
-            }, 10000);
-        }
-        active_poll = null;
-    }
+function handle_goal_update(goal: GoalUpdate | null): void {
+    current_goal = goal;
+    document.querySelectorAll<HTMLElement>(".goal-ui").forEach(render_goal);
+}
 
-    update(): void {
-        if (!poll_ui) return;
+// ============================================================================
+// Poll System
+// ============================================================================
 
-        const participants = this.voters.length;
-        let html = `<strong>${this.question}</strong><br><small>${participants} participants</small><ul>`;
-        for (let i = 0; i < this.options.length; i++) {
-            let percentage = 0;
-            if (this.total_votes > 0) {
-                percentage = (this.votes[i] / this.total_votes) * 100;
-            }
-            html += `<li>!vote ${i + 1}: ${this.options[i]} - ${this.votes[i]} (${percentage.toFixed(2)}%)</li>`;
-        }
-        html += "</ul><small>use !vote [number] to vote</small>";
-        poll_ui.innerHTML = html;
-    }
+// Polls are run and counted by the server; overlays render its poll_update broadcasts
+let active_poll: Poll | null = null;
+let poll_hide_timer: number | undefined;
+const superchat_ui = document.getElementById("superchat-ui");
 
-    handle_vote_message(data: ChatMessage): void {
-        if (this.voters.includes(data.username)) {
-            return;
-        }
+// The overlay's own #poll-ui and any layout `poll` elements
+function poll_containers(): HTMLElement[] {
+    return Array.from(document.querySelectorAll<HTMLElement>("#poll-ui, .poll-ui"));
+}
 
-        const args = data.message.replace("!vote", "").replace("!", "").trim();
-        let result = false;
+// Escape text for use in innerHTML
+function escape_text(text: string): string {
+    const tmp_div = document.createElement("div");
+    tmp_div.textContent = text;
+    return tmp_div.innerHTML;
+}
 
-        if (this.multi_vote) {
-            let votes = args.split(" ");
-            votes = [...new Set(votes)];
-            for (const vote of votes) {
-                result = this.handle_vote(vote) || result;
-            }
-        } else {
-            result = this.handle_vote(args);
+function render_poll(poll: Poll): string {
+    const ended = poll.ended_at !== null;
+    let winning_option = 0;
+    poll.options.forEach((option, i) => {
+        if (option.votes > poll.options[winning_option].votes) {
+            winning_option = i;
         }
+    });
 
-        if (result) {
-            this.voters.push(data.username);
-            this.update();
-        }
+    let html = `<strong>${escape_text(poll.question)}</strong><br><small>${poll.participants} participants</small><ul>`;
+    poll.options.forEach((option, i) => {
+        const percentage = poll.total_votes > 0 ? (option.votes / poll.total_votes) * 100 : 0;
+        const line = `!vote ${i + 1}: ${escape_text(option.text)} - ${option.votes} (${percentage.toFixed(2)}%)`;
+        html += ended && i === winning_option ? `<li><strong>${line}</strong></li>` : `<li>${line}</li>`;
+    });
+    html += "</ul>";
+    if (!ended) {
+        html += `<small>use !vote [${poll.multi_vote ? "numbers" : "number"}] to vote</small>`;
     }
+    return html;
+}
 
-    handle_vote(vote_index: string): boolean {
-        if (isNaN(parseInt(vote_index))) {
-            return false;
-        }
-
-        const i = parseInt(vote_index) - 1;
-        if (i < 0 || i >= this.options.length) {
-            return false;
+function handle_poll_update(poll: Poll): void {
+    const was_shown = active_poll !== null;
+    active_poll = poll.ended_at === null ? poll : null;
+    window.clearTimeout(poll_hide_timer);
+
+    const html = render_poll(poll);
+    for (const container of poll_containers()) {
+        container.innerHTML = html;
+        if (!was_shown) {
+            container.style.display = "block";
+            container.classList.remove("fade-out");
+            container.classList.add("fade-in");
         }
-
-        this.votes[i]++;
-        this.total_votes++;
-        return true;
+    }
+    if (active_poll !== null && superchat_ui) {
+        superchat_ui.classList.add("slide-down");
     }
 
-    is_valid_vote(message: string): boolean {
-        if (message.startsWith("!vote")) return true;
-        if (message.length === 1 && !isNaN(parseInt(message[0]))) return true;
-        if (message.startsWith("!") && !isNaN(parseInt(message[1]))) return true;
-        return false;
+    // Show the final result for a while, then clear it
+    if (active_poll === null) {
+        poll_hide_timer = window.setTimeout(() => {
+            for (const container of poll_containers()) {
+                container.classList.remove("fade-in");
+                container.classList.add("fade-out");
+                window.setTimeout(() => {
+                    container.style.display = "none";
+                    container.innerHTML = "";
+                }, 500);
+            }
+        }, 10000);
     }
 }
 
+function is_poll_vote(message: string): boolean {
+    if (message.startsWith("!vote")) return true;
+    if (/^\d+$/.test(message.trim())) return true;
+    if (message.startsWith("!") && !isNaN(parseInt(message[1]))) return true;
+    return false;
+}
+
 // ============================================================================
 // Command Handling
 // ============================================================================
 
+// Poll commands and votes are counted by the server; hide them from chat
 function handle_command(message: ChatMessage): boolean {
     function unescape(escaped_string: string): string {
         const tmp_div = document.createElement("div");
@@ -1148,36 +1386,11 @@ function handle_command(message: ChatMessage): boolean {
         return false;
     }
 
-    let msg = unescape(message.message);
-    const is_admin = message.is_owner;
-
-    if (msg.startsWith("!poll") && is_admin) {
-        msg = msg.replace("!poll", "").trim();
-        let parts = msg.split(";");
-        parts = parts.filter(el => el.length !== 0);
-        if (parts.length >= 3) {
-            active_poll = new Poll(parts[0], false, parts.slice(1));
-        }
-        return true;
-    } else if (msg.startsWith("!multipoll") && is_admin) {
-        msg = msg.replace("!multipoll", "").trim();
-        let parts = msg.split(";");
-        parts = parts.filter(el => el.length !== 0);
-        if (parts.length >= 3) {
-            active_poll = new Poll(parts[0], true, parts.slice(1));
-        }
-        return true;
-    } else if (msg.startsWith("!endpoll") && is_admin) {
-        if (active_poll !== null) {
-            active_poll.end_poll();
-        }
-        return true;
-    } else if (active_poll !== null && active_poll.is_valid_vote(message.message)) {
-        active_poll.handle_vote_message(message);
+    const msg = unescape(message.message);
+    if (message.is_owner && (msg.startsWith("!poll") || msg.startsWith("!multipoll") || msg.startsWith("!endpoll"))) {
         return true;
     }
-
-    return false;
+    return active_poll !== null && is_poll_vote(msg);
 }
 
 // ============================================================================
@@ -1207,3 +1420,17 @@ function set_date(dateObj: Date): void {
 }
 
 set_date(new Date());
+
+// The server embeds the layout in the page so the first paint is styled; the WebSocket
+// still sends it again on connect and keeps it current afterwards
+function apply_initial_layout(): void {
+    const embedded = document.getElementById("initial-layout");
+    if (!embedded?.textContent) return;
+    try {
+        apply_layout(JSON.parse(embedded.textContent) as Layout);
+    } catch (e) {
+        console.error("[SNEED] Failed to apply embedded layout:", e);
+    }
+}
+
+apply_initial_layout();
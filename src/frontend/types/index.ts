@@ -36,10 +36,18 @@ export interface Style {
     zIndex?: number;
     customCss?: string;
     compiledCss?: string;
+    compiledCssHash?: string;
 }
 
+export type AnchorPoint =
+    | 'top-left' | 'top' | 'top-right'
+    | 'left' | 'center' | 'right'
+    | 'bottom-left' | 'bottom' | 'bottom-right';
+
 export interface ElementConfig {
     enabled: boolean;
+    autoSize?: boolean;
+    anchor?: AnchorPoint;
     displayName?: string;
     position: Position;
     size: Size;
@@ -79,15 +87,33 @@ export interface MessageStyle {
     fontSize: string;
     backgroundColor?: string;
     textColor?: string;
+    firstTimeColor?: string;
     showAvatars?: boolean;
     showUsernames?: boolean;
     condensedMode?: boolean;
     direction?: 'bottom' | 'top';
+    /** Messages each chat element keeps on screen (default 100) */
+    maxMessages?: number;
     showOwnerBadge?: boolean;
     showStaffBadge?: boolean;
     showModBadge?: boolean;
     showVerifiedBadge?: boolean;
     showSubBadge?: boolean;
+    badgeImages?: Record<string, string>;
+    platformOverrides?: Record<string, PlatformStyle>;
+}
+
+// Per-platform overrides of MessageStyle; unset fields fall back to the base style
+export interface PlatformStyle {
+    backgroundColor?: string;
+    textColor?: string;
+    firstTimeColor?: string;
+    showOwnerBadge?: boolean;
+    showStaffBadge?: boolean;
+    showModBadge?: boolean;
+    showVerifiedBadge?: boolean;
+    showSubBadge?: boolean;
+    badgeImages?: Record<string, string>;
 }
 
 export interface Layout {
@@ -95,12 +121,36 @@ export interface Layout {
     version: number;
     elements: Record<string, ElementConfig>;
     messageStyle: MessageStyle;
+    /** SCSS variables and mixins compiled in front of every element's customCss */
+    scssPrelude?: string;
+    /** Web fonts declared with @font-face on overlays showing the layout */
+    fonts?: WebFont[];
+}
+
+export interface WebFont {
+    name: string;
+    /** http(s) URL of the font file */
+    url: string;
+}
+
+/** A single element change, sent instead of the whole layout */
+export interface LayoutElementDelta {
+    layoutName: string;
+    elementId: string;
+    config: ElementConfig;
 }
 
 // ============================================================================
 // Chat Message Types
 // ============================================================================
 
+export interface Attachment {
+    url: string;
+    mime_type: string;
+    width?: number;
+    height?: number;
+}
+
 export interface ChatMessage {
     id: string;
     platform: string;
@@ -109,7 +159,12 @@ export interface ChatMessage {
     received_at: number;
     message: string;
     html: string;
-    emojis: string[];
+    /** sent_at as a local time in the server's DISPLAY_TZ */
+    sent_at_display?: string;
+    alert_sound?: string; // File in /static/sounds/, only on a paid message's live broadcast
+    /** [find, replace, name] or [find, replace, name, width, height] */
+    emojis: Array<[string, string, string] | [string, string, string, number | null, number | null]>;
+    attachments: Attachment[];
     username: string;
     avatar: string;
     amount: number;
@@ -120,6 +175,7 @@ export interface ChatMessage {
     is_mod: boolean;
     is_owner: boolean;
     is_staff: boolean;
+    is_first_time?: boolean;
 }
 
 // ============================================================================
@@ -128,10 +184,28 @@ export interface ChatMessage {
 
 export type WebSocketMessageTag =
     | 'chat_message'
+    | 'replay_message'
+    | 'update_message'
     | 'feature_message'
     | 'viewers'
+    | 'session_total'
+    | 'server_stats'
+    | 'clear_all'
     | 'layout_update'
-    | 'layout_list';
+    | 'layout_element_update'
+    | 'layout_list'
+    | 'server_shutdown'
+    | 'tts_audio'
+    | 'poll_update'
+    | 'poll_error'
+    | 'goal_update'
+    | 'goal_error'
+    | 'edit_error'
+    | 'moderation'
+    | 'slow_mode'
+    | 'pending_message'
+    | 'pending_removed'
+    | 'search_results';
 
 export interface WebSocketMessage {
     tag: WebSocketMessageTag;
@@ -142,6 +216,44 @@ export interface ViewerCounts {
     [platform: string]: number;
 }
 
+export interface ServerStats {
+    clients: number;
+    viewers_total: number;
+    viewers_by_platform: ViewerCounts;
+}
+
+export interface TtsAudio {
+    id: string;
+    username: string; // HTML-escaped
+    amount: number; // USD
+    text: string;
+    audio: string; // URL or data: URI
+}
+
+export interface PollOption {
+    text: string;
+    votes: number;
+}
+
+// Running poll, or the final result once ended_at is set
+export interface Poll {
+    id: string;
+    question: string;
+    options: PollOption[];
+    multi_vote: boolean;
+    total_votes: number;
+    participants: number;
+    started_at: number;
+    ended_at: number | null;
+}
+
+/** Progress toward the donation goal, in USD. `current` may exceed `target`. */
+export interface GoalUpdate {
+    label: string;
+    target: number;
+    current: number;
+}
+
 export interface FeatureMessageResponse {
     id: string | null;
     html?: string;
@@ -150,6 +262,7 @@ export interface FeatureMessageResponse {
 export interface LayoutListResponse {
     layouts: string[];
     active: string;
+    outputs: Record<string, string>; // output name -> active layout
 }
 
 // ============================================================================
@@ -170,5 +283,6 @@ declare global {
         badgeSettings?: BadgeSettings;
         livestream_viewers: ViewerCounts;
         LAYOUT_NAME?: string;
+        LAYOUT_OUTPUT?: string;
     }
 }
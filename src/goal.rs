@@ -0,0 +1,60 @@
+use serde::Serialize;
+
+/// A donation target shown by the overlay's `goal` element.
+/// Progress toward it is the session total, so only the target is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Goal {
+    pub label: String,
+    pub target_usd: f64,
+}
+
+/// The `goal_update` payload
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GoalProgress {
+    pub label: String,
+    /// Target in USD
+    pub target: f64,
+    /// Session total in USD; may exceed the target
+    pub current: f64,
+}
+
+impl Goal {
+    /// Needs a positive target; the label may be empty
+    pub fn new(label: &str, target_usd: f64) -> Result<Self, String> {
+        if !target_usd.is_finite() || target_usd <= 0.0 {
+            return Err("goal target must be a positive amount".to_string());
+        }
+        Ok(Self {
+            label: label.trim().to_string(),
+            target_usd,
+        })
+    }
+
+    pub fn progress(&self, current_usd: f64) -> GoalProgress {
+        GoalProgress {
+            label: self.label.clone(),
+            target: self.target_usd,
+            current: current_usd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_goal() {
+        let goal = Goal::new("  New mic ", 250.0).unwrap();
+        assert_eq!(goal.label, "New mic");
+        assert_eq!(
+            serde_json::to_string(&goal.progress(12.5)).unwrap(),
+            r#"{"label":"New mic","target":250.0,"current":12.5}"#
+        );
+
+        assert!(Goal::new("", 0.0).is_err());
+        assert!(Goal::new("", -5.0).is_err());
+        assert!(Goal::new("", f64::NAN).is_err());
+        assert!(Goal::new("", f64::INFINITY).is_err());
+    }
+}
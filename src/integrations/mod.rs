@@ -0,0 +1,21 @@
+//! Side effects triggered by chat, such as driving OBS or reading out a big donation.
+//! Integrations register with the ChatServer as listeners and never block it.
+
+use actix::Message as ActixMessage;
+
+use crate::message::Message;
+
+pub mod obs;
+pub mod tts;
+pub mod webhook;
+
+/// A paid message the ChatServer accepted and broadcast. `amount` is already in USD
+/// and `message` is HTML-escaped, as sent to clients.
+#[derive(ActixMessage, Clone, Debug)]
+#[rtype(result = "()")]
+pub struct PaidMessage {
+    pub message: Message,
+    /// The amount before conversion, in `original_currency`
+    pub original_amount: f64,
+    pub original_currency: String,
+}
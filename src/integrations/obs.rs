@@ -0,0 +1,328 @@
+use actix::{Actor, Context, Handler, Recipient};
+use actix_web::rt;
+use anyhow::{anyhow, bail, Result};
+use aws_lc_rs::digest::{digest, SHA256};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::PaidMessage;
+use crate::platforms::websocket::{self, Incoming};
+
+const DEFAULT_URL: &str = "ws://127.0.0.1:4455";
+/// obs-websocket v5 speaks RPC version 1
+const RPC_VERSION: u64 = 1;
+/// Covers connecting, identifying and the request itself
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An obs-websocket request fired for paid messages of at least `min_usd`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ObsRule {
+    pub min_usd: f64,
+    pub request_type: String,
+    #[serde(default)]
+    pub request_data: Value,
+}
+
+/// Connection settings and rules, read from the environment
+#[derive(Debug, Clone)]
+pub struct ObsConfig {
+    pub url: String,
+    pub password: Option<String>,
+    pub rules: Vec<ObsRule>,
+}
+
+impl ObsConfig {
+    /// `OBS_DONATION_SCENE` switches to that scene for donations of at least
+    /// `OBS_DONATION_THRESHOLD` USD (default 50). `OBS_RULES` adds rules as a JSON list of
+    /// `{"min_usd", "request_type", "request_data"}`. None when no rules are configured.
+    pub fn from_env() -> Option<Self> {
+        let var = |key| dotenvy::var(key).ok().filter(|v| !v.trim().is_empty());
+        let mut rules = Vec::new();
+
+        if let Some(scene) = var("OBS_DONATION_SCENE") {
+            rules.push(ObsRule {
+                min_usd: crate::sneed_env::var_or("OBS_DONATION_THRESHOLD", 50.0),
+                request_type: "SetCurrentProgramScene".to_string(),
+                request_data: json!({ "sceneName": scene }),
+            });
+        }
+        if let Some(json) = var("OBS_RULES") {
+            match serde_json::from_str::<Vec<ObsRule>>(&json) {
+                Ok(extra) => rules.extend(extra),
+                Err(e) => warn!("Ignoring invalid OBS_RULES: {}", e),
+            }
+        }
+        if rules.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            url: var("OBS_WEBSOCKET_URL").unwrap_or_else(|| DEFAULT_URL.to_string()),
+            password: var("OBS_WEBSOCKET_PASSWORD"),
+            rules,
+        })
+    }
+
+    /// The rule with the highest threshold the amount reaches
+    pub fn rule_for(&self, usd: f64) -> Option<&ObsRule> {
+        self.rules
+            .iter()
+            .filter(|rule| usd >= rule.min_usd)
+            .max_by(|a, b| a.min_usd.total_cmp(&b.min_usd))
+    }
+}
+
+/// Fires OBS requests for paid messages that match a rule
+pub struct Obs {
+    config: Arc<ObsConfig>,
+}
+
+impl Actor for Obs {
+    type Context = Context<Self>;
+}
+
+/// Start the integration if any rules are configured, returning the listener to
+/// register with the ChatServer
+pub fn start() -> Option<Recipient<PaidMessage>> {
+    let Some(config) = ObsConfig::from_env() else {
+        debug!("No OBS rules configured; OBS integration disabled");
+        return None;
+    };
+    info!(url = %config.url, rules = config.rules.len(), "OBS integration enabled");
+    Some(
+        Obs {
+            config: Arc::new(config),
+        }
+        .start()
+        .recipient(),
+    )
+}
+
+impl Handler<PaidMessage> for Obs {
+    type Result = ();
+
+    fn handle(&mut self, msg: PaidMessage, _: &mut Self::Context) -> Self::Result {
+        let Some(rule) = self.config.rule_for(msg.message.amount).cloned() else {
+            return;
+        };
+        let config = self.config.clone();
+        // OBS may be closed or on another machine; a failed request only gets logged
+        rt::spawn(async move {
+            match rt::time::timeout(REQUEST_TIMEOUT, send_request(&config, &rule)).await {
+                Ok(Ok(())) => info!(
+                    request = %rule.request_type,
+                    usd = msg.message.amount,
+                    "Sent OBS request for paid message"
+                ),
+                Ok(Err(e)) => warn!(request = %rule.request_type, error = %e, "OBS request failed"),
+                Err(_) => warn!(request = %rule.request_type, "OBS request timed out"),
+            }
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct ObsPayload {
+    op: u8,
+    #[serde(default)]
+    d: Value,
+}
+
+async fn next_payload(conn: &mut websocket::Connection) -> Result<ObsPayload> {
+    match websocket::next_text(conn).await? {
+        Incoming::Text(text) => Ok(serde_json::from_str(&text)?),
+        Incoming::Close(reason) => bail!("OBS closed the connection: {:?}", reason),
+    }
+}
+
+/// Connect, identify and send one request, waiting for OBS to confirm it
+async fn send_request(config: &ObsConfig, rule: &ObsRule) -> Result<()> {
+    let mut conn = websocket::connect(&config.url).await?;
+
+    let hello = next_payload(&mut conn).await?;
+    if hello.op != 0 {
+        bail!("expected Hello, got op {}", hello.op);
+    }
+    let mut identify = json!({ "rpcVersion": RPC_VERSION, "eventSubscriptions": 0 });
+    if let Some(auth) = hello.d.get("authentication") {
+        let password = config
+            .password
+            .as_deref()
+            .ok_or_else(|| anyhow!("OBS requires a password; set OBS_WEBSOCKET_PASSWORD"))?;
+        let (Some(challenge), Some(salt)) = (auth["challenge"].as_str(), auth["salt"].as_str())
+        else {
+            bail!("OBS sent an incomplete authentication challenge");
+        };
+        identify["authentication"] = json!(authentication(password, salt, challenge));
+    }
+    websocket::send_text(&mut conn, json!({ "op": 1, "d": identify }).to_string()).await?;
+
+    let identified = next_payload(&mut conn).await?;
+    if identified.op != 2 {
+        bail!("expected Identified, got op {}", identified.op);
+    }
+
+    let request_id = Uuid::new_v4().to_string();
+    let request = json!({
+        "op": 6,
+        "d": {
+            "requestType": rule.request_type,
+            "requestId": request_id,
+            "requestData": rule.request_data,
+        },
+    });
+    websocket::send_text(&mut conn, request.to_string()).await?;
+
+    loop {
+        let response = next_payload(&mut conn).await?;
+        if response.op != 7 || response.d["requestId"] != request_id.as_str() {
+            continue;
+        }
+        let status = &response.d["requestStatus"];
+        if status["result"].as_bool() != Some(true) {
+            bail!(
+                "OBS rejected {} ({}): {}",
+                rule.request_type,
+                status["code"],
+                status["comment"].as_str().unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+}
+
+/// obs-websocket's auth string: base64(sha256(base64(sha256(password + salt)) + challenge))
+fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let secret = b64.encode(digest(&SHA256, format!("{}{}", password, salt).as_bytes()));
+    b64.encode(digest(
+        &SHA256,
+        format!("{}{}", secret, challenge).as_bytes(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_codec::Framed;
+    use actix_http::ws::{Codec, Frame, Message as WsMessage};
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn rule(min_usd: f64, scene: &str) -> ObsRule {
+        ObsRule {
+            min_usd,
+            request_type: "SetCurrentProgramScene".to_string(),
+            request_data: json!({ "sceneName": scene }),
+        }
+    }
+
+    #[test]
+    fn test_rule_for() {
+        let config = ObsConfig {
+            url: DEFAULT_URL.to_string(),
+            password: None,
+            rules: vec![rule(100.0, "Huge"), rule(20.0, "Big")],
+        };
+        assert_eq!(config.rule_for(5.0), None);
+        assert_eq!(config.rule_for(20.0), Some(&rule(20.0, "Big")));
+        assert_eq!(config.rule_for(250.0), Some(&rule(100.0, "Huge")));
+    }
+
+    #[test]
+    fn test_authentication() {
+        assert_eq!(
+            authentication(
+                "supersecretpassword",
+                "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=",
+                "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY="
+            ),
+            "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+        );
+    }
+
+    async fn recv(conn: &mut Framed<tokio::net::TcpStream, Codec>) -> Value {
+        match conn.next().await {
+            Some(Ok(Frame::Text(text))) => serde_json::from_slice(&text).unwrap(),
+            other => panic!("unexpected frame {:?}", other),
+        }
+    }
+
+    /// Answer one client like OBS would, returning the request it sent
+    async fn fake_obs(listener: TcpListener, result: bool) -> Value {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8];
+            socket.read_exact(&mut byte).await.unwrap();
+            request.push(byte[0]);
+        }
+        socket
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut conn = Framed::new(socket, Codec::new());
+        let send = |value: Value| WsMessage::Text(value.to_string().into());
+
+        let hello = json!({ "op": 0, "d": { "obsWebSocketVersion": "5.0.0", "rpcVersion": 1 } });
+        conn.send(send(hello)).await.unwrap();
+        let identify = recv(&mut conn).await;
+        assert_eq!(identify["op"], 1);
+        conn.send(send(json!({ "op": 2, "d": { "negotiatedRpcVersion": 1 } })))
+            .await
+            .unwrap();
+
+        let request = recv(&mut conn).await;
+        let response = json!({
+            "op": 7,
+            "d": {
+                "requestType": request["d"]["requestType"],
+                "requestId": request["d"]["requestId"],
+                "requestStatus": { "result": result, "code": if result { 100 } else { 600 } },
+            },
+        });
+        conn.send(send(response)).await.unwrap();
+        request
+    }
+
+    #[actix::test]
+    async fn test_send_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ObsConfig {
+            url: format!("ws://{}", listener.local_addr().unwrap()),
+            password: None,
+            rules: vec![rule(20.0, "Big")],
+        };
+        let obs = actix::spawn(fake_obs(listener, true));
+
+        send_request(&config, &config.rules[0]).await.unwrap();
+        let request = obs.await.unwrap();
+        assert_eq!(request["op"], 6);
+        assert_eq!(request["d"]["requestType"], "SetCurrentProgramScene");
+        assert_eq!(request["d"]["requestData"]["sceneName"], "Big");
+    }
+
+    #[actix::test]
+    async fn test_send_request_failures() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ObsConfig {
+            url: format!("ws://{}", listener.local_addr().unwrap()),
+            password: None,
+            rules: vec![rule(20.0, "Missing")],
+        };
+        let obs = actix::spawn(fake_obs(listener, false));
+        let err = send_request(&config, &config.rules[0]).await.unwrap_err();
+        assert!(err.to_string().contains("600"), "{}", err);
+        obs.await.unwrap();
+
+        // Nothing listening
+        assert!(send_request(&config, &config.rules[0]).await.is_err());
+    }
+}
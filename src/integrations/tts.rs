@@ -0,0 +1,303 @@
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Recipient, WrapFuture};
+use anyhow::{bail, Result};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::PaidMessage;
+use crate::message::Message;
+use crate::sneed_env;
+use crate::web::{ChatServer, TtsAudio};
+
+/// Time allowed for the engine to answer one request
+const SYNTH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest audio accepted from the engine
+const MAX_AUDIO_BYTES: usize = 5 * 1024 * 1024;
+/// Alerts waiting beyond this are dropped rather than read minutes late
+const MAX_QUEUE: usize = 50;
+
+/// Engine settings, read from the environment
+#[derive(Debug, Clone)]
+pub struct TtsConfig {
+    /// Receives `{"text", "voice"}` as JSON and answers with audio, or JSON with a `url`
+    pub endpoint: String,
+    pub voice: Option<String>,
+    pub min_usd: f64,
+    pub max_chars: usize,
+}
+
+impl TtsConfig {
+    /// `TTS_ENDPOINT` is required; `TTS_VOICE`, `TTS_MIN_USD` (default 5) and
+    /// `TTS_MAX_CHARS` (default 300) are optional
+    pub fn from_env() -> Option<Self> {
+        let var = |key| dotenvy::var(key).ok().filter(|v| !v.trim().is_empty());
+        Some(Self {
+            endpoint: var("TTS_ENDPOINT")?,
+            voice: var("TTS_VOICE"),
+            min_usd: sneed_env::var_or("TTS_MIN_USD", 5.0),
+            max_chars: sneed_env::var_or("TTS_MAX_CHARS", 300),
+        })
+    }
+}
+
+/// Synthesizes paid messages one at a time, so alerts reach overlays in order
+pub struct Tts {
+    config: TtsConfig,
+    http: reqwest::Client,
+    server: Addr<ChatServer>,
+    queue: VecDeque<Message>,
+    busy: bool,
+}
+
+impl Actor for Tts {
+    type Context = Context<Self>;
+}
+
+/// Start the integration if an engine is configured, returning the listener to
+/// register with the ChatServer
+pub fn start(server: Addr<ChatServer>) -> Option<Recipient<PaidMessage>> {
+    let Some(config) = TtsConfig::from_env() else {
+        debug!("TTS_ENDPOINT not set; TTS disabled");
+        return None;
+    };
+    let http = match reqwest::Client::builder().timeout(SYNTH_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!(error = %e, "Could not create the TTS HTTP client; TTS disabled");
+            return None;
+        }
+    };
+    info!(endpoint = %config.endpoint, min_usd = config.min_usd, "TTS enabled");
+    Some(
+        Tts {
+            config,
+            http,
+            server,
+            queue: VecDeque::new(),
+            busy: false,
+        }
+        .start()
+        .recipient(),
+    )
+}
+
+impl Handler<PaidMessage> for Tts {
+    type Result = ();
+
+    fn handle(&mut self, msg: PaidMessage, ctx: &mut Self::Context) -> Self::Result {
+        if msg.message.amount < self.config.min_usd {
+            return;
+        }
+        if self.queue.len() >= MAX_QUEUE {
+            warn!(id = %msg.message.id, "TTS queue is full; skipping alert");
+            return;
+        }
+        self.queue.push_back(msg.message);
+        self.synthesize_next(ctx);
+    }
+}
+
+impl Tts {
+    fn synthesize_next(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        let Some(message) = self.queue.pop_front() else {
+            return;
+        };
+        let Some(text) = spoken_text(&message, self.config.max_chars) else {
+            return self.synthesize_next(ctx);
+        };
+
+        self.busy = true;
+        let http = self.http.clone();
+        let config = self.config.clone();
+        let request_text = text.clone();
+        ctx.spawn(
+            async move { synthesize(&http, &config, &request_text).await }
+                .into_actor(self)
+                .map(move |res, act, ctx| {
+                    match res {
+                        Ok(audio) => act.server.do_send(TtsAudio {
+                            id: message.id,
+                            username: message.username,
+                            amount: message.amount,
+                            text,
+                            audio,
+                        }),
+                        Err(e) => warn!(id = %message.id, error = %e, "TTS synthesis failed"),
+                    }
+                    act.busy = false;
+                    act.synthesize_next(ctx);
+                }),
+        );
+    }
+}
+
+/// What gets read aloud: the message without emoji markup, cut to `max_chars`.
+/// None when there is nothing to say.
+fn spoken_text(message: &Message, max_chars: usize) -> Option<String> {
+    let text = message.to_plaintext();
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.chars().take(max_chars).collect())
+}
+
+#[derive(Deserialize)]
+struct EngineResponse {
+    url: String,
+}
+
+/// Ask the engine for audio. Audio bodies become a `data:` URI; JSON bodies give a URL.
+async fn synthesize(http: &reqwest::Client, config: &TtsConfig, text: &str) -> Result<String> {
+    let body = json!({ "text": text, "voice": config.voice });
+    let response = http
+        .post(&config.endpoint)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("TTS engine answered {}", response.status());
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("audio/mpeg")
+        .to_string();
+    let bytes = response.bytes().await?;
+    if bytes.len() > MAX_AUDIO_BYTES {
+        bail!("TTS audio is too large ({} bytes)", bytes.len());
+    }
+
+    if content_type.starts_with("application/json") {
+        let EngineResponse { url } = serde_json::from_slice(&bytes)?;
+        return Ok(url);
+    }
+    if !content_type.starts_with("audio/") {
+        bail!("TTS engine sent {} instead of audio", content_type);
+    }
+    Ok(format!(
+        "data:{};base64,{}",
+        content_type,
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Emoji;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn config(endpoint: String) -> TtsConfig {
+        TtsConfig {
+            endpoint,
+            voice: Some("alloy".to_string()),
+            min_usd: 5.0,
+            max_chars: 300,
+        }
+    }
+
+    /// Answer one HTTP request with `response`, returning the request body
+    async fn fake_engine(listener: TcpListener, response: &'static [u8]) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        let body_start = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let head = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        while request.len() < body_start + length {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        socket.write_all(response).await.unwrap();
+        String::from_utf8(request[body_start..].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_spoken_text() {
+        let wave = Emoji::new(":wave:", "https://example.com/wave.png", "wave");
+        let message = Message {
+            message: format!("Tom &amp; Jerry {}", wave.to_html()),
+            emojis: vec![wave],
+            ..Default::default()
+        };
+        assert_eq!(
+            spoken_text(&message, 300).as_deref(),
+            Some("Tom & Jerry :wave:")
+        );
+        assert_eq!(spoken_text(&message, 3).as_deref(), Some("Tom"));
+
+        let empty = Message {
+            message: "  ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(spoken_text(&empty, 300), None);
+    }
+
+    #[actix::test]
+    async fn test_synthesize_audio() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = config(format!("http://{}/tts", listener.local_addr().unwrap()));
+        let engine = actix::spawn(fake_engine(
+            listener,
+            b"HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: 3\r\n\r\nabc",
+        ));
+
+        let audio = synthesize(&reqwest::Client::new(), &config, "hello")
+            .await
+            .unwrap();
+        assert_eq!(audio, "data:audio/mpeg;base64,YWJj");
+        let request: serde_json::Value = serde_json::from_str(&engine.await.unwrap()).unwrap();
+        assert_eq!(request, json!({ "text": "hello", "voice": "alloy" }));
+    }
+
+    #[actix::test]
+    async fn test_synthesize_url_and_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = config(format!("http://{}/tts", listener.local_addr().unwrap()));
+        let engine = actix::spawn(fake_engine(
+            listener,
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 35\r\n\r\n{\"url\":\"https://cdn.example.com/a\"}",
+        ));
+        let http = reqwest::Client::new();
+        assert_eq!(
+            synthesize(&http, &config, "hello").await.unwrap(),
+            "https://cdn.example.com/a"
+        );
+        engine.await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = TtsConfig {
+            endpoint: format!("http://{}/tts", listener.local_addr().unwrap()),
+            ..config
+        };
+        let engine = actix::spawn(fake_engine(
+            listener,
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+        ));
+        assert!(synthesize(&http, &config, "hello").await.is_err());
+        engine.await.unwrap();
+    }
+}
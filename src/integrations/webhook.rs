@@ -0,0 +1,246 @@
+use actix::{Actor, Context, Handler, Recipient};
+use actix_web::rt;
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::PaidMessage;
+use crate::sneed_env;
+
+/// Time allowed for one delivery attempt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Attempts per URL before the event is given up on
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Where donation events are POSTed, read from the environment
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    pub min_usd: f64,
+}
+
+impl WebhookConfig {
+    /// `WEBHOOK_URLS` is a comma-separated list and is required; `WEBHOOK_MIN_USD`
+    /// (default 0) skips smaller paid messages
+    pub fn from_env() -> Option<Self> {
+        let urls = parse_urls(&dotenvy::var("WEBHOOK_URLS").unwrap_or_default());
+        (!urls.is_empty()).then(|| Self {
+            urls,
+            min_usd: sneed_env::var_or("WEBHOOK_MIN_USD", 0.0),
+        })
+    }
+}
+
+fn parse_urls(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// POSTs paid messages to every configured URL
+pub struct Webhook {
+    config: Arc<WebhookConfig>,
+    http: reqwest::Client,
+}
+
+impl Actor for Webhook {
+    type Context = Context<Self>;
+}
+
+/// Start the integration if any URLs are configured, returning the listener to
+/// register with the ChatServer
+pub fn start() -> Option<Recipient<PaidMessage>> {
+    let Some(config) = WebhookConfig::from_env() else {
+        debug!("WEBHOOK_URLS not set; outbound webhooks disabled");
+        return None;
+    };
+    let http = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!(error = %e, "Could not create the webhook HTTP client; webhooks disabled");
+            return None;
+        }
+    };
+    info!(
+        urls = config.urls.len(),
+        min_usd = config.min_usd,
+        "Outbound webhooks enabled"
+    );
+    Some(
+        Webhook {
+            config: Arc::new(config),
+            http,
+        }
+        .start()
+        .recipient(),
+    )
+}
+
+impl Handler<PaidMessage> for Webhook {
+    type Result = ();
+
+    fn handle(&mut self, msg: PaidMessage, _: &mut Self::Context) -> Self::Result {
+        if msg.message.amount < self.config.min_usd {
+            return;
+        }
+        let body = Arc::new(payload(&msg));
+        // Each URL retries on its own; a slow or dead receiver only gets logged
+        for url in &self.config.urls {
+            let (http, url, body) = (self.http.clone(), url.clone(), body.clone());
+            rt::spawn(async move {
+                match deliver(&http, &url, &body, INITIAL_BACKOFF).await {
+                    Ok(()) => debug!(url = %url, id = %msg.message.id, "Delivered webhook"),
+                    Err(e) => warn!(url = %url, error = %e, "Webhook delivery failed"),
+                }
+            });
+        }
+    }
+}
+
+/// The JSON body POSTed for a paid message. `message` and `username` are HTML-escaped,
+/// as sent to overlays.
+fn payload(msg: &PaidMessage) -> Value {
+    let message = &msg.message;
+    json!({
+        "event": "paid_message",
+        "id": message.id,
+        "platform": message.platform,
+        "username": message.username,
+        "message": message.message,
+        "amount_usd": message.amount,
+        "original_amount": msg.original_amount,
+        "original_currency": msg.original_currency,
+        "sent_at": message.sent_at,
+    })
+}
+
+/// POST the body, retrying failures and non-2xx answers with exponential backoff
+async fn deliver(http: &reqwest::Client, url: &str, body: &Value, backoff: Duration) -> Result<()> {
+    let mut backoff = backoff;
+    let mut attempt = 1;
+    loop {
+        let request = http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => format!("answered {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= MAX_ATTEMPTS {
+            bail!("{} (gave up after {} attempts)", error, attempt);
+        }
+        debug!(url = %url, attempt, error = %error, "Retrying webhook in {:?}", backoff);
+        rt::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_urls() {
+        assert_eq!(
+            parse_urls(" https://a.example/hook, ,https://b.example/x "),
+            ["https://a.example/hook", "https://b.example/x"]
+        );
+        assert!(parse_urls("").is_empty());
+    }
+
+    #[test]
+    fn test_payload() {
+        let msg = PaidMessage {
+            message: Message {
+                platform: "youtube".to_string(),
+                username: "Donor".to_string(),
+                message: "hi &amp; bye".to_string(),
+                amount: 11.0,
+                currency: "USD".to_string(),
+                sent_at: 1707139765000,
+                ..Default::default()
+            },
+            original_amount: 10.0,
+            original_currency: "EUR".to_string(),
+        };
+        let body = payload(&msg);
+        assert_eq!(body["amount_usd"], 11.0);
+        assert_eq!(body["original_amount"], 10.0);
+        assert_eq!(body["original_currency"], "EUR");
+        assert_eq!(body["message"], "hi &amp; bye");
+        assert_eq!(body["id"], msg.message.id.to_string());
+    }
+
+    /// Answer each connection with the next status, returning the bodies received
+    async fn fake_receiver(listener: TcpListener, statuses: &[u16]) -> Vec<String> {
+        let mut bodies = Vec::new();
+        for status in statuses {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8];
+            while !request.ends_with(b"\r\n\r\n") {
+                socket.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            let head = String::from_utf8_lossy(&request).to_ascii_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |len| len.trim().parse().unwrap());
+            let mut body = vec![0u8; length];
+            socket.read_exact(&mut body).await.unwrap();
+            bodies.push(String::from_utf8(body).unwrap());
+            socket
+                .write_all(
+                    format!(
+                        "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                        status
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+        bodies
+    }
+
+    #[actix::test]
+    async fn test_deliver_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = rt::spawn(fake_receiver(listener, &[500, 204]));
+
+        let body = json!({ "amount_usd": 5.0 });
+        let http = reqwest::Client::new();
+        deliver(&http, &url, &body, Duration::from_millis(10))
+            .await
+            .unwrap();
+        let bodies = receiver.await.unwrap();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(serde_json::from_str::<Value>(&bodies[1]).unwrap(), body);
+    }
+
+    #[actix::test]
+    async fn test_deliver_gives_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiver = rt::spawn(fake_receiver(listener, &[503, 503, 503]));
+
+        let http = reqwest::Client::new();
+        let result = deliver(&http, &url, &json!({}), Duration::from_millis(10)).await;
+        assert!(result.unwrap_err().to_string().contains("3 attempts"));
+        receiver.await.unwrap();
+    }
+}
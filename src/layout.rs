@@ -1,8 +1,11 @@
+use crate::css::{sanitize_css, CssPolicy};
+use crate::message::is_valid_avatar;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 
 /// A dimension value with explicit unit type
@@ -16,6 +19,12 @@ pub enum Dimension {
     Vh(f64),
     /// Percentage
     Percent(f64),
+    /// Relative to the element's font size
+    Em(f64),
+    /// Relative to the root font size
+    Rem(f64),
+    /// Width of the "0" glyph in the element's font
+    Ch(f64),
     /// CSS calc() expression or other complex value
     Calc(String),
 }
@@ -26,7 +35,7 @@ impl Dimension {
         let s = s.trim();
 
         if s.starts_with("calc(") {
-            return Some(Dimension::Calc(s.to_string()));
+            return is_valid_calc(s).then(|| Dimension::Calc(s.to_string()));
         }
 
         if let Some(num_str) = s.strip_suffix("vw") {
@@ -38,6 +47,16 @@ impl Dimension {
         if let Some(num_str) = s.strip_suffix('%') {
             return num_str.trim().parse().ok().map(Dimension::Percent);
         }
+        // "rem" must be checked before "em"
+        if let Some(num_str) = s.strip_suffix("rem") {
+            return num_str.trim().parse().ok().map(Dimension::Rem);
+        }
+        if let Some(num_str) = s.strip_suffix("em") {
+            return num_str.trim().parse().ok().map(Dimension::Em);
+        }
+        if let Some(num_str) = s.strip_suffix("ch") {
+            return num_str.trim().parse().ok().map(Dimension::Ch);
+        }
         if let Some(num_str) = s.strip_suffix("px") {
             return num_str.trim().parse().ok().map(Dimension::Px);
         }
@@ -53,11 +72,148 @@ impl Dimension {
             Dimension::Vw(v) => format!("{}vw", v),
             Dimension::Vh(v) => format!("{}vh", v),
             Dimension::Percent(v) => format!("{}%", v),
+            Dimension::Em(v) => format!("{}em", v),
+            Dimension::Rem(v) => format!("{}rem", v),
+            Dimension::Ch(v) => format!("{}ch", v),
             Dimension::Calc(s) => s.clone(),
         }
     }
 }
 
+/// Units accepted for numbers inside calc() expressions
+const CALC_UNITS: &[&str] = &["px", "vw", "vh", "vmin", "vmax", "%", "em", "rem", "ch"];
+/// Functions that may appear inside calc() expressions
+const CALC_FUNCTIONS: &[&str] = &["calc", "min", "max", "clamp"];
+/// Guards against pathological nesting
+const CALC_MAX_DEPTH: usize = 32;
+
+/// Check that a calc() expression is well formed: balanced parentheses,
+/// known units and functions, and an operand on both sides of every operator.
+fn is_valid_calc(s: &str) -> bool {
+    let mut parser = CalcParser {
+        input: s.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    parser.function().is_some() && parser.pos == parser.input.len()
+}
+
+/// Minimal recursive-descent parser over a calc() expression.
+/// Each method returns None as soon as the input is malformed.
+struct CalcParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl CalcParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        self.skip_whitespace();
+        (self.peek() == Some(c)).then(|| self.pos += 1)
+    }
+
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos]).unwrap_or_default()
+    }
+
+    /// name(args) where name is an allowed function or var(--custom-property)
+    fn function(&mut self) -> Option<()> {
+        self.depth += 1;
+        if self.depth > CALC_MAX_DEPTH {
+            return None;
+        }
+
+        let name = self.take_while(|c| c.is_ascii_alphabetic()).to_owned();
+        self.expect(b'(')?;
+
+        if name == "var" {
+            self.skip_whitespace();
+            if !self.input[self.pos..].starts_with(b"--") {
+                return None;
+            }
+            self.pos += 2;
+            if self
+                .take_while(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+                .is_empty()
+            {
+                return None;
+            }
+        } else if CALC_FUNCTIONS.contains(&name.as_str()) {
+            self.expression()?;
+            while self.expect(b',').is_some() {
+                self.expression()?;
+            }
+        } else {
+            return None;
+        }
+
+        self.expect(b')')?;
+        self.depth -= 1;
+        Some(())
+    }
+
+    /// operand (operator operand)*
+    fn expression(&mut self) -> Option<()> {
+        self.operand()?;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b'+' | b'-' | b'*' | b'/') => {
+                    self.pos += 1;
+                    self.operand()?;
+                }
+                _ => return Some(()),
+            }
+        }
+    }
+
+    /// A number with optional unit, a parenthesized expression, or a function call
+    fn operand(&mut self) -> Option<()> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'(' => {
+                self.depth += 1;
+                if self.depth > CALC_MAX_DEPTH {
+                    return None;
+                }
+                self.pos += 1;
+                self.expression()?;
+                self.expect(b')')?;
+                self.depth -= 1;
+                Some(())
+            }
+            c if c.is_ascii_alphabetic() => self.function(),
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<()> {
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.pos += 1;
+        }
+        let digits = self.take_while(|c| c.is_ascii_digit() || c == b'.');
+        if digits.parse::<f64>().is_err() {
+            return None;
+        }
+        let unit = self.take_while(|c| c.is_ascii_alphabetic() || c == b'%');
+        (unit.is_empty() || CALC_UNITS.contains(&unit)).then_some(())
+    }
+}
+
 impl Serialize for Dimension {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
@@ -192,6 +348,38 @@ pub struct Style {
     /// Compiled CSS (populated by server from custom_css)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compiled_css: Option<String>,
+    /// Hash of the custom_css that produced compiled_css, so unchanged SCSS isn't recompiled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compiled_css_hash: Option<String>,
+}
+
+impl Style {
+    /// Inline CSS for the properties that are set, as the overlay applies them
+    pub fn to_css(&self) -> String {
+        let opacity = self.opacity.map(|opacity| opacity.to_string());
+        let z_index = self.z_index.map(|z_index| z_index.to_string());
+        [
+            ("background-color", &self.background_color),
+            ("font-size", &self.font_size),
+            ("font-family", &self.font_family),
+            ("font-weight", &self.font_weight),
+            ("font-style", &self.font_style),
+            ("color", &self.color),
+            ("line-height", &self.line_height),
+            ("letter-spacing", &self.letter_spacing),
+            ("text-align", &self.text_align),
+            ("padding", &self.padding),
+            ("margin", &self.margin),
+            ("border-radius", &self.border_radius),
+            ("opacity", &opacity),
+            ("transform", &self.transform),
+            ("z-index", &z_index),
+        ]
+        .iter()
+        .filter_map(|(prop, value)| value.as_ref().map(|value| format!("{}: {};", prop, value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
 }
 
 /// Anchor point for auto-sized elements
@@ -209,6 +397,17 @@ pub enum AnchorPoint {
     BottomRight,
 }
 
+impl AnchorPoint {
+    /// Whether the anchor is measured from the (right, bottom) edges.
+    /// Center anchors measure from the left/top, matching the editor.
+    fn edges(&self) -> (bool, bool) {
+        use AnchorPoint::*;
+        let right = matches!(self, TopRight | Right | BottomRight);
+        let bottom = matches!(self, BottomLeft | Bottom | BottomRight);
+        (right, bottom)
+    }
+}
+
 /// Configuration for an individual overlay element
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -252,6 +451,202 @@ impl Default for ElementConfig {
     }
 }
 
+impl ElementConfig {
+    /// Absolute-position CSS for this element, as the overlay applies it.
+    ///
+    /// Auto-sized elements with an anchor are placed from the anchor's edges, with a
+    /// missing offset treated as 0, and get no width/height. Fixed-size elements use
+    /// whichever of x/y/right/bottom are set.
+    pub fn computed_style(&self) -> String {
+        let pos = &self.position;
+        let zero = Dimension::Px(0.0);
+        let mut decls: Vec<(&str, String)> = vec![("position", "absolute".to_string())];
+
+        match (&self.anchor, self.auto_size) {
+            (Some(anchor), true) => {
+                let (right, bottom) = anchor.edges();
+                let (h_prop, h) = if right {
+                    ("right", &pos.right)
+                } else {
+                    ("left", &pos.x)
+                };
+                let (v_prop, v) = if bottom {
+                    ("bottom", &pos.bottom)
+                } else {
+                    ("top", &pos.y)
+                };
+                decls.push((h_prop, h.as_ref().unwrap_or(&zero).to_css()));
+                decls.push((v_prop, v.as_ref().unwrap_or(&zero).to_css()));
+            }
+            _ => {
+                for (prop, value) in [
+                    ("left", &pos.x),
+                    ("top", &pos.y),
+                    ("right", &pos.right),
+                    ("bottom", &pos.bottom),
+                ] {
+                    if let Some(value) = value {
+                        decls.push((prop, value.to_css()));
+                    }
+                }
+            }
+        }
+
+        if !self.auto_size {
+            if let Some(width) = &self.size.width {
+                decls.push(("width", width.to_css()));
+            }
+            if let Some(height) = &self.size.height {
+                decls.push(("height", height.to_css()));
+            }
+        }
+        // Content constraints apply either way
+        if let Some(max_width) = &self.size.max_width {
+            decls.push(("max-width", max_width.clone()));
+        }
+        if let Some(max_height) = &self.size.max_height {
+            decls.push(("max-height", max_height.clone()));
+        }
+        if let Some(z_index) = pos.z_index {
+            decls.push(("z-index", z_index.to_string()));
+        }
+
+        decls
+            .iter()
+            .map(|(prop, value)| format!("{}: {};", prop, value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Typed view of `ElementConfig.options` for element types that define options.
+/// Unknown keys are ignored so options from newer editors still parse; known keys
+/// must have the right type.
+#[derive(Debug)]
+pub enum ElementOptions {
+    Chat(ChatOptions),
+    Live(LiveOptions),
+    /// `text` and `attribution`
+    Text(TextOptions),
+    Featured(FeaturedOptions),
+    /// Element types without typed options
+    Other,
+}
+
+impl ElementOptions {
+    /// Parse `options` for an element of `base_type` (its key without `-N`)
+    pub fn parse(base_type: &str, options: Option<&serde_json::Value>) -> Result<Self, String> {
+        fn typed<T: serde::de::DeserializeOwned + Default>(
+            options: Option<&serde_json::Value>,
+        ) -> Result<T, String> {
+            match options {
+                None | Some(serde_json::Value::Null) => Ok(T::default()),
+                Some(value) => T::deserialize(value).map_err(|e| e.to_string()),
+            }
+        }
+
+        Ok(match base_type {
+            "chat" => Self::Chat(typed(options)?),
+            "live" => Self::Live(typed(options)?),
+            "text" | "attribution" => {
+                let text: TextOptions = typed(options)?;
+                // A misspelt key would otherwise leave the element silently blank
+                if options.is_some_and(|o| !o.is_null()) && text.content.is_none() {
+                    return Err("missing \"content\"".to_string());
+                }
+                Self::Text(text)
+            }
+            "featured" => {
+                let featured: FeaturedOptions = typed(options)?;
+                if featured.scale.is_some_and(|s| !s.is_finite() || s <= 0.0) {
+                    return Err("\"scale\" must be a positive number".to_string());
+                }
+                Self::Featured(featured)
+            }
+            _ => Self::Other,
+        })
+    }
+}
+
+/// Options for `chat` elements; unset fields fall back to `MessageStyle`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatOptions {
+    pub show_avatars: Option<bool>,
+    pub show_usernames: Option<bool>,
+    pub condensed_mode: Option<bool>,
+    pub direction: Option<ChatDirection>,
+    pub show_owner_badge: Option<bool>,
+    pub show_staff_badge: Option<bool>,
+    pub show_mod_badge: Option<bool>,
+    pub show_verified_badge: Option<bool>,
+    pub show_sub_badge: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatDirection {
+    Bottom,
+    Top,
+}
+
+impl ChatOptions {
+    /// The layout's message style with this chat's options applied over it
+    pub fn message_style(&self, base: &MessageStyle) -> MessageStyle {
+        MessageStyle {
+            show_avatars: self.show_avatars.unwrap_or(base.show_avatars),
+            show_usernames: self.show_usernames.unwrap_or(base.show_usernames),
+            condensed_mode: self.condensed_mode.unwrap_or(base.condensed_mode),
+            direction: match self.direction {
+                Some(ChatDirection::Top) => "top".to_string(),
+                Some(ChatDirection::Bottom) => "bottom".to_string(),
+                None => base.direction.clone(),
+            },
+            show_owner_badge: self.show_owner_badge.unwrap_or(base.show_owner_badge),
+            show_staff_badge: self.show_staff_badge.unwrap_or(base.show_staff_badge),
+            show_mod_badge: self.show_mod_badge.unwrap_or(base.show_mod_badge),
+            show_verified_badge: self.show_verified_badge.unwrap_or(base.show_verified_badge),
+            show_sub_badge: self.show_sub_badge.unwrap_or(base.show_sub_badge),
+            ..base.clone()
+        }
+    }
+}
+
+/// Options for `live` badge elements
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)] // Parsed for validation; the overlay applies options itself
+pub struct LiveOptions {
+    pub platform_mode: Option<PlatformMode>,
+    pub platforms: Option<Vec<String>>,
+    pub show_icon: Option<bool>,
+    pub show_label: Option<bool>,
+    pub show_count: Option<bool>,
+}
+
+/// Which platforms a live badge counts
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlatformMode {
+    All,
+    Include,
+    Exclude,
+}
+
+/// Options for `text` and `attribution` elements
+#[derive(Debug, Default, Deserialize)]
+pub struct TextOptions {
+    /// Text to show; may contain tokens like `{{datetime:HH:mm:ss}}`
+    pub content: Option<String>,
+}
+
+/// Options for `featured` message elements
+#[derive(Debug, Default, Deserialize)]
+pub struct FeaturedOptions {
+    /// Scale applied to the featured message's content
+    pub scale: Option<f64>,
+}
+
 /// Message styling configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -268,6 +663,9 @@ pub struct MessageStyle {
     pub background_color: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text_color: Option<String>,
+    /// Highlight for a user's first message (`msg--first`); overlays use their own default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_time_color: Option<String>,
 
     // Display options
     #[serde(default = "default_true")]
@@ -278,6 +676,9 @@ pub struct MessageStyle {
     pub condensed_mode: bool,
     #[serde(default = "default_direction")]
     pub direction: String,
+    /// Messages a chat element keeps on screen, and that are replayed to it on connect
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
 
     // Badge visibility
     #[serde(default = "default_true")]
@@ -290,6 +691,70 @@ pub struct MessageStyle {
     pub show_verified_badge: bool,
     #[serde(default = "default_true")]
     pub show_sub_badge: bool,
+    /// Images shown instead of the built-in icons, keyed by badge type (`owner`, `staff`,
+    /// `mod`, `verified`, `sub`)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub badge_images: HashMap<String, String>,
+
+    /// Per-platform overrides keyed by platform name (case-insensitive)
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub platform_overrides: HashMap<String, PlatformStyle>,
+}
+
+/// Overrides of `MessageStyle` for one platform's messages.
+/// Unset fields fall back to the base style.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformStyle {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_time_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_owner_badge: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_staff_badge: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_mod_badge: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_verified_badge: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_sub_badge: Option<bool>,
+    /// Merged over the base style's `badge_images` per badge type
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub badge_images: HashMap<String, String>,
+}
+
+impl MessageStyle {
+    /// The style for messages from `platform`: its override merged over the base style,
+    /// with every badge setting filled in
+    pub fn for_platform(&self, platform: &str) -> PlatformStyle {
+        let o = self
+            .platform_overrides
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(platform))
+            .map(|(_, style)| style.clone())
+            .unwrap_or_default();
+
+        PlatformStyle {
+            background_color: o.background_color.or_else(|| self.background_color.clone()),
+            text_color: o.text_color.or_else(|| self.text_color.clone()),
+            first_time_color: o.first_time_color.or_else(|| self.first_time_color.clone()),
+            show_owner_badge: Some(o.show_owner_badge.unwrap_or(self.show_owner_badge)),
+            show_staff_badge: Some(o.show_staff_badge.unwrap_or(self.show_staff_badge)),
+            show_mod_badge: Some(o.show_mod_badge.unwrap_or(self.show_mod_badge)),
+            show_verified_badge: Some(o.show_verified_badge.unwrap_or(self.show_verified_badge)),
+            show_sub_badge: Some(o.show_sub_badge.unwrap_or(self.show_sub_badge)),
+            badge_images: self
+                .badge_images
+                .clone()
+                .into_iter()
+                .chain(o.badge_images)
+                .collect(),
+        }
+    }
 }
 
 fn default_avatar_size() -> String {
@@ -304,6 +769,11 @@ fn default_border_radius() -> String {
 fn default_font_size() -> String {
     "16px".to_string()
 }
+fn default_max_messages() -> usize {
+    100
+}
+/// Most `maxMessages` a layout may ask for; the server keeps this many recent messages
+pub const MAX_MESSAGES: usize = 1000;
 fn default_true() -> bool {
     true
 }
@@ -320,15 +790,19 @@ impl Default for MessageStyle {
             font_size: default_font_size(),
             background_color: None,
             text_color: None,
+            first_time_color: None,
             show_avatars: true,
             show_usernames: true,
             condensed_mode: false,
             direction: default_direction(),
+            max_messages: default_max_messages(),
             show_owner_badge: true,
             show_staff_badge: true,
             show_mod_badge: true,
             show_verified_badge: true,
             show_sub_badge: true,
+            badge_images: HashMap::new(),
+            platform_overrides: HashMap::new(),
         }
     }
 }
@@ -344,12 +818,112 @@ pub struct Layout {
     pub elements: HashMap<String, ElementConfig>,
     #[serde(default)]
     pub message_style: MessageStyle,
+    /// SCSS variables and mixins available to every element's `custom_css`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scss_prelude: Option<String>,
+    /// Web fonts overlays load (and preload) for this layout's `fontFamily` values
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fonts: Vec<WebFont>,
+}
+
+/// A font file declared with `@font-face` on overlays showing the layout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebFont {
+    /// Family name, as used in `fontFamily`
+    pub name: String,
+    /// http(s) URL of the font file
+    pub url: String,
 }
 
+impl WebFont {
+    /// Name and URL are written into CSS and HTML, so neither may break out of its quotes
+    fn problem(&self) -> Option<String> {
+        if self.name.trim().is_empty()
+            || self
+                .name
+                .contains(['"', '\\', ';', '{', '}', '<', '>', '\n', '\r'])
+        {
+            return Some(format!("invalid name {:?}", self.name));
+        }
+        let lower = self.url.to_ascii_lowercase();
+        if !(lower.starts_with("https://") || lower.starts_with("http://"))
+            || self
+                .url
+                .contains(|c: char| c.is_whitespace() || "\"'()\\<>".contains(c))
+        {
+            return Some(format!("invalid URL {:?}, expected http(s)", self.url));
+        }
+        None
+    }
+}
+
+/// Layout schema version written by this build
+pub const LAYOUT_VERSION: u32 = 2;
+
+/// Files written before versioning existed are schema 1
 fn default_version() -> u32 {
     1
 }
 
+/// Upgrade a layout's raw JSON to `LAYOUT_VERSION`, one schema step at a time.
+/// Runs before typed deserialization so older shapes never have to parse as `Layout`.
+/// Returns whether anything was migrated.
+fn migrate_layout_value(value: &mut serde_json::Value) -> Result<bool> {
+    let version = match value.get("version") {
+        None => default_version(),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .context("Layout version must be a positive integer")?,
+    };
+
+    if version > LAYOUT_VERSION {
+        anyhow::bail!(
+            "Layout schema version {} is newer than this server supports ({}); upgrade stream-nexus to load it",
+            version,
+            LAYOUT_VERSION
+        );
+    }
+
+    let mut current = version;
+    while current < LAYOUT_VERSION {
+        match current {
+            1 => migrate_v1_to_v2(value),
+            _ => unreachable!("missing layout migration from version {}", current),
+        }
+        current += 1;
+    }
+
+    if version == LAYOUT_VERSION {
+        return Ok(false);
+    }
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), LAYOUT_VERSION.into());
+    }
+    Ok(true)
+}
+
+/// v2: auto-sized elements always carry an anchor (previously implied top-left)
+fn migrate_v1_to_v2(value: &mut serde_json::Value) {
+    let Some(elements) = value.get_mut("elements").and_then(|e| e.as_object_mut()) else {
+        return;
+    };
+
+    for element in elements.values_mut() {
+        let Some(element) = element.as_object_mut() else {
+            continue;
+        };
+        let auto_size = element
+            .get("autoSize")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if auto_size && element.get("anchor").is_none_or(|a| a.is_null()) {
+            element.insert("anchor".to_string(), "top-left".into());
+        }
+    }
+}
+
 impl Layout {
     /// Create a default layout matching the current hardcoded overlay style
     pub fn default_layout() -> Self {
@@ -510,39 +1084,329 @@ impl Layout {
             },
         );
 
+        // Donation goal bar - top right (hidden until a goal is set)
+        elements.insert(
+            "goal".to_string(),
+            ElementConfig {
+                enabled: true,
+                locked: false,
+                auto_size: false,
+                anchor: None,
+                display_name: None,
+                position: Position {
+                    x: None,
+                    y: Some(Dimension::Vh(0.0)),
+                    right: Some(Dimension::Vw(0.0)),
+                    bottom: None,
+                    z_index: None,
+                },
+                size: Size {
+                    width: Some(Dimension::Vw(25.0)),
+                    height: None, // auto
+                    max_width: None,
+                    max_height: None,
+                },
+                style: Style::default(),
+                options: None,
+            },
+        );
+
         Layout {
             name: "default".to_string(),
-            version: 1,
+            version: LAYOUT_VERSION,
             elements,
             message_style: MessageStyle::default(),
+            scss_prelude: None,
+            fonts: Vec::new(),
         }
     }
 
-    /// Compile SCSS in all elements' custom_css fields
-    pub fn compile_scss(&mut self) {
-        for (_id, config) in self.elements.iter_mut() {
-            if let Some(scss) = &config.style.custom_css {
-                if !scss.trim().is_empty() {
-                    match compile_scss_to_css(scss) {
-                        Ok(css) => {
-                            config.style.compiled_css = Some(css);
-                        }
-                        Err(e) => {
-                            warn!("Failed to compile SCSS: {}", e);
-                            // Fall back to using the source as-is
-                            config.style.compiled_css = Some(scss.clone());
-                        }
+    /// Check element keys and free-form dimension strings, returning every problem found.
+    /// Keys must name a built-in element type or one in `custom_types`, optionally with a
+    /// `-N` instance suffix. Known `options` keys are type-checked (see `ElementOptions`);
+    /// unrecognised JSON fields and option keys are not, so layouts from newer editors
+    /// still save.
+    pub fn validate(&self, custom_types: &HashSet<String>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = validate_layout_name(&self.name) {
+            problems.push(e.to_string());
+        }
+
+        let mut keys: Vec<&String> = self.elements.keys().collect();
+        keys.sort();
+        for key in keys {
+            let base = element_base_type(key);
+            if !BUILTIN_ELEMENT_TYPES.contains(&base) && !custom_types.contains(base) {
+                problems.push(format!("Unknown element key: {:?}", key));
+            }
+
+            if let Err(e) = ElementOptions::parse(base, self.elements[key].options.as_ref()) {
+                problems.push(format!("{}: invalid options: {}", key, e));
+            }
+
+            let size = &self.elements[key].size;
+            for (field, value) in [
+                ("maxWidth", &size.max_width),
+                ("maxHeight", &size.max_height),
+            ] {
+                if let Some(value) = value {
+                    if value.trim() != "none" && Dimension::parse(value).is_none() {
+                        problems.push(format!("{}: invalid {} {:?}", key, field, value));
                     }
                 }
             }
         }
+
+        let style = &self.message_style;
+        for (field, value) in [
+            ("avatarSize", &style.avatar_size),
+            ("maxHeight", &style.max_height),
+            ("fontSize", &style.font_size),
+        ] {
+            if Dimension::parse(value).is_none() {
+                problems.push(format!("messageStyle: invalid {} {:?}", field, value));
+            }
+        }
+
+        if !(1..=MAX_MESSAGES).contains(&style.max_messages) {
+            problems.push(format!(
+                "messageStyle: maxMessages must be between 1 and {}, not {}",
+                MAX_MESSAGES, style.max_messages
+            ));
+        }
+
+        validate_badge_images("messageStyle", &style.badge_images, &mut problems);
+
+        for (i, font) in self.fonts.iter().enumerate() {
+            if let Some(problem) = font.problem() {
+                problems.push(format!("fonts[{}]: {}", i, problem));
+            }
+        }
+
+        // Override colors are written into CSS text, so they must stay a single value
+        let mut platforms: Vec<&String> = style.platform_overrides.keys().collect();
+        platforms.sort();
+        for platform in platforms {
+            let o = &style.platform_overrides[platform];
+            validate_badge_images(
+                &format!("messageStyle.platformOverrides.{}", platform),
+                &o.badge_images,
+                &mut problems,
+            );
+            for (field, value) in [
+                ("backgroundColor", &o.background_color),
+                ("textColor", &o.text_color),
+                ("firstTimeColor", &o.first_time_color),
+            ] {
+                if let Some(value) = value {
+                    if value.contains([';', '{', '}', '"', '<']) {
+                        problems.push(format!(
+                            "messageStyle.platformOverrides.{}: invalid {} {:?}",
+                            platform, field, value
+                        ));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Fonts safe to write into a page; invalid ones can only come from hand-edited files
+    pub fn web_fonts(&self) -> Vec<WebFont> {
+        self.fonts
+            .iter()
+            .filter(|font| font.problem().is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// `@font-face` rules for `web_fonts`. `font-display: block` keeps OBS from painting
+    /// a fallback font while the file loads.
+    pub fn font_face_css(&self) -> String {
+        self.web_fonts()
+            .iter()
+            .map(|font| {
+                format!(
+                    "@font-face {{ font-family: \"{}\"; src: url(\"{}\"); font-display: block; }}",
+                    font.name.trim(),
+                    font.url
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Compile SCSS in all elements' custom_css fields, skipping elements whose source
+    /// hasn't changed since it was last compiled. Output is sanitized against `policy`.
+    /// Elements that fail to compile fall back to their raw (sanitized) source and are
+    /// listed in the report's `errors`. The layout's `scss_prelude` is prepended to each
+    /// element; a prelude that fails to compile or emits CSS of its own is reported as
+    /// `scssPrelude` and left out.
+    pub fn compile_scss(&mut self, policy: &CssPolicy) -> ScssReport {
+        let mut report = ScssReport::default();
+        let prelude = match self.scss_prelude.as_deref().map(str::trim) {
+            Some(prelude) if !prelude.is_empty() => match check_scss_prelude(prelude) {
+                Ok(()) => prelude,
+                Err(e) => {
+                    warn!("Ignoring SCSS prelude of {}: {}", self.name, e);
+                    report
+                        .errors
+                        .push(("scssPrelude".to_string(), e.to_string()));
+                    ""
+                }
+            },
+            _ => "",
+        };
+        for (id, config) in self.elements.iter_mut() {
+            let style = &mut config.style;
+            let scss = match &style.custom_css {
+                Some(scss) if !scss.trim().is_empty() => scss,
+                _ => {
+                    // Compiled CSS without a source can only have come from the client
+                    style.compiled_css = None;
+                    style.compiled_css_hash = None;
+                    continue;
+                }
+            };
+
+            // Editing the prelude has to invalidate every element compiled with it
+            let hash = if prelude.is_empty() {
+                scss_hash(scss)
+            } else {
+                scss_hash(&format!("{}\n{}", prelude, scss))
+            };
+            if let Some(css) = &style.compiled_css {
+                if style.compiled_css_hash.as_deref() == Some(hash.as_str()) {
+                    // Clients send compiled_css back with the layout, so even a cache hit
+                    // isn't trusted as-is
+                    style.compiled_css = Some(sanitize_css(css, policy));
+                    continue;
+                }
+            }
+
+            match compile_scss_to_css(prelude, scss) {
+                Ok(css) => {
+                    style.compiled_css = Some(sanitize_css(&css, policy));
+                    style.compiled_css_hash = Some(hash);
+                }
+                Err(e) => {
+                    warn!("Failed to compile SCSS for {}: {}", id, e);
+                    // Fall back to using the source as-is
+                    style.compiled_css = Some(sanitize_css(scss, policy));
+                    // Leave uncached so the error is reported again on the next save
+                    style.compiled_css_hash = None;
+                    report.errors.push((id.clone(), e.to_string()));
+                }
+            }
+            report.recompiled.push(id.clone());
+        }
+        report.recompiled.sort();
+        report.errors.sort();
+        report
+    }
+
+    /// Sanitize already-compiled CSS, for layouts broadcast without being saved
+    pub fn sanitize_compiled_css(&mut self, policy: &CssPolicy) {
+        for config in self.elements.values_mut() {
+            if let Some(css) = &config.style.compiled_css {
+                config.style.compiled_css = Some(sanitize_css(css, policy));
+            }
+        }
     }
 }
 
-/// Compile SCSS source to CSS
-fn compile_scss_to_css(scss: &str) -> Result<String> {
+/// Outcome of `Layout::compile_scss`
+#[derive(Debug, Default)]
+pub struct ScssReport {
+    /// Elements whose SCSS was compiled rather than served from cache
+    pub recompiled: Vec<String>,
+    /// (element id, error) for each element whose SCSS failed to compile
+    pub errors: Vec<(String, String)>,
+}
+
+/// FNV-1a hash of SCSS source, hex encoded. Stable across builds, unlike `DefaultHasher`,
+/// since it is persisted in layout files.
+fn scss_hash(scss: &str) -> String {
+    let hash = scss.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Element types the overlay knows how to render
+const BUILTIN_ELEMENT_TYPES: &[&str] = &[
+    "chat",
+    "live",
+    "text",
+    "attribution",
+    "featured",
+    "poll",
+    "superchat",
+    "matter",
+    "goal",
+];
+
+/// Strip a `-N` instance suffix ("text-2" -> "text"), matching the overlay's lookup
+pub fn element_base_type(key: &str) -> &str {
+    match key.rsplit_once('-') {
+        Some((base, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => base,
+        _ => key,
+    }
+}
+
+/// Badge types messages can carry, as used in `badge_images` keys and `msg-badge--*` classes
+pub const BADGE_TYPES: &[&str] = &["owner", "staff", "mod", "verified", "sub"];
+
+fn validate_badge_images(path: &str, images: &HashMap<String, String>, problems: &mut Vec<String>) {
+    let mut badges: Vec<&String> = images.keys().collect();
+    badges.sort();
+    for badge in badges {
+        let url = &images[badge];
+        if !BADGE_TYPES.contains(&badge.as_str()) {
+            problems.push(format!("{}.badgeImages: unknown badge {:?}", path, badge));
+        } else if !is_valid_avatar(url) {
+            problems.push(format!(
+                "{}.badgeImages.{}: invalid image URL {:?}",
+                path, badge, url
+            ));
+        }
+    }
+}
+
+/// Returned by `LayoutManager::save` when `Layout::validate` finds problems
+#[derive(Debug)]
+pub struct InvalidLayout {
+    pub problems: Vec<String>,
+}
+
+impl std::fmt::Display for InvalidLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid layout: {}", self.problems.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidLayout {}
+
+/// Check that a layout's SCSS prelude compiles and only defines things (variables,
+/// mixins, functions), since anything it emitted would land outside every element
+fn check_scss_prelude(prelude: &str) -> Result<()> {
+    let options = grass::Options::default();
+    let compiled = grass::from_string(prelude.to_string(), &options)
+        .map_err(|e| anyhow::anyhow!("SCSS prelude compilation error: {}", e))?;
+    if !compiled.trim().is_empty() {
+        anyhow::bail!(
+            "SCSS prelude may only define variables, mixins and functions, but it outputs CSS"
+        );
+    }
+    Ok(())
+}
+
+/// Compile SCSS source to CSS, with `prelude` (checked by `check_scss_prelude`) in scope
+fn compile_scss_to_css(prelude: &str, scss: &str) -> Result<String> {
     // Wrap in a dummy selector so grass can parse it
-    let wrapped = format!(".element {{ {} }}", scss);
+    let wrapped = format!("{}\n.element {{ {} }}", prelude, scss);
 
     let options = grass::Options::default().style(grass::OutputStyle::Expanded);
     let compiled = grass::from_string(wrapped, &options)
@@ -567,9 +1431,103 @@ fn compile_scss_to_css(scss: &str) -> Result<String> {
     Ok(scss.to_string())
 }
 
+/// Current format version written by `LayoutManager::export_bundle`
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+/// Several layouts packaged for sharing as one JSON document
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutBundle<T> {
+    format_version: u32,
+    layouts: Vec<T>,
+}
+
+/// Per-layout outcome of `LayoutManager::import_bundle`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: Vec<String>,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFailure {
+    /// Layout name, or its index in the bundle if it has none
+    pub name: String,
+    pub error: String,
+}
+
+/// Layout names become file names, so keep them to a safe character set
+fn validate_layout_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ')
+    {
+        anyhow::bail!("Invalid layout name: {:?}", name);
+    }
+    Ok(())
+}
+
+/// Write `contents` to `path` so readers see either the old file or the new one, never
+/// a partial write. The data goes to a temporary sibling, is synced, then renamed over
+/// the destination (atomic on the same filesystem).
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .context("Path has no file name")?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let result = (|| -> Result<()> {
+        let mut file = fs::File::create(&tmp_path)
+            .context(format!("Failed to create temp file: {}", tmp_path.display()))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Prior versions kept per layout unless configured otherwise
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// A previously saved version of a layout, kept for undo
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutVersion {
+    pub version: u32,
+    /// Unix time (seconds) this version was replaced by a newer save
+    pub saved_at: u64,
+}
+
+/// Read and parse a layout file, migrating it to `LAYOUT_VERSION` in memory.
+/// Returns whether a migration was applied.
+fn read_layout(path: &Path) -> Result<(Layout, bool)> {
+    let content = fs::read_to_string(path)
+        .context(format!("Failed to read layout file: {}", path.display()))?;
+    let mut value: serde_json::Value = serde_json::from_str(&content)
+        .context(format!("Failed to parse layout file: {}", path.display()))?;
+    let migrated = migrate_layout_value(&mut value)
+        .context(format!("Failed to migrate: {}", path.display()))?;
+    let layout: Layout = serde_json::from_value(value)
+        .context(format!("Failed to parse layout file: {}", path.display()))?;
+    Ok((layout, migrated))
+}
+
 /// Manages layout storage and retrieval
 pub struct LayoutManager {
     layouts_dir: String,
+    /// Element types accepted by `save` in addition to the built-in ones
+    custom_element_types: HashSet<String>,
+    /// Applied to compiled custom CSS on save
+    css_policy: CssPolicy,
+    /// Prior versions kept per layout in `.history/<name>/` (0 disables history)
+    history_limit: usize,
 }
 
 impl LayoutManager {
@@ -584,6 +1542,9 @@ impl LayoutManager {
 
         let manager = Self {
             layouts_dir: layouts_dir.to_string(),
+            custom_element_types: HashSet::new(),
+            css_policy: CssPolicy::default(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
         };
 
         // Create default layout if no layouts exist
@@ -596,6 +1557,34 @@ impl LayoutManager {
         Ok(manager)
     }
 
+    /// Accept elements of type `element_type` (and its `-N` instances) when saving
+    pub fn register_element_type(&mut self, element_type: &str) {
+        self.custom_element_types.insert(element_type.to_string());
+    }
+
+    pub fn set_css_policy(&mut self, policy: CssPolicy) {
+        self.css_policy = policy;
+    }
+
+    pub fn css_policy(&self) -> &CssPolicy {
+        &self.css_policy
+    }
+
+    /// Compile one SCSS snippet the way `save` compiles an element's `custom_css`, without
+    /// touching any layout, so the editor can preview output and errors
+    pub fn compile_snippet(&self, prelude: Option<&str>, source: &str) -> Result<String> {
+        let prelude = prelude.map(str::trim).unwrap_or_default();
+        if !prelude.is_empty() {
+            check_scss_prelude(prelude)?;
+        }
+        let css = compile_scss_to_css(prelude, source)?;
+        Ok(sanitize_css(&css, &self.css_policy))
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+    }
+
     /// List all available layout names
     pub fn list(&self) -> Result<Vec<String>> {
         let mut layouts = Vec::new();
@@ -615,34 +1604,61 @@ impl LayoutManager {
         Ok(layouts)
     }
 
-    /// Load a layout by name
+    /// Load a layout by name, migrating (and re-saving) files from older schema versions
     pub fn load(&self, name: &str) -> Result<Layout> {
         let path = format!("{}/{}.json", self.layouts_dir, name);
-        let content =
-            fs::read_to_string(&path).context(format!("Failed to read layout file: {}", path))?;
-        let layout: Layout = serde_json::from_str(&content)
-            .context(format!("Failed to parse layout file: {}", path))?;
+        let (layout, migrated) = read_layout(Path::new(&path))?;
+
+        if migrated {
+            info!(
+                "Migrated layout {} to schema version {}",
+                name, LAYOUT_VERSION
+            );
+            // Don't validate here: a migration shouldn't reject what is already on disk
+            self.write(&layout)?;
+        }
         Ok(layout)
     }
 
-    /// Save a layout (compiles SCSS before saving)
-    pub fn save(&self, layout: &Layout) -> Result<()> {
+    /// Validate and save a layout (compiles SCSS before saving).
+    /// Fails with `InvalidLayout` if validation finds problems. SCSS errors don't prevent
+    /// saving; they are returned as (element id, error) so the caller can report them.
+    pub fn save(&self, layout: &Layout) -> Result<Vec<(String, String)>> {
+        let problems = layout.validate(&self.custom_element_types);
+        if !problems.is_empty() {
+            return Err(InvalidLayout { problems }.into());
+        }
+        // History is best-effort; never lose a save over it
+        if let Err(e) = self.push_history(&layout.name) {
+            warn!(
+                "Failed to record history for layout {}: {:#}",
+                layout.name, e
+            );
+        }
+        self.write(layout)
+    }
+
+    fn write(&self, layout: &Layout) -> Result<Vec<(String, String)>> {
         // Clone and compile SCSS
         let mut layout = layout.clone();
-        layout.compile_scss();
+        let scss = layout.compile_scss(&self.css_policy);
+        // Anything that deserialized into `Layout` is in the current schema
+        layout.version = LAYOUT_VERSION;
 
         let path = format!("{}/{}.json", self.layouts_dir, layout.name);
         let content =
             serde_json::to_string_pretty(&layout).context("Failed to serialize layout")?;
-        fs::write(&path, content).context(format!("Failed to write layout file: {}", path))?;
+        write_atomic(Path::new(&path), content.as_bytes())
+            .context(format!("Failed to write layout file: {}", path))?;
         info!("Saved layout: {}", layout.name);
-        Ok(())
+        Ok(scss.errors)
     }
 
-    /// Delete a layout
+    /// Delete a layout and its history
     pub fn delete(&self, name: &str) -> Result<()> {
         let path = format!("{}/{}.json", self.layouts_dir, name);
         fs::remove_file(&path).context(format!("Failed to delete layout file: {}", path))?;
+        let _ = fs::remove_dir_all(self.history_dir(name));
         info!("Deleted layout: {}", name);
         Ok(())
     }
@@ -652,6 +1668,189 @@ impl LayoutManager {
         let path = format!("{}/{}.json", self.layouts_dir, name);
         Path::new(&path).exists()
     }
+
+    /// Copy layout `src` to a new layout named `dst`, including its compiled CSS
+    pub fn duplicate(&self, src: &str, dst: &str) -> Result<()> {
+        validate_layout_name(dst)?;
+        if self.exists(dst) {
+            anyhow::bail!("Layout already exists: {}", dst);
+        }
+
+        let mut layout = self.load(src)?;
+        layout.name = dst.to_string();
+        self.save(&layout)?;
+        info!("Duplicated layout {} as {}", src, dst);
+        Ok(())
+    }
+
+    /// Rename layout `old` to `new`, failing if `new` already exists
+    pub fn rename(&self, old: &str, new: &str) -> Result<()> {
+        validate_layout_name(new)?;
+        if self.exists(new) {
+            anyhow::bail!("Layout already exists: {}", new);
+        }
+
+        let mut layout = self.load(old)?;
+        layout.name = new.to_string();
+        self.save(&layout)?;
+        // Carry the history over so undo still works after a rename
+        if self.history_dir(old).exists() {
+            fs::rename(self.history_dir(old), self.history_dir(new))
+                .context(format!("Failed to move history of layout: {}", old))?;
+        }
+        self.delete(old)?;
+        info!("Renamed layout {} to {}", old, new);
+        Ok(())
+    }
+
+    fn history_dir(&self, name: &str) -> PathBuf {
+        Path::new(&self.layouts_dir).join(".history").join(name)
+    }
+
+    /// Version numbers in a layout's history, oldest first
+    fn history_versions(&self, name: &str) -> Vec<u32> {
+        let mut versions: Vec<u32> = fs::read_dir(self.history_dir(name))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| {
+                        entry
+                            .file_name()
+                            .to_str()?
+                            .strip_suffix(".json")?
+                            .parse()
+                            .ok()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        versions.sort_unstable();
+        versions
+    }
+
+    /// Copy the currently saved version of `name` into its history, pruning the oldest
+    /// versions beyond `history_limit`
+    fn push_history(&self, name: &str) -> Result<()> {
+        if self.history_limit == 0 || !self.exists(name) {
+            return Ok(());
+        }
+
+        let dir = self.history_dir(name);
+        fs::create_dir_all(&dir).context(format!("Failed to create {}", dir.display()))?;
+        let current = fs::read(format!("{}/{}.json", self.layouts_dir, name))?;
+        let versions = self.history_versions(name);
+        let next = versions.last().map_or(1, |v| v + 1);
+        write_atomic(&dir.join(format!("{}.json", next)), &current)?;
+
+        let excess = (versions.len() + 1).saturating_sub(self.history_limit);
+        for version in &versions[..excess] {
+            fs::remove_file(dir.join(format!("{}.json", version)))?;
+        }
+        Ok(())
+    }
+
+    /// Prior versions of a layout, newest first
+    pub fn list_history(&self, name: &str) -> Result<Vec<LayoutVersion>> {
+        let dir = self.history_dir(name);
+        let mut history = Vec::new();
+        for version in self.history_versions(name).into_iter().rev() {
+            let modified = fs::metadata(dir.join(format!("{}.json", version)))?.modified()?;
+            history.push(LayoutVersion {
+                version,
+                saved_at: modified
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(history)
+    }
+
+    /// Save a version from the layout's history as the current layout. The version being
+    /// replaced goes into history too, so a restore can itself be undone.
+    pub fn restore_version(&self, name: &str, version: u32) -> Result<Layout> {
+        let path = self.history_dir(name).join(format!("{}.json", version));
+        if !path.exists() {
+            anyhow::bail!("Layout {} has no version {}", name, version);
+        }
+        let (mut layout, _) = read_layout(&path)?;
+        layout.name = name.to_string();
+        self.save(&layout)?;
+        info!("Restored layout {} to version {}", name, version);
+        self.load(name)
+    }
+
+    /// Export layouts as a single JSON bundle. An empty list exports every layout.
+    pub fn export_bundle(&self, names: &[String]) -> Result<String> {
+        let names = if names.is_empty() {
+            self.list()?
+        } else {
+            names.to_vec()
+        };
+
+        let layouts = names
+            .iter()
+            .map(|name| self.load(name))
+            .collect::<Result<Vec<_>>>()?;
+
+        serde_json::to_string_pretty(&LayoutBundle {
+            format_version: BUNDLE_FORMAT_VERSION,
+            layouts,
+        })
+        .context("Failed to serialize layout bundle")
+    }
+
+    /// Import a bundle produced by `export_bundle`, compiling SCSS as each layout is saved.
+    /// Each layout is validated and saved independently; failures are reported, not fatal.
+    pub fn import_bundle(&self, json: &str, overwrite: bool) -> Result<ImportReport> {
+        let bundle: LayoutBundle<serde_json::Value> =
+            serde_json::from_str(json).context("Failed to parse layout bundle")?;
+        if bundle.format_version > BUNDLE_FORMAT_VERSION {
+            anyhow::bail!(
+                "Layout bundle format {} is newer than supported ({})",
+                bundle.format_version,
+                BUNDLE_FORMAT_VERSION
+            );
+        }
+
+        let mut report = ImportReport::default();
+        for (index, value) in bundle.layouts.into_iter().enumerate() {
+            let name = value
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("#{}", index));
+
+            match self.import_one(value, overwrite) {
+                Ok(()) => report.imported.push(name),
+                Err(e) => {
+                    warn!("Failed to import layout {}: {:#}", name, e);
+                    report.failed.push(ImportFailure {
+                        name,
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+
+        info!(
+            "Imported {} layouts ({} failed)",
+            report.imported.len(),
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    fn import_one(&self, mut value: serde_json::Value, overwrite: bool) -> Result<()> {
+        migrate_layout_value(&mut value)?;
+        let layout: Layout = serde_json::from_value(value).context("Invalid layout")?;
+        validate_layout_name(&layout.name)?;
+        if !overwrite && self.exists(&layout.name) {
+            anyhow::bail!("Layout already exists");
+        }
+        self.save(&layout)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -670,6 +1869,55 @@ mod tests {
             Some(Dimension::Calc("calc(100% - 20px)".to_string()))
         );
         assert_eq!(Dimension::parse("15.63vw"), Some(Dimension::Vw(15.63)));
+        assert_eq!(Dimension::parse("1.5em"), Some(Dimension::Em(1.5)));
+        assert_eq!(Dimension::parse("2rem"), Some(Dimension::Rem(2.0)));
+        assert_eq!(Dimension::parse("40ch"), Some(Dimension::Ch(40.0)));
+        assert_eq!(Dimension::parse("12pt"), None);
+        assert_eq!(Dimension::parse("1fr"), None);
+        assert_eq!(Dimension::parse("em"), None);
+    }
+
+    #[test]
+    fn test_calc_validation() {
+        // Valid expressions, including nesting
+        for valid in [
+            "calc(100% - 20px)",
+            "calc(100vh - (2 * 1.5em))",
+            "calc(min(50%, 200px) + 1rem)",
+            "calc(clamp(1rem, 2.5vw, 2rem) / 2)",
+            "calc(100% - var(--gap))",
+            "calc(calc(100% / 3) - -4px)",
+            "calc( 10px )",
+        ] {
+            assert_eq!(
+                Dimension::parse(valid),
+                Some(Dimension::Calc(valid.to_string())),
+                "{}",
+                valid
+            );
+        }
+
+        // Malformed expressions
+        for invalid in [
+            "calc()",
+            "calc(   )",
+            "calc(100% -)",
+            "calc(- 20px)",
+            "calc(100% - 20px",
+            "calc((100% - 20px)",
+            "calc(100% - 20px))",
+            "calc(100% 20px)",
+            "calc(100% - 20foo)",
+            "calc(100% - 20px); background: red",
+            "calc(url(x))",
+            "calc(var(gap))",
+        ] {
+            assert_eq!(Dimension::parse(invalid), None, "{}", invalid);
+        }
+
+        // Nesting beyond the depth limit is rejected rather than recursing forever
+        let deep = format!("calc({}1px{})", "(".repeat(100), ")".repeat(100));
+        assert_eq!(Dimension::parse(&deep), None);
     }
 
     #[test]
@@ -678,6 +1926,9 @@ mod tests {
         assert_eq!(Dimension::Vw(50.0).to_css(), "50vw");
         assert_eq!(Dimension::Vh(100.0).to_css(), "100vh");
         assert_eq!(Dimension::Percent(75.0).to_css(), "75%");
+        assert_eq!(Dimension::Em(1.5).to_css(), "1.5em");
+        assert_eq!(Dimension::Rem(2.0).to_css(), "2rem");
+        assert_eq!(Dimension::Ch(40.0).to_css(), "40ch");
         assert_eq!(
             Dimension::Calc("calc(100% - 20px)".to_string()).to_css(),
             "calc(100% - 20px)"
@@ -699,6 +1950,15 @@ mod tests {
 
         let pct = Dimension::Percent(75.0);
         assert_eq!(serde_json::to_string(&pct).unwrap(), "\"75%\"");
+
+        let em = Dimension::Em(1.5);
+        assert_eq!(serde_json::to_string(&em).unwrap(), "\"1.5em\"");
+
+        let rem = Dimension::Rem(2.0);
+        assert_eq!(serde_json::to_string(&rem).unwrap(), "\"2rem\"");
+
+        let ch = Dimension::Ch(40.0);
+        assert_eq!(serde_json::to_string(&ch).unwrap(), "\"40ch\"");
     }
 
     #[test]
@@ -722,6 +1982,18 @@ mod tests {
 
         let calc: Dimension = serde_json::from_str("\"calc(100% - 20px)\"").unwrap();
         assert_eq!(calc, Dimension::Calc("calc(100% - 20px)".to_string()));
+
+        let em: Dimension = serde_json::from_str("\"1.5em\"").unwrap();
+        assert_eq!(em, Dimension::Em(1.5));
+
+        let rem: Dimension = serde_json::from_str("\"2rem\"").unwrap();
+        assert_eq!(rem, Dimension::Rem(2.0));
+
+        let ch: Dimension = serde_json::from_str("\"40ch\"").unwrap();
+        assert_eq!(ch, Dimension::Ch(40.0));
+
+        // Unknown units are rejected
+        assert!(serde_json::from_str::<Dimension>("\"12pt\"").is_err());
     }
 
     #[test]
@@ -743,23 +2015,664 @@ mod tests {
         assert_eq!(parsed.bottom, Some(Dimension::Px(50.0)));
     }
 
+    fn test_manager() -> (LayoutManager, tempfile::TempDir) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        (manager, dir)
+    }
+
+    #[test]
+    fn test_load_migrates_v1_layout() {
+        let (manager, dir) = test_manager();
+        let v1 = serde_json::json!({
+            "name": "legacy",
+            "elements": {
+                "live": { "enabled": true, "autoSize": true },
+                "text": { "enabled": true, "autoSize": true, "anchor": "bottom-left" },
+                "chat": { "enabled": true }
+            }
+        });
+        fs::write(dir.path().join("legacy.json"), v1.to_string()).unwrap();
+
+        let layout = manager.load("legacy").unwrap();
+        assert_eq!(layout.version, LAYOUT_VERSION);
+        assert_eq!(layout.elements["live"].anchor, Some(AnchorPoint::TopLeft));
+        assert_eq!(layout.elements["text"].anchor, Some(AnchorPoint::BottomLeft));
+        assert_eq!(layout.elements["chat"].anchor, None);
+
+        // Re-saved in the current schema
+        let on_disk: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("legacy.json")).unwrap())
+                .unwrap();
+        assert_eq!(on_disk["version"], LAYOUT_VERSION);
+    }
+
+    #[test]
+    fn test_load_rejects_future_version() {
+        let (manager, dir) = test_manager();
+        let future = serde_json::json!({ "name": "future", "version": LAYOUT_VERSION + 1 });
+        fs::write(dir.path().join("future.json"), future.to_string()).unwrap();
+
+        let err = format!("{:#}", manager.load("future").unwrap_err());
+        assert!(err.contains("newer than this server supports"), "{}", err);
+    }
+
+    #[test]
+    fn test_save_leaves_no_temp_files() {
+        let (manager, dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        layout.name = "atomic".to_string();
+        manager.save(&layout).unwrap();
+        layout.message_style.condensed_mode = true;
+        manager.save(&layout).unwrap();
+
+        let files: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(files.iter().all(|f| !f.ends_with(".tmp")), "{:?}", files);
+        assert!(manager.load("atomic").unwrap().message_style.condensed_mode);
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_existing() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("file.json");
+        fs::write(&path, "old contents that are longer").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        // Failure (missing directory) cleans up and leaves nothing behind
+        let missing = dir.path().join("missing").join("file.json");
+        assert!(write_atomic(&missing, b"x").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_layout() {
+        let (manager, _dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        layout.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("color: red;".to_string());
+        manager.save(&layout).unwrap();
+
+        manager.duplicate("default", "copy").unwrap();
+        let copy = manager.load("copy").unwrap();
+        assert_eq!(copy.name, "copy");
+        assert!(copy.elements["chat"].style.compiled_css.is_some());
+
+        // Destination must not exist, source must
+        assert!(manager.duplicate("default", "copy").is_err());
+        assert!(manager.duplicate("missing", "other").is_err());
+    }
+
+    #[test]
+    fn test_rename_layout() {
+        let (manager, _dir) = test_manager();
+        manager.duplicate("default", "old").unwrap();
+
+        manager.rename("old", "new").unwrap();
+        assert!(!manager.exists("old"));
+        assert_eq!(manager.load("new").unwrap().name, "new");
+
+        assert!(manager.rename("new", "default").is_err());
+        assert!(manager.rename("missing", "other").is_err());
+        assert!(manager.exists("new"));
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let (source, _source_dir) = test_manager();
+        let mut alt = Layout::default_layout();
+        alt.name = "alt".to_string();
+        alt.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("$c: #00ff00; color: $c;".to_string());
+        source.save(&alt).unwrap();
+
+        let bundle = source.export_bundle(&[]).unwrap();
+
+        let (target, _target_dir) = test_manager();
+        let report = target.import_bundle(&bundle, true).unwrap();
+        assert_eq!(report.imported, vec!["alt", "default"]);
+        assert!(report.failed.is_empty());
+
+        let imported = target.load("alt").unwrap();
+        let compiled = imported.elements["chat"].style.compiled_css.as_ref().unwrap();
+        assert!(compiled.contains("color:"));
+    }
+
+    #[test]
+    fn test_bundle_import_reports_failures() {
+        let (manager, _dir) = test_manager();
+        let bundle = serde_json::json!({
+            "formatVersion": 1,
+            "layouts": [
+                { "name": "good" },
+                { "name": "default" },
+                { "name": "../escape" },
+                { "elements": "not a map" },
+            ]
+        })
+        .to_string();
+
+        let report = manager.import_bundle(&bundle, false).unwrap();
+        assert_eq!(report.imported, vec!["good"]);
+        let failed: Vec<_> = report.failed.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(failed, vec!["default", "../escape", "#3"]);
+        assert!(manager.exists("good"));
+    }
+
+    #[test]
+    fn test_bundle_rejects_newer_format() {
+        let (manager, _dir) = test_manager();
+        let bundle = r#"{"formatVersion": 99, "layouts": []}"#;
+        assert!(manager.import_bundle(bundle, true).is_err());
+    }
+
     #[test]
     fn test_scss_compilation() {
         // Test basic SCSS with variables
         let scss = "$color: #ff0000; background: $color;";
-        let result = super::compile_scss_to_css(scss).unwrap();
+        let result = super::compile_scss_to_css("", scss).unwrap();
         assert!(result.contains("background:"));
         assert!(result.contains("#ff0000") || result.contains("red"));
 
         // Test color functions
         let scss_color = "color: lighten(#000, 50%);";
-        let result = super::compile_scss_to_css(scss_color).unwrap();
+        let result = super::compile_scss_to_css("", scss_color).unwrap();
         assert!(result.contains("color:"));
 
         // Test plain CSS passthrough
         let plain_css = "margin: 10px; padding: 5px;";
-        let result = super::compile_scss_to_css(plain_css).unwrap();
+        let result = super::compile_scss_to_css("", plain_css).unwrap();
         assert!(result.contains("margin:"));
         assert!(result.contains("padding:"));
     }
+
+    #[test]
+    fn test_validate_element_keys() {
+        let mut layout = Layout::default_layout();
+        assert!(layout.validate(&HashSet::new()).is_empty());
+
+        let chat = layout.elements["chat"].clone();
+        layout.elements.insert("chat-2".to_string(), chat.clone());
+        assert!(layout.validate(&HashSet::new()).is_empty());
+
+        layout.elements.insert("chta".to_string(), chat.clone());
+        layout
+            .elements
+            .insert("chat-copy".to_string(), chat.clone());
+        layout.elements.insert("ticker-3".to_string(), chat);
+        assert_eq!(layout.validate(&HashSet::new()).len(), 3);
+
+        let custom = HashSet::from(["ticker".to_string()]);
+        assert_eq!(
+            layout.validate(&custom),
+            vec![
+                "Unknown element key: \"chat-copy\"".to_string(),
+                "Unknown element key: \"chta\"".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_dimensions() {
+        let mut layout = Layout::default_layout();
+        let chat = layout.elements.get_mut("chat").unwrap();
+        chat.size.max_width = Some("none".to_string());
+        chat.size.max_height = Some("lots".to_string());
+        layout.message_style.font_size = "big".to_string();
+
+        let problems = layout.validate(&HashSet::new());
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("maxHeight"));
+        assert!(problems[1].contains("fontSize"));
+
+        layout.message_style.font_size = "16px".to_string();
+        layout.message_style.max_messages = 0;
+        let problems = layout.validate(&HashSet::new());
+        assert_eq!(problems.len(), 2);
+        assert!(problems[1].contains("maxMessages"));
+    }
+
+    #[test]
+    fn test_save_rejects_invalid_layout() {
+        let (mut manager, _dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        layout.name = "typo".to_string();
+        let chat = layout.elements["chat"].clone();
+        layout.elements.insert("ticker".to_string(), chat);
+
+        let err = manager.save(&layout).unwrap_err();
+        let invalid = err.downcast_ref::<InvalidLayout>().unwrap();
+        assert_eq!(invalid.problems.len(), 1);
+        assert!(!manager.exists("typo"));
+
+        manager.register_element_type("ticker");
+        manager.save(&layout).unwrap();
+        assert!(manager.exists("typo"));
+
+        // Names become file names
+        layout.name = "../escape".to_string();
+        let err = manager.save(&layout).unwrap_err();
+        let invalid = err.downcast_ref::<InvalidLayout>().unwrap();
+        assert_eq!(invalid.problems, vec!["Invalid layout name: \"../escape\""]);
+    }
+
+    #[test]
+    fn test_validate_ignores_unknown_fields() {
+        let json = r#"{
+            "name": "future",
+            "version": 2,
+            "elements": {
+                "chat": { "enabled": true, "glow": "lots", "options": { "anything": 1 } }
+            },
+            "messageStyle": { "sparkles": true }
+        }"#;
+        let layout: Layout = serde_json::from_str(json).unwrap();
+        assert!(layout.validate(&HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_element_options() {
+        let mut layout = Layout::default_layout();
+        assert!(layout.validate(&HashSet::new()).is_empty());
+
+        let set_options = |layout: &mut Layout, key: &str, options: serde_json::Value| {
+            layout.elements.get_mut(key).unwrap().options = Some(options);
+        };
+        set_options(
+            &mut layout,
+            "text",
+            serde_json::json!({ "contnet": "Hello" }),
+        );
+        set_options(
+            &mut layout,
+            "featured",
+            serde_json::json!({ "scale": "big" }),
+        );
+        set_options(
+            &mut layout,
+            "chat",
+            serde_json::json!({ "direction": "sideways" }),
+        );
+        set_options(
+            &mut layout,
+            "live",
+            serde_json::json!({ "platforms": "YouTube" }),
+        );
+
+        let problems = layout.validate(&HashSet::new());
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].starts_with("chat: invalid options: unknown variant `sideways`"));
+        assert!(problems[1].starts_with("featured: invalid options: invalid type"));
+        assert!(problems[2].starts_with("live: invalid options: invalid type"));
+        assert_eq!(problems[3], "text: invalid options: missing \"content\"");
+
+        set_options(
+            &mut layout,
+            "text",
+            serde_json::json!({ "content": "Hi", "extra": 1 }),
+        );
+        set_options(&mut layout, "featured", serde_json::json!({ "scale": 1.5 }));
+        set_options(
+            &mut layout,
+            "chat",
+            serde_json::json!({ "direction": "top" }),
+        );
+        set_options(
+            &mut layout,
+            "live",
+            serde_json::json!({ "platformMode": "include", "platforms": ["YouTube"] }),
+        );
+        assert!(layout.validate(&HashSet::new()).is_empty());
+
+        set_options(&mut layout, "featured", serde_json::json!({ "scale": 0 }));
+        assert_eq!(layout.validate(&HashSet::new()).len(), 1);
+    }
+
+    #[test]
+    fn test_scss_recompiles_only_changed_elements() {
+        let (manager, _dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        layout.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("$c: red; color: $c;".to_string());
+        layout.elements.get_mut("live").unwrap().style.custom_css =
+            Some("margin: 1px;".to_string());
+        let policy = CssPolicy::default();
+        assert_eq!(
+            layout.compile_scss(&policy).recompiled,
+            vec!["chat", "live"]
+        );
+        assert!(layout.compile_scss(&policy).recompiled.is_empty());
+
+        // Hashes survive a save/load roundtrip
+        manager.save(&layout).unwrap();
+        let mut layout = manager.load("default").unwrap();
+        assert!(layout.compile_scss(&policy).recompiled.is_empty());
+
+        layout.elements.get_mut("live").unwrap().style.custom_css =
+            Some("margin: 2px;".to_string());
+        assert_eq!(layout.compile_scss(&policy).recompiled, vec!["live"]);
+        let live = &layout.elements["live"].style;
+        assert!(live.compiled_css.as_deref().unwrap().contains("2px"));
+    }
+
+    #[test]
+    fn test_save_reports_scss_errors() {
+        let (manager, _dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        layout.elements.get_mut("poll").unwrap().style.custom_css =
+            Some("color: $undefined;".to_string());
+        layout.elements.get_mut("chat").unwrap().style.custom_css = Some("color: red;".to_string());
+
+        let errors = manager.save(&layout).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "poll");
+
+        // Saved with the raw source as a fallback, and reported again next time
+        let mut loaded = manager.load("default").unwrap();
+        let poll = &loaded.elements["poll"].style;
+        assert_eq!(poll.compiled_css.as_deref(), Some("color: $undefined;"));
+        let recompiled = loaded.compile_scss(&CssPolicy::default()).recompiled;
+        assert_eq!(recompiled, vec!["poll"]);
+        assert_eq!(manager.save(&loaded).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_font_face_css() {
+        let mut layout = Layout::default_layout();
+        assert_eq!(layout.font_face_css(), "");
+
+        layout.fonts = vec![
+            WebFont {
+                name: "Comic Neue".to_string(),
+                url: "https://fonts.example.com/comic-neue.woff2".to_string(),
+            },
+            WebFont {
+                name: "Evil\"; } body { x".to_string(),
+                url: "https://fonts.example.com/evil.woff2".to_string(),
+            },
+            WebFont {
+                name: "Local".to_string(),
+                url: "file:///etc/fonts/local.ttf".to_string(),
+            },
+        ];
+        assert_eq!(
+            layout.font_face_css(),
+            r#"@font-face { font-family: "Comic Neue"; src: url("https://fonts.example.com/comic-neue.woff2"); font-display: block; }"#
+        );
+
+        let problems = layout.validate(&HashSet::new());
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("fonts[1]: invalid name"));
+        assert!(problems[1].starts_with("fonts[2]: invalid URL"));
+    }
+
+    #[test]
+    fn test_scss_prelude() {
+        let policy = CssPolicy::default();
+        let mut layout = Layout::default_layout();
+        layout.scss_prelude =
+            Some("$accent: #ff8800;\n@mixin pill { border-radius: 9999px; }".to_string());
+        layout.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("color: $accent; @include pill;".to_string());
+        let report = layout.compile_scss(&policy);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        let chat = layout.elements["chat"].style.compiled_css.clone().unwrap();
+        assert!(chat.contains("#ff8800"), "{}", chat);
+        assert!(chat.contains("border-radius: 9999px"), "{}", chat);
+
+        // Changing the prelude recompiles elements that were cached
+        layout.scss_prelude = Some("$accent: #0088ff;".to_string());
+        layout.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("color: $accent;".to_string());
+        assert_eq!(layout.compile_scss(&policy).recompiled, vec!["chat"]);
+        layout.scss_prelude = Some("$accent: #00ff88;".to_string());
+        assert_eq!(layout.compile_scss(&policy).recompiled, vec!["chat"]);
+        let chat = layout.elements["chat"].style.compiled_css.clone().unwrap();
+        assert!(chat.contains("#00ff88"), "{}", chat);
+
+        // A prelude that emits CSS or doesn't compile is reported and left out
+        for prelude in [".stray { color: red; }", "$accent: ;;; {"] {
+            layout.scss_prelude = Some(prelude.to_string());
+            let report = layout.compile_scss(&policy);
+            let ids: Vec<&str> = report.errors.iter().map(|(id, _)| id.as_str()).collect();
+            // $accent is undefined without the prelude
+            assert_eq!(ids, ["chat", "scssPrelude"], "{:?}", report.errors);
+        }
+    }
+
+    #[test]
+    fn test_save_sanitizes_custom_css() {
+        let (manager, _dir) = test_manager();
+        let mut layout = Layout::default_layout();
+        let chat = &mut layout.elements.get_mut("chat").unwrap().style;
+        chat.custom_css = Some("@import url(https://evil.example/x.css); color: red;".to_string());
+        // A client can't smuggle CSS past the sanitizer by forging the cache hash
+        let live = &mut layout.elements.get_mut("live").unwrap().style;
+        live.custom_css = Some("color: blue;".to_string());
+        live.compiled_css = Some("@import url(https://evil.example/y.css);".to_string());
+        live.compiled_css_hash = Some(scss_hash("color: blue;"));
+        // Compiled CSS with no source is dropped
+        layout.elements.get_mut("poll").unwrap().style.compiled_css =
+            Some("color: green;".to_string());
+
+        manager.save(&layout).unwrap();
+        let saved = manager.load("default").unwrap();
+        let chat = saved.elements["chat"]
+            .style
+            .compiled_css
+            .as_deref()
+            .unwrap();
+        assert!(!chat.contains("@import"));
+        assert!(chat.contains("color: red"));
+        assert_eq!(
+            saved.elements["live"].style.compiled_css.as_deref(),
+            Some("")
+        );
+        assert_eq!(saved.elements["poll"].style.compiled_css, None);
+    }
+
+    #[test]
+    fn test_history_records_and_prunes() {
+        let (mut manager, _dir) = test_manager();
+        manager.set_history_limit(3);
+        // The initial default layout was a create, not an overwrite
+        assert!(manager.list_history("default").unwrap().is_empty());
+
+        let mut layout = manager.load("default").unwrap();
+        for size in ["1px", "2px", "3px", "4px", "5px"] {
+            layout.message_style.font_size = size.to_string();
+            manager.save(&layout).unwrap();
+        }
+
+        let versions: Vec<u32> = manager
+            .list_history("default")
+            .unwrap()
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![5, 4, 3]);
+
+        // Version 5 holds what was current before the fifth save
+        let restored = manager.restore_version("default", 5).unwrap();
+        assert_eq!(restored.message_style.font_size, "4px");
+        assert_eq!(
+            manager.load("default").unwrap().message_style.font_size,
+            "4px"
+        );
+        // ...and the restore is itself undoable
+        assert_eq!(manager.list_history("default").unwrap()[0].version, 6);
+        assert!(manager.restore_version("default", 1).is_err());
+    }
+
+    #[test]
+    fn test_history_follows_rename_and_delete() {
+        let (manager, _dir) = test_manager();
+        let layout = manager.load("default").unwrap();
+        manager.save(&layout).unwrap();
+        manager.duplicate("default", "spare").unwrap();
+
+        manager.rename("default", "main").unwrap();
+        assert_eq!(manager.list_history("main").unwrap().len(), 1);
+        assert!(manager.list_history("default").unwrap().is_empty());
+        assert_eq!(manager.list().unwrap(), vec!["main", "spare"]);
+
+        manager.delete("main").unwrap();
+        assert!(manager.list_history("main").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_platform_overrides_roundtrip() {
+        let json = r##"{
+            "avatarSize": "2em",
+            "maxHeight": "10em",
+            "borderRadius": "0",
+            "fontSize": "16px",
+            "showModBadge": false,
+            "platformOverrides": {
+                "youtube": { "backgroundColor": "#c00", "showSubBadge": false },
+                "rumble": { "textColor": "#85c742" }
+            }
+        }"##;
+        let style: MessageStyle = serde_json::from_str(json).unwrap();
+        assert_eq!(style.platform_overrides.len(), 2);
+
+        let value = serde_json::to_value(&style).unwrap();
+        assert_eq!(
+            value["platformOverrides"]["youtube"],
+            serde_json::json!({ "backgroundColor": "#c00", "showSubBadge": false })
+        );
+        let reparsed: MessageStyle = serde_json::from_value(value).unwrap();
+        assert_eq!(reparsed.platform_overrides, style.platform_overrides);
+
+        // No overrides: the key is omitted entirely
+        let value = serde_json::to_value(MessageStyle::default()).unwrap();
+        assert!(value.get("platformOverrides").is_none());
+    }
+
+    #[test]
+    fn test_platform_style_falls_back_to_base() {
+        let mut style = MessageStyle {
+            background_color: Some("black".to_string()),
+            show_mod_badge: false,
+            ..MessageStyle::default()
+        };
+        style.platform_overrides.insert(
+            "YouTube".to_string(),
+            PlatformStyle {
+                background_color: Some("#c00".to_string()),
+                show_sub_badge: Some(false),
+                ..PlatformStyle::default()
+            },
+        );
+
+        let youtube = style.for_platform("youtube");
+        assert_eq!(youtube.background_color.as_deref(), Some("#c00"));
+        assert_eq!(youtube.show_sub_badge, Some(false));
+        assert_eq!(youtube.show_mod_badge, Some(false));
+        assert_eq!(youtube.show_owner_badge, Some(true));
+
+        let kick = style.for_platform("kick");
+        assert_eq!(kick.background_color.as_deref(), Some("black"));
+        assert_eq!(kick.show_sub_badge, Some(true));
+
+        let mut layout = Layout::default_layout();
+        layout.message_style = style;
+        assert!(layout.validate(&HashSet::new()).is_empty());
+        layout
+            .message_style
+            .platform_overrides
+            .get_mut("YouTube")
+            .unwrap()
+            .text_color = Some("red; background: url(x)".to_string());
+        assert_eq!(layout.validate(&HashSet::new()).len(), 1);
+
+        let badge_images = &mut layout.message_style.badge_images;
+        badge_images.insert("owner".to_string(), "/static/img/crown.png".to_string());
+        badge_images.insert("vip".to_string(), "/static/img/vip.png".to_string());
+        badge_images.insert("sub".to_string(), "javascript:alert(1)".to_string());
+        let problems = layout.validate(&HashSet::new());
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.contains("unknown badge \"vip\"")));
+    }
+
+    #[test]
+    fn test_computed_style_anchors() {
+        let element = |anchor: AnchorPoint| ElementConfig {
+            auto_size: true,
+            anchor: Some(anchor),
+            position: Position {
+                x: Some(Dimension::Vw(5.0)),
+                y: Some(Dimension::Vh(10.0)),
+                right: Some(Dimension::Px(20.0)),
+                bottom: Some(Dimension::Px(30.0)),
+                z_index: None,
+            },
+            size: Size {
+                width: Some(Dimension::Px(300.0)),
+                height: Some(Dimension::Px(200.0)),
+                max_width: None,
+                max_height: None,
+            },
+            ..ElementConfig::default()
+        };
+
+        let cases = [
+            (AnchorPoint::TopLeft, "left: 5vw; top: 10vh;"),
+            (AnchorPoint::Top, "left: 5vw; top: 10vh;"),
+            (AnchorPoint::TopRight, "right: 20px; top: 10vh;"),
+            (AnchorPoint::Left, "left: 5vw; top: 10vh;"),
+            (AnchorPoint::Center, "left: 5vw; top: 10vh;"),
+            (AnchorPoint::Right, "right: 20px; top: 10vh;"),
+            (AnchorPoint::BottomLeft, "left: 5vw; bottom: 30px;"),
+            (AnchorPoint::Bottom, "left: 5vw; bottom: 30px;"),
+            (AnchorPoint::BottomRight, "right: 20px; bottom: 30px;"),
+        ];
+        for (anchor, expected) in cases {
+            // Auto-sized: no width/height
+            assert_eq!(
+                element(anchor.clone()).computed_style(),
+                format!("position: absolute; {}", expected),
+                "{:?}",
+                anchor
+            );
+        }
+    }
+
+    #[test]
+    fn test_computed_style_fixed_size() {
+        let mut element = ElementConfig {
+            anchor: Some(AnchorPoint::BottomRight),
+            position: Position {
+                x: Some(Dimension::Px(10.0)),
+                bottom: Some(Dimension::Percent(5.0)),
+                z_index: Some(3),
+                ..Position::default()
+            },
+            size: Size {
+                width: Some(Dimension::Calc("calc(100% - 20px)".to_string())),
+                height: Some(Dimension::Vh(50.0)),
+                max_width: None,
+                max_height: Some("none".to_string()),
+            },
+            ..ElementConfig::default()
+        };
+        // The anchor only applies to auto-sized elements
+        assert_eq!(
+            element.computed_style(),
+            "position: absolute; left: 10px; bottom: 5%; width: calc(100% - 20px); \
+             height: 50vh; max-height: none; z-index: 3;"
+        );
+
+        // Anchored offsets that were never set count as 0
+        element.auto_size = true;
+        element.position = Position::default();
+        assert_eq!(
+            element.computed_style(),
+            "position: absolute; right: 0px; bottom: 0px; max-height: none;"
+        );
+    }
 }
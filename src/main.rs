@@ -1,73 +1,205 @@
+mod alert_sounds;
+mod cli;
+mod console;
+mod css;
 mod database;
 mod exchange;
+mod goal;
+mod integrations;
 mod layout;
 mod message;
+mod platforms;
+mod poll;
 mod sneed_env; // naming it "env" can be confusing.
+mod timezone;
 mod web;
 
+use crate::css::CssPolicy;
 use crate::layout::LayoutManager;
 use crate::web::ChatServer;
 
-use actix::Actor;
-use actix_web::{App, HttpServer};
+use actix::{Actor, AsyncContext};
+use actix_web::{middleware::Compress, rt, web::scope, App, HttpServer};
 use anyhow::Result;
 use std::sync::{Arc, Mutex};
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[actix_web::main]
 async fn main() -> Result<(), std::io::Error> {
     sneed_env::get_env();
+    let args = cli::Args::parse(std::env::args().skip(1)).expect("Invalid command line");
 
     // Initialize tracing subscriber with RUST_LOG env filter
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
+        .with_ansi(console::console_supports_color())
+        .fmt_fields(console::ConsoleFields)
         .init();
 
     info!("Starting Stream Nexus server");
 
     // Initialize layout manager
-    let layout_manager = Arc::new(Mutex::new(
-        LayoutManager::new("layouts").expect("Failed to initialize layout manager"),
+    let mut layout_manager =
+        LayoutManager::new("layouts").expect("Failed to initialize layout manager");
+    // Element types provided by local overlay customisations, comma separated
+    for element_type in dotenvy::var("CUSTOM_ELEMENT_TYPES")
+        .unwrap_or_default()
+        .split(',')
+    {
+        let element_type = element_type.trim();
+        if !element_type.is_empty() {
+            layout_manager.register_element_type(element_type);
+        }
+    }
+    layout_manager.set_css_policy(CssPolicy::from_env());
+    layout_manager.set_history_limit(sneed_env::var_or(
+        "LAYOUT_HISTORY_LIMIT",
+        layout::DEFAULT_HISTORY_LIMIT,
     ));
+    let layout_manager = Arc::new(Mutex::new(layout_manager));
 
-    let chat = ChatServer::new(
+    if web::ingest_token().is_none() {
+        warn!("INGEST_TOKEN is not set; anyone who can reach /ingest.ws, /ingest/webhook or /api/layouts can send chat and edit layouts");
+    }
+
+    let heartbeat = web::Heartbeat::from_env();
+    let static_max_age = web::StaticMaxAge::from_env();
+    let max_message_size = web::MaxMessageSize::from_env();
+    let mut chat = ChatServer::new(
         exchange::fetch_exchange_rates()
             .await
             .expect("Failed to fetch exchange rates."),
         layout_manager,
-    )
-    .start();
+    );
+    chat.client_timeout = heartbeat.timeout;
+    let avatar_proxy = web::AvatarConfig::from_env().map(|config| {
+        chat.avatar_proxy = Some(config.clone());
+        actix_web::web::Data::new(
+            web::AvatarProxy::new(config).expect("Failed to create the avatar proxy"),
+        )
+    });
+    if let Some(path) = args.record {
+        chat.recorder = Some(platforms::record::start(path).expect("Failed to start recording"));
+    }
+    chat.paid_message_listeners
+        .extend(integrations::obs::start());
+    chat.paid_message_listeners
+        .extend(integrations::webhook::start());
+    // TTS answers back through the ChatServer, so it needs the address before startup
+    let chat = ChatServer::create(|ctx| {
+        chat.paid_message_listeners
+            .extend(integrations::tts::start(ctx.address()));
+        chat
+    });
+    platforms::twitch::start(chat.clone());
+    platforms::discord::start(chat.clone());
+    platforms::youtube::start(chat.clone());
+    platforms::rumble::start(chat.clone());
+    if let Some(replay) = args.replay {
+        platforms::replay::start(chat.clone(), replay).expect("Failed to start replay");
+    }
     let chat_for_server = chat.clone();
+    let allowed_origins = web::allowed_origins();
+    let tls = web::tls_config().expect("Invalid TLS configuration");
 
-    HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
+            // gzip, deflate, brotli or zstd per Accept-Encoding; images are left alone
+            .wrap(Compress::default())
             .app_data(chat_for_server.clone())
+            .app_data(heartbeat)
+            .app_data(static_max_age)
+            .app_data(max_message_size)
+            .configure(|cfg| {
+                if let Some(avatar_proxy) = &avatar_proxy {
+                    cfg.app_data(avatar_proxy.clone());
+                }
+            })
             // Views
             .service(web::home)
             .service(web::layout_view)
+            .service(web::overlay_view)
             .service(web::chat)
             .service(web::dashboard)
             .service(web::editor)
             .service(web::editor_static)
             // Static files
             .service(web::static_files)
+            .service(web::avatar_proxy)
             // WebSocket
             .service(web::websocket)
+            .service(web::event_stream)
+            .service(web::ingest_websocket)
+            .service(web::ingest_webhook)
+            .service(web::ingest_kofi)
+            .service(web::ingest_streamlabs)
+            // Health checks
+            .service(web::healthz)
+            .service(web::readyz)
+            .service(web::metrics_view)
             // Layout REST API
-            .service(web::list_layouts)
-            .service(web::get_layout)
-            .service(web::save_layout)
-            .service(web::delete_layout)
-            .service(web::activate_layout)
+            .service(
+                scope("/api")
+                    .wrap(web::api_cors(&allowed_origins))
+                    .service(web::list_layouts)
+                    .service(web::get_layout)
+                    .service(web::preview_layout)
+                    .service(web::save_layout)
+                    .service(web::delete_layout)
+                    .service(web::activate_layout)
+                    .service(web::compile_scss)
+                    .service(web::list_bans)
+                    .service(web::list_viewers)
+                    .service(web::list_messages)
+                    .service(web::get_message),
+            )
     })
     //.workers(1)
-    .bind(format!(
-        "{}:{}",
-        dotenvy::var("SERVER_IP").expect("SERVER_IP not defined."),
-        dotenvy::var("SERVER_PORT").expect("SERVER_PORT not defined.")
-    ))
-    .expect("Could not bind requested address.")
-    .run()
-    .await
+    // Shutdown is driven by shutdown_signal() below so clients get closed first
+    .disable_signals()
+    .shutdown_timeout(web::SHUTDOWN_TIMEOUT.as_secs());
+    for address in sneed_env::bind_addresses() {
+        server = match &tls {
+            Some(config) => server.bind_rustls_0_23(&address, config.clone()),
+            None => server.bind(&address),
+        }
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not bind {}: {}", address, e)))?;
+        info!(%address, tls = tls.is_some(), "Listening");
+    }
+    let server = server.run();
+
+    let handle = server.handle();
+    rt::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutdown requested, draining clients");
+        if let Err(e) = chat.send(web::Shutdown).await {
+            warn!("ChatServer did not acknowledge shutdown: {}", e);
+        }
+        // Give close frames time to reach the browsers before workers stop
+        rt::time::sleep(web::SHUTDOWN_DRAIN).await;
+        handle.stop(true).await;
+    });
+
+    server.await
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    let ctrl_c = Box::pin(async {
+        let _ = rt::signal::ctrl_c().await;
+    });
+
+    #[cfg(unix)]
+    {
+        use rt::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        let sigterm = Box::pin(async move {
+            sigterm.recv().await;
+        });
+        futures::future::select(ctrl_c, sigterm).await;
+    }
+
+    #[cfg(not(unix))]
+    ctrl_c.await;
 }
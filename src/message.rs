@@ -1,7 +1,11 @@
+use crate::layout::{MessageStyle, PlatformStyle};
+use crate::timezone;
+use crate::web::BroadcastError;
 use actix::prelude::Message as ActixMessage;
 use askama::Template;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::time::SystemTime;
+use tracing::warn;
 use uuid::Uuid;
 
 // Custom deserializer to handle both string and number channel values
@@ -21,10 +25,304 @@ where
     }
 }
 
+/// Escape text from bridges for display inside overlay HTML. Entities already in the
+/// text (`&amp;`, `&#039;`, `&#x1F600;`) are kept rather than escaped again, since some
+/// bridges send encoded text; that also makes escaping twice harmless.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for (i, c) in s.char_indices() {
+        match c {
+            '&' if is_entity(&s[i..]) => escaped.push('&'),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#039;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether `s` starts with a character reference: `&name;`, `&#123;` or `&#x7B;`
+fn is_entity(s: &str) -> bool {
+    let Some(end) = s.find(';') else {
+        return false;
+    };
+    let body = &s[1..end];
+    if let Some(number) = body.strip_prefix('#') {
+        let (digits, radix) = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16),
+            None => (number, 10),
+        };
+        !digits.is_empty() && digits.len() <= 8 && u32::from_str_radix(digits, radix).is_ok()
+    } else {
+        // The longest named entity, `&CounterClockwiseContourIntegral;`, is 31 letters
+        !body.is_empty() && body.len() <= 32 && body.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+/// Reverse `escape_html` (and the few other entities bridges send) for plain text output.
+fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        // Messages stored before escape_html closed `&quot;` lack the semicolon
+        .replace("&quot", "\"")
+        .replace("&#039;", "'")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A platform emoji or sticker: `find` in the text is replaced by the image at `replace`.
+/// Bridges send `[find, replace, name]`, or `[find, replace, name, width, height]` in
+/// pixels (either may be null) for stickers that shouldn't shrink to the size of text.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "EmojiFields", into = "EmojiFields")]
+pub struct Emoji {
+    pub find: String,
+    pub replace: String,
+    pub name: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// The array forms an `Emoji` is sent and stored as
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum EmojiFields {
+    Sized(String, String, String, Option<u32>, Option<u32>),
+    Plain(String, String, String),
+}
+
+impl From<EmojiFields> for Emoji {
+    fn from(fields: EmojiFields) -> Self {
+        let (find, replace, name, width, height) = match fields {
+            EmojiFields::Sized(find, replace, name, width, height) => {
+                (find, replace, name, width, height)
+            }
+            EmojiFields::Plain(find, replace, name) => (find, replace, name, None, None),
+        };
+        Self {
+            find,
+            replace,
+            name,
+            // A zero size is as good as none
+            width: width.filter(|width| *width > 0),
+            height: height.filter(|height| *height > 0),
+        }
+    }
+}
+
+impl From<Emoji> for EmojiFields {
+    fn from(emoji: Emoji) -> Self {
+        if emoji.width.is_none() && emoji.height.is_none() {
+            EmojiFields::Plain(emoji.find, emoji.replace, emoji.name)
+        } else {
+            EmojiFields::Sized(
+                emoji.find,
+                emoji.replace,
+                emoji.name,
+                emoji.width,
+                emoji.height,
+            )
+        }
+    }
+}
+
+impl Emoji {
+    pub fn new(
+        find: impl Into<String>,
+        replace: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        Self {
+            find: find.into(),
+            replace: replace.into(),
+            name: name.into(),
+            width: None,
+            height: None,
+        }
+    }
+
+    /// The `<img>` tag the emoji is expanded into on ingest. Sized emojis get their
+    /// dimensions inline and `emoji--sized`, which lifts the text-sized cap.
+    pub fn to_html(&self) -> String {
+        let mut class = "emoji";
+        let mut style = String::new();
+        if self.width.is_some() || self.height.is_some() {
+            class = "emoji emoji--sized";
+            let sizes = [("width", self.width), ("height", self.height)];
+            let rules: Vec<String> = sizes
+                .iter()
+                .filter_map(|(property, size)| Some(format!("{}: {}px", property, (*size)?)))
+                .collect();
+            style = format!(" style=\"{}\"", rules.join("; "));
+        }
+        format!(
+            "<img class=\"{}\" src=\"{}\" data-emoji=\"{}\" alt=\"{}\"{} />",
+            class,
+            escape_html(&self.replace),
+            escape_html(&self.name),
+            escape_html(&self.name),
+            style
+        )
+    }
+}
+
+/// Canonical lowercase slug for a platform name, so "YouTube", "youtube" and "YT" from
+/// different bridges share one CSS class, stats bucket and rate limit.
+/// Unknown platforms pass through slugified.
+pub fn canonical_platform(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    match slug {
+        "" => "none",
+        "yt" | "youtube-live" => "youtube",
+        "ttv" | "twitch-tv" => "twitch",
+        "kick-com" => "kick",
+        "lbry" => "odysee",
+        "twitter" | "x-com" => "x",
+        "vkontakte" | "vk-video" => "vk",
+        "xmr-chat" => "xmrchat",
+        "ko-fi" => "kofi",
+        "usps" => "mail",
+        other => other,
+    }
+    .to_string()
+}
+
 #[derive(Template)]
 #[template(path = "message.html")]
 struct MessageTemplate<'a> {
     message: &'a Message,
+    /// Resolved layout style for the message's platform, when rendering for one layout
+    style: Option<PlatformStyle>,
+    /// Part of a condensed run from one user that doesn't show the avatar and name
+    grouped: bool,
+}
+
+impl MessageTemplate<'_> {
+    fn show_badge(&self, badge: &str) -> bool {
+        let Some(style) = &self.style else {
+            return true;
+        };
+        match badge {
+            "owner" => style.show_owner_badge,
+            "staff" => style.show_staff_badge,
+            "mod" => style.show_mod_badge,
+            "verified" => style.show_verified_badge,
+            "sub" => style.show_sub_badge,
+            _ => None,
+        }
+        .unwrap_or(true)
+    }
+
+    /// The layout's image for a badge type, if it replaces the built-in icon
+    fn badge_image(&self, badge: &str) -> Option<&str> {
+        self.style
+            .as_ref()?
+            .badge_images
+            .get(badge)
+            .map(String::as_str)
+    }
+
+    /// CSS custom properties appended to the message's inline style
+    fn style_vars(&self) -> String {
+        let Some(style) = &self.style else {
+            return String::new();
+        };
+        let mut vars = String::new();
+        if let Some(color) = &style.background_color {
+            vars.push_str(&format!("; --message-bg: {}", color));
+        }
+        if let Some(color) = &style.text_color {
+            vars.push_str(&format!("; --message-color: {}", color));
+        }
+        if let Some(color) = style
+            .first_time_color
+            .as_ref()
+            .filter(|_| self.message.is_first_time)
+        {
+            vars.push_str(&format!("; --message-first-color: {}", color));
+        }
+        vars
+    }
+}
+
+/// Render messages, oldest first, as one layout's chat shows them. With `condensed_mode`,
+/// runs of messages from one user are grouped: the avatar and name stay on the message
+/// nearest where the chat grows from (the oldest of the run for `direction: "bottom"`, the
+/// newest for `"top"`) and the rest get `msg--grouped`. Overlays group the shared broadcast
+/// HTML the same way client-side.
+pub fn render_messages(messages: &[Message], style: &MessageStyle) -> String {
+    let mut grouped = vec![false; messages.len()];
+    if style.condensed_mode {
+        let top_first = style.direction == "top";
+        for i in 1..messages.len() {
+            if messages[i].continues(&messages[i - 1]) {
+                grouped[if top_first { i - 1 } else { i }] = true;
+            }
+        }
+    }
+    messages
+        .iter()
+        .zip(grouped)
+        .map(|(message, grouped)| message.render_with_style(style, grouped))
+        .collect()
+}
+
+/// 1x1 transparent GIF, shown when a message has no usable avatar
+pub const BLANK_AVATAR: &str =
+    "data:image/gif;base64,R0lGODlhAQABAAAAACH5BAEKAAEALAAAAAABAAEAAAICTAEAOw==";
+
+/// Whether an avatar can go in an `<img src>` under the /chat policy: http(s), an image
+/// data URI, or a path on this server (such as the `/avatar` proxy)
+pub fn is_valid_avatar(avatar: &str) -> bool {
+    let lower = avatar.to_ascii_lowercase();
+    let rest = ["https://", "http://", "data:image/"]
+        .iter()
+        .find_map(|prefix| lower.strip_prefix(prefix))
+        .or_else(|| {
+            lower
+                .strip_prefix('/')
+                .filter(|path| !path.starts_with('/'))
+        });
+    rest.is_some_and(|rest| !rest.is_empty())
+        && !avatar.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Media attached to a chat message (e.g. Kick or Discord image uploads).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub url: String,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+impl Attachment {
+    /// Whether the attachment can be shown as a thumbnail.
+    /// Only images over http(s) or inline data URIs are rendered, matching the
+    /// `img-src * 'self' data:` policy served with /chat.
+    pub fn is_renderable_image(&self) -> bool {
+        let url = self.url.trim_start().to_ascii_lowercase();
+        self.mime_type.starts_with("image/")
+            && (url.starts_with("https://")
+                || url.starts_with("http://")
+                || url.starts_with("data:image/"))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, ActixMessage, Clone)]
@@ -37,7 +335,9 @@ pub struct Message {
     pub is_placeholder: bool,
 
     pub message: String,
-    pub emojis: Vec<(String, String, String)>,
+    pub emojis: Vec<Emoji>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 
     pub username: String,
     pub avatar: String, // URL
@@ -52,6 +352,9 @@ pub struct Message {
     pub is_mod: bool,
     pub is_owner: bool,
     pub is_staff: bool,
+    /// The user's first message in the channel, for platforms that say so
+    #[serde(default)]
+    pub is_first_time: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, ActixMessage, Clone)]
@@ -69,6 +372,9 @@ pub struct LivestreamUpdate {
 #[rtype(result = "()")]
 pub struct CommandFeatureMessage {
     pub feature_message: Option<Uuid>,
+    /// Unfeature automatically after this many seconds; absent or 0 stays featured
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -76,6 +382,11 @@ struct JsonWrapper<'a> {
     #[serde(flatten)]
     message: &'a Message,
     html: String,
+    /// `sent_at` as a local time in `DISPLAY_TZ`
+    sent_at_display: String,
+    /// Clip for overlays to play, only on the live broadcast of a paid message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert_sound: Option<&'a str>,
 }
 
 impl Default for Message {
@@ -95,10 +406,10 @@ impl Default for Message {
 
             message: "DEFAULT_MESSAGE".to_string(),
             emojis: Vec::new(),
+            attachments: Vec::new(),
 
             username: "NO_USERNAME".to_string(),
-            avatar: "data:image/gif;base64,R0lGODlhAQABAAAAACH5BAEKAAEALAAAAAABAAEAAAICTAEAOw=="
-                .to_string(),
+            avatar: BLANK_AVATAR.to_string(),
 
             amount: 0.0,
             currency: "ZWL".to_string(),
@@ -108,11 +419,27 @@ impl Default for Message {
             is_mod: false,
             is_owner: false,
             is_staff: false,
+            is_first_time: false,
         }
     }
 }
 
 impl Message {
+    /// Clamp untrusted fields from bridges into sane values.
+    /// Non-finite or negative amounts are treated as unpaid, and the platform is canonicalized.
+    pub fn sanitize(&mut self) {
+        self.platform = canonical_platform(&self.platform);
+        if !self.amount.is_finite() || self.amount < 0.0 {
+            warn!(
+                id = %self.id,
+                platform = %self.platform,
+                amount = %self.amount,
+                "Rejecting invalid message amount"
+            );
+            self.amount = 0.0;
+        }
+    }
+
     pub fn get_badge_string(&self) -> String {
         let mut badges = Vec::new();
         if self.is_verified {
@@ -131,13 +458,19 @@ impl Message {
             badges.push("staff");
         }
 
-        if badges.len() == 0 {
+        if badges.is_empty() {
             return "".to_string();
         }
 
         format!("msg--b-{}", badges.join(" msg--b-"))
     }
 
+    /// Escape the username and text for display inside overlay HTML (see `escape_html`)
+    pub fn escape_for_display(&mut self) {
+        self.username = escape_html(&self.username);
+        self.message = escape_html(&self.message);
+    }
+
     pub fn is_premium(&self) -> bool {
         self.amount > 0.0
     }
@@ -148,7 +481,7 @@ impl Message {
 
     pub fn get_paid_readable_amount(&self) -> String {
         if self.is_premium() {
-            format!("{} {}", format!("{:.2}", self.amount), self.currency)
+            format!("{:.2} {}", self.amount, self.currency)
         } else {
             String::new()
         }
@@ -186,36 +519,611 @@ impl Message {
         }
     }
 
+    /// Whether the text is nothing but emojis from the message's emoji table and
+    /// whitespace, so overlays can show them larger (`msg--jumbo`). Works before and
+    /// after ingest expanded the emojis.
+    pub fn is_emoji_only(&self) -> bool {
+        let mut text = self.message.to_owned();
+        let mut found = false;
+        for emoji in &self.emojis {
+            // The expanded tag first, as `find` may appear inside it
+            for emoji in [emoji.to_html(), emoji.find.to_owned()] {
+                if !emoji.is_empty() && text.contains(&emoji) {
+                    found = true;
+                    text = text.replace(&emoji, " ");
+                }
+            }
+        }
+        found && text.trim().is_empty()
+    }
+
+    /// Who sent the message, as `data-author` for grouping runs in condensed chats
+    pub fn get_author_key(&self) -> String {
+        format!("{}:{}", canonical_platform(&self.platform), self.username)
+    }
+
+    /// Whether this message continues a run from `previous`: the same user on the same
+    /// platform, with neither paid. Paid messages always stand on their own.
+    pub fn continues(&self, previous: &Message) -> bool {
+        !self.is_premium()
+            && !previous.is_premium()
+            && self.get_author_key() == previous.get_author_key()
+    }
+
     pub fn get_platform_string(&self) -> String {
-        format!("msg--p-{}", self.platform)
+        format!("msg--p-{}", canonical_platform(&self.platform))
+    }
+
+    /// The avatar to render, blank rather than broken when it isn't usable
+    pub fn avatar_src(&self) -> &str {
+        if is_valid_avatar(&self.avatar) {
+            &self.avatar
+        } else {
+            BLANK_AVATAR
+        }
+    }
+
+    pub fn get_image_attachments(&self) -> Vec<&Attachment> {
+        self.attachments
+            .iter()
+            .filter(|a| a.is_renderable_image())
+            .collect()
     }
 
     pub fn get_sent_at_secs(&self) -> i64 {
         self.sent_at / 1000
     }
 
+    /// `sent_at` as a local time in `DISPLAY_TZ`
+    pub fn get_sent_at_display(&self) -> String {
+        timezone::format_display(self.sent_at)
+    }
+
+    /// Console line; the `Content` handler logs paid ones with `highlight`, which
+    /// `console::ConsoleFields` shows in color on a terminal
     pub fn to_console_msg(&self) -> String {
         if self.is_premium() {
             format!(
-                "[{}] [${} {}] ({}): {}",
-                self.platform, self.currency, self.amount, self.username, self.message
+                "[{}] [${} {}] [tier {}] ({}): {}",
+                self.platform,
+                self.currency,
+                self.amount,
+                self.get_paid_tier(),
+                self.username,
+                self.message
             )
         } else {
             format!("[{}] {}: {}", self.platform, self.username, self.message)
         }
     }
 
+    /// Human-readable text for TTS and other plain outputs.
+    /// Emojis become `:name:` and HTML entities are decoded. Works on messages
+    /// both before and after ingest expanded their emojis into `<img>` tags.
+    pub fn to_plaintext(&self) -> String {
+        let mut text = self.message.to_owned();
+        let mut replacements: Vec<String> = Vec::with_capacity(self.emojis.len());
+
+        // Swap emojis for tokens first so unescaping can't form new matches.
+        for emoji in &self.emojis {
+            let token = format!("\u{E000}{}\u{E000}", replacements.len());
+            text = text
+                .replace(&emoji.to_html(), &token)
+                .replace(&emoji.find, &token);
+            replacements.push(format!(":{}:", emoji.name));
+        }
+
+        let mut text = unescape_html(&text);
+        for (i, value) in replacements.iter().enumerate() {
+            text = text.replace(&format!("\u{E000}{}\u{E000}", i), value);
+        }
+        text
+    }
+
     pub fn to_html(&self) -> String {
-        MessageTemplate { message: &self }
-            .render()
-            .expect("Failed to render chat message.")
+        self.try_to_html().expect("Failed to render chat message.")
+    }
+
+    fn try_to_html(&self) -> askama::Result<String> {
+        MessageTemplate {
+            message: self,
+            style: None,
+            grouped: false,
+        }
+        .render()
+    }
+
+    /// Render with a layout's message style applied, including its override for this
+    /// message's platform. Broadcast HTML is shared by every layout, so overlays apply
+    /// the same overrides client-side instead.
+    pub fn to_html_with_style(&self, style: &MessageStyle) -> String {
+        self.render_with_style(style, false)
+    }
+
+    fn render_with_style(&self, style: &MessageStyle, grouped: bool) -> String {
+        MessageTemplate {
+            message: self,
+            style: Some(style.for_platform(&self.platform)),
+            grouped,
+        }
+        .render()
+        .expect("Failed to render chat message.")
     }
 
     pub fn to_json(&self) -> String {
-        serde_json::to_string(&JsonWrapper {
+        self.to_json_with_alert_sound(None)
+    }
+
+    pub fn to_json_with_alert_sound(&self, alert_sound: Option<&str>) -> String {
+        self.try_to_json_with_alert_sound(alert_sound)
+            .expect("Failed to serialize chat message wrapper.")
+    }
+
+    /// Like `to_json_with_alert_sound`, but returns render and serialization failures
+    pub fn try_to_json_with_alert_sound(
+        &self,
+        alert_sound: Option<&str>,
+    ) -> Result<String, BroadcastError> {
+        Ok(serde_json::to_string(&JsonWrapper {
             message: self,
-            html: self.to_html(),
-        })
-        .expect("Failed to serialize chat message wrapper.")
+            html: self.try_to_html()?,
+            sent_at_display: self.get_sent_at_display(),
+            alert_sound,
+        })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_with_amount(amount: f64) -> Message {
+        Message {
+            amount,
+            currency: "USD".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sanitize_rejects_nan() {
+        let mut msg = message_with_amount(f64::NAN);
+        msg.sanitize();
+        assert_eq!(msg.amount, 0.0);
+        assert!(!msg.is_premium());
+    }
+
+    #[test]
+    fn test_sanitize_rejects_negative() {
+        let mut msg = message_with_amount(-5.0);
+        msg.sanitize();
+        assert_eq!(msg.amount, 0.0);
+    }
+
+    #[test]
+    fn test_sanitize_rejects_infinity() {
+        let mut msg = message_with_amount(f64::INFINITY);
+        msg.sanitize();
+        assert_eq!(msg.amount, 0.0);
+
+        let mut msg = message_with_amount(f64::NEG_INFINITY);
+        msg.sanitize();
+        assert_eq!(msg.amount, 0.0);
+    }
+
+    #[test]
+    fn test_console_msg_shows_paid_tier() {
+        let paid = message_with_amount(20.0);
+        assert!(paid.to_console_msg().contains("[tier 20]"));
+        assert!(!paid.to_console_msg().contains('\x1b'));
+
+        let free = message_with_amount(0.0);
+        assert!(!free.to_console_msg().contains("tier"));
+    }
+
+    #[test]
+    fn test_canonical_platform() {
+        for (name, canonical) in [
+            ("YouTube", "youtube"),
+            ("youtube", "youtube"),
+            ("YT", "youtube"),
+            (" YouTube Live ", "youtube"),
+            ("Twitch", "twitch"),
+            ("twitch.tv", "twitch"),
+            ("Kick", "kick"),
+            ("Twitter", "x"),
+            ("X", "x"),
+            ("XMRChat", "xmrchat"),
+            ("Ko-fi", "kofi"),
+            ("USPS", "mail"),
+            ("NONE", "none"),
+            ("", "none"),
+            ("My Custom_Site!", "my-custom-site"),
+        ] {
+            assert_eq!(canonical_platform(name), canonical, "{:?}", name);
+        }
+
+        let mut msg = Message {
+            platform: "YT".to_string(),
+            ..Default::default()
+        };
+        msg.sanitize();
+        assert_eq!(msg.platform, "youtube");
+        assert_eq!(msg.get_platform_string(), "msg--p-youtube");
+    }
+
+    #[test]
+    fn test_attachment_is_renderable_image() {
+        let image = |url: &str, mime: &str| Attachment {
+            url: url.to_string(),
+            mime_type: mime.to_string(),
+            width: None,
+            height: None,
+        };
+
+        assert!(image("https://files.kick.com/a.png", "image/png").is_renderable_image());
+        assert!(image("data:image/gif;base64,R0lGOD", "image/gif").is_renderable_image());
+        assert!(!image("https://files.kick.com/a.mp4", "video/mp4").is_renderable_image());
+        assert!(!image("javascript:alert(1)", "image/png").is_renderable_image());
+    }
+
+    #[test]
+    fn test_empty_avatar_renders_blank() {
+        for avatar in [
+            "",
+            "  ",
+            "javascript:alert(1)",
+            "//evil.example/a.png",
+            "https://",
+        ] {
+            assert!(!is_valid_avatar(avatar), "{:?}", avatar);
+            let msg = Message {
+                avatar: avatar.to_string(),
+                ..Default::default()
+            };
+            let html = msg.to_html();
+            assert!(
+                html.contains(&format!("src=\"{}\"", BLANK_AVATAR)),
+                "{:?}",
+                avatar
+            );
+        }
+        for avatar in [
+            "https://yt3.ggpht.com/a.jpg",
+            "/avatar?url=https%3A%2F%2Fyt3.ggpht.com%2Fa.jpg",
+            BLANK_AVATAR,
+        ] {
+            assert!(is_valid_avatar(avatar), "{:?}", avatar);
+        }
+    }
+
+    #[test]
+    fn test_attachments_in_html() {
+        let msg = Message {
+            attachments: vec![Attachment {
+                url: "https://cdn.discordapp.com/a.png".to_string(),
+                mime_type: "image/png".to_string(),
+                width: Some(64),
+                height: Some(32),
+            }],
+            ..Default::default()
+        };
+        let html = msg.to_html();
+        assert!(html.contains("class=\"msg-attachment\""));
+        assert!(html.contains("https://cdn.discordapp.com/a.png"));
+        assert!(html.contains("width=\"64\""));
+
+        // Missing from older bridges
+        let json = r#"{"url":"https://x/a.png","mime_type":"image/png"}"#;
+        let parsed: Attachment = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.width, None);
+    }
+
+    #[test]
+    fn test_html_with_platform_style() {
+        let msg = Message {
+            platform: "YouTube".to_string(),
+            is_sub: true,
+            is_mod: true,
+            ..Default::default()
+        };
+        let mut style = MessageStyle::default();
+        style.platform_overrides.insert(
+            "youtube".to_string(),
+            PlatformStyle {
+                background_color: Some("#c00".to_string()),
+                show_sub_badge: Some(false),
+                ..PlatformStyle::default()
+            },
+        );
+
+        let html = msg.to_html_with_style(&style);
+        assert!(html.contains("--message-bg: #c00"));
+        assert!(!html.contains("msg-badge--sub"));
+        assert!(html.contains("msg-badge--mod"));
+
+        // Base rendering is unaffected by layout styling
+        let html = msg.to_html();
+        assert!(!html.contains("--message-bg"));
+        assert!(html.contains("msg-badge--sub"));
+
+        // Other platforms fall back to the base style
+        let kick = Message {
+            platform: "Kick".to_string(),
+            is_sub: true,
+            ..Default::default()
+        };
+        assert!(kick.to_html_with_style(&style).contains("msg-badge--sub"));
+    }
+
+    #[test]
+    fn test_first_time_roundtrip() {
+        let first = Message {
+            is_first_time: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&first).unwrap();
+        let parsed: Message = serde_json::from_str(&json).unwrap();
+        assert!(parsed.is_first_time);
+        assert!(parsed.to_html().contains("msg--first"));
+
+        // Bridges that don't know about the flag
+        let mut value = serde_json::to_value(Message::default()).unwrap();
+        value.as_object_mut().unwrap().remove("is_first_time");
+        let parsed: Message = serde_json::from_value(value).unwrap();
+        assert!(!parsed.is_first_time);
+        assert!(!parsed.to_html().contains("msg--first"));
+
+        let style = MessageStyle {
+            first_time_color: Some("#ffd700".to_string()),
+            ..MessageStyle::default()
+        };
+        assert!(first
+            .to_html_with_style(&style)
+            .contains("--message-first-color: #ffd700"));
+        assert!(!parsed
+            .to_html_with_style(&style)
+            .contains("--message-first-color"));
+    }
+
+    #[test]
+    fn test_badge_images() {
+        let msg = Message {
+            platform: "YouTube".to_string(),
+            is_owner: true,
+            is_mod: true,
+            is_sub: true,
+            ..Default::default()
+        };
+        let mut style = MessageStyle::default();
+        style
+            .badge_images
+            .insert("owner".to_string(), "/static/img/crown.png".to_string());
+        style.platform_overrides.insert(
+            "youtube".to_string(),
+            PlatformStyle {
+                badge_images: std::collections::HashMap::from([(
+                    "mod".to_string(),
+                    "https://cdn.example.com/wrench.png".to_string(),
+                )]),
+                ..PlatformStyle::default()
+            },
+        );
+
+        let html = msg.to_html_with_style(&style);
+        assert!(html.contains(r#"<img class="badge" src="/static/img/crown.png" alt="Owner" />"#));
+        assert!(html.contains(r#"src="https://cdn.example.com/wrench.png" alt="Moderator""#));
+        // Unconfigured badges keep the built-in icon
+        let sub = &html[html.find("msg-badge--sub").unwrap()..];
+        assert!(sub[..sub.find("</span>").unwrap()].contains("<svg"));
+        assert_eq!(html.matches(r#"<img class="badge""#).count(), 2);
+
+        // Hidden badges stay hidden, image or not
+        style.show_owner_badge = false;
+        assert!(!msg.to_html_with_style(&style).contains("crown.png"));
+        // The broadcast render has no layout, so it never uses images
+        assert!(!msg.to_html().contains(r#"<img class="badge""#));
+    }
+
+    #[test]
+    fn test_condensed_mode_groups_runs() {
+        let from = |username: &str, amount: f64| Message {
+            platform: "YouTube".to_string(),
+            username: username.to_string(),
+            amount,
+            ..Default::default()
+        };
+        let messages = [
+            from("alice", 0.0),
+            from("alice", 0.0),
+            from("alice", 0.0),
+            from("bob", 0.0),
+            from("alice", 0.0),
+            from("alice", 5.0),
+            from("alice", 0.0),
+        ];
+        let grouped = |style: &MessageStyle| -> Vec<bool> {
+            let html = render_messages(&messages, style);
+            messages
+                .iter()
+                .map(|message| {
+                    let start = html.find(&format!("id=\"{}\"", message.id)).unwrap();
+                    let class_end = start + html[start..].find("style=").unwrap();
+                    html[start..class_end].contains("msg--grouped")
+                })
+                .collect()
+        };
+
+        let mut style = MessageStyle::default();
+        assert_eq!(grouped(&style), [false; 7]);
+
+        style.condensed_mode = true;
+        assert_eq!(
+            grouped(&style),
+            [false, true, true, false, false, false, false]
+        );
+        // Growing from the top, the newest of a run keeps the name
+        style.direction = "top".to_string();
+        assert_eq!(
+            grouped(&style),
+            [true, true, false, false, false, false, false]
+        );
+
+        assert!(messages[1].continues(&messages[0]));
+        let kick = Message {
+            platform: "Kick".to_string(),
+            ..from("alice", 0.0)
+        };
+        assert!(!kick.continues(&messages[0]));
+    }
+
+    fn emoji(find: &str, name: &str) -> Emoji {
+        Emoji::new(
+            find,
+            format!("https://cdn.example.com/{}.png?a=1&b=2", name),
+            name,
+        )
+    }
+
+    #[test]
+    fn test_emoji_sizes() {
+        let emojis: Vec<Emoji> = serde_json::from_str(
+            r#"[
+                [":wave:", "https://cdn.example.com/wave.png", "wave"],
+                [":big:", "https://cdn.example.com/big.png", "big", 160, 120],
+                [":wide:", "https://cdn.example.com/wide.png", "wide", 200, null],
+                [":zero:", "https://cdn.example.com/zero.png", "zero", 0, 0]
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            emojis[0],
+            Emoji::new(":wave:", "https://cdn.example.com/wave.png", "wave")
+        );
+        assert_eq!((emojis[1].width, emojis[1].height), (Some(160), Some(120)));
+        assert_eq!((emojis[2].width, emojis[2].height), (Some(200), None));
+        assert_eq!((emojis[3].width, emojis[3].height), (None, None));
+
+        assert_eq!(
+            emojis[0].to_html(),
+            r#"<img class="emoji" src="https://cdn.example.com/wave.png" data-emoji="wave" alt="wave" />"#
+        );
+        assert_eq!(
+            emojis[1].to_html(),
+            r#"<img class="emoji emoji--sized" src="https://cdn.example.com/big.png" data-emoji="big" alt="big" style="width: 160px; height: 120px" />"#
+        );
+        assert!(emojis[2].to_html().ends_with(r#" style="width: 200px" />"#));
+
+        // Unsized emojis keep the three-element form older readers expect
+        assert_eq!(
+            serde_json::to_string(&emojis[..2]).unwrap(),
+            r#"[[":wave:","https://cdn.example.com/wave.png","wave"],[":big:","https://cdn.example.com/big.png","big",160,120]]"#
+        );
+        assert!(serde_json::from_str::<Emoji>(r#"[":x:", "url"]"#).is_err());
+    }
+
+    #[test]
+    fn test_emoji_only_is_jumbo() {
+        let emojis = vec![emoji("KEKW", "kekw"), emoji(":wave:", "wave")];
+        let expanded = |text: &str| {
+            let mut text = text.to_string();
+            for emoji in &emojis {
+                text = text.replace(emoji.find.as_str(), &emoji.to_html());
+            }
+            text
+        };
+        let msg = |text: String| Message {
+            message: text,
+            emojis: emojis.clone(),
+            ..Default::default()
+        };
+
+        let only = msg(expanded(" KEKW :wave:  KEKW "));
+        assert!(only.is_emoji_only());
+        assert!(only.to_html().contains("msg--jumbo"));
+        // Bridges that haven't expanded them yet
+        assert!(msg("KEKW:wave:".to_string()).is_emoji_only());
+
+        let mixed = msg(expanded("KEKW that was close"));
+        assert!(!mixed.is_emoji_only());
+        assert!(!mixed.to_html().contains("msg--jumbo"));
+        // Text that merely looks like an emoji name isn't in the table
+        assert!(!msg("LUL".to_string()).is_emoji_only());
+        assert!(!msg("   ".to_string()).is_emoji_only());
+    }
+
+    #[test]
+    fn test_escape_for_display() {
+        let mut msg = Message {
+            username: "<b>O'Brien</b>".to_string(),
+            message: r#"Tom & Jerry say "hi" <3"#.to_string(),
+            ..Default::default()
+        };
+        msg.escape_for_display();
+        assert_eq!(msg.username, "&lt;b&gt;O&#039;Brien&lt;/b&gt;");
+        assert_eq!(msg.message, "Tom &amp; Jerry say &quot;hi&quot; &lt;3");
+
+        // Escaping again changes nothing
+        let escaped = msg.message.clone();
+        msg.escape_for_display();
+        assert_eq!(msg.message, escaped);
+    }
+
+    #[test]
+    fn test_escape_keeps_encoded_input() {
+        assert_eq!(
+            escape_html("it&#39;s &amp; &#x1F600; &hearts;"),
+            "it&#39;s &amp; &#x1F600; &hearts;"
+        );
+        // Ampersands that don't start an entity are still escaped
+        assert_eq!(
+            escape_html("AT&T a & b; &#; &#xZZ; & ;"),
+            "AT&amp;T a &amp; b; &amp;#; &amp;#xZZ; &amp; ;"
+        );
+    }
+
+    #[test]
+    fn test_plaintext_from_bridge_message() {
+        let msg = Message {
+            message: "hello :wave: &lt;3 Tom &amp; Jerry".to_string(),
+            emojis: vec![emoji(":wave:", "wave")],
+            ..Default::default()
+        };
+        assert_eq!(msg.to_plaintext(), "hello :wave: <3 Tom & Jerry");
+    }
+
+    #[test]
+    fn test_plaintext_from_ingested_message() {
+        let emojis = vec![emoji("KEKW", "kekw")];
+        let msg = Message {
+            message: format!("it&#039;s {} &quot;fine&quot", emojis[0].to_html()),
+            emojis,
+            ..Default::default()
+        };
+        assert_eq!(msg.to_plaintext(), "it's :kekw: \"fine\"");
+    }
+
+    #[test]
+    fn test_plaintext_all_emojis() {
+        let emojis = vec![emoji("PogU", "pogu"), emoji("LUL", "lul")];
+        let msg = Message {
+            message: format!(
+                "{} {}{}",
+                emojis[0].to_html(),
+                emojis[1].to_html(),
+                emojis[0].to_html(),
+            ),
+            emojis,
+            ..Default::default()
+        };
+        assert_eq!(msg.to_plaintext(), ":pogu: :lul::pogu:");
+    }
+
+    #[test]
+    fn test_sanitize_keeps_valid_amount() {
+        let mut msg = message_with_amount(4.99);
+        msg.sanitize();
+        assert_eq!(msg.amount, 4.99);
+        assert_eq!(msg.get_paid_tier(), 5);
     }
 }
@@ -0,0 +1,516 @@
+use actix::Addr;
+use actix_web::rt;
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use super::websocket::{self, Incoming};
+use crate::message::{Attachment, Emoji, Message};
+use crate::sneed_env;
+use crate::web::{self, ChatServer};
+
+const GATEWAY_URL: &str = "wss://gateway.discord.gg";
+const GATEWAY_QUERY: &str = "?v=10&encoding=json";
+const CDN_URL: &str = "https://cdn.discordapp.com";
+const PLATFORM: &str = "discord";
+
+/// GUILD_MESSAGES | MESSAGE_CONTENT
+const INTENTS: u64 = (1 << 9) | (1 << 15);
+/// Discord sends Hello right after connecting
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Close codes after which reconnecting with the same settings can never work
+const FATAL_CLOSE_CODES: [u16; 6] = [4004, 4010, 4011, 4012, 4013, 4014];
+/// Close codes that invalidate the session, so the next connection must identify
+const SESSION_CLOSE_CODES: [u16; 2] = [4007, 4009];
+
+/// Bot credentials and filters, read from the environment
+#[derive(Debug, Clone)]
+pub struct DiscordConfig {
+    /// Bot token; the bot needs the Message Content intent enabled
+    pub token: String,
+    pub channel_id: String,
+    /// Drop messages from bots and webhooks
+    pub ignore_bots: bool,
+    /// Drop messages starting with any of these, so bot commands stay off the overlay
+    pub command_prefixes: Vec<String>,
+}
+
+impl DiscordConfig {
+    /// `DISCORD_BOT_TOKEN` and `DISCORD_CHANNEL_ID` are required; `DISCORD_IGNORE_BOTS`
+    /// (default true) and `DISCORD_COMMAND_PREFIXES` (comma separated, default `!`) are optional
+    pub fn from_env() -> Option<Self> {
+        let var = |key| dotenvy::var(key).ok().filter(|v| !v.trim().is_empty());
+        Some(Self {
+            token: var("DISCORD_BOT_TOKEN")?,
+            channel_id: var("DISCORD_CHANNEL_ID")?,
+            ignore_bots: sneed_env::var_or("DISCORD_IGNORE_BOTS", true),
+            command_prefixes: dotenvy::var("DISCORD_COMMAND_PREFIXES")
+                .unwrap_or_else(|_| "!".to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|prefix| !prefix.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+/// Start the gateway client if a bot token and channel are configured
+pub fn start(server: Addr<ChatServer>) {
+    match DiscordConfig::from_env() {
+        Some(config) => {
+            rt::spawn(run(config, server));
+        }
+        None => debug!("Discord bot not configured; Discord ingest disabled"),
+    }
+}
+
+/// Where a dropped connection picks up again
+#[derive(Default)]
+struct Session {
+    id: Option<String>,
+    resume_url: Option<String>,
+    sequence: Option<u64>,
+}
+
+enum SessionEnd {
+    /// Reconnect right away, resuming if the session is still valid
+    Reconnect,
+    Closed(Option<u16>),
+}
+
+/// Keep a gateway connection open. Sessions are resumed where possible, since
+/// Discord limits how often a bot may identify; otherwise reconnects back off.
+async fn run(config: DiscordConfig, server: Addr<ChatServer>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut session = Session::default();
+
+    loop {
+        let mut ready = false;
+        match connection(&config, &server, &mut session, &mut ready).await {
+            Ok(SessionEnd::Reconnect) => {
+                info!("Reconnecting to the Discord gateway");
+                continue;
+            }
+            Ok(SessionEnd::Closed(Some(code))) if FATAL_CLOSE_CODES.contains(&code) => {
+                error!(
+                    code,
+                    "Discord gateway refused the bot; check the token and intents"
+                );
+                return;
+            }
+            Ok(SessionEnd::Closed(code)) => {
+                if code.is_some_and(|code| SESSION_CLOSE_CODES.contains(&code)) {
+                    session = Session::default();
+                }
+                warn!(?code, "Discord gateway closed the connection");
+            }
+            Err(e) => warn!(error = %e, "Discord gateway connection failed"),
+        }
+
+        if ready {
+            backoff = INITIAL_BACKOFF;
+        }
+        info!("Reconnecting to the Discord gateway in {:?}", backoff);
+        rt::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[derive(Deserialize)]
+struct GatewayPayload {
+    op: u8,
+    #[serde(default)]
+    d: Value,
+    s: Option<u64>,
+    t: Option<String>,
+}
+
+async fn connection(
+    config: &DiscordConfig,
+    server: &Addr<ChatServer>,
+    session: &mut Session,
+    ready: &mut bool,
+) -> Result<SessionEnd> {
+    let base = match (&session.id, &session.resume_url) {
+        (Some(_), Some(url)) => url.as_str(),
+        _ => GATEWAY_URL,
+    };
+    let mut conn = websocket::connect(&format!("{}/{}", base, GATEWAY_QUERY)).await?;
+
+    let hello = match rt::time::timeout(HELLO_TIMEOUT, websocket::next_text(&mut conn)).await {
+        Ok(incoming) => match incoming? {
+            Incoming::Text(text) => serde_json::from_str::<GatewayPayload>(&text)?,
+            Incoming::Close(reason) => {
+                return Ok(SessionEnd::Closed(reason.map(|r| r.code.into())))
+            }
+        },
+        Err(_) => bail!("no Hello from the gateway"),
+    };
+    if hello.op != 10 {
+        bail!("expected Hello, got op {}", hello.op);
+    }
+    let interval = hello.d["heartbeat_interval"]
+        .as_u64()
+        .map(Duration::from_millis)
+        .ok_or_else(|| anyhow!("Hello without a heartbeat interval"))?;
+
+    let start = match (&session.id, session.sequence) {
+        (Some(session_id), Some(seq)) => json!({
+            "op": 6,
+            "d": { "token": config.token, "session_id": session_id, "seq": seq },
+        }),
+        _ => json!({
+            "op": 2,
+            "d": {
+                "token": config.token,
+                "intents": INTENTS,
+                "properties": {
+                    "os": std::env::consts::OS,
+                    "browser": "stream-nexus",
+                    "device": "stream-nexus",
+                },
+            },
+        }),
+    };
+    websocket::send_text(&mut conn, start.to_string()).await?;
+
+    // The first heartbeat is jittered so reconnecting bots don't all beat at once
+    let mut next_heartbeat = Instant::now() + interval.mul_f64(rand::random::<f64>());
+    let mut acked = true;
+
+    loop {
+        let until_heartbeat = next_heartbeat.saturating_duration_since(Instant::now());
+        let text = match rt::time::timeout(until_heartbeat, websocket::next_text(&mut conn)).await {
+            Ok(incoming) => match incoming? {
+                Incoming::Text(text) => text,
+                Incoming::Close(reason) => {
+                    return Ok(SessionEnd::Closed(reason.map(|r| r.code.into())))
+                }
+            },
+            Err(_) => {
+                if !acked {
+                    warn!("Discord gateway stopped acknowledging heartbeats");
+                    return Ok(SessionEnd::Reconnect);
+                }
+                heartbeat(&mut conn, session.sequence).await?;
+                acked = false;
+                next_heartbeat += interval;
+                continue;
+            }
+        };
+
+        let payload: GatewayPayload = serde_json::from_str(&text)?;
+        if payload.s.is_some() {
+            session.sequence = payload.s;
+        }
+        match payload.op {
+            // Dispatch
+            0 => match payload.t.as_deref() {
+                Some("READY") => {
+                    session.id = payload.d["session_id"].as_str().map(str::to_string);
+                    session.resume_url =
+                        payload.d["resume_gateway_url"].as_str().map(str::to_string);
+                    *ready = true;
+                    info!("Connected to the Discord gateway");
+                }
+                Some("RESUMED") => {
+                    *ready = true;
+                    info!("Resumed the Discord gateway session");
+                }
+                Some("MESSAGE_CREATE") => match event_to_message(config, payload.d) {
+                    Ok(Some(chat_message)) => server.do_send(web::ChatMessage { chat_message }),
+                    Ok(None) => {}
+                    Err(e) => warn!(error = %e, "Unreadable Discord message"),
+                },
+                _ => {}
+            },
+            // Heartbeat request
+            1 => heartbeat(&mut conn, session.sequence).await?,
+            // Reconnect
+            7 => return Ok(SessionEnd::Reconnect),
+            // Invalid Session; `d` says whether it can still be resumed
+            9 => {
+                if payload.d.as_bool() != Some(true) {
+                    *session = Session::default();
+                }
+                // Discord asks for a random 1-5 second wait before identifying again
+                rt::time::sleep(Duration::from_millis(1000 + rand::random::<u64>() % 4000)).await;
+                return Ok(SessionEnd::Reconnect);
+            }
+            // Heartbeat ACK
+            11 => acked = true,
+            op => debug!(op, "Ignoring Discord gateway payload"),
+        }
+    }
+}
+
+async fn heartbeat(conn: &mut websocket::Connection, sequence: Option<u64>) -> Result<()> {
+    websocket::send_text(conn, json!({ "op": 1, "d": sequence }).to_string()).await
+}
+
+#[derive(Deserialize)]
+struct MessageEvent {
+    id: String,
+    channel_id: String,
+    content: String,
+    timestamp: String,
+    author: Author,
+    member: Option<Member>,
+    webhook_id: Option<String>,
+    #[serde(default)]
+    attachments: Vec<DiscordAttachment>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    id: String,
+    username: String,
+    global_name: Option<String>,
+    avatar: Option<String>,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Deserialize)]
+struct Member {
+    nick: Option<String>,
+    /// Set while the member is boosting the server
+    premium_since: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscordAttachment {
+    url: String,
+    content_type: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Map a MESSAGE_CREATE event to a chat message, or None when it is filtered out
+fn event_to_message(config: &DiscordConfig, event: Value) -> Result<Option<Message>> {
+    let event: MessageEvent = serde_json::from_value(event)?;
+    if event.channel_id != config.channel_id {
+        return Ok(None);
+    }
+    if config.ignore_bots && (event.author.bot || event.webhook_id.is_some()) {
+        return Ok(None);
+    }
+    let content = event.content.trim_start();
+    if config
+        .command_prefixes
+        .iter()
+        .any(|prefix| content.starts_with(prefix.as_str()))
+    {
+        return Ok(None);
+    }
+
+    let (message, emojis) = replace_custom_emojis(&event.content);
+    let snowflake: u64 = event.id.parse()?;
+    let avatar = match &event.author.avatar {
+        Some(hash) => format!(
+            "{}/avatars/{}/{}.{}",
+            CDN_URL,
+            event.author.id,
+            hash,
+            if hash.starts_with("a_") { "gif" } else { "png" }
+        ),
+        // Default avatars are picked from the user id
+        None => {
+            let user_id: u64 = event.author.id.parse().unwrap_or_default();
+            format!("{}/embed/avatars/{}.png", CDN_URL, (user_id >> 22) % 6)
+        }
+    };
+    let member = event.member.as_ref();
+
+    Ok(Some(Message {
+        // Snowflakes are unique, so a replayed event maps to the same id
+        id: Uuid::from_u64_pair(0, snowflake),
+        platform: PLATFORM.to_string(),
+        sent_at: chrono::DateTime::parse_from_rfc3339(&event.timestamp)
+            .map(|time| time.timestamp_millis())
+            .unwrap_or_else(|_| Message::default().sent_at),
+        message,
+        emojis,
+        attachments: event
+            .attachments
+            .into_iter()
+            .filter_map(|attachment| {
+                Some(Attachment {
+                    mime_type: attachment.content_type?,
+                    url: attachment.url,
+                    width: attachment.width,
+                    height: attachment.height,
+                })
+            })
+            .collect(),
+        username: member
+            .and_then(|member| member.nick.clone())
+            .or(event.author.global_name)
+            .unwrap_or(event.author.username),
+        avatar,
+        is_sub: member.is_some_and(|member| member.premium_since.is_some()),
+        ..Default::default()
+    }))
+}
+
+/// Swap custom emojis (`<:name:id>`, `<a:name:id>` when animated) for `:name:` and
+/// list them as emojis, so the server can replace them after escaping the text
+fn replace_custom_emojis(content: &str) -> (String, Vec<Emoji>) {
+    let mut message = String::with_capacity(content.len());
+    let mut emojis: Vec<Emoji> = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find('<') {
+        message.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let parsed = tail.find('>').and_then(|end| {
+            let inner = &tail[1..end];
+            let (animated, inner) = match inner.strip_prefix("a:") {
+                Some(inner) => (true, inner),
+                None => (false, inner.strip_prefix(':')?),
+            };
+            let (name, id) = inner.split_once(':')?;
+            let valid_name =
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            let valid_id = !id.is_empty() && id.chars().all(|c| c.is_ascii_digit());
+            (valid_name && valid_id).then_some((end, animated, name, id))
+        });
+
+        match parsed {
+            Some((end, animated, name, id)) => {
+                let find = format!(":{}:", name);
+                if !emojis.iter().any(|emoji| emoji.find == find) {
+                    let extension = if animated { "gif" } else { "png" };
+                    emojis.push(Emoji::new(
+                        find.clone(),
+                        format!("{}/emojis/{}.{}", CDN_URL, id, extension),
+                        name,
+                    ));
+                }
+                message.push_str(&find);
+                rest = &tail[end + 1..];
+            }
+            None => {
+                message.push('<');
+                rest = &tail[1..];
+            }
+        }
+    }
+    message.push_str(rest);
+
+    (message, emojis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DiscordConfig {
+        DiscordConfig {
+            token: "token".to_string(),
+            channel_id: "1100000000000000000".to_string(),
+            ignore_bots: true,
+            command_prefixes: vec!["!".to_string()],
+        }
+    }
+
+    fn event(content: &str) -> Value {
+        json!({
+            "id": "1200000000000000001",
+            "channel_id": "1100000000000000000",
+            "guild_id": "1000000000000000000",
+            "content": content,
+            "timestamp": "2024-02-05T13:29:25.123000+00:00",
+            "author": {
+                "id": "80351110224678912",
+                "username": "nelly",
+                "global_name": "Nelly",
+                "avatar": "8342729096ea3675442027381ff50dfe",
+            },
+            "member": { "nick": null, "roles": [], "premium_since": "2024-01-01T00:00:00+00:00" },
+            "attachments": [{
+                "id": "1",
+                "filename": "cat.png",
+                "url": "https://cdn.discordapp.com/attachments/1/1/cat.png",
+                "content_type": "image/png",
+                "width": 64,
+                "height": 48,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_message() {
+        let msg = event_to_message(&config(), event("hi <:pepe:123> <a:dance:456> :)"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.platform, "discord");
+        assert_eq!(msg.id, Uuid::from_u64_pair(0, 1200000000000000001));
+        assert_eq!(msg.sent_at, 1707139765123);
+        assert_eq!(msg.username, "Nelly");
+        assert_eq!(
+            msg.avatar,
+            "https://cdn.discordapp.com/avatars/80351110224678912/8342729096ea3675442027381ff50dfe.png"
+        );
+        assert!(msg.is_sub);
+        assert_eq!(msg.message, "hi :pepe: :dance: :)");
+        assert_eq!(
+            msg.emojis,
+            vec![
+                Emoji::new(
+                    ":pepe:",
+                    "https://cdn.discordapp.com/emojis/123.png",
+                    "pepe"
+                ),
+                Emoji::new(
+                    ":dance:",
+                    "https://cdn.discordapp.com/emojis/456.gif",
+                    "dance"
+                ),
+            ]
+        );
+        assert_eq!(msg.attachments.len(), 1);
+        assert_eq!(msg.attachments[0].mime_type, "image/png");
+    }
+
+    #[test]
+    fn test_filters() {
+        let config = config();
+        assert!(event_to_message(&config, event("!uptime"))
+            .unwrap()
+            .is_none());
+
+        let mut bot = event("beep");
+        bot["author"]["bot"] = json!(true);
+        assert!(event_to_message(&config, bot.clone()).unwrap().is_none());
+        let keep_bots = DiscordConfig {
+            ignore_bots: false,
+            ..config.clone()
+        };
+        assert!(event_to_message(&keep_bots, bot).unwrap().is_some());
+
+        let mut other_channel = event("hi");
+        other_channel["channel_id"] = json!("1");
+        assert!(event_to_message(&config, other_channel).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_default_avatar_and_plain_angle_brackets() {
+        let mut event = event("a <b> <:x:> <3");
+        event["author"]["avatar"] = Value::Null;
+        event["member"] = Value::Null;
+        let msg = event_to_message(&config(), event).unwrap().unwrap();
+        assert_eq!(msg.avatar, "https://cdn.discordapp.com/embed/avatars/5.png");
+        assert!(!msg.is_sub);
+        assert_eq!(msg.message, "a <b> <:x:> <3");
+        assert!(msg.emojis.is_empty());
+    }
+}
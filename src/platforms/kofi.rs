@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::message::Message;
+
+const PLATFORM: &str = "kofi";
+
+/// Webhook payload, sent form-encoded as JSON in the `data` field
+#[derive(Deserialize)]
+struct Payload {
+    verification_token: String,
+    message_id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    is_public: bool,
+    from_name: String,
+    message: Option<String>,
+    amount: String,
+    currency: String,
+}
+
+/// Map a Ko-fi payment (donation, subscription or shop order) to a paid message.
+/// Private payments keep their amount but hide the supporter's name and message.
+pub fn parse(body: &str) -> Result<Message> {
+    let payload: Payload = serde_json::from_str(body)?;
+    let amount: f64 = payload
+        .amount
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid Ko-fi amount {:?}", payload.amount))?;
+
+    Ok(Message {
+        id: Uuid::parse_str(&payload.message_id).unwrap_or_else(|_| Uuid::new_v4()),
+        platform: PLATFORM.to_string(),
+        username: if payload.is_public {
+            payload.from_name
+        } else {
+            "Anonymous".to_string()
+        },
+        message: match payload.message {
+            Some(message) if payload.is_public => message,
+            _ => String::new(),
+        },
+        amount,
+        currency: payload.currency.to_uppercase(),
+        is_sub: payload.kind == "Subscription",
+        ..Default::default()
+    })
+}
+
+/// Check the payload's `verification_token` against the one shown in Ko-fi's webhook settings
+pub fn verify(body: &str, expected: &str) -> bool {
+    serde_json::from_str::<Payload>(body)
+        .is_ok_and(|payload| payload.verification_token == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DONATION: &str = r#"{
+        "verification_token": "8c2ca4e1-3b5c-4c0e-9b6c-0c9a6bfa2e1d",
+        "message_id": "3a1fac0c-f960-4506-a60e-824979a74e74",
+        "timestamp": "2024-02-05T13:29:25Z",
+        "type": "Donation",
+        "is_public": true,
+        "from_name": "Jo Example",
+        "message": "Good luck with the integration!",
+        "amount": "3.00",
+        "url": "https://ko-fi.com/Home/CoffeeShop?txid=00000000-1111-2222-3333-444444444444",
+        "email": "jo.example@example.com",
+        "currency": "usd",
+        "is_subscription_payment": false,
+        "is_first_subscription_payment": false,
+        "kofi_transaction_id": "00000000-1111-2222-3333-444444444444",
+        "shop_items": null,
+        "tier_name": null,
+        "shipping": null
+    }"#;
+
+    #[test]
+    fn test_donation() {
+        let msg = parse(DONATION).unwrap();
+        assert_eq!(msg.id.to_string(), "3a1fac0c-f960-4506-a60e-824979a74e74");
+        assert_eq!(msg.platform, "kofi");
+        assert_eq!(msg.username, "Jo Example");
+        assert_eq!(msg.message, "Good luck with the integration!");
+        assert_eq!(msg.amount, 3.0);
+        assert_eq!(msg.currency, "USD");
+        assert!(!msg.is_sub);
+
+        assert!(verify(DONATION, "8c2ca4e1-3b5c-4c0e-9b6c-0c9a6bfa2e1d"));
+        assert!(!verify(DONATION, "wrong"));
+    }
+
+    #[test]
+    fn test_private_subscription() {
+        let body = DONATION
+            .replace(r#""is_public": true"#, r#""is_public": false"#)
+            .replace(r#""type": "Donation""#, r#""type": "Subscription""#)
+            .replace(
+                r#""message": "Good luck with the integration!""#,
+                r#""message": null"#,
+            );
+        let msg = parse(&body).unwrap();
+        assert_eq!(msg.username, "Anonymous");
+        assert_eq!(msg.message, "");
+        assert_eq!(msg.amount, 3.0);
+        assert!(msg.is_sub);
+    }
+
+    #[test]
+    fn test_invalid_amount() {
+        let body = DONATION.replace(r#""amount": "3.00""#, r#""amount": "three""#);
+        assert!(parse(&body).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Server-side integrations that ingest chat directly from a platform instead of
+//! through a userscript bridge. Each feeds the ChatServer the same way a bridge does:
+//! Twitch and Discord over their own sockets, YouTube by polling its API, Rumble
+//! over its event stream, and Ko-fi and Streamlabs through `/ingest/*` webhooks.
+//! `replay` plays back a file instead, for designing overlays offline; `record` writes one.
+
+pub mod discord;
+pub mod kofi;
+pub mod record;
+pub mod replay;
+pub mod rumble;
+pub mod streamlabs;
+pub mod twitch;
+pub mod websocket;
+pub mod youtube;
+
+use aws_lc_rs::digest::{digest, SHA256};
+use uuid::Uuid;
+
+/// Map a platform's own message id to a UUID that is the same every time the message
+/// is seen, so replays after a reconnect are dropped as duplicates
+pub fn stable_id(platform: &str, id: &str) -> Uuid {
+    let hash = digest(&SHA256, format!("{}:{}", platform, id).as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash.as_ref()[..16]);
+    Uuid::from_bytes(bytes)
+}
@@ -0,0 +1,153 @@
+use actix::{Actor, Handler, Recipient, SyncArbiter, SyncContext};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use super::replay::ReplayLine;
+use crate::sneed_env;
+
+/// Older recordings kept next to the live file, as `<file>.1` (newest) to `<file>.5`
+const ROTATED_FILES: usize = 5;
+
+/// Appends inbound traffic to a replay file (`--record <file>`). Runs on its own thread so
+/// disk writes never hold up the ChatServer.
+pub struct Recorder {
+    path: String,
+    file: File,
+    written: u64,
+    /// Rotate once the file reaches this size; 0 never rotates
+    max_bytes: u64,
+}
+
+impl Actor for Recorder {
+    type Context = SyncContext<Self>;
+}
+
+/// Open the file for appending and start the recorder, returning the recipient for the
+/// ChatServer. `RECORD_MAX_MB` (default 100, 0 = unlimited) sets the rotation size.
+pub fn start(path: String) -> Result<Recipient<ReplayLine>> {
+    let max_bytes = sneed_env::var_or("RECORD_MAX_MB", 100u64) * 1024 * 1024;
+    let recorder = Recorder::open(path, max_bytes)?;
+    info!(path = %recorder.path, max_bytes, "Recording inbound chat");
+    // SyncArbiter takes a factory, but the file is opened once above so errors surface here
+    let recorder = Mutex::new(Some(recorder));
+    let addr = SyncArbiter::start(1, move || {
+        recorder
+            .lock()
+            .unwrap()
+            .take()
+            .expect("Recorder runs on a single thread")
+    });
+    Ok(addr.recipient())
+}
+
+impl Recorder {
+    fn open(path: String, max_bytes: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path))?;
+        let written = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+        })
+    }
+
+    /// Shift `<file>.N` up by one, dropping the oldest, and start an empty file
+    fn rotate(&mut self) -> Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = format!("{}.{}", self.path, n);
+            if std::path::Path::new(&from).exists() {
+                std::fs::rename(&from, format!("{}.{}", self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, format!("{}.1", self.path))?;
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        info!(path = %self.path, "Rotated recording");
+        Ok(())
+    }
+
+    fn write(&mut self, line: &ReplayLine) -> Result<()> {
+        let mut bytes = serde_json::to_vec(line)?;
+        bytes.push(b'\n');
+        if self.max_bytes > 0
+            && self.written > 0
+            && self.written + bytes.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(&bytes)?;
+        self.written += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+impl Handler<ReplayLine> for Recorder {
+    type Result = ();
+
+    fn handle(&mut self, line: ReplayLine, _: &mut Self::Context) -> Self::Result {
+        // A full disk shouldn't take the stream down with it
+        if let Err(e) = self.write(&line) {
+            warn!(path = %self.path, error = %e, "Failed to record update");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::LivestreamUpdate;
+    use crate::platforms::replay;
+    use tempfile::TempDir;
+
+    fn line(viewers: usize) -> ReplayLine {
+        ReplayLine {
+            recorded_at: Some(1_000),
+            update: LivestreamUpdate {
+                platform: "youtube".to_string(),
+                channel: None,
+                messages: None,
+                removals: None,
+                viewers: Some(viewers),
+            },
+        }
+    }
+
+    #[test]
+    fn test_records_and_rotates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.ndjson");
+        let path = path.to_str().unwrap().to_string();
+        let size = serde_json::to_vec(&line(10)).unwrap().len() as u64 + 1;
+
+        // Room for two lines per file
+        let mut recorder = Recorder::open(path.clone(), size * 2).unwrap();
+        for viewers in 10..17 {
+            recorder.write(&line(viewers)).unwrap();
+        }
+
+        let viewers = |path: &str| -> Vec<usize> {
+            let content = std::fs::read_to_string(path).unwrap();
+            replay::parse(&content)
+                .unwrap()
+                .into_iter()
+                .map(|line| line.update.viewers.unwrap())
+                .collect()
+        };
+        assert_eq!(viewers(&path), [16]);
+        assert_eq!(viewers(&format!("{}.1", path)), [14, 15]);
+        assert_eq!(viewers(&format!("{}.3", path)), [10, 11]);
+
+        // Reopening appends
+        let mut recorder = Recorder::open(path.clone(), 0).unwrap();
+        recorder.write(&line(17)).unwrap();
+        assert_eq!(viewers(&path), [16, 17]);
+    }
+}
@@ -0,0 +1,212 @@
+use actix::{Addr, Message as ActixMessage};
+use actix_web::rt;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::message::LivestreamUpdate;
+use crate::web::{self, ChatServer};
+
+/// Longest wait taken from a recording's timestamps, so a quiet stretch doesn't stall the replay
+const MAX_GAP: Duration = Duration::from_secs(10);
+
+/// What to replay and how fast, from `--replay <file>`, `--replay-interval <secs>` and
+/// `--replay-loop`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig {
+    pub path: String,
+    /// Fixed time between updates; None follows the file's timestamps
+    pub interval: Option<Duration>,
+    pub looping: bool,
+}
+
+/// One line of a replay file: a `LivestreamUpdate` as bridges send it. Recordings add
+/// `recorded_at` so updates without messages keep their place in time.
+#[derive(Serialize, Deserialize, ActixMessage, Debug, Clone)]
+#[rtype(result = "()")]
+pub struct ReplayLine {
+    /// Unix milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<i64>,
+    #[serde(flatten)]
+    pub update: LivestreamUpdate,
+}
+
+/// Parse newline-delimited lines, skipping blank ones
+pub fn parse(content: &str) -> Result<Vec<ReplayLine>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).with_context(|| format!("line {}", index + 1))
+        })
+        .collect()
+}
+
+/// Read the file and start feeding it to the ChatServer
+pub fn start(server: Addr<ChatServer>, config: ReplayConfig) -> Result<()> {
+    let content = std::fs::read_to_string(&config.path)
+        .with_context(|| format!("Failed to read {}", config.path))?;
+    let lines = parse(&content).with_context(|| format!("Invalid replay file {}", config.path))?;
+    if lines.is_empty() {
+        bail!("{} has no updates to replay", config.path);
+    }
+    info!(path = %config.path, updates = lines.len(), looping = config.looping, "Replaying chat");
+    rt::spawn(run(server, config, lines));
+    Ok(())
+}
+
+async fn run(server: Addr<ChatServer>, config: ReplayConfig, lines: Vec<ReplayLine>) {
+    loop {
+        // Messages get new ids each pass, or the server would drop them as duplicates
+        let mut ids = HashMap::new();
+        let mut last_at = None;
+        for line in &lines {
+            let delay = match config.interval {
+                Some(interval) => interval,
+                None => gap(&mut last_at, line),
+            };
+            rt::time::sleep(delay).await;
+            send(&server, fresh(&line.update, &mut ids));
+        }
+        if !config.looping {
+            info!("Replay finished");
+            return;
+        }
+    }
+}
+
+/// Time since the previous line, by `recorded_at` or else the `sent_at` of the first message
+fn gap(last_at: &mut Option<i64>, line: &ReplayLine) -> Duration {
+    let Some(at) = line.recorded_at.or_else(|| {
+        line.update
+            .messages
+            .iter()
+            .flatten()
+            .map(|message| message.sent_at)
+            .find(|sent_at| *sent_at > 0)
+    }) else {
+        return Duration::ZERO;
+    };
+    let millis = last_at.map_or(0, |last| (at - last).max(0) as u64);
+    *last_at = Some(at);
+    Duration::from_millis(millis).min(MAX_GAP)
+}
+
+/// Copy an update with new message ids and timestamps, pointing removals at the new ids
+fn fresh(update: &LivestreamUpdate, ids: &mut HashMap<Uuid, Uuid>) -> LivestreamUpdate {
+    let mut update = update.clone();
+    let now = chrono::Utc::now().timestamp_millis();
+    for message in update.messages.iter_mut().flatten() {
+        let id = Uuid::new_v4();
+        ids.insert(message.id, id);
+        message.id = id;
+        message.sent_at = now;
+        message.received_at = now;
+    }
+    for id in update.removals.iter_mut().flatten() {
+        *id = ids.get(id).copied().unwrap_or(*id);
+    }
+    update
+}
+
+fn send(server: &Addr<ChatServer>, update: LivestreamUpdate) {
+    for chat_message in update.messages.into_iter().flatten() {
+        server.do_send(web::ChatMessage { chat_message });
+    }
+    for id in update.removals.into_iter().flatten() {
+        server.do_send(web::RemoveMessage { id });
+    }
+    if let Some(viewers) = update.viewers {
+        server.do_send(web::ViewCount {
+            platform: update.platform,
+            viewers,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+
+    fn line(update: LivestreamUpdate, recorded_at: Option<i64>) -> ReplayLine {
+        ReplayLine {
+            recorded_at,
+            update,
+        }
+    }
+
+    fn update(sent_at: i64) -> LivestreamUpdate {
+        LivestreamUpdate {
+            platform: "youtube".to_string(),
+            channel: None,
+            messages: Some(vec![Message {
+                sent_at,
+                ..Default::default()
+            }]),
+            removals: None,
+            viewers: None,
+        }
+    }
+
+    #[test]
+    fn test_gap() {
+        let mut last = None;
+        let mut gap = |update, recorded_at| gap(&mut last, &line(update, recorded_at));
+        assert_eq!(gap(update(1_000), None), Duration::ZERO);
+        assert_eq!(gap(update(3_500), None), Duration::from_millis(2_500));
+        assert_eq!(gap(update(3_000), None), Duration::ZERO);
+        assert_eq!(gap(update(900_000), None), MAX_GAP);
+
+        let viewers = LivestreamUpdate {
+            messages: None,
+            viewers: Some(10),
+            ..update(0)
+        };
+        assert_eq!(gap(viewers.clone(), None), Duration::ZERO);
+        // A recording's own timestamp wins over the message's
+        assert_eq!(gap(viewers, Some(901_000)), Duration::from_secs(1));
+        assert_eq!(gap(update(0), Some(901_500)), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_fresh_ids() {
+        let original = update(1_000);
+        let old_id = original.messages.as_ref().unwrap()[0].id;
+        let removal = LivestreamUpdate {
+            messages: None,
+            removals: Some(vec![old_id]),
+            ..update(0)
+        };
+
+        let mut ids = HashMap::new();
+        let first = fresh(&original, &mut ids);
+        let new_id = first.messages.as_ref().unwrap()[0].id;
+        assert_ne!(new_id, old_id);
+        assert_eq!(fresh(&removal, &mut ids).removals, Some(vec![new_id]));
+
+        let mut ids = HashMap::new();
+        let second = fresh(&original, &mut ids);
+        assert_ne!(second.messages.unwrap()[0].id, new_id);
+    }
+
+    #[test]
+    fn test_parse() {
+        let bridge = serde_json::to_string(&update(1_000)).unwrap();
+        let recorded = serde_json::to_string(&line(update(1_000), Some(2_000))).unwrap();
+        let content = format!("{}\n\n{}\n", bridge, recorded);
+        let lines = parse(&content).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].recorded_at, None);
+        assert_eq!(lines[1].recorded_at, Some(2_000));
+        assert_eq!(lines[1].update.platform, "youtube");
+
+        let err = parse(&format!("{}\n{{\"platform\": 1}}", bridge)).unwrap_err();
+        assert_eq!(err.to_string(), "line 2");
+    }
+}
@@ -0,0 +1,343 @@
+use actix::Addr;
+use actix_web::rt;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use super::stable_id;
+use crate::message::Message;
+use crate::web::{self, ChatServer};
+
+const CHAT_URL: &str = "https://web7.rumble.com/chat/api/chat";
+const PLATFORM: &str = "rumble";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Rumble sends keepalives; a stream silent for this long is treated as dead
+const READ_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// The chat to follow, read from the environment
+#[derive(Debug, Clone)]
+pub struct RumbleConfig {
+    /// Numeric id from the stream's popout chat URL (`rumble.com/chat/popup/<id>`)
+    pub chat_id: String,
+}
+
+impl RumbleConfig {
+    /// `RUMBLE_CHAT_ID` is required
+    pub fn from_env() -> Option<Self> {
+        let chat_id = dotenvy::var("RUMBLE_CHAT_ID").ok()?;
+        let chat_id = chat_id.trim();
+        (!chat_id.is_empty()).then(|| Self {
+            chat_id: chat_id.to_string(),
+        })
+    }
+}
+
+/// Start following the chat stream if a chat id is configured
+pub fn start(server: Addr<ChatServer>) {
+    match RumbleConfig::from_env() {
+        Some(config) => {
+            rt::spawn(run(config, server));
+        }
+        None => debug!("RUMBLE_CHAT_ID not set; Rumble ingest disabled"),
+    }
+}
+
+/// Keep the SSE stream open, reconnecting when it ends (e.g. the stream restarts)
+async fn run(config: RumbleConfig, server: Addr<ChatServer>) {
+    let http = reqwest::Client::new();
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let mut connected = false;
+        match stream(&config, &http, &server, &mut connected).await {
+            Ok(()) => info!("Rumble chat stream ended"),
+            Err(e) => warn!(error = %e, "Rumble chat stream failed"),
+        }
+
+        if connected {
+            backoff = INITIAL_BACKOFF;
+        }
+        info!("Reconnecting to Rumble chat in {:?}", backoff);
+        rt::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn stream(
+    config: &RumbleConfig,
+    http: &reqwest::Client,
+    server: &Addr<ChatServer>,
+    connected: &mut bool,
+) -> Result<()> {
+    let url = format!("{}/{}/stream", CHAT_URL, config.chat_id);
+    let mut response = http
+        .get(&url)
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("chat stream answered {}", response.status());
+    }
+    *connected = true;
+    info!(chat = %config.chat_id, "Connected to Rumble chat");
+
+    let mut parser = SseParser::default();
+    loop {
+        let chunk = match rt::time::timeout(READ_TIMEOUT, response.chunk()).await {
+            Ok(chunk) => chunk?,
+            Err(_) => bail!("no data from the chat stream in {:?}", READ_TIMEOUT),
+        };
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        for data in parser.push(&chunk) {
+            match serde_json::from_str::<Event>(&data) {
+                Ok(event) => handle_event(server, event),
+                Err(e) => warn!(error = %e, "Unreadable Rumble chat event"),
+            }
+        }
+    }
+}
+
+/// Splits a `text/event-stream` body into the `data` of each event
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+            // Comments (`:`), `event:`, `id:` and `retry:` lines aren't needed
+        }
+        events
+    }
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    data: EventData,
+}
+
+#[derive(Deserialize, Default)]
+struct EventData {
+    #[serde(default)]
+    messages: Vec<ChatEntry>,
+    #[serde(default)]
+    users: Vec<User>,
+    #[serde(default)]
+    message_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatEntry {
+    id: String,
+    time: Option<String>,
+    user_id: String,
+    #[serde(default)]
+    text: String,
+    rant: Option<Rant>,
+}
+
+#[derive(Deserialize)]
+struct Rant {
+    price_cents: u64,
+}
+
+#[derive(Deserialize, Clone)]
+struct User {
+    id: String,
+    username: String,
+    #[serde(rename = "image.1")]
+    image: Option<String>,
+    #[serde(default)]
+    badges: Vec<String>,
+}
+
+fn handle_event(server: &Addr<ChatServer>, event: Event) {
+    match event.kind.as_str() {
+        // `init` carries recent history; like YouTube's first page, only Rants from it are
+        // shown, and ones already stored are dropped as duplicates
+        "init" | "messages" => {
+            let backlog = event.kind == "init";
+            for chat_message in event_to_messages(event.data) {
+                if !backlog || chat_message.amount > 0.0 {
+                    server.do_send(web::ChatMessage { chat_message });
+                }
+            }
+        }
+        "delete_messages" => {
+            for id in event.data.message_ids {
+                server.do_send(web::RemoveMessage {
+                    id: stable_id(PLATFORM, &id),
+                });
+            }
+        }
+        other => debug!(kind = other, "Ignoring Rumble chat event"),
+    }
+}
+
+/// Map the messages of an `init` or `messages` event, looking authors up in its users
+fn event_to_messages(data: EventData) -> Vec<Message> {
+    let users: HashMap<&str, &User> = data.users.iter().map(|u| (u.id.as_str(), u)).collect();
+
+    data.messages
+        .into_iter()
+        .map(|entry| {
+            let user = users.get(entry.user_id.as_str());
+            let has_badge = |badge: &str| user.is_some_and(|u| u.badges.iter().any(|b| b == badge));
+
+            let mut message = Message {
+                id: stable_id(PLATFORM, &entry.id),
+                platform: PLATFORM.to_string(),
+                message: entry.text,
+                username: user.map_or_else(|| "Unknown".to_string(), |u| u.username.clone()),
+                is_staff: has_badge("admin"),
+                is_mod: has_badge("moderator"),
+                is_verified: has_badge("verified"),
+                is_sub: has_badge("premium")
+                    || has_badge("recurring_subscription")
+                    || has_badge("locals_supporter"),
+                ..Default::default()
+            };
+            if let Some(image) = user.and_then(|u| u.image.clone()) {
+                message.avatar = image;
+            }
+            if let Some(time) = entry
+                .time
+                .and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok())
+            {
+                message.sent_at = time.timestamp_millis();
+            }
+            // Rants are priced in USD; their levels ($1, $2, $5, ... $100) fall on the
+            // same paid tiers as Super Chats of that amount
+            if let Some(rant) = entry.rant {
+                message.amount = rant.price_cents as f64 / 100.0;
+                message.currency = "USD".to_string();
+            }
+            message
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGES_EVENT: &str = r##"{
+        "request_id": "abc",
+        "type": "messages",
+        "data": {
+            "messages": [
+                {
+                    "id": "1346698286432321234",
+                    "time": "2024-02-05T13:29:25+00:00",
+                    "user_id": "88",
+                    "text": "great stream",
+                    "blocks": [{ "type": "text.1", "data": { "text": "great stream" } }],
+                    "rant": { "price_cents": 1000, "duration": 600, "expires_on": "2024-02-05T13:39:25+00:00" }
+                },
+                {
+                    "id": "1346698286432321235",
+                    "time": "2024-02-05T13:29:26+00:00",
+                    "user_id": "99",
+                    "text": "hello"
+                }
+            ],
+            "users": [
+                {
+                    "id": "88",
+                    "username": "RantUser",
+                    "link": "/user/RantUser",
+                    "is_follower": true,
+                    "image.1": "https://ak2.rmbl.ws/z0/avatar.jpeg",
+                    "color": "#e5b93f",
+                    "badges": ["premium", "verified"]
+                },
+                { "id": "99", "username": "Chatter", "link": "/user/Chatter", "is_follower": false }
+            ],
+            "channels": [[]]
+        }
+    }"##;
+
+    #[test]
+    fn test_messages_event() {
+        let event: Event = serde_json::from_str(MESSAGES_EVENT).unwrap();
+        assert_eq!(event.kind, "messages");
+        let messages = event_to_messages(event.data);
+        assert_eq!(messages.len(), 2);
+
+        let rant = &messages[0];
+        assert_eq!(rant.platform, "rumble");
+        assert_eq!(rant.id, stable_id("rumble", "1346698286432321234"));
+        assert_eq!(rant.username, "RantUser");
+        assert_eq!(rant.avatar, "https://ak2.rmbl.ws/z0/avatar.jpeg");
+        assert_eq!(rant.message, "great stream");
+        assert_eq!(rant.amount, 10.0);
+        assert_eq!(rant.currency, "USD");
+        assert_eq!(rant.sent_at, 1707139765000);
+        assert!(rant.is_sub && rant.is_verified && !rant.is_mod);
+
+        let chat = &messages[1];
+        assert_eq!(chat.username, "Chatter");
+        assert_eq!(chat.amount, 0.0);
+        assert!(!chat.is_premium());
+    }
+
+    #[test]
+    fn test_rant_levels_match_paid_tiers() {
+        for (cents, tier) in [
+            (100, 1),
+            (200, 2),
+            (500, 5),
+            (1000, 10),
+            (2000, 20),
+            (5000, 50),
+            (10000, 100),
+            (50000, 100),
+        ] {
+            let message = Message {
+                amount: cents as f64 / 100.0,
+                ..Default::default()
+            };
+            assert_eq!(message.get_paid_tier(), tier, "{} cents", cents);
+        }
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(parser
+            .push(b": keepalive\n\nevent: message\ndata: {\"a\":")
+            .is_empty());
+        assert_eq!(
+            parser.push(b"1}\r\n\r\ndata: x\ndata: y\n"),
+            vec!["{\"a\":1}"]
+        );
+        assert_eq!(parser.push(b"\n"), vec!["x\ny"]);
+    }
+}
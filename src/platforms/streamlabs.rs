@@ -0,0 +1,129 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::message::Message;
+
+const PLATFORM: &str = "streamlabs";
+
+/// A socket API event, e.g. `{"type": "donation", "message": [donation, ...]}`
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    message: Vec<Donation>,
+}
+
+#[derive(Deserialize)]
+struct Donation {
+    #[serde(rename = "_id", default)]
+    id: Option<String>,
+    name: String,
+    /// Streamlabs sends amounts both as strings and as numbers
+    amount: Value,
+    currency: String,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+/// Map a Streamlabs donation to a paid message. Accepts either a socket API
+/// `donation` event (the first donation is used) or a bare donation object.
+pub fn parse(body: &str) -> Result<Message> {
+    let value: Value = serde_json::from_str(body)?;
+    let donation: Donation = if value.get("message").is_some_and(Value::is_array) {
+        let event: Event = serde_json::from_value(value)?;
+        if event.kind != "donation" {
+            bail!("unsupported Streamlabs event type {:?}", event.kind);
+        }
+        event
+            .message
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Streamlabs donation event is empty"))?
+    } else {
+        serde_json::from_value(value)?
+    };
+
+    let amount = match &donation.amount {
+        Value::Number(amount) => amount.as_f64(),
+        Value::String(amount) => amount.trim().parse().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("invalid Streamlabs amount {}", donation.amount))?;
+
+    // Streamlabs ids are 32 hex digits, which parse as a UUID
+    let id = donation.id.and_then(|id| Uuid::parse_str(&id).ok());
+
+    Ok(Message {
+        id: id.unwrap_or_else(Uuid::new_v4),
+        platform: PLATFORM.to_string(),
+        username: donation.name,
+        message: donation.message.unwrap_or_default(),
+        amount,
+        currency: donation.currency.to_uppercase(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DONATION_EVENT: &str = r#"{
+        "type": "donation",
+        "message": [{
+            "id": 96164121,
+            "name": "Jo_Example",
+            "amount": "13.37",
+            "formatted_amount": "$13.37",
+            "formattedAmount": "$13.37",
+            "message": "Keep it up",
+            "currency": "USD",
+            "emotes": null,
+            "iconClassName": "fab fa-paypal",
+            "to": { "name": "streamer" },
+            "from": "Jo_Example",
+            "from_user_id": null,
+            "_id": "96bbc3d5ba5fa1bd3f8e3e6bd2fa7b9c"
+        }],
+        "for": "streamlabs",
+        "event_id": "evt_0123456789"
+    }"#;
+
+    #[test]
+    fn test_donation_event() {
+        let msg = parse(DONATION_EVENT).unwrap();
+        assert_eq!(
+            msg.id.simple().to_string(),
+            "96bbc3d5ba5fa1bd3f8e3e6bd2fa7b9c"
+        );
+        assert_eq!(msg.platform, "streamlabs");
+        assert_eq!(msg.username, "Jo_Example");
+        assert_eq!(msg.message, "Keep it up");
+        assert_eq!(msg.amount, 13.37);
+        assert_eq!(msg.currency, "USD");
+    }
+
+    #[test]
+    fn test_bare_donation() {
+        let body = r#"{
+            "donation_id": 101,
+            "name": "Jo_Example",
+            "amount": 5,
+            "currency": "eur",
+            "message": null
+        }"#;
+        let msg = parse(body).unwrap();
+        assert_eq!(msg.amount, 5.0);
+        assert_eq!(msg.currency, "EUR");
+        assert_eq!(msg.message, "");
+    }
+
+    #[test]
+    fn test_rejects_other_events() {
+        let body = r#"{ "type": "follow", "message": [] }"#;
+        assert!(parse(body).is_err());
+        assert!(parse(r#"{ "name": "x", "amount": "lots", "currency": "USD" }"#).is_err());
+    }
+}
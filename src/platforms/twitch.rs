@@ -0,0 +1,472 @@
+use actix::Addr;
+use actix_web::rt;
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::websocket::{self, Incoming};
+use crate::message::{Emoji, Message};
+use crate::web::{self, ChatServer};
+
+const EVENTSUB_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+const SUBSCRIPTIONS_URL: &str = "https://api.twitch.tv/helix/eventsub/subscriptions";
+const EMOTE_URL: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+const PLATFORM: &str = "twitch";
+
+/// Twitch pays the streamer one cent per bit
+pub const BITS_PER_USD: f64 = 100.0;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Extra time allowed past Twitch's keepalive timeout before the socket is considered dead
+const KEEPALIVE_GRACE: Duration = Duration::from_secs(5);
+
+/// Credentials for EventSub, read from the environment
+#[derive(Debug, Clone)]
+pub struct TwitchConfig {
+    pub client_id: String,
+    /// User access token with `user:read:chat`, `bits:read` and `channel:read:subscriptions`
+    pub access_token: String,
+    pub broadcaster_id: String,
+    /// Account reading chat (defaults to the broadcaster)
+    pub user_id: String,
+}
+
+impl TwitchConfig {
+    /// `TWITCH_CLIENT_ID`, `TWITCH_ACCESS_TOKEN` and `TWITCH_BROADCASTER_ID` are required;
+    /// `TWITCH_USER_ID` is optional
+    pub fn from_env() -> Option<Self> {
+        let var = |key| dotenvy::var(key).ok().filter(|v| !v.trim().is_empty());
+        let broadcaster_id = var("TWITCH_BROADCASTER_ID")?;
+        Some(Self {
+            client_id: var("TWITCH_CLIENT_ID")?,
+            access_token: var("TWITCH_ACCESS_TOKEN")?,
+            user_id: var("TWITCH_USER_ID").unwrap_or_else(|| broadcaster_id.clone()),
+            broadcaster_id,
+        })
+    }
+}
+
+/// Start the EventSub client if Twitch credentials are configured
+pub fn start(server: Addr<ChatServer>) {
+    match TwitchConfig::from_env() {
+        Some(config) => {
+            rt::spawn(run(config, server));
+        }
+        None => debug!("Twitch credentials not set; EventSub ingest disabled"),
+    }
+}
+
+/// Keep a session open, reconnecting with exponential backoff when it drops
+async fn run(config: TwitchConfig, server: Addr<ChatServer>) {
+    let http = reqwest::Client::new();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut url = EVENTSUB_URL.to_string();
+    let mut subscribe = true;
+
+    loop {
+        let mut welcomed = false;
+        match session(&config, &http, &server, &url, subscribe, &mut welcomed).await {
+            Ok(SessionEnd::Reconnect(next)) => {
+                // Twitch is moving us; subscriptions carry over to the new session
+                info!("Twitch EventSub asked us to reconnect");
+                url = next;
+                subscribe = false;
+                continue;
+            }
+            Ok(SessionEnd::Closed) => warn!("Twitch EventSub closed the connection"),
+            Err(e) => warn!(error = %e, "Twitch EventSub connection failed"),
+        }
+
+        if welcomed {
+            backoff = INITIAL_BACKOFF;
+        }
+        url = EVENTSUB_URL.to_string();
+        subscribe = true;
+        info!("Reconnecting to Twitch EventSub in {:?}", backoff);
+        rt::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+enum SessionEnd {
+    Reconnect(String),
+    Closed,
+}
+
+async fn session(
+    config: &TwitchConfig,
+    http: &reqwest::Client,
+    server: &Addr<ChatServer>,
+    url: &str,
+    subscribe: bool,
+    welcomed: &mut bool,
+) -> Result<SessionEnd> {
+    let mut conn = websocket::connect(url).await?;
+    // Twitch sends the welcome within 10 seconds
+    let mut keepalive = Duration::from_secs(10);
+
+    loop {
+        let text =
+            match rt::time::timeout(keepalive + KEEPALIVE_GRACE, websocket::next_text(&mut conn))
+                .await
+            {
+                Ok(incoming) => match incoming? {
+                    Incoming::Text(text) => text,
+                    Incoming::Close(_) => return Ok(SessionEnd::Closed),
+                },
+                Err(_) => bail!("no message within the keepalive timeout"),
+            };
+
+        let envelope: Envelope = serde_json::from_str(&text)?;
+        match envelope.metadata.message_type.as_str() {
+            "session_welcome" => {
+                let session = envelope
+                    .payload
+                    .session
+                    .ok_or_else(|| anyhow!("welcome without a session"))?;
+                if let Some(secs) = session.keepalive_timeout_seconds {
+                    keepalive = Duration::from_secs(secs);
+                }
+                if subscribe {
+                    subscribe_all(config, http, &session.id).await?;
+                }
+                *welcomed = true;
+                info!("Connected to Twitch EventSub");
+            }
+            "session_keepalive" => {}
+            "session_reconnect" => {
+                let url = envelope
+                    .payload
+                    .session
+                    .and_then(|session| session.reconnect_url)
+                    .ok_or_else(|| anyhow!("reconnect without a URL"))?;
+                return Ok(SessionEnd::Reconnect(url));
+            }
+            "notification" => {
+                let (Some(subscription), Some(event)) =
+                    (envelope.payload.subscription, envelope.payload.event)
+                else {
+                    continue;
+                };
+                match event_to_message(&subscription.kind, &envelope.metadata.message_id, event) {
+                    Ok(Some(chat_message)) => server.do_send(web::ChatMessage { chat_message }),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!(error = %e, kind = %subscription.kind, "Unreadable Twitch event")
+                    }
+                }
+            }
+            "revocation" => {
+                if let Some(subscription) = envelope.payload.subscription {
+                    warn!(
+                        kind = %subscription.kind,
+                        status = %subscription.status,
+                        "Twitch revoked an EventSub subscription"
+                    );
+                }
+            }
+            other => debug!(message_type = other, "Ignoring Twitch EventSub message"),
+        }
+    }
+}
+
+/// Subscribe the session to chat, cheers and subscriptions
+async fn subscribe_all(
+    config: &TwitchConfig,
+    http: &reqwest::Client,
+    session_id: &str,
+) -> Result<()> {
+    let broadcaster = json!({ "broadcaster_user_id": config.broadcaster_id });
+    let chat = json!({
+        "broadcaster_user_id": config.broadcaster_id,
+        "user_id": config.user_id,
+    });
+
+    for (kind, condition) in [
+        ("channel.chat.message", chat),
+        ("channel.cheer", broadcaster.clone()),
+        ("channel.subscribe", broadcaster),
+    ] {
+        let body = json!({
+            "type": kind,
+            "version": "1",
+            "condition": condition,
+            "transport": { "method": "websocket", "session_id": session_id },
+        });
+        let response = http
+            .post(SUBSCRIPTIONS_URL)
+            .header("Client-Id", &config.client_id)
+            .bearer_auth(&config.access_token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("subscribing to {} failed ({}): {}", kind, status, text);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    metadata: Metadata,
+    #[serde(default)]
+    payload: Payload,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    message_id: String,
+    message_type: String,
+}
+
+#[derive(Deserialize, Default)]
+struct Payload {
+    session: Option<Session>,
+    subscription: Option<Subscription>,
+    event: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    id: String,
+    keepalive_timeout_seconds: Option<u64>,
+    reconnect_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Subscription {
+    #[serde(rename = "type")]
+    kind: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ChatMessageEvent {
+    message_id: String,
+    chatter_user_name: String,
+    message: ChatText,
+    #[serde(default)]
+    badges: Vec<Badge>,
+    cheer: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ChatText {
+    text: String,
+    #[serde(default)]
+    fragments: Vec<Fragment>,
+}
+
+#[derive(Deserialize)]
+struct Fragment {
+    text: String,
+    emote: Option<Emote>,
+}
+
+#[derive(Deserialize)]
+struct Emote {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Badge {
+    set_id: String,
+}
+
+#[derive(Deserialize)]
+struct CheerEvent {
+    is_anonymous: bool,
+    user_name: Option<String>,
+    message: String,
+    bits: u64,
+}
+
+#[derive(Deserialize)]
+struct SubscribeEvent {
+    user_name: String,
+    tier: String,
+    is_gift: bool,
+}
+
+/// Twitch ids are UUIDs; anything else gets a fresh one
+fn message_id(id: &str) -> Uuid {
+    Uuid::parse_str(id).unwrap_or_else(|_| Uuid::new_v4())
+}
+
+/// Map an EventSub notification to a chat message, or None for events that aren't shown
+fn event_to_message(kind: &str, notification_id: &str, event: Value) -> Result<Option<Message>> {
+    let message = match kind {
+        "channel.chat.message" => {
+            let event: ChatMessageEvent = serde_json::from_value(event)?;
+            // Cheers also arrive as channel.cheer, which carries the bits
+            if event.cheer.is_some() {
+                return Ok(None);
+            }
+            let has_badge = |set: &str| event.badges.iter().any(|b| b.set_id == set);
+            Message {
+                id: message_id(&event.message_id),
+                platform: PLATFORM.to_string(),
+                emojis: event
+                    .message
+                    .fragments
+                    .iter()
+                    .filter_map(|fragment| {
+                        let emote = fragment.emote.as_ref()?;
+                        Some(Emoji::new(
+                            fragment.text.clone(),
+                            format!("{}/{}/default/dark/1.0", EMOTE_URL, emote.id),
+                            fragment.text.clone(),
+                        ))
+                    })
+                    .collect(),
+                username: event.chatter_user_name,
+                is_owner: has_badge("broadcaster"),
+                is_mod: has_badge("moderator"),
+                is_staff: has_badge("staff"),
+                is_verified: has_badge("partner"),
+                is_sub: has_badge("subscriber") || has_badge("founder"),
+                message: event.message.text,
+                ..Default::default()
+            }
+        }
+        "channel.cheer" => {
+            let event: CheerEvent = serde_json::from_value(event)?;
+            Message {
+                id: message_id(notification_id),
+                platform: PLATFORM.to_string(),
+                username: match event.user_name {
+                    Some(name) if !event.is_anonymous => name,
+                    _ => "Anonymous".to_string(),
+                },
+                message: event.message,
+                amount: event.bits as f64 / BITS_PER_USD,
+                currency: "USD".to_string(),
+                ..Default::default()
+            }
+        }
+        "channel.subscribe" => {
+            let event: SubscribeEvent = serde_json::from_value(event)?;
+            let tier = event.tier.trim_end_matches("000");
+            Message {
+                id: message_id(notification_id),
+                platform: PLATFORM.to_string(),
+                username: event.user_name,
+                message: if event.is_gift {
+                    format!("Received a gifted Tier {} sub", tier)
+                } else {
+                    format!("Subscribed at Tier {}", tier)
+                },
+                is_sub: true,
+                ..Default::default()
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTIFICATION_ID: &str = "befa7b53-d79d-478f-86b9-120f112b044e";
+
+    #[test]
+    fn test_chat_message() {
+        let event = json!({
+            "message_id": "cc106a89-1814-919d-454c-f4f2f970aae7",
+            "chatter_user_name": "Cool_User",
+            "message": {
+                "text": "Hi chat Kappa",
+                "fragments": [
+                    { "type": "text", "text": "Hi chat ", "emote": null },
+                    { "type": "emote", "text": "Kappa", "emote": { "id": "25" } }
+                ]
+            },
+            "badges": [{ "set_id": "moderator", "id": "1", "info": "" }],
+            "cheer": null
+        });
+        let msg = event_to_message("channel.chat.message", NOTIFICATION_ID, event)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.id.to_string(), "cc106a89-1814-919d-454c-f4f2f970aae7");
+        assert_eq!(msg.platform, "twitch");
+        assert_eq!(msg.username, "Cool_User");
+        assert_eq!(msg.message, "Hi chat Kappa");
+        assert_eq!(
+            msg.emojis,
+            vec![Emoji::new(
+                "Kappa",
+                "https://static-cdn.jtvnw.net/emoticons/v2/25/default/dark/1.0",
+                "Kappa"
+            )]
+        );
+        assert!(msg.is_mod && !msg.is_sub && !msg.is_owner);
+        assert_eq!(msg.amount, 0.0);
+    }
+
+    #[test]
+    fn test_chat_message_with_cheer_is_skipped() {
+        let event = json!({
+            "message_id": "cc106a89-1814-919d-454c-f4f2f970aae7",
+            "chatter_user_name": "Cool_User",
+            "message": { "text": "Cheer100 hi", "fragments": [] },
+            "badges": [],
+            "cheer": { "bits": 100 }
+        });
+        assert!(
+            event_to_message("channel.chat.message", NOTIFICATION_ID, event)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_cheer() {
+        let event = json!({
+            "is_anonymous": false,
+            "user_name": "Cool_User",
+            "message": "pogchamp",
+            "bits": 1000
+        });
+        let msg = event_to_message("channel.cheer", NOTIFICATION_ID, event)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.id.to_string(), NOTIFICATION_ID);
+        assert_eq!(msg.amount, 10.0);
+        assert_eq!(msg.currency, "USD");
+        assert_eq!(msg.username, "Cool_User");
+
+        let anonymous = json!({
+            "is_anonymous": true,
+            "user_name": null,
+            "message": "",
+            "bits": 100
+        });
+        let msg = event_to_message("channel.cheer", NOTIFICATION_ID, anonymous)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.username, "Anonymous");
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let event = json!({ "user_name": "Cool_User", "tier": "2000", "is_gift": true });
+        let msg = event_to_message("channel.subscribe", NOTIFICATION_ID, event)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.message, "Received a gifted Tier 2 sub");
+        assert!(msg.is_sub);
+        assert_eq!(msg.amount, 0.0);
+    }
+}
@@ -0,0 +1,255 @@
+use actix_codec::Framed;
+use actix_http::ws::{CloseReason, Codec, Frame, Item, Message};
+use actix_web::web::BytesMut;
+use anyhow::{anyhow, bail, Result};
+use base64::Engine;
+use futures::{SinkExt, StreamExt};
+use reqwest::Url;
+use rustls_platform_verifier::ConfigVerifierExt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{pki_types::ServerName, ClientConfig};
+use tokio_rustls::TlsConnector;
+
+/// Largest message accepted from a platform
+const MAX_FRAME_SIZE: usize = 1 << 20;
+/// Largest handshake response head accepted
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// A client WebSocket connection, framed with actix-http's codec
+pub type Connection = Framed<Box<dyn Io>, Codec>;
+
+/// Open a `ws://` or `wss://` connection
+pub async fn connect(url: &str) -> Result<Connection> {
+    let url = Url::parse(url)?;
+    let tls = match url.scheme() {
+        "wss" => true,
+        "ws" => false,
+        scheme => bail!("unsupported WebSocket scheme {}", scheme),
+    };
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("WebSocket URL has no host"))?
+        .to_string();
+    let port = url
+        .port_or_known_default()
+        .unwrap_or(if tls { 443 } else { 80 });
+
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    let mut io: Box<dyn Io> = if tls {
+        let connector = TlsConnector::from(Arc::new(ClientConfig::with_platform_verifier()?));
+        let name = ServerName::try_from(host.clone())?;
+        Box::new(connector.connect(name, tcp).await?)
+    } else {
+        Box::new(tcp)
+    };
+
+    let key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host_header}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    );
+    io.write_all(request.as_bytes()).await?;
+
+    // The server may send its first frames right behind the response head
+    let mut buf = BytesMut::with_capacity(1024);
+    let head_len = loop {
+        if io.read_buf(&mut buf).await? == 0 {
+            bail!("connection closed during WebSocket handshake");
+        }
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            bail!("WebSocket handshake response too large");
+        }
+    };
+    let head = buf.split_to(head_len);
+    let status_line = String::from_utf8_lossy(&head);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("101") {
+        bail!("WebSocket handshake rejected: {}", status_line);
+    }
+
+    let codec = Codec::new().client_mode().max_size(MAX_FRAME_SIZE);
+    let io: Box<dyn Io> = Box::new(Prefixed { prefix: buf, io });
+    Ok(Framed::new(io, codec))
+}
+
+/// Replays bytes read past the handshake before reading from the stream.
+/// `FramedParts::with_read_buf` would not decode them until more data arrives.
+struct Prefixed {
+    prefix: BytesMut,
+    io: Box<dyn Io>,
+}
+
+impl AsyncRead for Prefixed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.io).poll_read(cx, buf);
+        }
+        let len = self.prefix.len().min(buf.remaining());
+        buf.put_slice(&self.prefix.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Prefixed {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// What the server sent next
+pub enum Incoming {
+    Text(String),
+    /// Close frame, with the server's code and description if it sent one,
+    /// or the stream simply ended
+    Close(Option<CloseReason>),
+}
+
+/// Wait for the next text message, answering pings along the way
+pub async fn next_text(conn: &mut Connection) -> Result<Incoming> {
+    let mut fragments: Option<BytesMut> = None;
+
+    while let Some(frame) = conn.next().await {
+        let text = match frame? {
+            Frame::Text(text) => text,
+            Frame::Ping(payload) => {
+                conn.send(Message::Pong(payload)).await?;
+                continue;
+            }
+            Frame::Close(reason) => return Ok(Incoming::Close(reason)),
+            Frame::Binary(_) | Frame::Pong(_) => continue,
+            Frame::Continuation(item) => match item {
+                Item::FirstText(part) => {
+                    fragments = Some(BytesMut::from(&part[..]));
+                    continue;
+                }
+                Item::FirstBinary(_) => continue,
+                Item::Continue(part) => {
+                    if let Some(fragments) = fragments.as_mut() {
+                        fragments.extend_from_slice(&part);
+                    }
+                    continue;
+                }
+                Item::Last(part) => match fragments.take() {
+                    Some(mut fragments) => {
+                        fragments.extend_from_slice(&part);
+                        fragments.freeze()
+                    }
+                    // End of a binary message
+                    None => continue,
+                },
+            },
+        };
+        return Ok(Incoming::Text(String::from_utf8(Vec::from(&text[..]))?));
+    }
+
+    Ok(Incoming::Close(None))
+}
+
+/// Send a text message
+pub async fn send_text(conn: &mut Connection, text: String) -> Result<()> {
+    conn.send(Message::Text(text.into())).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[actix::test]
+    async fn test_connect_and_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = actix::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8];
+                socket.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            assert!(request.starts_with("GET /ws?a=1 HTTP/1.1\r\n"));
+            assert!(request.contains("Upgrade: websocket\r\n"));
+
+            // A frame right behind the response head, then a ping
+            let mut response = b"HTTP/1.1 101 Switching Protocols\r\n\r\n".to_vec();
+            response.extend_from_slice(&[0x81, 5]);
+            response.extend_from_slice(b"hello");
+            response.extend_from_slice(&[0x89, 0]);
+            socket.write_all(&response).await.unwrap();
+
+            // The client answers with a masked pong
+            let mut pong = [0u8; 6];
+            socket.read_exact(&mut pong).await.unwrap();
+            assert_eq!(&pong[..2], &[0x8A, 0x80]);
+            socket.write_all(&[0x88, 2, 0x0F, 0xA4]).await.unwrap();
+        });
+
+        let mut conn = connect(&format!("ws://127.0.0.1:{}/ws?a=1", port))
+            .await
+            .unwrap();
+        match next_text(&mut conn).await.unwrap() {
+            Incoming::Text(text) => assert_eq!(text, "hello"),
+            Incoming::Close(_) => panic!("expected text"),
+        }
+        match next_text(&mut conn).await.unwrap() {
+            Incoming::Close(Some(reason)) => assert_eq!(u16::from(reason.code), 4004),
+            _ => panic!("expected a close with a code"),
+        }
+        server.await.unwrap();
+    }
+
+    #[actix::test]
+    async fn test_rejected_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        actix::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 401 Unauthorized\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let err = connect(&format!("ws://127.0.0.1:{}/", port))
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("401"), "{}", err);
+    }
+}
@@ -0,0 +1,459 @@
+use actix::Addr;
+use actix_web::rt;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use super::stable_id;
+use crate::message::Message;
+use crate::web::{self, ChatServer};
+
+const VIDEOS_URL: &str = "https://www.googleapis.com/youtube/v3/videos";
+const MESSAGES_URL: &str = "https://www.googleapis.com/youtube/v3/liveChat/messages";
+const PLATFORM: &str = "youtube";
+
+/// Used when a response doesn't say how long to wait
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Quota resets daily, so retrying sooner only burns what is left
+const QUOTA_BACKOFF: Duration = Duration::from_secs(5 * 60);
+const MAX_QUOTA_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// API key and stream, read from the environment
+#[derive(Debug, Clone)]
+pub struct YouTubeConfig {
+    pub api_key: String,
+    pub video_id: String,
+}
+
+impl YouTubeConfig {
+    /// `YOUTUBE_API_KEY` and `YOUTUBE_VIDEO_ID` are both required
+    pub fn from_env() -> Option<Self> {
+        let var = |key| dotenvy::var(key).ok().filter(|v| !v.trim().is_empty());
+        Some(Self {
+            api_key: var("YOUTUBE_API_KEY")?,
+            video_id: var("YOUTUBE_VIDEO_ID")?,
+        })
+    }
+}
+
+/// Start polling if an API key and video are configured
+pub fn start(server: Addr<ChatServer>) {
+    match YouTubeConfig::from_env() {
+        Some(config) => {
+            rt::spawn(run(config, server));
+        }
+        None => debug!("YouTube API key or video not set; YouTube polling disabled"),
+    }
+}
+
+/// Why a request failed, as far as retrying is concerned
+#[derive(Debug, PartialEq)]
+enum ApiError {
+    /// Daily quota or rate limit hit
+    Quota,
+    /// The chat is gone or never existed; polling again can't help
+    ChatEnded(String),
+    Other(String),
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Other(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        ApiError::Other(e.to_string())
+    }
+}
+
+/// Poll the video's live chat until it ends, honouring `pollingIntervalMillis`
+async fn run(config: YouTubeConfig, server: Addr<ChatServer>) {
+    let http = reqwest::Client::new();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut quota_backoff = QUOTA_BACKOFF;
+    let mut live_chat_id: Option<String> = None;
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let result = match &live_chat_id {
+            Some(chat_id) => poll(&config, &http, chat_id, page_token.as_deref()).await,
+            None => match find_live_chat(&config, &http).await {
+                Ok(chat_id) => {
+                    info!(video = %config.video_id, "Polling YouTube live chat");
+                    live_chat_id = Some(chat_id);
+                    continue;
+                }
+                Err(e) => Err(e),
+            },
+        };
+
+        let wait = match result {
+            Ok(page) => {
+                // The first page is backlog from before we started; only paid messages in it
+                // are worth showing, and those are dropped as duplicates if already stored
+                let backlog = page_token.is_none();
+                for item in page.items {
+                    match item_to_message(item) {
+                        Ok(Some(chat_message)) if !backlog || chat_message.amount > 0.0 => {
+                            server.do_send(web::ChatMessage { chat_message })
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!(error = %e, "Unreadable YouTube chat message"),
+                    }
+                }
+                page_token = page.next_page_token;
+                backoff = INITIAL_BACKOFF;
+                quota_backoff = QUOTA_BACKOFF;
+                page.polling_interval_millis
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_POLL_INTERVAL)
+            }
+            Err(ApiError::Quota) => {
+                error!(
+                    "YouTube API quota exceeded; retrying in {:?}",
+                    quota_backoff
+                );
+                let wait = quota_backoff;
+                quota_backoff = (quota_backoff * 2).min(MAX_QUOTA_BACKOFF);
+                wait
+            }
+            Err(ApiError::ChatEnded(reason)) => {
+                info!(video = %config.video_id, reason, "YouTube live chat ended; stopped polling");
+                return;
+            }
+            Err(ApiError::Other(e)) => {
+                warn!(error = %e, "YouTube live chat request failed; retrying in {:?}", backoff);
+                let wait = backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                wait
+            }
+        };
+        rt::time::sleep(wait).await;
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessagesPage {
+    next_page_token: Option<String>,
+    polling_interval_millis: Option<u64>,
+    #[serde(default)]
+    items: Vec<Value>,
+}
+
+async fn get(http: &reqwest::Client, url: &str, query: &[(&str, &str)]) -> Result<Value, ApiError> {
+    let url = reqwest::Url::parse_with_params(url, query).map_err(anyhow::Error::from)?;
+    let response = http.get(url).send().await?;
+    let status = response.status();
+    let body: Value = serde_json::from_slice(&response.bytes().await?)
+        .map_err(|e| anyhow!("invalid response ({}): {}", status, e))?;
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(classify_error(&body))
+    }
+}
+
+/// Read the `reason` of a Google API error body
+fn classify_error(body: &Value) -> ApiError {
+    let error = &body["error"];
+    let reason = error["errors"][0]["reason"].as_str().unwrap_or_default();
+    match reason {
+        "quotaExceeded" | "rateLimitExceeded" | "dailyLimitExceeded" => ApiError::Quota,
+        "liveChatEnded" | "liveChatDisabled" | "liveChatNotFound" => {
+            ApiError::ChatEnded(reason.to_string())
+        }
+        _ => ApiError::Other(format!(
+            "{} {}",
+            error["code"],
+            error["message"].as_str().unwrap_or(reason)
+        )),
+    }
+}
+
+/// Look up the video's active live chat
+async fn find_live_chat(
+    config: &YouTubeConfig,
+    http: &reqwest::Client,
+) -> Result<String, ApiError> {
+    let body = get(
+        http,
+        VIDEOS_URL,
+        &[
+            ("part", "liveStreamingDetails"),
+            ("id", &config.video_id),
+            ("key", &config.api_key),
+        ],
+    )
+    .await?;
+    body["items"][0]["liveStreamingDetails"]["activeLiveChatId"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| ApiError::ChatEnded("video has no active live chat".to_string()))
+}
+
+async fn poll(
+    config: &YouTubeConfig,
+    http: &reqwest::Client,
+    live_chat_id: &str,
+    page_token: Option<&str>,
+) -> Result<MessagesPage, ApiError> {
+    let mut query = vec![
+        ("liveChatId", live_chat_id),
+        ("part", "snippet,authorDetails"),
+        ("key", config.api_key.as_str()),
+    ];
+    if let Some(page_token) = page_token {
+        query.push(("pageToken", page_token));
+    }
+    let body = get(http, MESSAGES_URL, &query).await?;
+    Ok(serde_json::from_value(body).map_err(anyhow::Error::from)?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    id: String,
+    snippet: Snippet,
+    author_details: AuthorDetails,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Snippet {
+    #[serde(rename = "type")]
+    kind: String,
+    published_at: String,
+    #[serde(default)]
+    display_message: String,
+    super_chat_details: Option<SuperChatDetails>,
+    super_sticker_details: Option<SuperStickerDetails>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SuperChatDetails {
+    amount_micros: String,
+    currency: String,
+    user_comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SuperStickerDetails {
+    amount_micros: String,
+    currency: String,
+    super_sticker_metadata: Option<SuperStickerMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SuperStickerMetadata {
+    alt_text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorDetails {
+    display_name: String,
+    profile_image_url: String,
+    #[serde(default)]
+    is_verified: bool,
+    #[serde(default)]
+    is_chat_owner: bool,
+    #[serde(default)]
+    is_chat_sponsor: bool,
+    #[serde(default)]
+    is_chat_moderator: bool,
+}
+
+fn micros(amount: &str) -> Result<f64> {
+    let micros: u64 = amount
+        .parse()
+        .map_err(|_| anyhow!("invalid amountMicros {:?}", amount))?;
+    Ok(micros as f64 / 1_000_000.0)
+}
+
+/// Map a liveChatMessage to a chat message, or None for events that aren't shown
+fn item_to_message(item: Value) -> Result<Option<Message>> {
+    let item: Item = serde_json::from_value(item)?;
+    let snippet = item.snippet;
+
+    let (message, amount, currency) = match snippet.kind.as_str() {
+        "textMessageEvent" => (snippet.display_message, 0.0, None),
+        "superChatEvent" => {
+            let details = snippet
+                .super_chat_details
+                .ok_or_else(|| anyhow!("superChatEvent without superChatDetails"))?;
+            (
+                details.user_comment.unwrap_or_default(),
+                micros(&details.amount_micros)?,
+                Some(details.currency),
+            )
+        }
+        "superStickerEvent" => {
+            let details = snippet
+                .super_sticker_details
+                .ok_or_else(|| anyhow!("superStickerEvent without superStickerDetails"))?;
+            (
+                details
+                    .super_sticker_metadata
+                    .map(|sticker| sticker.alt_text)
+                    .unwrap_or_default(),
+                micros(&details.amount_micros)?,
+                Some(details.currency),
+            )
+        }
+        _ => return Ok(None),
+    };
+
+    let author = item.author_details;
+    let mut chat_message = Message {
+        // Re-polled messages keep their id
+        id: stable_id(PLATFORM, &item.id),
+        platform: PLATFORM.to_string(),
+        message,
+        amount,
+        username: author.display_name,
+        avatar: author.profile_image_url,
+        is_verified: author.is_verified,
+        is_owner: author.is_chat_owner,
+        is_sub: author.is_chat_sponsor,
+        is_mod: author.is_chat_moderator,
+        ..Default::default()
+    };
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(&snippet.published_at) {
+        chat_message.sent_at = time.timestamp_millis();
+    }
+    if let Some(currency) = currency {
+        chat_message.currency = currency;
+    }
+    Ok(Some(chat_message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn author() -> Value {
+        json!({
+            "channelId": "UCxxxxxxxxxxxxxxxxxxxxxx",
+            "channelUrl": "http://www.youtube.com/channel/UCxxxxxxxxxxxxxxxxxxxxxx",
+            "displayName": "Cool User",
+            "profileImageUrl": "https://yt3.ggpht.com/avatar.jpg",
+            "isVerified": false,
+            "isChatOwner": false,
+            "isChatSponsor": true,
+            "isChatModerator": false
+        })
+    }
+
+    #[test]
+    fn test_text_message() {
+        let item = json!({
+            "kind": "youtube#liveChatMessage",
+            "id": "LCC.abc123",
+            "snippet": {
+                "type": "textMessageEvent",
+                "liveChatId": "chat",
+                "publishedAt": "2024-02-05T13:29:25.123+00:00",
+                "hasDisplayContent": true,
+                "displayMessage": "hello :wave:",
+                "textMessageDetails": { "messageText": "hello :wave:" }
+            },
+            "authorDetails": author()
+        });
+        let msg = item_to_message(item.clone()).unwrap().unwrap();
+        assert_eq!(msg.platform, "youtube");
+        assert_eq!(msg.message, "hello :wave:");
+        assert_eq!(msg.username, "Cool User");
+        assert_eq!(msg.avatar, "https://yt3.ggpht.com/avatar.jpg");
+        assert_eq!(msg.sent_at, 1707139765123);
+        assert_eq!(msg.amount, 0.0);
+        assert!(msg.is_sub && !msg.is_mod);
+        // Polling the same message again maps to the same id
+        assert_eq!(msg.id, item_to_message(item).unwrap().unwrap().id);
+    }
+
+    #[test]
+    fn test_super_chat_and_sticker() {
+        let item = json!({
+            "id": "LCC.superchat",
+            "snippet": {
+                "type": "superChatEvent",
+                "publishedAt": "2024-02-05T13:29:25Z",
+                "displayMessage": "$5.00 from Cool User: keep it up",
+                "superChatDetails": {
+                    "amountMicros": "5000000",
+                    "currency": "USD",
+                    "amountDisplayString": "$5.00",
+                    "userComment": "keep it up",
+                    "tier": 2
+                }
+            },
+            "authorDetails": author()
+        });
+        let msg = item_to_message(item).unwrap().unwrap();
+        assert_eq!(msg.message, "keep it up");
+        assert_eq!(msg.amount, 5.0);
+        assert_eq!(msg.currency, "USD");
+
+        let item = json!({
+            "id": "LCC.sticker",
+            "snippet": {
+                "type": "superStickerEvent",
+                "publishedAt": "2024-02-05T13:29:25Z",
+                "displayMessage": "",
+                "superStickerDetails": {
+                    "amountMicros": "2500000",
+                    "currency": "EUR",
+                    "superStickerMetadata": { "stickerId": "x", "altText": "Dancing cat", "language": "en" }
+                }
+            },
+            "authorDetails": author()
+        });
+        let msg = item_to_message(item).unwrap().unwrap();
+        assert_eq!(msg.message, "Dancing cat");
+        assert_eq!(msg.amount, 2.5);
+        assert_eq!(msg.currency, "EUR");
+    }
+
+    #[test]
+    fn test_other_events_are_skipped() {
+        let item = json!({
+            "id": "LCC.tombstone",
+            "snippet": { "type": "messageDeletedEvent", "publishedAt": "2024-02-05T13:29:25Z" },
+            "authorDetails": author()
+        });
+        assert!(item_to_message(item).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_classify_error() {
+        let error = |reason: &str| {
+            json!({
+                "error": {
+                    "code": 403,
+                    "message": "The request cannot be completed.",
+                    "errors": [{ "domain": "youtube.quota", "reason": reason }]
+                }
+            })
+        };
+        assert_eq!(classify_error(&error("quotaExceeded")), ApiError::Quota);
+        assert_eq!(
+            classify_error(&error("liveChatEnded")),
+            ApiError::ChatEnded("liveChatEnded".to_string())
+        );
+        assert_eq!(
+            classify_error(&error("forbidden")),
+            ApiError::Other("403 The request cannot be completed.".to_string())
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// Most options a poll may have, matching the dashboard's limit
+pub const MAX_OPTIONS: usize = 15;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PollOption {
+    pub text: String,
+    pub votes: u32,
+}
+
+/// A poll counted on the server so every overlay shows the same tally.
+/// Serialized as the `poll_update` payload.
+#[derive(Serialize, Debug, Clone)]
+pub struct Poll {
+    pub id: Uuid,
+    pub question: String,
+    pub options: Vec<PollOption>,
+    /// Voters may pick several options in one vote
+    pub multi_vote: bool,
+    pub total_votes: u32,
+    pub participants: u32,
+    pub started_at: i64,
+    /// Set once the poll is over; the update carrying it is the final result
+    pub ended_at: Option<i64>,
+    /// Voters who already voted, so each is counted once
+    #[serde(skip)]
+    voters: HashSet<String>,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+impl Poll {
+    /// Start a poll, trimming blank options. Needs a question and 2 to `MAX_OPTIONS` options.
+    pub fn new(question: &str, options: &[String], multi_vote: bool) -> Result<Self, String> {
+        let question = question.trim();
+        if question.is_empty() {
+            return Err("poll needs a question".to_string());
+        }
+        let options: Vec<PollOption> = options
+            .iter()
+            .map(|text| text.trim())
+            .filter(|text| !text.is_empty())
+            .map(|text| PollOption {
+                text: text.to_string(),
+                votes: 0,
+            })
+            .collect();
+        if options.len() < 2 {
+            return Err("poll needs at least two options".to_string());
+        }
+        if options.len() > MAX_OPTIONS {
+            return Err(format!("poll can have at most {} options", MAX_OPTIONS));
+        }
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            question: question.to_string(),
+            options,
+            multi_vote,
+            total_votes: 0,
+            participants: 0,
+            started_at: now_ms(),
+            ended_at: None,
+            voters: HashSet::new(),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    /// Count a vote for 1-based `choices`. Single-vote polls take the first valid choice.
+    /// Named voters are counted once; anonymous votes (e.g. from the dashboard) always count.
+    /// Returns whether anything was counted.
+    pub fn vote(&mut self, voter: Option<&str>, choices: &[usize]) -> bool {
+        if !self.is_active() || voter.is_some_and(|v| self.voters.contains(v)) {
+            return false;
+        }
+
+        let mut picked: Vec<usize> = Vec::new();
+        for &choice in choices {
+            if (1..=self.options.len()).contains(&choice) && !picked.contains(&choice) {
+                picked.push(choice);
+            }
+        }
+        if !self.multi_vote {
+            picked.truncate(1);
+        }
+        if picked.is_empty() {
+            return false;
+        }
+
+        for choice in picked {
+            self.options[choice - 1].votes += 1;
+            self.total_votes += 1;
+        }
+        self.participants += 1;
+        if let Some(voter) = voter {
+            self.voters.insert(voter.to_string());
+        }
+        true
+    }
+
+    pub fn end(&mut self) {
+        if self.is_active() {
+            self.ended_at = Some(now_ms());
+        }
+    }
+}
+
+/// The options a chat message votes for: `!vote 2`, `!2` or a bare `2`.
+/// `!vote 1 3` picks several for multi-vote polls. None if the message isn't a vote.
+pub fn parse_vote(text: &str) -> Option<Vec<usize>> {
+    let text = text.trim();
+    let args = match text.strip_prefix("!vote") {
+        Some(args) => args,
+        None => match text.strip_prefix('!') {
+            Some(args) if args.starts_with(|c: char| c.is_ascii_digit()) => args,
+            Some(_) => return None,
+            None => {
+                // Bare numbers only count on their own, so "2 cool" stays chat
+                let choice: usize = text.parse().ok()?;
+                return (choice <= MAX_OPTIONS).then(|| vec![choice]);
+            }
+        },
+    };
+
+    let choices: Vec<usize> = args
+        .split_whitespace()
+        .map_while(|arg| arg.parse().ok())
+        .collect();
+    (!choices.is_empty()).then_some(choices)
+}
+
+/// `!poll question; option; option` (or `!multipoll`) from the channel owner.
+/// Returns (question, options, multi_vote).
+pub fn parse_start_command(text: &str) -> Option<(String, Vec<String>, bool)> {
+    let text = text.trim();
+    let (args, multi_vote) = if let Some(args) = text.strip_prefix("!multipoll") {
+        (args, true)
+    } else {
+        (text.strip_prefix("!poll")?, false)
+    };
+    let mut parts = args.split(';').map(|part| part.trim().to_string());
+    let question = parts.next()?;
+    Some((question, parts.collect(), multi_vote))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn votes(poll: &Poll) -> Vec<u32> {
+        poll.options.iter().map(|o| o.votes).collect()
+    }
+
+    #[test]
+    fn test_new_validates() {
+        assert!(Poll::new(" ", &options(&["a", "b"]), false).is_err());
+        assert!(Poll::new("Q?", &options(&["a", " "]), false).is_err());
+        assert!(Poll::new("Q?", &options(&["x"; MAX_OPTIONS + 1]), false).is_err());
+
+        let poll = Poll::new(" Q? ", &options(&["a", "", " b "]), false).unwrap();
+        assert_eq!(poll.question, "Q?");
+        assert_eq!(poll.options.len(), 2);
+        assert_eq!(poll.options[1].text, "b");
+        assert!(poll.is_active());
+    }
+
+    #[test]
+    fn test_single_vote() {
+        let mut poll = Poll::new("Q?", &options(&["a", "b", "c"]), false).unwrap();
+        assert!(poll.vote(Some("youtube:alice"), &[2, 3]));
+        assert!(!poll.vote(Some("youtube:alice"), &[1]));
+        assert!(!poll.vote(Some("kick:bob"), &[0, 4]));
+        assert!(poll.vote(Some("kick:bob"), &[3]));
+        assert!(poll.vote(None, &[3]));
+        assert!(poll.vote(None, &[3]));
+        assert_eq!(votes(&poll), vec![0, 1, 3]);
+        assert_eq!(poll.total_votes, 4);
+        assert_eq!(poll.participants, 4);
+
+        poll.end();
+        assert!(!poll.is_active());
+        assert!(!poll.vote(Some("kick:carol"), &[1]));
+    }
+
+    #[test]
+    fn test_multi_vote() {
+        let mut poll = Poll::new("Q?", &options(&["a", "b", "c"]), true).unwrap();
+        assert!(poll.vote(Some("alice"), &[1, 3, 3, 9]));
+        assert!(poll.vote(Some("bob"), &[3]));
+        assert_eq!(votes(&poll), vec![1, 0, 2]);
+        assert_eq!(poll.total_votes, 3);
+        assert_eq!(poll.participants, 2);
+    }
+
+    #[test]
+    fn test_parse_vote() {
+        assert_eq!(parse_vote("!vote 2"), Some(vec![2]));
+        assert_eq!(parse_vote("!vote 1 3 hi"), Some(vec![1, 3]));
+        assert_eq!(parse_vote("!2"), Some(vec![2]));
+        assert_eq!(parse_vote(" 3 "), Some(vec![3]));
+        assert_eq!(parse_vote("!vote"), None);
+        assert_eq!(parse_vote("!poll a; b; c"), None);
+        assert_eq!(parse_vote("2 cool"), None);
+        assert_eq!(parse_vote("2024"), None);
+        assert_eq!(parse_vote("hello"), None);
+    }
+
+    #[test]
+    fn test_parse_start_command() {
+        assert_eq!(
+            parse_start_command("!poll Best map? ; Dust; Inferno;"),
+            Some((
+                "Best map?".to_string(),
+                options(&["Dust", "Inferno", ""]),
+                false
+            ))
+        );
+        assert_eq!(
+            parse_start_command("!multipoll Q; a; b").map(|(_, _, multi)| multi),
+            Some(true)
+        );
+        assert_eq!(parse_start_command("!endpoll"), None);
+        assert_eq!(parse_start_command("hello"), None);
+    }
+}
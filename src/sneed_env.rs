@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::str::FromStr;
 
 pub fn get_env() {
     let env_map = HashMap::from([
@@ -38,3 +39,52 @@ pub fn get_env() {
         dotenvy::dotenv().expect("Failed to re-read .env file");
     }
 }
+
+/// Read an optional setting from the environment, falling back to `default`
+/// when it is unset or fails to parse.
+pub fn var_or<T: FromStr>(key: &str, default: T) -> T {
+    match dotenvy::var(key) {
+        Ok(v) => v.trim().parse().unwrap_or_else(|_| {
+            tracing::warn!("Invalid value for {key}: {v:?}, using default");
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// Addresses the HTTP server listens on. `SERVER_BIND` (comma separated, e.g.
+/// `127.0.0.1:8080,0.0.0.0:8081`) takes precedence; without it the single
+/// `SERVER_IP:SERVER_PORT` is used.
+pub fn bind_addresses() -> Vec<String> {
+    let addresses = parse_bind_list(&dotenvy::var("SERVER_BIND").unwrap_or_default());
+    if !addresses.is_empty() {
+        return addresses;
+    }
+    vec![format!(
+        "{}:{}",
+        dotenvy::var("SERVER_IP").expect("SERVER_IP not defined."),
+        dotenvy::var("SERVER_PORT").expect("SERVER_PORT not defined.")
+    )]
+}
+
+fn parse_bind_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bind_list() {
+        assert_eq!(
+            parse_bind_list(" 127.0.0.1:8080, 0.0.0.0:8081 ,,[::1]:8082"),
+            ["127.0.0.1:8080", "0.0.0.0:8081", "[::1]:8082"]
+        );
+        assert!(parse_bind_list(" , ").is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// How timestamps are shown to people, e.g. `2024-03-10 01:59:59 EST`
+const DISPLAY_FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+
+/// The zone timestamps are displayed in: `DISPLAY_TZ` (an IANA name such as
+/// `America/New_York`), read once. UTC when unset or unknown.
+pub fn display_tz() -> Tz {
+    static DISPLAY_TZ: OnceLock<Tz> = OnceLock::new();
+    *DISPLAY_TZ.get_or_init(|| parse_tz(dotenvy::var("DISPLAY_TZ").ok().as_deref()))
+}
+
+fn parse_tz(name: Option<&str>) -> Tz {
+    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return Tz::UTC;
+    };
+    name.parse().unwrap_or_else(|_| {
+        warn!(
+            "Invalid value for DISPLAY_TZ: {:?}, showing times in UTC",
+            name
+        );
+        Tz::UTC
+    })
+}
+
+/// Unix milliseconds as a local time in `tz`, with its abbreviation so the offset
+/// in effect (e.g. across a DST change) is unambiguous
+pub fn format_millis(millis: i64, tz: Tz) -> String {
+    DateTime::<Utc>::from_timestamp_millis(millis)
+        .map(|time| time.with_timezone(&tz).format(DISPLAY_FORMAT).to_string())
+        .unwrap_or_default()
+}
+
+/// Unix milliseconds in the display zone
+pub fn format_display(millis: i64) -> String {
+    format_millis(millis, display_tz())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unix milliseconds of a UTC time
+    fn utc(time: &str) -> i64 {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn test_parse_tz() {
+        assert_eq!(parse_tz(None), Tz::UTC);
+        assert_eq!(parse_tz(Some("  ")), Tz::UTC);
+        assert_eq!(parse_tz(Some("Europe/Berlin")), Tz::Europe__Berlin);
+        assert_eq!(parse_tz(Some(" Asia/Tokyo ")), Tz::Asia__Tokyo);
+        assert_eq!(parse_tz(Some("Mars/Olympus_Mons")), Tz::UTC);
+    }
+
+    #[test]
+    fn test_format_millis() {
+        let t = utc("2024-07-01T12:00:00Z");
+        assert_eq!(format_millis(t, Tz::UTC), "2024-07-01 12:00:00 UTC");
+        assert_eq!(
+            format_millis(t, Tz::Asia__Kolkata),
+            "2024-07-01 17:30:00 IST"
+        );
+        assert_eq!(format_millis(0, Tz::UTC), "1970-01-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn test_format_millis_across_dst() {
+        let new_york = Tz::America__New_York;
+        // Spring forward: 02:00 EST becomes 03:00 EDT
+        assert_eq!(
+            format_millis(utc("2024-03-10T06:59:59Z"), new_york),
+            "2024-03-10 01:59:59 EST"
+        );
+        assert_eq!(
+            format_millis(utc("2024-03-10T07:00:00Z"), new_york),
+            "2024-03-10 03:00:00 EDT"
+        );
+
+        let london = Tz::Europe__London;
+        // Fall back: 01:00-02:00 happens twice, told apart by the abbreviation
+        assert_eq!(
+            format_millis(utc("2024-10-27T00:30:00Z"), london),
+            "2024-10-27 01:30:00 BST"
+        );
+        assert_eq!(
+            format_millis(utc("2024-10-27T01:30:00Z"), london),
+            "2024-10-27 01:30:00 GMT"
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use actix_web::web::Bytes;
+use anyhow::{bail, Result};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::sneed_env;
+
+/// Platform CDNs avatars are fetched from, matched as the host or a subdomain of it
+const DEFAULT_HOSTS: &[&str] = &[
+    "ggpht.com",
+    "googleusercontent.com",
+    "ytimg.com",
+    "jtvnw.net",
+    "discordapp.com",
+    "discordapp.net",
+    "rmbl.ws",
+    "kick.com",
+];
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 5;
+
+/// The bytes of `message::BLANK_AVATAR`, served when a fetch fails
+pub const BLANK_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x21, 0xf9, 0x04,
+    0x01, 0x0a, 0x00, 0x01, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02,
+    0x02, 0x4c, 0x01, 0x00, 0x3b,
+];
+
+/// Which avatars are proxied and how they are cached, read from the environment
+#[derive(Debug, Clone)]
+pub struct AvatarConfig {
+    pub hosts: Vec<String>,
+    pub max_bytes: usize,
+    pub ttl: Duration,
+    /// Images kept before the least recently used is evicted
+    pub capacity: usize,
+}
+
+impl AvatarConfig {
+    /// None when `AVATAR_PROXY=false`. `AVATAR_PROXY_HOSTS` (comma separated) replaces the
+    /// default CDN list; `AVATAR_MAX_KB` (default 512), `AVATAR_CACHE_TTL_SECS` (default
+    /// 3600) and `AVATAR_CACHE_SIZE` (default 1000) bound the cache.
+    pub fn from_env() -> Option<Self> {
+        if !sneed_env::var_or("AVATAR_PROXY", true) {
+            return None;
+        }
+        let hosts = match dotenvy::var("AVATAR_PROXY_HOSTS")
+            .ok()
+            .filter(|list| !list.trim().is_empty())
+        {
+            Some(list) => list
+                .split(',')
+                .map(|host| host.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            None => DEFAULT_HOSTS.iter().map(|host| host.to_string()).collect(),
+        };
+        Some(Self {
+            hosts,
+            max_bytes: sneed_env::var_or("AVATAR_MAX_KB", 512) * 1024,
+            ttl: Duration::from_secs(sneed_env::var_or("AVATAR_CACHE_TTL_SECS", 3600)),
+            capacity: sneed_env::var_or("AVATAR_CACHE_SIZE", 1000).max(1),
+        })
+    }
+
+    /// Whether the URL is an http(s) image on one of the allowed hosts
+    pub fn allows(&self, url: &str) -> bool {
+        let Ok(url) = Url::parse(url) else {
+            return false;
+        };
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return false;
+        };
+        matches!(url.scheme(), "http" | "https")
+            && self.hosts.iter().any(|allowed| {
+                host == *allowed
+                    || host
+                        .strip_suffix(allowed.as_str())
+                        .is_some_and(|rest| rest.ends_with('.'))
+            })
+    }
+
+    /// The `/avatar` URL to show instead, or None to leave the avatar as it is
+    pub fn proxied(&self, avatar: &str) -> Option<String> {
+        if !self.allows(avatar) {
+            return None;
+        }
+        let mut url = Url::parse("http://localhost/avatar").expect("Static URL is valid");
+        url.query_pairs_mut().append_pair("url", avatar);
+        Some(format!("/avatar?{}", url.query().unwrap_or_default()))
+    }
+}
+
+#[derive(Clone)]
+pub struct Avatar {
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+struct CacheEntry {
+    avatar: Avatar,
+    fetched_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, CacheEntry>,
+    /// Bumped on every lookup to order entries by use
+    clock: u64,
+}
+
+/// Fetches allowed avatars and keeps them in a small LRU cache, shared by all workers
+pub struct AvatarProxy {
+    pub config: AvatarConfig,
+    http: reqwest::Client,
+    cache: Mutex<Cache>,
+}
+
+impl AvatarProxy {
+    pub fn new(config: AvatarConfig) -> Result<Self> {
+        // Redirects are only followed to allowed hosts, so an allowed URL can't send the
+        // server to an internal address
+        let allowed = config.clone();
+        let redirects = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if allowed.allows(attempt.url().as_str()) {
+                attempt.follow()
+            } else {
+                let error = format!("redirected to {}", attempt.url());
+                attempt.error(error)
+            }
+        });
+        let http = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .redirect(redirects)
+            .build()?;
+        Ok(Self {
+            config,
+            http,
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    fn cached(&self, url: &str) -> Option<Avatar> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let now = cache.clock;
+        let entry = cache.entries.get_mut(url)?;
+        if entry.fetched_at.elapsed() > self.config.ttl {
+            cache.entries.remove(url);
+            return None;
+        }
+        entry.last_used = now;
+        Some(entry.avatar.clone())
+    }
+
+    fn insert(&self, url: &str, avatar: Avatar) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let now = cache.clock;
+        if !cache.entries.contains_key(url) && cache.entries.len() >= self.config.capacity {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(url, _)| url.clone());
+            if let Some(oldest) = oldest {
+                cache.entries.remove(&oldest);
+            }
+        }
+        cache.entries.insert(
+            url.to_string(),
+            CacheEntry {
+                avatar,
+                fetched_at: Instant::now(),
+                last_used: now,
+            },
+        );
+    }
+
+    /// The image at an allowed URL, from the cache or fetched. None for a URL that isn't
+    /// allowed; a failed fetch is logged and None as well.
+    pub async fn get(&self, url: &str) -> Option<Avatar> {
+        if !self.config.allows(url) {
+            return None;
+        }
+        if let Some(avatar) = self.cached(url) {
+            return Some(avatar);
+        }
+        match self.fetch(url).await {
+            Ok(avatar) => {
+                debug!(url, bytes = avatar.bytes.len(), "Cached avatar");
+                self.insert(url, avatar.clone());
+                Some(avatar)
+            }
+            Err(e) => {
+                warn!(url, error = %e, "Failed to fetch avatar");
+                None
+            }
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Avatar> {
+        let mut response = self.http.get(url).send().await?;
+        if !response.status().is_success() {
+            bail!("answered {}", response.status());
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.starts_with("image/") || content_type.starts_with("image/svg") {
+            bail!("not a raster image ({:?})", content_type);
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > self.config.max_bytes {
+                bail!("larger than {} bytes", self.config.max_bytes);
+            }
+        }
+        Ok(Avatar {
+            content_type,
+            bytes: Bytes::from(bytes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use std::sync::Arc;
+
+    fn config(capacity: usize) -> AvatarConfig {
+        AvatarConfig {
+            hosts: DEFAULT_HOSTS.iter().map(|host| host.to_string()).collect(),
+            max_bytes: 1024,
+            ttl: Duration::from_secs(60),
+            capacity,
+        }
+    }
+
+    #[test]
+    fn test_allows() {
+        let config = config(10);
+        assert!(config.allows("https://yt3.ggpht.com/a.jpg"));
+        assert!(config.allows("http://static-cdn.jtvnw.net/user.png"));
+        assert!(config.allows("https://ak2.rmbl.ws/z0/avatar.jpeg"));
+        assert!(!config.allows("https://evilggpht.com/a.jpg"));
+        assert!(!config.allows("https://ggpht.com.evil.net/a.jpg"));
+        assert!(!config.allows("ftp://yt3.ggpht.com/a.jpg"));
+        assert!(!config.allows("data:image/gif;base64,R0lGODlh"));
+        assert!(!config.allows("/avatar?url=x"));
+    }
+
+    #[test]
+    fn test_proxied() {
+        let config = config(10);
+        assert_eq!(
+            config
+                .proxied("https://yt3.ggpht.com/a b.jpg?sz=64&x=1")
+                .unwrap(),
+            "/avatar?url=https%3A%2F%2Fyt3.ggpht.com%2Fa+b.jpg%3Fsz%3D64%26x%3D1"
+        );
+        assert_eq!(config.proxied("https://example.com/a.jpg"), None);
+    }
+
+    #[test]
+    fn test_blank_gif_matches_blank_avatar() {
+        let encoded = crate::message::BLANK_AVATAR
+            .strip_prefix("data:image/gif;base64,")
+            .unwrap();
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        assert_eq!(decoded, BLANK_GIF);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let proxy = AvatarProxy::new(config(2)).unwrap();
+        let avatar = |name: &str| Avatar {
+            content_type: "image/png".to_string(),
+            bytes: Bytes::from(name.to_string()),
+        };
+        proxy.insert("a", avatar("a"));
+        proxy.insert("b", avatar("b"));
+        assert!(proxy.cached("a").is_some());
+        proxy.insert("c", avatar("c"));
+        assert!(proxy.cached("b").is_none());
+        assert_eq!(proxy.cached("a").unwrap().bytes, "a");
+        assert_eq!(proxy.cached("c").unwrap().bytes, "c");
+
+        let mut expired = config(2);
+        expired.ttl = Duration::ZERO;
+        let proxy = AvatarProxy::new(expired).unwrap();
+        proxy.insert("a", avatar("a"));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(proxy.cached("a").is_none());
+    }
+
+    /// Serve `/image.png`, and redirects to it on 127.0.0.1 (`/allowed`) and on localhost
+    /// (`/blocked`), recording the Host and path of every request
+    fn redirecting_server() -> (u16, Arc<Mutex<Vec<String>>>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                let host = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("host: ").or(line.strip_prefix("Host: ")))
+                    .unwrap_or_default()
+                    .to_string();
+                seen.lock().unwrap().push(format!("{host}{path}"));
+
+                let (status, headers, body) = match path.as_str() {
+                    "/image.png" => ("200 OK", "Content-Type: image/png".to_string(), "png"),
+                    "/allowed" => (
+                        "302 Found",
+                        format!("Location: http://127.0.0.1:{port}/image.png"),
+                        "",
+                    ),
+                    _ => (
+                        "302 Found",
+                        format!("Location: http://localhost:{port}/image.png"),
+                        "",
+                    ),
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\n{headers}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (port, requests)
+    }
+
+    #[actix_web::test]
+    async fn test_redirects_only_followed_to_allowed_hosts() {
+        let (port, requests) = redirecting_server();
+        let mut config = config(10);
+        config.hosts = vec!["127.0.0.1".to_string()];
+        let proxy = AvatarProxy::new(config).unwrap();
+
+        let avatar = proxy
+            .get(&format!("http://127.0.0.1:{port}/allowed"))
+            .await
+            .unwrap();
+        assert_eq!(avatar.bytes, "png");
+
+        // The hop to localhost is refused before it is requested
+        assert!(proxy
+            .get(&format!("http://127.0.0.1:{port}/blocked"))
+            .await
+            .is_none());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                format!("127.0.0.1:{port}/allowed"),
+                format!("127.0.0.1:{port}/image.png"),
+                format!("127.0.0.1:{port}/blocked"),
+            ]
+        );
+    }
+}
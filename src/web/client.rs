@@ -1,16 +1,63 @@
 use actix::*;
+use actix_http::ws::Item;
 use actix_web_actors::ws;
 use serde::Deserialize;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, trace, warn};
+use uuid::Uuid;
 
 use super::message;
 use super::ChatMessage;
 use super::ChatServer;
 use super::CLIENT_TIMEOUT;
 use super::HEARTBEAT_INTERVAL;
-use crate::layout::Layout;
+use crate::layout::{ElementConfig, Layout};
 use crate::message::{CommandFeatureMessage, LivestreamUpdate};
+use crate::sneed_env;
+
+/// How often clients are pinged, and how long one may go without a ping or pong
+/// before it is dropped
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: HEARTBEAT_INTERVAL,
+            timeout: CLIENT_TIMEOUT,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Read `WS_HEARTBEAT_SECS` and `WS_TIMEOUT_SECS` (fractions allowed)
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |key: &str, default: Duration| {
+            let secs = sneed_env::var_or(key, default.as_secs_f64());
+            if secs.is_finite() && secs > 0.0 {
+                Duration::from_secs_f64(secs)
+            } else {
+                warn!("{key} must be positive, using default");
+                default
+            }
+        };
+        let heartbeat = Self {
+            interval: secs("WS_HEARTBEAT_SECS", default.interval),
+            timeout: secs("WS_TIMEOUT_SECS", default.timeout),
+        };
+        if heartbeat.timeout <= heartbeat.interval {
+            warn!(
+                "WS_TIMEOUT_SECS ({:?}) should be longer than WS_HEARTBEAT_SECS ({:?}) or clients will be dropped between pings",
+                heartbeat.timeout, heartbeat.interval
+            );
+        }
+        heartbeat
+    }
+}
 
 /// Layout-related commands from WebSocket clients
 #[derive(Deserialize, Debug)]
@@ -18,21 +65,281 @@ struct LayoutCommand {
     #[serde(default)]
     layout_update: Option<Layout>,
     #[serde(default)]
-    switch_layout: Option<String>,
+    switch_layout: Option<SwitchLayoutCommand>,
     #[serde(default)]
     save_layout: Option<SaveLayoutCommand>,
+    /// Show a layout on its overlays without saving it
+    #[serde(default)]
+    preview_layout: Option<PreviewLayoutCommand>,
+    /// Replace one element of a saved layout (cheaper than save_layout while dragging)
+    #[serde(default)]
+    layout_element_update: Option<LayoutElementUpdateCommand>,
     #[serde(default)]
     delete_layout: Option<String>,
+    /// List the saved versions of a layout (for undo)
+    #[serde(default)]
+    list_layout_history: Option<String>,
+    #[serde(default)]
+    restore_layout_version: Option<RestoreLayoutVersionCommand>,
+    /// Restore a layout to the factory default
+    #[serde(default)]
+    reset_layout: Option<ResetLayoutCommand>,
+    #[serde(default)]
+    duplicate_layout: Option<LayoutFromToCommand>,
+    #[serde(default)]
+    rename_layout: Option<LayoutFromToCommand>,
     #[serde(default)]
     request_layout: Option<bool>,
     #[serde(default)]
     request_layouts: Option<bool>,
-    /// Subscribe to a specific layout by name (used by overlay views)
+    /// Subscribe to a specific layout by name, or to an output's active layout (used by overlay views)
+    #[serde(default)]
+    subscribe_layout: Option<SubscribeLayoutCommand>,
+    /// Only receive broadcasts with these tags; empty receives all (used by alert overlays)
     #[serde(default)]
-    subscribe_layout: Option<String>,
+    subscribe_tags: Option<SubscribeTagsCommand>,
     /// Request recent chat messages (used by overlay to sync state)
     #[serde(default)]
     request_messages: Option<bool>,
+    /// Search the session's messages by username or text (used by the dashboard)
+    #[serde(default)]
+    search_recent: Option<String>,
+    /// Request the running session donation total (used by goal bars)
+    #[serde(default)]
+    request_session_total: Option<bool>,
+    /// Request the running poll (used by overlays joining mid-poll)
+    #[serde(default)]
+    request_poll: Option<bool>,
+    /// Request the recently featured message ids (used by highlight tickers)
+    #[serde(default)]
+    request_featured_history: Option<bool>,
+    /// Request the donation goal's progress (null when no goal is set)
+    #[serde(default)]
+    request_goal: Option<bool>,
+    /// Request connected client and viewer totals (used by the dashboard)
+    #[serde(default)]
+    request_server_stats: Option<bool>,
+    /// Export the named layouts as a bundle (empty = all)
+    #[serde(default)]
+    export_layouts: Option<Vec<String>>,
+    /// Import a bundle produced by export_layouts
+    #[serde(default)]
+    import_layouts: Option<ImportLayoutsCommand>,
+    /// Export a layout as a standalone HTML snapshot
+    #[serde(default)]
+    export_overlay_html: Option<ExportOverlayHtmlCommand>,
+}
+
+impl LayoutCommand {
+    /// Whether the command changes layouts (or what overlays show) rather than reading them
+    fn is_write(&self) -> bool {
+        self.layout_update.is_some()
+            || self.switch_layout.is_some()
+            || self.save_layout.is_some()
+            || self.preview_layout.is_some()
+            || self.layout_element_update.is_some()
+            || self.delete_layout.is_some()
+            || self.restore_layout_version.is_some()
+            || self.reset_layout.is_some()
+            || self.duplicate_layout.is_some()
+            || self.rename_layout.is_some()
+            || self.import_layouts.is_some()
+    }
+}
+
+/// Chat-level commands from WebSocket clients
+#[derive(Deserialize, Debug)]
+struct ChatCommand {
+    /// Wipe every message in the session (dashboard "Clear chat")
+    #[serde(default)]
+    clear_messages: Option<ClearMessagesCommand>,
+    #[serde(default)]
+    start_poll: Option<StartPollCommand>,
+    #[serde(default)]
+    end_poll: Option<bool>,
+    #[serde(default)]
+    vote: Option<VoteCommand>,
+    #[serde(default)]
+    set_goal: Option<SetGoalCommand>,
+    #[serde(default)]
+    reset_goal: Option<bool>,
+    /// Hold incoming chat for approval (true) or broadcast it straight away (false)
+    #[serde(default)]
+    set_moderated: Option<bool>,
+    /// Seconds between a user's unpaid messages reaching clients; 0 turns slow mode off
+    #[serde(default)]
+    set_slow_mode: Option<u64>,
+    #[serde(default)]
+    approve_message: Option<MessageIdCommand>,
+    #[serde(default)]
+    reject_message: Option<MessageIdCommand>,
+    /// Show an earlier message again, e.g. an old donation's alert
+    #[serde(default)]
+    replay_message: Option<MessageIdCommand>,
+    /// Request moderated mode and the messages waiting for approval (dashboard)
+    #[serde(default)]
+    request_moderation: Option<bool>,
+    /// Hide or show one platform's chat and viewers
+    #[serde(default)]
+    set_platform_enabled: Option<SetPlatformEnabledCommand>,
+    /// Re-read the alert sound mapping after editing it
+    #[serde(default)]
+    reload_alert_sounds: Option<bool>,
+    #[serde(default)]
+    ban_user: Option<UserCommand>,
+    #[serde(default)]
+    unban_user: Option<UserCommand>,
+    #[serde(default)]
+    edit_paid_message: Option<EditPaidMessageCommand>,
+    /// Fake a message from a platform (dashboard "Test message")
+    #[serde(default)]
+    test_message: Option<TestMessageCommand>,
+}
+
+impl ChatCommand {
+    /// Whether no command is set, e.g. because the payload was meant for another kind
+    fn is_empty(&self) -> bool {
+        self.clear_messages.is_none()
+            && self.start_poll.is_none()
+            && self.end_poll.is_none()
+            && self.vote.is_none()
+            && self.set_goal.is_none()
+            && self.reset_goal.is_none()
+            && self.set_moderated.is_none()
+            && self.set_slow_mode.is_none()
+            && self.approve_message.is_none()
+            && self.reject_message.is_none()
+            && self.replay_message.is_none()
+            && self.request_moderation.is_none()
+            && self.set_platform_enabled.is_none()
+            && self.reload_alert_sounds.is_none()
+            && self.ban_user.is_none()
+            && self.unban_user.is_none()
+            && self.edit_paid_message.is_none()
+            && self.test_message.is_none()
+    }
+}
+
+/// An inbound WebSocket command, `{"type": "...", "data": {...}}`. `data` is the payload
+/// the untagged format sent on its own, so clients move over by wrapping what they send.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Command {
+    LivestreamUpdate(LivestreamUpdate),
+    FeatureMessage(CommandFeatureMessage),
+    Chat(Box<ChatCommand>),
+    Layout(Box<LayoutCommand>),
+}
+
+impl Command {
+    fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(text)?;
+        if value.get("type").is_some() {
+            return serde_json::from_value(value);
+        }
+        Self::parse_untagged(value)
+    }
+
+    /// The untagged format, accepted for one more release. The kind is told apart by which
+    /// fields are present, in the order the old parser tried them.
+    fn parse_untagged(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        trace!("Untagged WebSocket command");
+        if let Ok(update) = LivestreamUpdate::deserialize(&value) {
+            if update.messages.is_some() || update.removals.is_some() || update.viewers.is_some() {
+                return Ok(Self::LivestreamUpdate(update));
+            }
+        }
+        // The key alone counts: `"feature_message": null` unfeatures
+        if value.get("feature_message").is_some() {
+            return CommandFeatureMessage::deserialize(&value).map(Self::FeatureMessage);
+        }
+        let cmd = ChatCommand::deserialize(&value)?;
+        if !cmd.is_empty() {
+            return Ok(Self::Chat(Box::new(cmd)));
+        }
+        LayoutCommand::deserialize(value).map(|cmd| Self::Layout(Box::new(cmd)))
+    }
+}
+
+/// A made-up message; every field is optional
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct TestMessageCommand {
+    platform: String,
+    username: String,
+    message: String,
+    amount: f64,
+    currency: String,
+}
+
+impl Default for TestMessageCommand {
+    fn default() -> Self {
+        Self {
+            platform: "Test".to_string(),
+            username: "Test User".to_string(),
+            message: "This is a test message.".to_string(),
+            amount: 0.0,
+            currency: "USD".to_string(),
+        }
+    }
+}
+
+/// A corrected amount for a stored paid message
+#[derive(Deserialize, Debug)]
+struct EditPaidMessageCommand {
+    id: Uuid,
+    amount: f64,
+    currency: String,
+}
+
+/// A chatter, identified per platform
+#[derive(Deserialize, Debug)]
+struct UserCommand {
+    platform: String,
+    username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SetPlatformEnabledCommand {
+    platform: String,
+    enabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessageIdCommand {
+    id: Uuid,
+}
+
+#[derive(Deserialize, Debug)]
+struct SetGoalCommand {
+    #[serde(default)]
+    label: String,
+    /// Target in USD
+    target: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct StartPollCommand {
+    question: String,
+    options: Vec<String>,
+    #[serde(default)]
+    multi_vote: bool,
+}
+
+/// A vote for the 1-based `option`, as numbered on the overlay
+#[derive(Deserialize, Debug)]
+struct VoteCommand {
+    option: usize,
+    /// Who voted, so repeat votes are ignored; anonymous votes always count
+    #[serde(default)]
+    voter: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ClearMessagesCommand {
+    /// Also delete this session's paid messages from the database
+    #[serde(default)]
+    purge_db: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -41,24 +348,149 @@ struct SaveLayoutCommand {
     layout: Layout,
 }
 
+#[derive(Deserialize, Debug)]
+struct PreviewLayoutCommand {
+    layout: Layout,
+}
+
+#[derive(Deserialize, Debug)]
+struct LayoutElementUpdateCommand {
+    name: String,
+    element_id: String,
+    config: ElementConfig,
+}
+
+#[derive(Deserialize, Debug)]
+struct RestoreLayoutVersionCommand {
+    name: String,
+    version: u32,
+}
+
+/// `"name"` switches the default output; `{"name", "output"}` a named one
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum SwitchLayoutCommand {
+    Name(String),
+    Output { name: String, output: String },
+}
+
+/// `"name"` pins one layout; `{"output"}` follows whatever that output shows
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum SubscribeLayoutCommand {
+    Name(String),
+    Output { output: String },
+}
+
+#[derive(Deserialize, Debug)]
+struct SubscribeTagsCommand {
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResetLayoutCommand {
+    name: String,
+}
+
+/// Source and destination layout names for duplicate/rename
+#[derive(Deserialize, Debug)]
+struct LayoutFromToCommand {
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportOverlayHtmlCommand {
+    layout_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportLayoutsCommand {
+    bundle: serde_json::Value,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// Build a `layout_errors` reply for a layout save, if there is anything to report.
+/// SCSS errors still save; validation errors reject the layout.
+fn layout_errors_reply(
+    name: &str,
+    res: Result<Result<Vec<String>, Vec<String>>, MailboxError>,
+) -> Option<String> {
+    let (saved, errors) = match res {
+        Ok(Ok(scss_errors)) => (true, scss_errors),
+        Ok(Err(errors)) => {
+            warn!(layout = %name, ?errors, "Rejected layout save");
+            (false, errors)
+        }
+        Err(e) => {
+            error!(error = ?e, "Failed to save layout");
+            return None;
+        }
+    };
+    if errors.is_empty() {
+        return None;
+    }
+
+    let reply = serde_json::to_string(&message::ReplyInner {
+        tag: "layout_errors".to_owned(),
+        message: serde_json::json!({
+            "name": name,
+            "saved": saved,
+            "errors": errors,
+        })
+        .to_string(),
+    })
+    .unwrap();
+    Some(reply)
+}
+
+/// What a WebSocket connection may do, fixed by the endpoint it connected to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientRole {
+    /// `/ingest.ws`: bridges, the dashboard and the editor. May send chat and write layouts.
+    Ingest,
+    /// `/chat.ws`: overlays. Receives broadcasts and may only make read requests.
+    Viewer,
+}
+
 pub struct ChatClient {
     /// Connection ID
     pub id: usize,
+    pub role: ClientRole,
     /// Chat server
     pub server: Addr<ChatServer>,
+    pub heartbeat: Heartbeat,
     /// Last Heartbeat
-    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT), otherwise we drop connection.
+    /// Client must send a ping or pong at least once per `heartbeat.timeout` (5 seconds by default),
+    /// otherwise we drop connection.
     pub last_heartbeat_at: Instant,
+    /// Largest message accepted, whole or reassembled from fragments
+    pub max_message_size: usize,
+    /// A fragmented text message received so far
+    pub fragments: Option<Vec<u8>>,
 }
 
 impl ChatClient {
-    /// helper method that sends ping to client every second.
+    /// Log and report true if this connection may not send `what`
+    fn reject_write(&self, what: &str) -> bool {
+        if self.role == ClientRole::Ingest {
+            return false;
+        }
+        warn!(
+            client = self.id,
+            "Ignoring {} from a receive-only connection", what
+        );
+        true
+    }
+
+    /// helper method that sends ping to client every `heartbeat.interval`.
     ///
     /// also this method checks heartbeats from client
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.last_heartbeat_at) > CLIENT_TIMEOUT {
+            if Instant::now().duration_since(act.last_heartbeat_at) > act.heartbeat.timeout {
                 // heartbeat timed out
 
                 // notify chat server
@@ -102,6 +534,8 @@ impl ChatClient {
         self.server
             .send(message::Connect {
                 recipient: ctx.address().recipient(),
+                close: ctx.address().recipient(),
+                role: self.role,
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -117,6 +551,892 @@ impl ChatClient {
             })
             .wait(ctx);
     }
+
+    /// Forward an ingest update's messages, removals and viewer count. False if it had none.
+    fn handle_livestream_update(
+        &self,
+        update: LivestreamUpdate,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> bool {
+        let is_content =
+            update.messages.is_some() || update.removals.is_some() || update.viewers.is_some();
+        if is_content && self.reject_write("livestream update") {
+            return true;
+        }
+        let mut handled = false;
+        // Send Chat Messages
+        if let Some(messages) = update.messages {
+            handled = true;
+            for message in messages {
+                self.send_or_reply(
+                    ctx,
+                    ChatMessage {
+                        chat_message: message,
+                    },
+                );
+            }
+        }
+        // Send Removals
+        if let Some(removals) = update.removals {
+            handled = true;
+            for id in removals {
+                self.send_or_reply(ctx, message::RemoveMessage { id });
+            }
+        }
+        // Send Viewer Counts
+        if let Some(viewers) = update.viewers {
+            handled = true;
+            self.send_or_reply(
+                ctx,
+                message::ViewCount {
+                    platform: update.platform,
+                    viewers,
+                },
+            );
+        }
+        handled
+    }
+
+    fn handle_feature_message(
+        &self,
+        cmd: CommandFeatureMessage,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if self.reject_write("feature_message") {
+            return;
+        }
+        self.send_or_reply(
+            ctx,
+            message::FeatureMessage {
+                id: cmd.feature_message,
+                duration_secs: cmd.duration_secs,
+            },
+        );
+    }
+
+    /// Run the chat command that is set. False if none is.
+    fn handle_chat_command(&self, cmd: ChatCommand, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        if let Some(clear) = cmd.clear_messages {
+            if self.reject_write("clear_messages") {
+                return true;
+            }
+            self.send_or_reply(
+                ctx,
+                message::ClearMessages {
+                    purge_db: clear.purge_db,
+                },
+            );
+            return true;
+        }
+
+        if let Some(start) = cmd.start_poll {
+            if self.reject_write("start_poll") {
+                return true;
+            }
+            self.server
+                .send(message::StartPoll {
+                    question: start.question,
+                    options: start.options,
+                    multi_vote: start.multi_vote,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Err(e)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "poll_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if cmd.end_poll.unwrap_or(false) {
+            if self.reject_write("end_poll") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::EndPoll);
+            return true;
+        }
+
+        if let Some(vote) = cmd.vote {
+            if self.reject_write("vote") {
+                return true;
+            }
+            self.send_or_reply(
+                ctx,
+                message::Vote {
+                    voter: vote.voter,
+                    choices: vec![vote.option],
+                },
+            );
+            return true;
+        }
+
+        if let Some(goal) = cmd.set_goal {
+            if self.reject_write("set_goal") {
+                return true;
+            }
+            self.server
+                .send(message::SetGoal {
+                    label: goal.label,
+                    target_usd: goal.target,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Err(e)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "goal_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if cmd.reset_goal.unwrap_or(false) {
+            if self.reject_write("reset_goal") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::ResetGoal);
+            return true;
+        }
+
+        if let Some(enabled) = cmd.set_moderated {
+            if self.reject_write("set_moderated") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::SetModerated { enabled });
+            return true;
+        }
+
+        if let Some(secs) = cmd.set_slow_mode {
+            if self.reject_write("set_slow_mode") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::SetSlowMode { secs });
+            return true;
+        }
+
+        if let Some(approve) = cmd.approve_message {
+            if self.reject_write("approve_message") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::ApproveMessage { id: approve.id });
+            return true;
+        }
+
+        if let Some(reject) = cmd.reject_message {
+            if self.reject_write("reject_message") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::RejectMessage { id: reject.id });
+            return true;
+        }
+
+        if let Some(replay) = cmd.replay_message {
+            if self.reject_write("replay_message") {
+                return true;
+            }
+            self.send_or_reply(ctx, message::ReplayMessage { id: replay.id });
+            return true;
+        }
+
+        if let Some(toggle) = cmd.set_platform_enabled {
+            if self.reject_write("set_platform_enabled") {
+                return true;
+            }
+            self.send_or_reply(
+                ctx,
+                message::SetPlatformEnabled {
+                    platform: toggle.platform,
+                    enabled: toggle.enabled,
+                },
+            );
+            return true;
+        }
+
+        if let Some(user) = cmd.ban_user {
+            if self.reject_write("ban_user") {
+                return true;
+            }
+            self.server
+                .send(message::BanUser {
+                    platform: user.platform,
+                    username: user.username,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Err(e)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "ban_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if let Some(user) = cmd.unban_user {
+            if self.reject_write("unban_user") {
+                return true;
+            }
+            self.send_or_reply(
+                ctx,
+                message::UnbanUser {
+                    platform: user.platform,
+                    username: user.username,
+                },
+            );
+            return true;
+        }
+
+        if let Some(test) = cmd.test_message {
+            if self.reject_write("test_message") {
+                return true;
+            }
+            self.send_or_reply(
+                ctx,
+                message::TestMessage {
+                    platform: test.platform,
+                    username: test.username,
+                    message: test.message,
+                    amount: test.amount,
+                    currency: test.currency,
+                },
+            );
+            return true;
+        }
+
+        if let Some(edit) = cmd.edit_paid_message {
+            if self.reject_write("edit_paid_message") {
+                return true;
+            }
+            self.server
+                .send(message::EditPaidMessage {
+                    id: edit.id,
+                    amount: edit.amount,
+                    currency: edit.currency,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Err(e)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "edit_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if cmd.reload_alert_sounds.unwrap_or(false) {
+            if self.reject_write("reload_alert_sounds") {
+                return true;
+            }
+            self.server
+                .send(message::ReloadAlertSounds)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Err(e)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "alert_sounds_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if cmd.request_moderation.unwrap_or(false) {
+            // Unapproved messages are only for the dashboard
+            if self.reject_write("request_moderation") {
+                return true;
+            }
+            self.server
+                .send(message::RequestModeration)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(state) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "moderation".to_owned(),
+                            message: serde_json::to_string(&state.enabled).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "slow_mode".to_owned(),
+                            message: serde_json::to_string(&state.slow_mode_secs).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                        for msg in state.pending {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "pending_message".to_owned(),
+                                message: msg.to_json(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+        false
+    }
+
+    /// Run the layout command that is set. False if none is.
+    fn handle_layout_command(
+        &self,
+        cmd: LayoutCommand,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> bool {
+        trace!(command = ?cmd, "Parsed LayoutCommand");
+        if cmd.is_write() && self.reject_write("layout command") {
+            return true;
+        }
+
+        // Handle layout update broadcast
+        if let Some(layout) = cmd.layout_update {
+            debug!(layout = %layout.name, "Broadcasting layout update");
+            self.send_or_reply(ctx, message::LayoutUpdate { layout });
+            return true;
+        }
+
+        // Handle preview layout
+        if let Some(preview) = cmd.preview_layout {
+            debug!(layout = %preview.layout.name, "Previewing layout");
+            self.send_or_reply(
+                ctx,
+                message::PreviewLayout {
+                    layout: preview.layout,
+                },
+            );
+            return true;
+        }
+
+        // Handle switch layout
+        if let Some(switch) = cmd.switch_layout {
+            let (name, output) = match switch {
+                SwitchLayoutCommand::Name(name) => (name, message::DEFAULT_OUTPUT.to_string()),
+                SwitchLayoutCommand::Output { name, output } => (name, output),
+            };
+            self.send_or_reply(ctx, message::SwitchLayout { output, name });
+            return true;
+        }
+
+        // Handle save layout
+        if let Some(save_cmd) = cmd.save_layout {
+            debug!(layout = %save_cmd.name, "Saving layout");
+            let mut layout = save_cmd.layout;
+            layout.name = save_cmd.name.clone();
+            self.server
+                .send(message::SaveLayout { layout })
+                .into_actor(self)
+                .then(move |res, _, ctx| {
+                    if let Some(reply) = layout_errors_reply(&save_cmd.name, res) {
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle single element update
+        if let Some(update_cmd) = cmd.layout_element_update {
+            self.server
+                .send(message::LayoutElementUpdate {
+                    layout_name: update_cmd.name.clone(),
+                    element_id: update_cmd.element_id,
+                    config: update_cmd.config,
+                })
+                .into_actor(self)
+                .then(move |res, _, ctx| {
+                    if let Some(reply) = layout_errors_reply(&update_cmd.name, res) {
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle delete layout
+        if let Some(name) = cmd.delete_layout {
+            self.send_or_reply(ctx, message::DeleteLayout { name });
+            return true;
+        }
+
+        // Handle layout history listing
+        if let Some(name) = cmd.list_layout_history {
+            self.server
+                .send(message::ListLayoutHistory { name: name.clone() })
+                .into_actor(self)
+                .then(move |res, _, ctx| {
+                    match res {
+                        Ok(Ok(versions)) => {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "layout_history".to_owned(),
+                                message: serde_json::json!({
+                                    "name": name,
+                                    "versions": versions,
+                                })
+                                .to_string(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                        Ok(Err(e)) => {
+                            warn!(error = %e, "Failed to list layout history")
+                        }
+                        Err(e) => error!(error = ?e, "Failed to list layout history"),
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle layout version restore
+        if let Some(restore_cmd) = cmd.restore_layout_version {
+            debug!(layout = %restore_cmd.name, version = restore_cmd.version, "Restoring layout version");
+            self.send_or_reply(
+                ctx,
+                message::RestoreLayoutVersion {
+                    name: restore_cmd.name,
+                    version: restore_cmd.version,
+                },
+            );
+            return true;
+        }
+
+        // Handle reset layout
+        if let Some(reset_cmd) = cmd.reset_layout {
+            debug!(layout = %reset_cmd.name, "Resetting layout");
+            self.send_or_reply(
+                ctx,
+                message::ResetLayout {
+                    name: reset_cmd.name,
+                },
+            );
+            return true;
+        }
+
+        // Handle duplicate layout
+        if let Some(dup_cmd) = cmd.duplicate_layout {
+            debug!(from = %dup_cmd.from, to = %dup_cmd.to, "Duplicating layout");
+            self.send_or_reply(
+                ctx,
+                message::DuplicateLayout {
+                    from: dup_cmd.from,
+                    to: dup_cmd.to,
+                },
+            );
+            return true;
+        }
+
+        // Handle rename layout
+        if let Some(rename_cmd) = cmd.rename_layout {
+            debug!(from = %rename_cmd.from, to = %rename_cmd.to, "Renaming layout");
+            self.send_or_reply(
+                ctx,
+                message::RenameLayout {
+                    from: rename_cmd.from,
+                    to: rename_cmd.to,
+                },
+            );
+            return true;
+        }
+
+        // Handle layout bundle export
+        if let Some(names) = cmd.export_layouts {
+            self.server
+                .send(message::ExportLayouts { names })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    match res {
+                        Ok(Ok(bundle)) => {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "layout_bundle".to_owned(),
+                                message: bundle,
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                        Ok(Err(e)) => warn!(error = %e, "Failed to export layouts"),
+                        Err(e) => error!(error = ?e, "Failed to export layouts"),
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle standalone HTML export
+        if let Some(export_cmd) = cmd.export_overlay_html {
+            self.server
+                .send(message::ExportOverlayHtml {
+                    layout_name: export_cmd.layout_name,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    let reply = match res {
+                        Ok(Ok(html)) => message::ReplyInner {
+                            tag: "overlay_html".to_owned(),
+                            message: serde_json::to_string(&html).unwrap(),
+                        },
+                        Ok(Err(e)) => message::ReplyInner {
+                            tag: "export_error".to_owned(),
+                            message: serde_json::to_string(&e).unwrap(),
+                        },
+                        Err(e) => {
+                            error!(error = ?e, "Failed to export overlay HTML");
+                            return fut::ready(());
+                        }
+                    };
+                    ctx.text(serde_json::to_string(&reply).unwrap());
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle layout bundle import
+        if let Some(import_cmd) = cmd.import_layouts {
+            self.server
+                .send(message::ImportLayouts {
+                    bundle: import_cmd.bundle.to_string(),
+                    overwrite: import_cmd.overwrite,
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    match res {
+                        Ok(Ok(report)) => {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "layout_import".to_owned(),
+                                message: serde_json::to_string(&report).unwrap(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                        Ok(Err(e)) => warn!(error = %e, "Failed to import layouts"),
+                        Err(e) => error!(error = ?e, "Failed to import layouts"),
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request layout
+        if cmd.request_layout.unwrap_or(false) {
+            debug!("Client requesting current layout");
+            self.server
+                .send(message::RequestLayout)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(layout) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "layout_update".to_owned(),
+                            message: serde_json::to_string(&layout).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if let Some(subscription) = cmd.subscribe_tags {
+            self.server.do_send(message::SubscribeTags {
+                client_id: self.id,
+                tags: subscription.tags,
+            });
+            return true;
+        }
+
+        // Handle subscribe to an output
+        if let Some(SubscribeLayoutCommand::Output { output }) = &cmd.subscribe_layout {
+            debug!(output = %output, "Client subscribing to output");
+            self.server
+                .send(message::SubscribeOutput {
+                    client_id: self.id,
+                    output: output.clone(),
+                })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    match res {
+                        Ok(Ok(layout)) => {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "layout_update".to_owned(),
+                                message: serde_json::to_string(&layout).unwrap(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                        Ok(Err(e)) => {
+                            warn!(error = %e, "Output layout not found");
+                        }
+                        Err(e) => {
+                            error!(error = ?e, "Failed to subscribe to output");
+                        }
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle subscribe to specific layout
+        if let Some(SubscribeLayoutCommand::Name(name)) = cmd.subscribe_layout {
+            debug!(layout = %name, "Client subscribing to layout");
+            let client_id = self.id;
+            let server = self.server.clone();
+            self.server
+                .send(message::RequestLayoutByName { name: name.clone() })
+                .into_actor(self)
+                .then(move |res, _, ctx| {
+                    match res {
+                        Ok(Some(layout)) => {
+                            // Register the subscription with the server
+                            server.do_send(message::SubscribeLayout {
+                                client_id,
+                                layout_name: name.clone(),
+                            });
+
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "layout_update".to_owned(),
+                                message: serde_json::to_string(&layout).unwrap(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                        Ok(None) => {
+                            warn!(layout = %name, "Layout not found");
+                        }
+                        Err(e) => {
+                            error!(error = ?e, "Failed to fetch layout");
+                        }
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request layouts list
+        if cmd.request_layouts.unwrap_or(false) {
+            self.server
+                .send(message::RequestLayoutList)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(list) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "layout_list".to_owned(),
+                            message: serde_json::to_string(&list).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request session total
+        if cmd.request_session_total.unwrap_or(false) {
+            self.server
+                .send(message::RequestSessionTotal)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(total) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "session_total".to_owned(),
+                            message: serde_json::to_string(&total).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request poll
+        if cmd.request_poll.unwrap_or(false) {
+            self.server
+                .send(message::RequestPoll)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(Some(poll)) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "poll_update".to_owned(),
+                            message: serde_json::to_string(&poll).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request featured history
+        if cmd.request_featured_history.unwrap_or(false) {
+            self.server
+                .send(message::RequestFeaturedHistory)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(history) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "featured_history".to_owned(),
+                            message: serde_json::to_string(&history).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request goal
+        if cmd.request_goal.unwrap_or(false) {
+            self.server
+                .send(message::RequestGoal)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(progress) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "goal_update".to_owned(),
+                            message: serde_json::to_string(&progress).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request server stats
+        if cmd.request_server_stats.unwrap_or(false) {
+            self.server
+                .send(message::RequestServerStats)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(stats) = res {
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "server_stats".to_owned(),
+                            message: serde_json::to_string(&stats).unwrap(),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        if let Some(query) = cmd.search_recent {
+            self.server
+                .send(message::SearchRecent { query })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(messages) = res {
+                        // Same items as /api/messages: the message plus its `html`
+                        let items: Vec<String> = messages.iter().map(|msg| msg.to_json()).collect();
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "search_results".to_owned(),
+                            message: format!("[{}]", items.join(",")),
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+
+        // Handle request recent messages
+        if cmd.request_messages.unwrap_or(false) {
+            // Send recent messages
+            self.server
+                .send(message::RecentMessagesFor { client_id: self.id })
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(messages) = res {
+                        // Send each message as a chat_message event
+                        for msg in messages {
+                            let reply = serde_json::to_string(&message::ReplyInner {
+                                tag: "chat_message".to_owned(),
+                                message: msg.to_json(),
+                            })
+                            .unwrap();
+                            ctx.text(reply);
+                        }
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+
+            // Also send the current featured message if any (full message data)
+            self.server
+                .send(message::RequestFeaturedMessage)
+                .into_actor(self)
+                .then(|res, _, ctx| {
+                    if let Ok(featured_msg) = res {
+                        // Send full message JSON if featured, "null" if not
+                        let msg_json = match featured_msg {
+                            Some(msg) => msg.to_json(),
+                            None => "null".to_string(),
+                        };
+                        let reply = serde_json::to_string(&message::ReplyInner {
+                            tag: "feature_message".to_owned(),
+                            message: msg_json,
+                        })
+                        .unwrap();
+                        ctx.text(reply);
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+            return true;
+        }
+        false
+    }
 }
 
 impl Actor for ChatClient {
@@ -125,6 +1445,8 @@ impl Actor for ChatClient {
     /// Method is called on actor start.
     /// We register ws session with ChatServer
     fn started(&mut self, ctx: &mut Self::Context) {
+        // Room for chat bursts; the server evicts clients that stay full (see ChatServer::evict_stalled_clients)
+        ctx.set_mailbox_capacity(256);
         self.start_heartbeat(ctx);
     }
 
@@ -144,6 +1466,19 @@ impl Handler<message::Reply> for ChatClient {
     }
 }
 
+/// Server is shutting down; send a close frame so the browser reconnects promptly
+impl Handler<message::Close> for ChatClient {
+    type Result = ();
+
+    fn handle(&mut self, _: message::Close, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Away,
+            description: Some("Server is shutting down".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
 /// WebSocket message handler
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatClient {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
@@ -164,226 +1499,318 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatClient {
                 self.last_heartbeat_at = Instant::now();
             }
             ws::Message::Text(text) => {
-                // Try parsing as LivestreamUpdate first
-                if let Ok(update) = serde_json::from_str::<LivestreamUpdate>(&text) {
-                    let mut handled = false;
-                    // Send Chat Messages
-                    if let Some(messages) = update.messages {
-                        handled = true;
-                        for message in messages {
-                            self.send_or_reply(
-                                ctx,
-                                ChatMessage {
-                                    chat_message: message,
-                                },
-                            );
-                        }
+                let handled = match Command::parse(&text) {
+                    Ok(Command::LivestreamUpdate(update)) => {
+                        self.handle_livestream_update(update, ctx)
                     }
-                    // Send Removals
-                    if let Some(removals) = update.removals {
-                        handled = true;
-                        for id in removals {
-                            self.send_or_reply(ctx, message::RemoveMessage { id });
-                        }
+                    Ok(Command::FeatureMessage(cmd)) => {
+                        self.handle_feature_message(cmd, ctx);
+                        true
                     }
-                    // Send Viewer Counts
-                    if let Some(viewers) = update.viewers {
-                        handled = true;
-                        self.send_or_reply(
-                            ctx,
-                            message::ViewCount {
-                                platform: update.platform,
-                                viewers,
-                            },
-                        );
-                    }
-                    if handled {
+                    Ok(Command::Chat(cmd)) => self.handle_chat_command(*cmd, ctx),
+                    Ok(Command::Layout(cmd)) => self.handle_layout_command(*cmd, ctx),
+                    Err(e) => {
+                        warn!(message = %text, error = %e, "Invalid WebSocket command");
                         return;
                     }
+                };
+                if !handled {
+                    warn!(message = %text, "Unrecognized WebSocket message");
                 }
-
-                // Try parsing as FeatureMessage (only if it actually has a feature_message field)
-                if let Ok(cmd) = serde_json::from_str::<CommandFeatureMessage>(&text) {
-                    if cmd.feature_message.is_some() || text.contains("feature_message") {
-                        self.send_or_reply(
-                            ctx,
-                            message::FeatureMessage {
-                                id: cmd.feature_message,
-                            },
-                        );
+            }
+            ws::Message::Binary(_) => warn!("Unexpected binary message"),
+            ws::Message::Close(reason) => {
+                debug!(client_id = %self.id, reason = ?reason, "Client disconnecting");
+                ctx.close(reason);
+                ctx.stop();
+            }
+            ws::Message::Continuation(item) => {
+                // Reassemble fragmented text, within the same limit as whole messages
+                let (data, last) = match (item, self.fragments.take()) {
+                    (Item::FirstText(data), None) => (data.to_vec(), false),
+                    (Item::Continue(data), Some(mut fragments)) => {
+                        fragments.extend_from_slice(&data);
+                        (fragments, false)
+                    }
+                    (Item::Last(data), Some(mut fragments)) => {
+                        fragments.extend_from_slice(&data);
+                        (fragments, true)
+                    }
+                    // Binary messages aren't accepted, fragmented or not
+                    _ => {
+                        ctx.stop();
                         return;
                     }
+                };
+                if data.len() > self.max_message_size {
+                    warn!(client_id = %self.id, limit = self.max_message_size, "Fragmented WebSocket message too large, closing");
+                    ctx.close(Some(ws::CloseCode::Size.into()));
+                    ctx.stop();
+                    return;
+                }
+                if !last {
+                    self.fragments = Some(data);
+                    return;
+                }
+                match String::from_utf8(data) {
+                    Ok(text) => {
+                        StreamHandler::handle(self, Ok(ws::Message::Text(text.into())), ctx)
+                    }
+                    Err(_) => ctx.stop(),
                 }
+            }
+            ws::Message::Nop => (),
+        }
+    }
+}
 
-                // Try parsing as LayoutCommand
-                if let Ok(cmd) = serde_json::from_str::<LayoutCommand>(&text) {
-                    trace!(command = ?cmd, "Parsed LayoutCommand");
+#[cfg(test)]
+mod tests {
+    use super::{ChatClient, ClientRole, Command, Heartbeat, LayoutCommand};
+    use crate::database::Database;
+    use crate::exchange::ExchangeRates;
+    use crate::layout::LayoutManager;
+    use crate::web::ChatServer;
+    use actix::Actor;
+    use actix_web::{error::PayloadError, web::Bytes};
+    use actix_web_actors::ws;
+    use futures::{channel::mpsc, StreamExt};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
 
-                    // Handle layout update broadcast
-                    if let Some(layout) = cmd.layout_update {
-                        debug!(layout = %layout.name, "Broadcasting layout update");
-                        self.send_or_reply(ctx, message::LayoutUpdate { layout });
-                        return;
-                    }
+    fn command(json: &str) -> LayoutCommand {
+        serde_json::from_str(json).unwrap()
+    }
 
-                    // Handle switch layout
-                    if let Some(name) = cmd.switch_layout {
-                        self.send_or_reply(ctx, message::SwitchLayout { name });
-                        return;
-                    }
+    #[test]
+    fn test_layout_command_is_write() {
+        for json in [
+            r#"{"switch_layout": "alt"}"#,
+            r#"{"delete_layout": "alt"}"#,
+            r#"{"rename_layout": {"from": "a", "to": "b"}}"#,
+            r#"{"reset_layout": {"name": "a"}}"#,
+            r#"{"import_layouts": {"bundle": {}}}"#,
+        ] {
+            assert!(command(json).is_write(), "{}", json);
+        }
+        for json in [
+            r#"{"request_layout": true}"#,
+            r#"{"subscribe_layout": {"output": "alt"}}"#,
+            r#"{"list_layout_history": "default"}"#,
+            r#"{"export_layouts": []}"#,
+        ] {
+            assert!(!command(json).is_write(), "{}", json);
+        }
+    }
 
-                    // Handle save layout
-                    if let Some(save_cmd) = cmd.save_layout {
-                        debug!(layout = %save_cmd.name, "Saving layout");
-                        let mut layout = save_cmd.layout;
-                        layout.name = save_cmd.name.clone();
-                        self.send_or_reply(ctx, message::SaveLayout { layout });
-                        return;
-                    }
+    #[test]
+    fn test_tagged_commands() {
+        let parse = |json: &str| Command::parse(json).unwrap();
+        assert!(matches!(
+            parse(r#"{"type": "livestream_update", "data": {"platform": "YouTube", "channel": "c", "viewers": 5}}"#),
+            Command::LivestreamUpdate(update) if update.viewers == Some(5)
+        ));
+        assert!(matches!(
+            parse(r#"{"type": "feature_message", "data": {"feature_message": null}}"#),
+            Command::FeatureMessage(cmd) if cmd.feature_message.is_none()
+        ));
+        assert!(matches!(
+            parse(r#"{"type": "chat", "data": {"end_poll": true}}"#),
+            Command::Chat(cmd) if cmd.end_poll == Some(true)
+        ));
+        assert!(matches!(
+            parse(r#"{"type": "layout", "data": {"delete_layout": "feature_message"}}"#),
+            Command::Layout(cmd) if cmd.delete_layout.as_deref() == Some("feature_message")
+        ));
 
-                    // Handle delete layout
-                    if let Some(name) = cmd.delete_layout {
-                        self.send_or_reply(ctx, message::DeleteLayout { name });
-                        return;
-                    }
+        // A tagged command that doesn't fit its type is refused, not tried as another kind
+        assert!(Command::parse(r#"{"type": "chat", "data": {"set_slow_mode": "fast"}}"#).is_err());
+        assert!(Command::parse(r#"{"type": "poll", "data": {"end_poll": true}}"#).is_err());
+    }
 
-                    // Handle request layout
-                    if cmd.request_layout.unwrap_or(false) {
-                        debug!("Client requesting current layout");
-                        self.server
-                            .send(message::RequestLayout)
-                            .into_actor(self)
-                            .then(|res, _, ctx| {
-                                if let Ok(layout) = res {
-                                    let reply = serde_json::to_string(&message::ReplyInner {
-                                        tag: "layout_update".to_owned(),
-                                        message: serde_json::to_string(&layout).unwrap(),
-                                    })
-                                    .unwrap();
-                                    ctx.text(reply);
-                                }
-                                fut::ready(())
-                            })
-                            .wait(ctx);
-                        return;
-                    }
+    #[test]
+    fn test_untagged_commands() {
+        let parse = |json: &str| Command::parse(json).unwrap();
+        assert!(matches!(
+            parse(r#"{"platform": "YouTube", "channel": "c", "removals": []}"#),
+            Command::LivestreamUpdate(_)
+        ));
+        assert!(matches!(
+            parse(r#"{"feature_message": null, "duration_secs": 10}"#),
+            Command::FeatureMessage(cmd) if cmd.duration_secs == Some(10)
+        ));
+        assert!(matches!(
+            parse(r#"{"set_slow_mode": 30}"#),
+            Command::Chat(cmd) if cmd.set_slow_mode == Some(30)
+        ));
+        assert!(matches!(
+            parse(r#"{"request_layout": true}"#),
+            Command::Layout(cmd) if cmd.request_layout == Some(true)
+        ));
 
-                    // Handle subscribe to specific layout
-                    if let Some(name) = cmd.subscribe_layout {
-                        debug!(layout = %name, "Client subscribing to layout");
-                        let client_id = self.id;
-                        let server = self.server.clone();
-                        self.server
-                            .send(message::RequestLayoutByName { name: name.clone() })
-                            .into_actor(self)
-                            .then(move |res, _, ctx| {
-                                match res {
-                                    Ok(Some(layout)) => {
-                                        // Register the subscription with the server
-                                        server.do_send(message::SubscribeLayout {
-                                            client_id,
-                                            layout_name: name.clone(),
-                                        });
-
-                                        let reply = serde_json::to_string(&message::ReplyInner {
-                                            tag: "layout_update".to_owned(),
-                                            message: serde_json::to_string(&layout).unwrap(),
-                                        })
-                                        .unwrap();
-                                        ctx.text(reply);
-                                    }
-                                    Ok(None) => {
-                                        warn!(layout = %name, "Layout not found");
-                                    }
-                                    Err(e) => {
-                                        error!(error = ?e, "Failed to fetch layout");
-                                    }
-                                }
-                                fut::ready(())
-                            })
-                            .wait(ctx);
-                        return;
-                    }
+        // Mentioning feature_message in a value used to unfeature the current message
+        assert!(matches!(
+            parse(r#"{"delete_layout": "feature_message"}"#),
+            Command::Layout(cmd) if cmd.delete_layout.as_deref() == Some("feature_message")
+        ));
+        // An update without content is read as the layout command it could also be
+        assert!(matches!(
+            parse(r#"{"platform": "YouTube", "channel": "c", "request_goal": true}"#),
+            Command::Layout(cmd) if cmd.request_goal == Some(true)
+        ));
+    }
 
-                    // Handle request layouts list
-                    if cmd.request_layouts.unwrap_or(false) {
-                        self.server
-                            .send(message::RequestLayoutList)
-                            .into_actor(self)
-                            .then(|res, _, ctx| {
-                                if let Ok(list) = res {
-                                    let reply = serde_json::to_string(&message::ReplyInner {
-                                        tag: "layout_list".to_owned(),
-                                        message: serde_json::to_string(&list).unwrap(),
-                                    })
-                                    .unwrap();
-                                    ctx.text(reply);
-                                }
-                                fut::ready(())
-                            })
-                            .wait(ctx);
-                        return;
-                    }
+    /// An empty pong frame, masked as browsers must
+    const PONG: [u8; 6] = [0x8A, 0x80, 1, 2, 3, 4];
 
-                    // Handle request recent messages
-                    if cmd.request_messages.unwrap_or(false) {
-                        // Send recent messages
-                        self.server
-                            .send(message::RecentMessages)
-                            .into_actor(self)
-                            .then(|res, _, ctx| {
-                                if let Ok(messages) = res {
-                                    // Send each message as a chat_message event
-                                    for msg in messages {
-                                        let reply = serde_json::to_string(&message::ReplyInner {
-                                            tag: "chat_message".to_owned(),
-                                            message: msg.to_json(),
-                                        })
-                                        .unwrap();
-                                        ctx.text(reply);
-                                    }
-                                }
-                                fut::ready(())
-                            })
-                            .wait(ctx);
-
-                        // Also send the current featured message if any (full message data)
-                        self.server
-                            .send(message::RequestFeaturedMessage)
-                            .into_actor(self)
-                            .then(|res, _, ctx| {
-                                if let Ok(featured_msg) = res {
-                                    // Send full message JSON if featured, "null" if not
-                                    let msg_json = match featured_msg {
-                                        Some(msg) => msg.to_json(),
-                                        None => "null".to_string(),
-                                    };
-                                    let reply = serde_json::to_string(&message::ReplyInner {
-                                        tag: "feature_message".to_owned(),
-                                        message: msg_json,
-                                    })
-                                    .unwrap();
-                                    ctx.text(reply);
-                                }
-                                fut::ready(())
-                            })
-                            .wait(ctx);
-                        return;
+    /// Run a client over an in-memory socket for `run_for`, answering with a pong
+    /// every `pong_every` if set, and report whether the socket is still open
+    async fn stays_connected(pong_every: Option<Duration>, run_for: Duration) -> bool {
+        let dir = TempDir::new().unwrap();
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let server = ChatServer::with_database(
+            ExchangeRates::from_rates(HashMap::new()),
+            Arc::new(Mutex::new(layout_manager)),
+            Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let client = ChatClient {
+            id: 0,
+            role: ClientRole::Viewer,
+            server,
+            heartbeat: Heartbeat {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(100),
+            },
+            last_heartbeat_at: Instant::now(),
+            max_message_size: 1024,
+            fragments: None,
+        };
+
+        // Dropping the sender would end the stream, so it's held until the end
+        let (tx, rx) = mpsc::unbounded::<Result<Bytes, PayloadError>>();
+        if let Some(every) = pong_every {
+            let tx = tx.clone();
+            actix::spawn(async move {
+                loop {
+                    actix::clock::sleep(every).await;
+                    if tx.unbounded_send(Ok(Bytes::from_static(&PONG))).is_err() {
+                        break;
                     }
                 }
+            });
+        }
 
-                warn!(message = %text, "Unrecognized WebSocket message");
-            }
-            ws::Message::Binary(_) => warn!("Unexpected binary message"),
-            ws::Message::Close(reason) => {
-                debug!(client_id = %self.id, reason = ?reason, "Client disconnecting");
-                ctx.close(reason);
-                ctx.stop();
-            }
-            ws::Message::Continuation(_) => {
-                ctx.stop();
+        let mut output = ws::WebsocketContext::create(client, rx);
+        let closed = actix::clock::timeout(run_for, async {
+            // Pings (0x89) are expected; a close frame or the end of output means we were dropped
+            while let Some(frame) = output.next().await {
+                if frame.unwrap().first() == Some(&0x88) {
+                    return;
+                }
             }
-            ws::Message::Nop => (),
+        })
+        .await;
+        drop(tx);
+        closed.is_err()
+    }
+
+    #[actix::test]
+    async fn test_heartbeat_keeps_responsive_client() {
+        // Pongs well inside the timeout keep the client for several timeout windows
+        assert!(stays_connected(Some(Duration::from_millis(30)), Duration::from_millis(500)).await);
+        // A silent client is dropped once the timeout passes
+        assert!(!stays_connected(None, Duration::from_millis(500)).await);
+    }
+
+    /// A masked client frame; the zero mask leaves the payload as is
+    fn client_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first_byte];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Feed raw frames to an ingest client limited to 1KB messages and collect what it
+    /// writes back
+    async fn frames_out(input: Vec<Vec<u8>>) -> Vec<Bytes> {
+        let dir = TempDir::new().unwrap();
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let server = ChatServer::with_database(
+            ExchangeRates::from_rates(HashMap::new()),
+            Arc::new(Mutex::new(layout_manager)),
+            Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let client = ChatClient {
+            id: 0,
+            role: ClientRole::Ingest,
+            server,
+            heartbeat: Heartbeat::default(),
+            last_heartbeat_at: Instant::now(),
+            max_message_size: 1024,
+            fragments: None,
+        };
+
+        let (tx, rx) = mpsc::unbounded::<Result<Bytes, PayloadError>>();
+        for frame in input {
+            tx.unbounded_send(Ok(Bytes::from(frame))).unwrap();
         }
+        let codec = actix_http::ws::Codec::new().max_size(1024);
+        let mut output = ws::WebsocketContext::with_codec(client, rx, codec);
+        let mut frames = Vec::new();
+        let _ = actix::clock::timeout(Duration::from_millis(300), async {
+            while let Some(frame) = output.next().await {
+                frames.push(frame.unwrap());
+            }
+        })
+        .await;
+        drop(tx);
+        frames
+    }
+
+    /// A close frame with code 1009, "message too big"
+    const CLOSE_TOO_BIG: [u8; 4] = [0x88, 0x02, 0x03, 0xF1];
+
+    #[actix::test]
+    async fn test_fragmented_messages_are_limited() {
+        // Fragments that are each small but add up to more than the limit
+        let chunk = [b' '; 600];
+        let fragmented = vec![client_frame(0x01, &chunk), client_frame(0x00, &chunk)];
+        assert_eq!(frames_out(fragmented).await, [&CLOSE_TOO_BIG[..]]);
+
+        // Fragmented messages within the limit are reassembled and handled
+        let json = br#"{"request_server_stats": true}"#;
+        let fragmented = vec![
+            client_frame(0x01, &json[..10]),
+            client_frame(0x00, &json[10..20]),
+            client_frame(0x80, &json[20..]),
+        ];
+        let frames = frames_out(fragmented).await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][0], 0x81);
+        assert!(String::from_utf8_lossy(&frames[0]).contains("server_stats"));
+    }
+
+    #[actix::test]
+    async fn test_tagged_command_is_handled() {
+        let tagged = br#"{"type": "layout", "data": {"request_server_stats": true}}"#;
+        let frames = frames_out(vec![client_frame(0x81, tagged)]).await;
+        assert_eq!(frames.len(), 1);
+        assert!(String::from_utf8_lossy(&frames[0]).contains("server_stats"));
+
+        // An unknown type gets no reply
+        let unknown = br#"{"type": "stats", "data": {"request_server_stats": true}}"#;
+        assert!(frames_out(vec![client_frame(0x81, unknown)])
+            .await
+            .is_empty());
     }
 }
@@ -0,0 +1,164 @@
+use actix_web::error::PayloadError;
+use actix_web::web::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::warn;
+
+/// Refuses WebSocket frames longer than `max_size` from their header. actix's codec only
+/// rejects an oversized frame once all of it has arrived, buffering it meanwhile, so a
+/// client could otherwise claim a huge length and keep sending. Bytes are passed through
+/// untouched; only the frame headers are read as they stream past.
+pub struct FrameLimit<S> {
+    inner: S,
+    max_size: usize,
+    /// The current frame's header, while it is incomplete
+    header: Vec<u8>,
+    /// Payload bytes left in the current frame
+    remaining: u64,
+    failed: bool,
+}
+
+impl<S> FrameLimit<S> {
+    pub fn new(inner: S, max_size: usize) -> Self {
+        Self {
+            inner,
+            max_size,
+            header: Vec::with_capacity(14),
+            remaining: 0,
+            failed: false,
+        }
+    }
+
+    /// Follow frame boundaries through `chunk`, returning the first declared length over
+    /// the limit
+    fn scan(&mut self, mut chunk: &[u8]) -> Option<u64> {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let skip = self.remaining.min(chunk.len() as u64);
+                self.remaining -= skip;
+                chunk = &chunk[skip as usize..];
+                continue;
+            }
+
+            self.header.push(chunk[0]);
+            chunk = &chunk[1..];
+            let Some(length) = header_length(&self.header) else {
+                continue;
+            };
+            self.header.clear();
+            if length > self.max_size as u64 {
+                return Some(length);
+            }
+            self.remaining = length;
+        }
+        None
+    }
+}
+
+/// The payload length a complete frame header declares, or None if more bytes are needed
+fn header_length(header: &[u8]) -> Option<u64> {
+    let second = *header.get(1)?;
+    let mask = if second & 0x80 != 0 { 4 } else { 0 };
+    let (extended, length) = match second & 0x7F {
+        126 => (
+            2,
+            u64::from(u16::from_be_bytes(header.get(2..4)?.try_into().ok()?)),
+        ),
+        127 => (8, u64::from_be_bytes(header.get(2..10)?.try_into().ok()?)),
+        length => (0, u64::from(length)),
+    };
+    (header.len() == 2 + extended + mask).then_some(length)
+}
+
+impl<S> Stream for FrameLimit<S>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+        let chunk = match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            other => return other,
+        };
+        if let Some(length) = self.scan(&chunk) {
+            warn!(
+                length,
+                limit = self.max_size,
+                "WebSocket frame too large, closing"
+            );
+            self.failed = true;
+            return Poll::Ready(Some(Err(PayloadError::Overflow)));
+        }
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{stream, StreamExt};
+
+    /// A masked frame header declaring `length` payload bytes
+    fn header(length: u64) -> Vec<u8> {
+        let mut header = vec![0x81];
+        match length {
+            0..=125 => header.push(0x80 | length as u8),
+            126..=0xFFFF => {
+                header.push(0x80 | 126);
+                header.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            _ => {
+                header.push(0x80 | 127);
+                header.extend_from_slice(&length.to_be_bytes());
+            }
+        }
+        header.extend_from_slice(&[0, 0, 0, 0]);
+        header
+    }
+
+    async fn run(chunks: Vec<Vec<u8>>, max_size: usize) -> Vec<Result<usize, PayloadError>> {
+        let chunks = chunks.into_iter().map(|chunk| Ok(Bytes::from(chunk)));
+        FrameLimit::new(stream::iter(chunks), max_size)
+            .map(|chunk| chunk.map(|chunk| chunk.len()))
+            .collect()
+            .await
+    }
+
+    #[actix_web::test]
+    async fn test_frames_within_limit_pass() {
+        // A 300-byte frame split mid-header and mid-payload, then a small one
+        let mut bytes = header(300);
+        bytes.extend_from_slice(&[b'x'; 300]);
+        bytes.extend_from_slice(&header(5));
+        bytes.extend_from_slice(b"hello");
+        let chunks = vec![
+            bytes[..3].to_vec(),
+            bytes[3..100].to_vec(),
+            bytes[100..].to_vec(),
+        ];
+
+        let out = run(chunks, 300).await;
+        assert_eq!(out.len(), 3);
+        assert!(out.iter().all(Result::is_ok));
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_frame_refused_from_header() {
+        // Only the header of a frame claiming a terabyte: refused before any payload
+        let out = run(vec![header(1 << 40), vec![b'x'; 1024]], 256 * 1024).await;
+        assert_eq!(out.len(), 1);
+        assert!(matches!(out[0], Err(PayloadError::Overflow)));
+
+        // Just over the limit after a frame that fit
+        let mut bytes = header(10);
+        bytes.extend_from_slice(&[b'x'; 10]);
+        bytes.extend_from_slice(&header(1025));
+        let out = run(vec![bytes], 1024).await;
+        assert!(matches!(out[..], [Err(PayloadError::Overflow)]));
+    }
+}
@@ -1,11 +1,20 @@
-use crate::layout::Layout;
+use super::client::ClientRole;
+use crate::database::Ban;
+use crate::goal::GoalProgress;
+use crate::layout::{ElementConfig, ImportReport, Layout, LayoutVersion};
 use crate::message::Message as ChatMessage;
+use crate::poll::Poll;
 use actix::{Message, Recipient};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Client hello message.
 pub struct Connect {
     pub recipient: Recipient<Reply>,
+    /// Used to close the socket when the server shuts down
+    pub close: Recipient<Close>,
+    /// Only ingest clients are sent messages awaiting moderation
+    pub role: ClientRole,
 }
 
 impl Message for Connect {
@@ -29,6 +38,20 @@ impl Message for Reply {
     type Result = ();
 }
 
+/// Ask a client to send a close frame and stop
+pub struct Close;
+
+impl Message for Close {
+    type Result = ();
+}
+
+/// Server is shutting down: tell clients, close their sockets and flush the database
+pub struct Shutdown;
+
+impl Message for Shutdown {
+    type Result = ();
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ReplyInner {
     pub tag: String,
@@ -47,6 +70,8 @@ impl Message for Content {
 /// Feature/Unfeature message - now includes full message data for decoupled rendering
 pub struct FeatureMessage {
     pub id: Option<uuid::Uuid>,
+    /// Unfeature after this many seconds; None or 0 leaves it featured until changed
+    pub duration_secs: Option<u64>,
 }
 
 impl Message for FeatureMessage {
@@ -61,6 +86,40 @@ impl Message for RequestFeaturedMessage {
     type Result = Option<ChatMessage>;
 }
 
+/// A message that was featured, for the recently featured history
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeaturedEntry {
+    pub id: uuid::Uuid,
+    /// Unix milliseconds
+    pub featured_at: i64,
+}
+
+/// Look up one message by id, in memory and then (paid messages) in the database
+pub struct RequestMessageById {
+    pub id: uuid::Uuid,
+}
+
+impl Message for RequestMessageById {
+    type Result = Option<ChatMessage>;
+}
+
+/// Show a stored message again as a `replay_message`, without storing or counting it twice
+pub struct ReplayMessage {
+    pub id: uuid::Uuid,
+}
+
+impl Message for ReplayMessage {
+    /// Returns the message replayed, None if it wasn't found
+    type Result = Option<ChatMessage>;
+}
+
+/// Request the recently featured messages, newest first
+pub struct RequestFeaturedHistory;
+
+impl Message for RequestFeaturedHistory {
+    type Result = Vec<FeaturedEntry>;
+}
+
 /// Request paid messages with time filter (hours)
 pub struct PaidMessagesSince {
     pub hours: u32,
@@ -70,20 +129,261 @@ impl Message for PaidMessagesSince {
     type Result = Vec<ChatMessage>;
 }
 
-/// Request for paid messages.
-pub struct PaidMessages;
+/// Request paid messages worth at least `min_usd` from the last `hours`
+pub struct PaidMessagesAboveAmount {
+    pub min_usd: f64,
+    pub hours: u32,
+}
 
-impl Message for PaidMessages {
+impl Message for PaidMessagesAboveAmount {
     type Result = Vec<ChatMessage>;
 }
 
 /// Request for recent chat messages.
 pub struct RecentMessages;
 
+/// Request recent messages for one client: as many as its layout's `maxMessages`
+pub struct RecentMessagesFor {
+    pub client_id: usize,
+}
+
+impl Message for RecentMessagesFor {
+    type Result = Vec<ChatMessage>;
+}
+
 impl Message for RecentMessages {
     type Result = Vec<ChatMessage>;
 }
 
+/// Find messages in the session whose username or text contains `query`, ignoring case
+pub struct SearchRecent {
+    pub query: String,
+}
+
+impl Message for SearchRecent {
+    /// Oldest first; nothing for a blank query
+    type Result = Vec<ChatMessage>;
+}
+
+/// Forget every message in the session, optionally deleting saved paid messages too
+pub struct ClearMessages {
+    pub purge_db: bool,
+}
+
+impl Message for ClearMessages {
+    type Result = ();
+}
+
+/// Request the running total of paid messages this session (USD).
+pub struct RequestSessionTotal;
+
+impl Message for RequestSessionTotal {
+    type Result = f64;
+}
+
+/// Turn moderated mode on or off. While on, incoming chat waits in a queue,
+/// shown only to ingest clients, until it is approved or rejected.
+pub struct SetModerated {
+    pub enabled: bool,
+}
+
+impl Message for SetModerated {
+    type Result = ();
+}
+
+/// Inject a made-up message through the normal ingest path, for testing overlays
+pub struct TestMessage {
+    pub platform: String,
+    pub username: String,
+    pub message: String,
+    /// In `currency`; 0 sends plain chat
+    pub amount: f64,
+    pub currency: String,
+}
+
+impl Message for TestMessage {
+    type Result = ();
+}
+
+/// Let each user's unpaid chat through at most once every `secs` seconds (0 turns it off)
+pub struct SetSlowMode {
+    pub secs: u64,
+}
+
+impl Message for SetSlowMode {
+    type Result = ();
+}
+
+/// Release a message waiting for moderation to every client
+pub struct ApproveMessage {
+    pub id: uuid::Uuid,
+}
+
+impl Message for ApproveMessage {
+    type Result = ();
+}
+
+/// Drop a message waiting for moderation
+pub struct RejectMessage {
+    pub id: uuid::Uuid,
+}
+
+impl Message for RejectMessage {
+    type Result = ();
+}
+
+/// Moderated mode and the messages waiting for approval, oldest first
+#[derive(Debug, Clone)]
+pub struct ModerationState {
+    pub enabled: bool,
+    pub pending: Vec<ChatMessage>,
+    /// Slow mode window in seconds, 0 when off
+    pub slow_mode_secs: u64,
+}
+
+pub struct RequestModeration;
+
+impl Message for RequestModeration {
+    type Result = ModerationState;
+}
+
+/// Re-read the alert sound mapping. On failure the current mapping is kept.
+pub struct ReloadAlertSounds;
+
+impl Message for ReloadAlertSounds {
+    type Result = Result<(), String>;
+}
+
+/// Set the donation goal, replacing any current one. Fails unless the target is positive.
+pub struct SetGoal {
+    pub label: String,
+    pub target_usd: f64,
+}
+
+impl Message for SetGoal {
+    type Result = Result<(), String>;
+}
+
+/// Remove the donation goal
+pub struct ResetGoal;
+
+impl Message for ResetGoal {
+    type Result = ();
+}
+
+/// Request the donation goal and progress toward it, if a goal is set.
+pub struct RequestGoal;
+
+impl Message for RequestGoal {
+    type Result = Option<GoalProgress>;
+}
+
+/// Connected clients and viewer counts, for the dashboard
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    pub clients: usize,
+    pub viewers_total: usize,
+    pub viewers_by_platform: HashMap<String, usize>,
+}
+
+/// Spoken alert for a paid message, broadcast as `tts_audio` for overlays to play in order
+#[derive(Serialize, Debug, Clone)]
+pub struct TtsAudio {
+    pub id: uuid::Uuid,
+    /// HTML-escaped, like `chat_message` usernames
+    pub username: String,
+    pub amount: f64,
+    /// What was read aloud
+    pub text: String,
+    /// URL or `data:` URI of the audio
+    pub audio: String,
+}
+
+impl Message for TtsAudio {
+    type Result = ();
+}
+
+/// Start a poll, replacing any running one. Fails if the question or options are invalid.
+pub struct StartPoll {
+    pub question: String,
+    pub options: Vec<String>,
+    pub multi_vote: bool,
+}
+
+impl Message for StartPoll {
+    type Result = Result<(), String>;
+}
+
+/// End the running poll, broadcasting and saving its final result
+pub struct EndPoll;
+
+impl Message for EndPoll {
+    type Result = ();
+}
+
+/// Vote in the running poll for 1-based `choices`.
+/// Votes without a voter (e.g. from the dashboard) aren't deduplicated.
+pub struct Vote {
+    pub voter: Option<String>,
+    pub choices: Vec<usize>,
+}
+
+impl Message for Vote {
+    type Result = ();
+}
+
+/// Request the running poll, if any.
+pub struct RequestPoll;
+
+impl Message for RequestPoll {
+    type Result = Option<Poll>;
+}
+
+/// Request current server stats.
+pub struct RequestServerStats;
+
+impl Message for RequestServerStats {
+    type Result = ServerStats;
+}
+
+/// One per-minute viewer count sample
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ViewerSample {
+    /// Unix milliseconds
+    pub at: i64,
+    pub viewers: usize,
+}
+
+/// Current viewer counts with recent history per platform, for `/api/viewers`
+#[derive(Serialize, Debug)]
+pub struct Viewers {
+    pub current: HashMap<String, usize>,
+    /// Oldest first, one sample a minute for the last hour
+    pub history: HashMap<String, Vec<ViewerSample>>,
+}
+
+/// Request the current viewer counts and their recent history
+pub struct RequestViewers;
+
+impl Message for RequestViewers {
+    type Result = Viewers;
+}
+
+/// Render the server's metrics in Prometheus text format
+pub struct RequestMetrics;
+
+impl Message for RequestMetrics {
+    type Result = String;
+}
+
+/// Readiness check: answering proves the actor is processing messages.
+/// Fails if the database is unusable.
+pub struct Ping;
+
+impl Message for Ping {
+    type Result = Result<(), String>;
+}
+
 /// Remove message
 pub struct RemoveMessage {
     pub id: uuid::Uuid,
@@ -93,6 +393,17 @@ impl Message for RemoveMessage {
     type Result = ();
 }
 
+/// Correct a stored paid message's amount, given in `currency`
+pub struct EditPaidMessage {
+    pub id: uuid::Uuid,
+    pub amount: f64,
+    pub currency: String,
+}
+
+impl Message for EditPaidMessage {
+    type Result = Result<(), String>;
+}
+
 /// Request for view counts.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ViewCount {
@@ -105,6 +416,56 @@ impl Message for ViewCount {
     type Result = ();
 }
 
+/// Drop every future message from `username` on `platform` (case-insensitive)
+pub struct BanUser {
+    pub platform: String,
+    pub username: String,
+}
+
+impl Message for BanUser {
+    type Result = Result<(), String>;
+}
+
+pub struct UnbanUser {
+    pub platform: String,
+    pub username: String,
+}
+
+impl Message for UnbanUser {
+    type Result = Result<(), String>;
+}
+
+/// List the ban list, newest first
+pub struct RequestBans;
+
+impl Message for RequestBans {
+    type Result = Result<Vec<Ban>, String>;
+}
+
+/// Messages for /api/messages, newest first. Every filter that is set must match.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct RequestMessages {
+    pub platform: Option<String>,
+    /// true for paid messages from the database only, false for unpaid chat only
+    pub paid: Option<bool>,
+    pub since_hours: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+impl Message for RequestMessages {
+    type Result = Vec<ChatMessage>;
+}
+
+/// Show or hide one platform's chat and viewers without stopping its bridge
+pub struct SetPlatformEnabled {
+    pub platform: String,
+    pub enabled: bool,
+}
+
+impl Message for SetPlatformEnabled {
+    type Result = ();
+}
+
 // ============================================================================
 // Layout Messages
 // ============================================================================
@@ -118,8 +479,52 @@ impl Message for LayoutUpdate {
     type Result = ();
 }
 
-/// Switch the active layout (broadcasts to all clients)
+/// Compile and broadcast a layout to its subscribers without saving it
+pub struct PreviewLayout {
+    pub layout: Layout,
+}
+
+impl Message for PreviewLayout {
+    type Result = ();
+}
+
+/// Compile an SCSS snippet, with an optional layout prelude, returning its CSS or the error
+pub struct CompileScss {
+    pub source: String,
+    pub prelude: Option<String>,
+}
+
+impl Message for CompileScss {
+    type Result = Result<String, String>;
+}
+
+/// Replace one element of a saved layout and broadcast only that element.
+/// Results are as for `SaveLayout`.
+pub struct LayoutElementUpdate {
+    pub layout_name: String,
+    pub element_id: String,
+    pub config: ElementConfig,
+}
+
+impl Message for LayoutElementUpdate {
+    type Result = Result<Vec<String>, Vec<String>>;
+}
+
+/// Payload of a `layout_element_update` broadcast
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutElementDelta<'a> {
+    pub layout_name: &'a str,
+    pub element_id: &'a str,
+    pub config: &'a ElementConfig,
+}
+
+/// Output shown by overlays that don't name one, and by the REST/list APIs
+pub const DEFAULT_OUTPUT: &str = "overlay";
+
+/// Switch the layout shown on an output (e.g. one OBS scene)
 pub struct SwitchLayout {
+    pub output: String,
     pub name: String,
 }
 
@@ -132,8 +537,59 @@ pub struct SaveLayout {
     pub layout: Layout,
 }
 
+/// Succeeds with any SCSS compilation errors (the layout is saved regardless),
+/// or fails with every validation problem, or a single I/O error
 impl Message for SaveLayout {
-    type Result = Result<(), String>;
+    type Result = Result<Vec<String>, Vec<String>>;
+}
+
+/// Why a reply couldn't be built. Broadcasts log it and skip the reply, so one bad
+/// message can't take the ChatServer (and every connection) down with it.
+#[derive(Debug)]
+pub enum BroadcastError {
+    Serialize(serde_json::Error),
+    Render(askama::Error),
+}
+
+impl std::fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BroadcastError::Serialize(e) => write!(f, "Failed to serialize reply: {}", e),
+            BroadcastError::Render(e) => write!(f, "Failed to render chat message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BroadcastError {}
+
+impl From<serde_json::Error> for BroadcastError {
+    fn from(e: serde_json::Error) -> Self {
+        BroadcastError::Serialize(e)
+    }
+}
+
+impl From<askama::Error> for BroadcastError {
+    fn from(e: askama::Error) -> Self {
+        BroadcastError::Render(e)
+    }
+}
+
+/// Why a layout operation failed, so REST callers can pick a status code
+#[derive(Debug)]
+pub enum LayoutError {
+    NotFound(String),
+    /// The operation conflicts with server state (e.g. the layout is active)
+    Conflict(String),
+    Other(String),
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::NotFound(name) => write!(f, "Layout '{}' not found", name),
+            LayoutError::Conflict(e) | LayoutError::Other(e) => f.write_str(e),
+        }
+    }
 }
 
 /// Delete a layout from disk
@@ -142,10 +598,39 @@ pub struct DeleteLayout {
 }
 
 impl Message for DeleteLayout {
+    type Result = Result<(), LayoutError>;
+}
+
+/// Overwrite a layout with the factory default, keeping its name (broadcasts it)
+pub struct ResetLayout {
+    pub name: String,
+}
+
+impl Message for ResetLayout {
+    type Result = Result<(), String>;
+}
+
+/// Copy a layout to a new name
+pub struct DuplicateLayout {
+    pub from: String,
+    pub to: String,
+}
+
+impl Message for DuplicateLayout {
+    type Result = Result<(), String>;
+}
+
+/// Rename a layout, keeping it active and subscribed if it was
+pub struct RenameLayout {
+    pub from: String,
+    pub to: String,
+}
+
+impl Message for RenameLayout {
     type Result = Result<(), String>;
 }
 
-/// Request the current active layout
+/// Request the active layout of the default output
 pub struct RequestLayout;
 
 impl Message for RequestLayout {
@@ -168,13 +653,63 @@ pub struct RequestLayoutList;
 #[derive(Serialize, Clone)]
 pub struct LayoutListResponse {
     pub layouts: Vec<String>,
+    /// Active layout of the default output
     pub active: String,
+    /// Active layout of every output, keyed by output name
+    pub outputs: HashMap<String, String>,
 }
 
 impl Message for RequestLayoutList {
     type Result = LayoutListResponse;
 }
 
+/// List the saved history of a layout, newest first
+pub struct ListLayoutHistory {
+    pub name: String,
+}
+
+impl Message for ListLayoutHistory {
+    type Result = Result<Vec<LayoutVersion>, String>;
+}
+
+/// Restore a layout to a version from its history (broadcasts the restored layout)
+pub struct RestoreLayoutVersion {
+    pub name: String,
+    pub version: u32,
+}
+
+impl Message for RestoreLayoutVersion {
+    type Result = Result<(), String>;
+}
+
+/// Export layouts as a JSON bundle (empty list = all layouts)
+pub struct ExportLayouts {
+    pub names: Vec<String>,
+}
+
+impl Message for ExportLayouts {
+    type Result = Result<String, String>;
+}
+
+/// Export a layout as a standalone HTML snapshot
+pub struct ExportOverlayHtml {
+    pub layout_name: String,
+}
+
+impl Message for ExportOverlayHtml {
+    type Result = Result<String, String>;
+}
+
+/// Import a JSON bundle of layouts, reporting per-layout results
+pub struct ImportLayouts {
+    pub bundle: String,
+    pub overwrite: bool,
+}
+
+impl Message for ImportLayouts {
+    type Result = Result<ImportReport, String>;
+}
+
 /// Subscribe a client to a specific layout (only receives updates for that layout)
 pub struct SubscribeLayout {
     pub client_id: usize,
@@ -184,3 +719,23 @@ pub struct SubscribeLayout {
 impl Message for SubscribeLayout {
     type Result = ();
 }
+
+/// Only send a client broadcasts with these tags; an empty list restores all of them
+pub struct SubscribeTags {
+    pub client_id: usize,
+    pub tags: Vec<String>,
+}
+
+impl Message for SubscribeTags {
+    type Result = ();
+}
+
+/// Subscribe a client to whatever layout is active on an output, following switches
+pub struct SubscribeOutput {
+    pub client_id: usize,
+    pub output: String,
+}
+
+impl Message for SubscribeOutput {
+    type Result = Result<Layout, String>;
+}
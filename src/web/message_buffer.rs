@@ -0,0 +1,132 @@
+use crate::message::Message as ChatMessage;
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// The session's chat history, bounded so a long stream doesn't grow it forever.
+/// Once `capacity` is exceeded the oldest unpaid messages are evicted; paid messages
+/// are kept since they are few and already bounded by the session window.
+pub struct MessageBuffer {
+    capacity: usize,
+    messages: HashMap<Uuid, ChatMessage>,
+    /// Ids in arrival order, oldest first
+    order: VecDeque<Uuid>,
+}
+
+impl MessageBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Add a message as the newest, evicting old unpaid messages beyond the capacity.
+    /// A message already held is replaced in place.
+    pub fn insert(&mut self, chat_msg: ChatMessage) {
+        let id = chat_msg.id;
+        if self.messages.insert(id, chat_msg).is_some() {
+            return;
+        }
+        self.order.push_back(id);
+
+        while self.messages.len() > self.capacity {
+            let Some(index) = self
+                .order
+                .iter()
+                .position(|id| !self.messages[id].is_premium())
+            else {
+                break;
+            };
+            if let Some(oldest) = self.order.remove(index) {
+                self.messages.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&ChatMessage> {
+        self.messages.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut ChatMessage> {
+        self.messages.get_mut(id)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.messages.contains_key(id)
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<ChatMessage> {
+        let removed = self.messages.remove(id)?;
+        self.order.retain(|held| held != id);
+        Some(removed)
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.order.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// All held messages, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.messages.values()
+    }
+
+    /// The newest `limit` messages in arrival order, oldest first
+    pub fn recent(&self, limit: usize) -> impl Iterator<Item = &ChatMessage> {
+        self.order
+            .iter()
+            .skip(self.order.len().saturating_sub(limit))
+            .map(|id| &self.messages[id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(text: &str, amount: f64) -> ChatMessage {
+        ChatMessage {
+            message: text.to_string(),
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evicts_oldest_beyond_capacity() {
+        let mut buffer = MessageBuffer::new(10);
+        for i in 0..1000 {
+            buffer.insert(chat(&i.to_string(), 0.0));
+        }
+
+        assert_eq!(buffer.len(), 10);
+        let texts: Vec<&str> = buffer.recent(10).map(|m| m.message.as_str()).collect();
+        let newest: Vec<String> = (990..1000).map(|i| i.to_string()).collect();
+        assert_eq!(texts, newest);
+        assert_eq!(buffer.recent(3).count(), 3);
+    }
+
+    #[test]
+    fn test_paid_messages_are_kept() {
+        let mut buffer = MessageBuffer::new(3);
+        let donation = chat("thanks", 5.0);
+        buffer.insert(donation.clone());
+        for i in 0..100 {
+            buffer.insert(chat(&i.to_string(), 0.0));
+        }
+
+        assert_eq!(buffer.len(), 3);
+        assert!(buffer.contains(&donation.id));
+        let texts: Vec<&str> = buffer.recent(3).map(|m| m.message.as_str()).collect();
+        assert_eq!(texts, ["thanks", "98", "99"]);
+
+        buffer.remove(&donation.id);
+        assert_eq!(buffer.recent(3).count(), 2);
+    }
+}
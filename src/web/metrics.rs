@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Counters behind the Prometheus `/metrics` endpoint.
+/// Owned by the ChatServer actor; gauges are read from its state when rendering.
+#[derive(Default)]
+pub struct Metrics {
+    /// Chat messages broadcast, by platform
+    messages: BTreeMap<String, u64>,
+    /// Messages dropped before broadcast, by (platform, reason)
+    dropped: BTreeMap<(String, &'static str), u64>,
+    /// Paid messages broadcast, by platform
+    paid_messages: BTreeMap<String, u64>,
+    /// Paid message value received since startup, in USD
+    donations_usd: f64,
+}
+
+/// Point-in-time values rendered as gauges
+pub struct Gauges<'a> {
+    pub connected_clients: usize,
+    pub session_total_usd: f64,
+    pub viewers: &'a HashMap<String, usize>,
+}
+
+impl Metrics {
+    /// Count a broadcast message worth `usd` (0 for regular chat)
+    pub fn record_message(&mut self, platform: &str, usd: f64) {
+        *self.messages.entry(platform.to_string()).or_default() += 1;
+        if usd > 0.0 {
+            *self.paid_messages.entry(platform.to_string()).or_default() += 1;
+            self.donations_usd += usd;
+        }
+    }
+
+    /// Count a message that was not broadcast
+    pub fn record_dropped(&mut self, platform: &str, reason: &'static str) {
+        *self
+            .dropped
+            .entry((platform.to_string(), reason))
+            .or_default() += 1;
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "stream_nexus_chat_messages_total",
+            "counter",
+            "Chat messages broadcast",
+        );
+        for (platform, count) in &self.messages {
+            let _ = writeln!(
+                out,
+                "stream_nexus_chat_messages_total{{platform=\"{}\"}} {}",
+                escape_label(platform),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "stream_nexus_chat_messages_dropped_total",
+            "counter",
+            "Chat messages dropped before broadcast",
+        );
+        for ((platform, reason), count) in &self.dropped {
+            let _ = writeln!(
+                out,
+                "stream_nexus_chat_messages_dropped_total{{platform=\"{}\",reason=\"{}\"}} {}",
+                escape_label(platform),
+                reason,
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "stream_nexus_paid_messages_total",
+            "counter",
+            "Paid messages broadcast",
+        );
+        for (platform, count) in &self.paid_messages {
+            let _ = writeln!(
+                out,
+                "stream_nexus_paid_messages_total{{platform=\"{}\"}} {}",
+                escape_label(platform),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "stream_nexus_donations_usd_total",
+            "counter",
+            "Paid message value received since startup, in USD",
+        );
+        let _ = writeln!(
+            out,
+            "stream_nexus_donations_usd_total {}",
+            self.donations_usd
+        );
+
+        header(
+            &mut out,
+            "stream_nexus_session_total_usd",
+            "gauge",
+            "Paid message value in the current session window, in USD",
+        );
+        let _ = writeln!(
+            out,
+            "stream_nexus_session_total_usd {}",
+            gauges.session_total_usd
+        );
+
+        header(
+            &mut out,
+            "stream_nexus_connected_clients",
+            "gauge",
+            "Open WebSocket connections",
+        );
+        let _ = writeln!(
+            out,
+            "stream_nexus_connected_clients {}",
+            gauges.connected_clients
+        );
+
+        header(
+            &mut out,
+            "stream_nexus_viewers",
+            "gauge",
+            "Latest viewer count reported",
+        );
+        let viewers: BTreeMap<_, _> = gauges.viewers.iter().collect();
+        for (platform, count) in viewers {
+            let _ = writeln!(
+                out,
+                "stream_nexus_viewers{{platform=\"{}\"}} {}",
+                escape_label(platform),
+                count
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Platform names come from bridges, so escape them for label values
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics::default();
+        metrics.record_message("YouTube", 0.0);
+        metrics.record_message("YouTube", 5.0);
+        metrics.record_message("Kick", 0.0);
+        metrics.record_dropped("Kick", "rate_limited");
+        let viewers = HashMap::from([("Kick".to_string(), 12)]);
+
+        let text = metrics.render(&Gauges {
+            connected_clients: 3,
+            session_total_usd: 25.5,
+            viewers: &viewers,
+        });
+        for line in [
+            "# TYPE stream_nexus_chat_messages_total counter",
+            "stream_nexus_chat_messages_total{platform=\"Kick\"} 1",
+            "stream_nexus_chat_messages_total{platform=\"YouTube\"} 2",
+            "stream_nexus_chat_messages_dropped_total{platform=\"Kick\",reason=\"rate_limited\"} 1",
+            "stream_nexus_paid_messages_total{platform=\"YouTube\"} 1",
+            "stream_nexus_donations_usd_total 5",
+            "stream_nexus_session_total_usd 25.5",
+            "stream_nexus_connected_clients 3",
+            "stream_nexus_viewers{platform=\"Kick\"} 12",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {:?} in\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
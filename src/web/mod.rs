@@ -1,23 +1,57 @@
+mod avatar;
 mod client;
+mod frame_limit;
 mod message;
+mod message_buffer;
+mod metrics;
+mod preview;
+mod rate_limit;
 mod server;
+mod sse;
+mod tls;
 
+pub use avatar::{AvatarConfig, AvatarProxy};
 pub use client::ChatClient;
+use client::ClientRole;
+pub use client::Heartbeat;
+pub use message::BroadcastError;
 pub use message::Content as ChatMessage;
-pub use message::PaidMessages;
+pub use message::RemoveMessage;
+pub use message::Shutdown;
+pub use message::TtsAudio;
+pub use message::ViewCount;
 pub use server::ChatServer;
+pub use tls::tls_config;
 
-use actix::Addr;
-use actix_web::{http::header, web, Error, HttpRequest, HttpResponse, Responder};
+use actix::{Actor, Addr};
+use actix_cors::Cors;
+use actix_web::{http::header, web, Error, HttpMessage, HttpRequest, HttpResponse, Responder};
 use actix_web_actors::ws;
 use askama::Template;
-use std::time::{Duration, Instant};
-use tracing::debug;
+use futures::StreamExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
 
-use crate::layout::Layout;
+use crate::layout::{Layout, WebFont};
+use crate::message::LivestreamUpdate;
+use crate::platforms;
+use crate::sneed_env;
 
+/// Defaults for `WS_HEARTBEAT_SECS` and `WS_TIMEOUT_SECS`
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default for `WS_MAX_MESSAGE_KB`
+const MAX_MESSAGE_KB: usize = 256;
+/// Time between telling clients we're shutting down and stopping the HTTP server
+pub const SHUTDOWN_DRAIN: Duration = Duration::from_secs(1);
+/// How long HTTP workers get to finish in-flight requests once stopped
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Default for `STATIC_MAX_AGE_SECS`
+const STATIC_MAX_AGE: u32 = 300;
+/// Already-compressed file types `Compress` would otherwise gzip again. It skips images
+/// (other than SVG) by itself.
+const PRECOMPRESSED_TYPES: &[&str] = &["audio/mpeg", "audio/ogg"];
 
 #[derive(Template)]
 #[template(path = "home.html")]
@@ -42,15 +76,95 @@ pub async fn home() -> impl Responder {
 #[template(path = "frame.html")]
 struct LayoutTemplate {
     layout_name: String,
+    /// Set when the view follows an output instead of pinning `layout_name`
+    output: Option<String>,
+    /// Static elements to render instead of loading the overlay script
+    preview: Option<Vec<preview::PreviewElement>>,
+    /// The layout as JSON, applied before the WebSocket connects so the first paint is styled
+    layout_json: Option<String>,
+    /// The layout's web fonts, preloaded so OBS doesn't paint chat in a fallback font
+    fonts: Vec<WebFont>,
+    /// `@font-face` rules for `fonts`
+    font_css: String,
+    /// Inlined in place of the `/static` stylesheets, for exports opened without the server
+    stylesheet: Option<String>,
+}
+
+/// Stylesheets an overlay page loads from `/static`, inlined by `export_overlay_html`.
+/// `style.css` imports `overlay-elements.css`, so that comes first in place of the import;
+/// `user-colors.css` is optional.
+const OVERLAY_STYLESHEETS: &[&str] = &[
+    "public/overlay-elements.css",
+    "public/style.css",
+    "public/user-colors.css",
+];
+
+/// The layout as one standalone HTML file: the static preview with the overlay
+/// stylesheets and each element's compiled SCSS inlined. It's a snapshot with sample
+/// chat; nothing connects or updates.
+fn export_overlay_html(layout: &Layout) -> String {
+    let mut elements = preview::elements(layout);
+    for element in &mut elements {
+        let compiled = layout.elements[&element.id]
+            .style
+            .compiled_css
+            .as_deref()
+            .unwrap_or_default();
+        if !compiled.is_empty() {
+            element.style = format!("{} {}", element.style, compiled).trim().to_string();
+        }
+    }
+
+    let stylesheet = OVERLAY_STYLESHEETS
+        .iter()
+        .filter_map(|path| match std::fs::read_to_string(path) {
+            Ok(css) => Some(
+                css.lines()
+                    .filter(|line| !line.trim_start().starts_with("@import"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            Err(e) => {
+                debug!(path, error = %e, "Stylesheet left out of overlay export");
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    LayoutTemplate {
+        layout_name: layout.name.clone(),
+        output: None,
+        preview: Some(elements),
+        layout_json: None,
+        fonts: layout.web_fonts(),
+        font_css: layout.font_face_css(),
+        stylesheet: Some(stylesheet),
+    }
+    .to_string()
+}
+
+/// Serialize a layout for a `<script type="application/json">` block. `<` is escaped so
+/// text like `</script>` in an element can't end the block early.
+fn embedded_layout(layout: &Layout) -> Option<String> {
+    match serde_json::to_string(layout) {
+        Ok(json) => Some(json.replace('<', "\\u003c")),
+        Err(e) => {
+            warn!(layout = %layout.name, error = %e, "Failed to embed layout");
+            None
+        }
+    }
 }
 
 /// Query parameters for /layout endpoint
 #[derive(serde::Deserialize)]
 pub struct LayoutQuery {
     name: Option<String>,
+    output: Option<String>,
 }
 
 /// GET /layout?name= - Load a specific layout by name
+/// GET /layout?output= - Show whatever layout is active on an output (default "overlay")
 #[actix_web::get("/layout")]
 pub async fn layout_view(req: HttpRequest, query: web::Query<LayoutQuery>) -> impl Responder {
     let chat_server = req
@@ -58,34 +172,103 @@ pub async fn layout_view(req: HttpRequest, query: web::Query<LayoutQuery>) -> im
         .expect("ChatServer missing in app data!")
         .clone();
 
-    // If name is provided, try to load that layout; otherwise use active layout
-    let layout_name = if let Some(name) = &query.name {
-        // Verify the layout exists
-        match chat_server
-            .send(message::RequestLayoutByName {
-                name: name.clone(),
-            })
-            .await
-        {
-            Ok(Some(_)) => name.clone(),
-            Ok(None) => {
-                return HttpResponse::NotFound().body(format!("Layout '{}' not found", name));
-            }
-            Err(e) => {
-                return HttpResponse::InternalServerError().body(format!("Error: {}", e));
-            }
+    // If name is provided, try to load that layout; otherwise follow an output
+    let Some(name) = &query.name else {
+        let output = query
+            .output
+            .clone()
+            .unwrap_or_else(|| message::DEFAULT_OUTPUT.to_string());
+        return output_view(&chat_server, output).await;
+    };
+    named_view(&chat_server, name.clone()).await
+}
+
+/// Render an overlay frame pinned to one layout
+async fn named_view(chat_server: &Addr<ChatServer>, name: String) -> HttpResponse {
+    let layout = match chat_server
+        .send(message::RequestLayoutByName { name: name.clone() })
+        .await
+    {
+        Ok(Some(layout)) => layout,
+        Ok(None) => {
+            return HttpResponse::NotFound().body(format!("Layout '{}' not found", name));
         }
-    } else {
-        // No name provided, use active layout
-        let layout = chat_server.send(message::RequestLayout).await.unwrap();
-        layout.name.clone()
+        Err(e) => {
+            return HttpResponse::InternalServerError().body(format!("Error: {}", e));
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            LayoutTemplate {
+                layout_json: embedded_layout(&layout),
+                fonts: layout.web_fonts(),
+                font_css: layout.font_face_css(),
+                layout_name: name,
+                output: None,
+                preview: None,
+                stylesheet: None,
+            }
+            .to_string(),
+        )
+}
+
+/// Query parameters for /overlay endpoint
+#[derive(serde::Deserialize)]
+pub struct OverlayQuery {
+    layout: Option<String>,
+    output: Option<String>,
+}
+
+/// GET /overlay?output= - Show whatever layout is active on an output (default "overlay")
+/// GET /overlay?layout= - Load a specific layout by name, like /layout?name=
+#[actix_web::get("/overlay")]
+pub async fn overlay_view(req: HttpRequest, query: web::Query<OverlayQuery>) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+    let query = query.into_inner();
+    if let Some(name) = query.layout {
+        return named_view(&chat_server, name).await;
+    }
+    let output = query
+        .output
+        .unwrap_or_else(|| message::DEFAULT_OUTPUT.to_string());
+    output_view(&chat_server, output).await
+}
+
+/// Render an overlay frame that follows an output's active layout
+async fn output_view(chat_server: &Addr<ChatServer>, output: String) -> HttpResponse {
+    let list = match chat_server.send(message::RequestLayoutList).await {
+        Ok(list) => list,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Error: {}", e)),
     };
+    let layout_name = list.outputs.get(&output).cloned().unwrap_or(list.active);
+    // A missing layout still renders; the WebSocket falls back to the default one
+    let layout = chat_server
+        .send(message::RequestLayoutByName {
+            name: layout_name.clone(),
+        })
+        .await
+        .ok()
+        .flatten();
 
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(
             LayoutTemplate {
+                layout_json: layout.as_ref().and_then(embedded_layout),
+                fonts: layout.as_ref().map(Layout::web_fonts).unwrap_or_default(),
+                font_css: layout
+                    .as_ref()
+                    .map(Layout::font_face_css)
+                    .unwrap_or_default(),
                 layout_name,
+                output: Some(output),
+                preview: None,
+                stylesheet: None,
             }
             .to_string(),
         )
@@ -101,78 +284,563 @@ pub async fn chat() -> impl Responder {
         .body(ChatTemplate {}.to_string())
 }
 
+/// Query parameters for /dashboard
+#[derive(serde::Deserialize)]
+pub struct DashboardQuery {
+    /// Only list paid messages worth at least this much USD
+    #[serde(default)]
+    min: f64,
+}
+
+/// GET /dashboard?min= - The dashboard, listing the last day's paid messages of at least `min` USD
 #[actix_web::get("/dashboard")]
-pub async fn dashboard(req: HttpRequest) -> impl Responder {
+pub async fn dashboard(req: HttpRequest, query: web::Query<DashboardQuery>) -> impl Responder {
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
         .clone();
     DashboardTemplate {
-        super_chats: chat_server.send(PaidMessages).await.unwrap(),
+        super_chats: chat_server
+            .send(message::PaidMessagesAboveAmount {
+                min_usd: query.min,
+                hours: 24,
+            })
+            .await
+            .unwrap(),
+    }
+}
+
+/// Resolve a requested file under `root`, following `..` and symlinks, and refuse anything
+/// that ends up outside it. Backslashes count as separators on every platform.
+fn resolve_within(root: &Path, filename: &str) -> Result<PathBuf, Box<HttpResponse>> {
+    let not_found = |_| Box::new(HttpResponse::NotFound().body("File not found"));
+    let root = root.canonicalize().map_err(not_found)?;
+    let file_path = root
+        .join(filename.replace('\\', "/"))
+        .canonicalize()
+        .map_err(not_found)?;
+    if !file_path.starts_with(&root) {
+        warn!(filename, "Refused a path outside the served directory");
+        return Err(Box::new(HttpResponse::BadRequest().body("Invalid path")));
+    }
+    Ok(file_path)
+}
+
+/// How long browsers may reuse /static and /editor files before revalidating them
+#[derive(Clone, Copy, Debug)]
+pub struct StaticMaxAge(pub u32);
+
+impl Default for StaticMaxAge {
+    fn default() -> Self {
+        Self(STATIC_MAX_AGE)
+    }
+}
+
+impl StaticMaxAge {
+    /// Read `STATIC_MAX_AGE_SECS`
+    pub fn from_env() -> Self {
+        Self(sneed_env::var_or("STATIC_MAX_AGE_SECS", STATIC_MAX_AGE))
+    }
+}
+
+/// Largest WebSocket message a client may send, in bytes. Bigger frames are refused by
+/// the codec from their header, before the payload is buffered, and fragmented messages
+/// may not add up to more.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxMessageSize(pub usize);
+
+impl Default for MaxMessageSize {
+    fn default() -> Self {
+        Self(MAX_MESSAGE_KB * 1024)
+    }
+}
+
+impl MaxMessageSize {
+    /// Read `WS_MAX_MESSAGE_KB`
+    pub fn from_env() -> Self {
+        Self(sneed_env::var_or("WS_MAX_MESSAGE_KB", MAX_MESSAGE_KB).max(1) * 1024)
+    }
+}
+
+/// Serve a file with an ETag and Last-Modified from its mtime and size, answering 304 when
+/// the browser's copy is still current. Content-Length comes from the sized body.
+fn file_response(req: &HttpRequest, file_path: &Path, content_type: &str) -> HttpResponse {
+    let Ok(metadata) = std::fs::metadata(file_path) else {
+        return HttpResponse::NotFound().body("File not found");
+    };
+    let modified = metadata.modified().ok();
+    let since_epoch = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let modified_nanos = modified.map_or(0, |modified| since_epoch(modified).as_nanos());
+    let etag = header::EntityTag::new_strong(format!("{:x}-{:x}", modified_nanos, metadata.len()));
+
+    // If-None-Match takes precedence; If-Modified-Since only counts without it
+    let not_modified = match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => match (req.get_header::<header::IfModifiedSince>(), modified) {
+            // HTTP dates have whole seconds
+            (Some(header::IfModifiedSince(since)), Some(modified)) => {
+                since_epoch(modified).as_secs() <= since_epoch(since.into()).as_secs()
+            }
+            _ => false,
+        },
+    };
+
+    let max_age = req.app_data::<StaticMaxAge>().copied().unwrap_or_default();
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(header::CacheControl(vec![header::CacheDirective::MaxAge(
+            max_age.0,
+        )]));
+    if let Some(modified) = modified {
+        response.insert_header(header::LastModified(modified.into()));
+    }
+    if not_modified {
+        return response.finish();
+    }
+
+    if PRECOMPRESSED_TYPES.contains(&content_type) {
+        response.insert_header(header::ContentEncoding::Identity);
+    }
+    match std::fs::read(file_path) {
+        Ok(contents) => response
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .body(contents),
+        Err(_) => HttpResponse::NotFound().body("File not found"),
     }
 }
 
 #[actix_web::get("/static/{filename:.*}")]
-pub async fn static_files(path: web::Path<String>) -> impl Responder {
+pub async fn static_files(req: HttpRequest, path: web::Path<String>) -> impl Responder {
     let filename = path.into_inner();
 
-    // Prevent directory traversal
-    if filename.contains("..") || filename.starts_with('/') {
-        return HttpResponse::BadRequest().body("Invalid path");
-    }
-
-    let file_path = format!("public/{}", filename);
-
-    match std::fs::read(&file_path) {
-        Ok(contents) => {
-            let content_type = match std::path::Path::new(&filename)
-                .extension()
-                .and_then(|ext| ext.to_str())
-            {
-                Some("js") => "text/javascript",
-                Some("css") => "text/css",
-                Some("html") => "text/html",
-                Some("png") => "image/png",
-                Some("jpg") | Some("jpeg") => "image/jpeg",
-                Some("svg") => "image/svg+xml",
-                Some("gif") => "image/gif",
-                Some("ico") => "image/x-icon",
-                Some("json") => "application/json",
-                Some("txt") => "text/plain",
-                _ => "application/octet-stream",
-            };
+    let file_path = match resolve_within(Path::new("public"), &filename) {
+        Ok(file_path) => file_path,
+        Err(response) => return *response,
+    };
 
-            HttpResponse::Ok()
-                .append_header((header::CONTENT_TYPE, content_type))
-                .body(contents)
-        }
-        Err(_) => HttpResponse::NotFound().body("File not found"),
+    let content_type = match Path::new(&filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        Some("html") => "text/html",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        _ => "application/octet-stream",
+    };
+
+    file_response(&req, &file_path, content_type)
+}
+
+/// Query parameters for /avatar
+#[derive(serde::Deserialize)]
+pub struct AvatarQuery {
+    url: String,
+}
+
+/// GET /avatar?url= - An avatar from an allowed platform CDN, fetched and cached so
+/// overlays load it from this origin. Falls back to a blank image if the fetch fails;
+/// 403 for other hosts, 404 when `AVATAR_PROXY=false`.
+#[actix_web::get("/avatar")]
+pub async fn avatar_proxy(req: HttpRequest, query: web::Query<AvatarQuery>) -> HttpResponse {
+    let Some(proxy) = req.app_data::<web::Data<AvatarProxy>>() else {
+        return HttpResponse::NotFound().body("Avatar proxy is disabled");
+    };
+    if !proxy.config.allows(&query.url) {
+        return HttpResponse::Forbidden().body("Host not allowed");
+    }
+    match proxy.get(&query.url).await {
+        Some(avatar) => HttpResponse::Ok()
+            .content_type(avatar.content_type)
+            .insert_header((
+                header::CACHE_CONTROL,
+                format!("public, max-age={}", proxy.config.ttl.as_secs()),
+            ))
+            .body(avatar.bytes),
+        // Don't let browsers hold on to the fallback; the CDN may answer next time
+        None => HttpResponse::Ok()
+            .content_type("image/gif")
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .body(avatar::BLANK_GIF),
     }
 }
 
+/// GET /chat.ws - Receive-only WebSocket for overlays
 #[actix_web::get("/chat.ws")]
 pub async fn websocket(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+    start_client(req, stream, ClientRole::Viewer)
+}
+
+/// GET /chat.sse - The `/chat.ws` broadcasts as server-sent events, for tools that can't
+/// open a WebSocket. Each event's data is a `{"tag", "message"}` reply.
+#[actix_web::get("/chat.sse")]
+pub async fn event_stream(req: HttpRequest) -> HttpResponse {
+    let server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+    let heartbeat = req.app_data::<Heartbeat>().copied().unwrap_or_default();
+    let (client, body) = sse::SseClient::new(server, heartbeat.interval);
+    client.start();
+    debug!("SSE client connected");
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Keep nginx from buffering events
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body.map(Ok::<_, Error>))
+}
+
+/// Query parameters for /ingest.ws
+#[derive(serde::Deserialize)]
+pub struct IngestQuery {
+    token: Option<String>,
+}
+
+/// GET /ingest.ws?token= - WebSocket for bridges, the dashboard and the editor, which
+/// may send chat and change layouts. Requires `INGEST_TOKEN` when it is set.
+#[actix_web::get("/ingest.ws")]
+pub async fn ingest_websocket(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<IngestQuery>,
+) -> Result<HttpResponse, Error> {
+    if let Some(expected) = ingest_token() {
+        if !token_matches(&expected, query.token.as_deref().unwrap_or_default()) {
+            warn!("Rejected ingest connection with a bad token");
+            return Ok(HttpResponse::Unauthorized().body("Invalid ingest token"));
+        }
+    }
+    start_client(req, stream, ClientRole::Ingest)
+}
+
+fn start_client(
+    req: HttpRequest,
+    stream: web::Payload,
+    role: ClientRole,
+) -> Result<HttpResponse, Error> {
     let server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
         .clone();
+    let heartbeat = req.app_data::<Heartbeat>().copied().unwrap_or_default();
+    let max_message_size = req
+        .app_data::<MaxMessageSize>()
+        .copied()
+        .unwrap_or_default()
+        .0;
     let client = ChatClient {
         id: rand::random(),
+        role,
         server,
+        heartbeat,
         last_heartbeat_at: Instant::now(),
+        max_message_size,
+        fragments: None,
     };
 
-    let resp = ws::start(client, &req, stream);
-    debug!("WebSocket client connected");
+    // No permessage-deflate: actix-http's codec never negotiates Sec-WebSocket-Extensions
+    // and rejects frames with RSV1 set, so browsers always fall back to uncompressed frames.
+    let stream = frame_limit::FrameLimit::new(stream, max_message_size);
+    let resp = ws::WsResponseBuilder::new(client, &req, stream)
+        .frame_size(max_message_size)
+        .start();
+    debug!(?role, "WebSocket client connected");
     resp
 }
 
+/// POST /ingest/webhook?token= - Accept a `LivestreamUpdate`, or a single chat `Message`, as
+/// JSON from tools without a userscript. Requires `INGEST_TOKEN` when it is set, given as
+/// `?token=` or `Authorization: Bearer`. Responds 202 once forwarded, 400 on a bad payload.
+#[actix_web::post("/ingest/webhook")]
+pub async fn ingest_webhook(
+    req: HttpRequest,
+    query: web::Query<IngestQuery>,
+    body: web::Bytes,
+) -> impl Responder {
+    if !webhook_authorized(&req, &query) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+
+    let update = match parse_webhook(&body) {
+        Ok(update) => update,
+        Err(errors) => return HttpResponse::BadRequest().body(errors.join("\n")),
+    };
+
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!");
+    for chat_message in update.messages.into_iter().flatten() {
+        chat_server.do_send(ChatMessage { chat_message });
+    }
+    for id in update.removals.into_iter().flatten() {
+        chat_server.do_send(message::RemoveMessage { id });
+    }
+    if let Some(viewers) = update.viewers {
+        chat_server.do_send(message::ViewCount {
+            platform: update.platform,
+            viewers,
+        });
+    }
+    HttpResponse::Accepted().body("Accepted")
+}
+
+/// Form body Ko-fi posts to its webhook
+#[derive(serde::Deserialize)]
+pub struct KofiForm {
+    data: String,
+}
+
+/// POST /ingest/kofi?token= - Ko-fi payment webhook. Also checks the payload's verification
+/// token when `KOFI_VERIFICATION_TOKEN` is set. Ko-fi retries unless it gets a 200.
+#[actix_web::post("/ingest/kofi")]
+pub async fn ingest_kofi(
+    req: HttpRequest,
+    query: web::Query<IngestQuery>,
+    form: web::Form<KofiForm>,
+) -> impl Responder {
+    if !webhook_authorized(&req, &query) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+    if let Some(expected) = dotenvy::var("KOFI_VERIFICATION_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+    {
+        if !platforms::kofi::verify(&form.data, &expected) {
+            warn!("Rejected Ko-fi webhook with a bad verification token");
+            return HttpResponse::Unauthorized().body("Invalid Ko-fi verification token");
+        }
+    }
+
+    match platforms::kofi::parse(&form.data) {
+        Ok(chat_message) => {
+            forward_message(&req, chat_message);
+            HttpResponse::Ok().body("Accepted")
+        }
+        Err(e) => HttpResponse::BadRequest().body(format!("Invalid Ko-fi payload: {}", e)),
+    }
+}
+
+/// POST /ingest/streamlabs?token= - Streamlabs donation, as a socket API `donation` event
+/// or a bare donation object
+#[actix_web::post("/ingest/streamlabs")]
+pub async fn ingest_streamlabs(
+    req: HttpRequest,
+    query: web::Query<IngestQuery>,
+    body: String,
+) -> impl Responder {
+    if !webhook_authorized(&req, &query) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+
+    match platforms::streamlabs::parse(&body) {
+        Ok(chat_message) => {
+            forward_message(&req, chat_message);
+            HttpResponse::Accepted().body("Accepted")
+        }
+        Err(e) => HttpResponse::BadRequest().body(format!("Invalid Streamlabs payload: {}", e)),
+    }
+}
+
+/// Webhooks and REST layout writes pass `INGEST_TOKEN` as `?token=` or `Authorization: Bearer`
+fn webhook_authorized(req: &HttpRequest, query: &IngestQuery) -> bool {
+    let Some(expected) = ingest_token() else {
+        return true;
+    };
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let given = query.token.as_deref().or(bearer).unwrap_or_default();
+    if !token_matches(&expected, given) {
+        warn!(
+            path = req.path(),
+            "Rejected ingest request with a bad token"
+        );
+        return false;
+    }
+    true
+}
+
+fn forward_message(req: &HttpRequest, chat_message: crate::message::Message) {
+    req.app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .do_send(ChatMessage { chat_message });
+}
+
+/// Read a webhook body as a `LivestreamUpdate`. A lone `Message` is told apart by its `id`.
+fn parse_webhook(body: &[u8]) -> Result<LivestreamUpdate, Vec<String>> {
+    let value: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| vec![format!("Invalid JSON: {}", e)])?;
+    let update = if value.get("id").is_some() {
+        let message: crate::message::Message =
+            serde_json::from_value(value).map_err(|e| vec![format!("Invalid message: {}", e)])?;
+        LivestreamUpdate {
+            platform: message.platform.clone(),
+            channel: None,
+            messages: Some(vec![message]),
+            removals: None,
+            viewers: None,
+        }
+    } else {
+        serde_json::from_value(value).map_err(|e| vec![format!("Invalid update: {}", e)])?
+    };
+
+    let mut errors = Vec::new();
+    if update.platform.trim().is_empty() {
+        errors.push("platform must not be empty".to_string());
+    }
+    if update.messages.is_none() && update.removals.is_none() && update.viewers.is_none() {
+        errors.push("update has no messages, removals or viewers".to_string());
+    }
+    for message in update.messages.iter().flatten() {
+        if message.platform.trim().is_empty() {
+            errors.push(format!("message {} has no platform", message.id));
+        }
+        if message.username.trim().is_empty() {
+            errors.push(format!("message {} has no username", message.id));
+        }
+    }
+    if errors.is_empty() {
+        Ok(update)
+    } else {
+        Err(errors)
+    }
+}
+
+/// `INGEST_TOKEN`, if set to something non-empty
+pub fn ingest_token() -> Option<String> {
+    dotenvy::var("INGEST_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Compare tokens without exiting early on the first differing byte
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// ============================================================================
+// Health checks
+// ============================================================================
+
+/// How long /readyz waits for the ChatServer to answer
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// GET /healthz - Liveness: the HTTP server is up
+#[actix_web::get("/healthz")]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// GET /readyz - Readiness: the ChatServer actor answers and the database is usable.
+/// Responds 503 naming the failed check.
+#[actix_web::get("/readyz")]
+pub async fn readyz(req: HttpRequest) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    // The database is checked inside the actor, so it is unknown if the actor is down
+    let (chat_server, database) = match chat_server.send(message::Ping).timeout(READY_TIMEOUT).await
+    {
+        Ok(Ok(())) => ("ok".to_string(), "ok".to_string()),
+        Ok(Err(e)) => ("ok".to_string(), e),
+        Err(e) => (e.to_string(), "unknown".to_string()),
+    };
+
+    let ready = chat_server == "ok" && database == "ok";
+    let body = serde_json::json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": { "chatServer": chat_server, "database": database },
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// GET /metrics - Prometheus text format (metric names are listed in CLAUDE.md)
+#[actix_web::get("/metrics")]
+pub async fn metrics_view(req: HttpRequest) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    match chat_server.send(message::RequestMetrics).await {
+        Ok(text) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(text),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
 // ============================================================================
 // Layout REST API
 // ============================================================================
+// Handlers are mounted under the `/api` scope in main.rs.
+
+/// Origins from `ALLOWED_ORIGINS` (comma separated, `*` for any), read once at startup
+pub fn allowed_origins() -> Vec<String> {
+    dotenvy::var("ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .filter(|origin| {
+            // actix-cors refuses to start a worker over an unparseable origin
+            let valid = *origin == "*" || origin.parse::<actix_web::http::Uri>().is_ok();
+            if !valid {
+                warn!("Ignoring invalid ALLOWED_ORIGINS entry: {}", origin);
+            }
+            valid
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// CORS for the `/api` scope. With no allowed origins only same-origin callers work.
+pub fn api_cors(allowed_origins: &[String]) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(["GET", "POST", "PUT", "DELETE"])
+        .allowed_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .max_age(3600);
+
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        return cors.allow_any_origin();
+    }
+    allowed_origins
+        .iter()
+        .fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
 
 /// GET /api/layouts - List all available layouts
-#[actix_web::get("/api/layouts")]
+#[actix_web::get("/layouts")]
 pub async fn list_layouts(req: HttpRequest) -> impl Responder {
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
@@ -186,39 +854,71 @@ pub async fn list_layouts(req: HttpRequest) -> impl Responder {
 }
 
 /// GET /api/layouts/{name} - Get a specific layout
-#[actix_web::get("/api/layouts/{name}")]
+#[actix_web::get("/layouts/{name}")]
 pub async fn get_layout(req: HttpRequest, name: web::Path<String>) -> impl Responder {
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
         .clone();
 
-    // We need to get the layout manager from the ChatServer
-    // For now, we'll use RequestLayout for the active layout and handle others via WebSocket
-    // A proper implementation would require exposing LayoutManager via app_data
-
-    // For simplicity, only support getting the active layout via REST
-    // Full layout operations go through WebSocket
-    match chat_server.send(message::RequestLayout).await {
-        Ok(layout) => {
-            if layout.name == name.into_inner() {
-                HttpResponse::Ok().json(layout)
-            } else {
-                HttpResponse::NotFound()
-                    .body("Layout not found (use WebSocket for non-active layouts)")
-            }
-        }
+    let name = name.into_inner();
+    match chat_server
+        .send(message::RequestLayoutByName { name: name.clone() })
+        .await
+    {
+        Ok(Some(layout)) => HttpResponse::Ok().json(layout),
+        Ok(None) => HttpResponse::NotFound().body(format!("Layout '{}' not found", name)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// GET /api/layouts/{name}/preview - The layout as static HTML with sample chat, for
+/// gallery thumbnails and headless screenshots. The page doesn't connect or update.
+#[actix_web::get("/layouts/{name}/preview")]
+pub async fn preview_layout(req: HttpRequest, name: web::Path<String>) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    let name = name.into_inner();
+    match chat_server
+        .send(message::RequestLayoutByName { name: name.clone() })
+        .await
+    {
+        Ok(Some(layout)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(
+                LayoutTemplate {
+                    layout_name: layout.name.clone(),
+                    output: None,
+                    preview: Some(preview::elements(&layout)),
+                    layout_json: None,
+                    fonts: layout.web_fonts(),
+                    font_css: layout.font_face_css(),
+                    stylesheet: None,
+                }
+                .to_string(),
+            ),
+        Ok(None) => HttpResponse::NotFound().body(format!("Layout '{}' not found", name)),
         Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
     }
 }
 
-/// POST /api/layouts/{name} - Save a layout
-#[actix_web::post("/api/layouts/{name}")]
+/// PUT (or POST) /api/layouts/{name} - Save a layout, compiling its SCSS and
+/// broadcasting it like the WebSocket `save_layout` command. Requires `INGEST_TOKEN` when
+/// it is set, like the webhooks.
+#[actix_web::route("/layouts/{name}", method = "PUT", method = "POST")]
 pub async fn save_layout(
     req: HttpRequest,
     name: web::Path<String>,
+    query: web::Query<IngestQuery>,
     body: web::Json<Layout>,
 ) -> impl Responder {
+    if !webhook_authorized(&req, &query) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
@@ -228,15 +928,62 @@ pub async fn save_layout(
     layout.name = name.into_inner();
 
     match chat_server.send(message::SaveLayout { layout }).await {
-        Ok(Ok(())) => HttpResponse::Ok().body("Layout saved"),
+        Ok(Ok(scss_errors)) if scss_errors.is_empty() => HttpResponse::Ok().body("Layout saved"),
+        Ok(Ok(scss_errors)) => HttpResponse::Ok().body(format!(
+            "Layout saved with SCSS errors:\n{}",
+            scss_errors.join("\n")
+        )),
+        Ok(Err(errors)) => HttpResponse::BadRequest().body(errors.join("\n")),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// Body of /api/compile-scss
+#[derive(serde::Deserialize)]
+pub struct CompileScssBody {
+    source: String,
+    /// A layout's `scssPrelude`, for snippets that use its variables
+    #[serde(default)]
+    prelude: Option<String>,
+}
+
+/// POST /api/compile-scss - Compile `{"source", "prelude"?}` as an element's custom CSS
+/// would be on save, without saving anything. Returns the sanitized CSS, or 400 with the
+/// compiler's error.
+#[actix_web::post("/compile-scss")]
+pub async fn compile_scss(req: HttpRequest, body: web::Json<CompileScssBody>) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    let body = body.into_inner();
+    match chat_server
+        .send(message::CompileScss {
+            source: body.source,
+            prelude: body.prelude,
+        })
+        .await
+    {
+        Ok(Ok(css)) => HttpResponse::Ok()
+            .content_type("text/css; charset=utf-8")
+            .body(css),
         Ok(Err(e)) => HttpResponse::BadRequest().body(e),
         Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
     }
 }
 
-/// DELETE /api/layouts/{name} - Delete a layout
-#[actix_web::delete("/api/layouts/{name}")]
-pub async fn delete_layout(req: HttpRequest, name: web::Path<String>) -> impl Responder {
+/// DELETE /api/layouts/{name} - Delete a layout. Requires `INGEST_TOKEN` when it is set.
+#[actix_web::delete("/layouts/{name}")]
+pub async fn delete_layout(
+    req: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<IngestQuery>,
+) -> impl Responder {
+    if !webhook_authorized(&req, &query) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
@@ -249,14 +996,36 @@ pub async fn delete_layout(req: HttpRequest, name: web::Path<String>) -> impl Re
         .await
     {
         Ok(Ok(())) => HttpResponse::Ok().body("Layout deleted"),
-        Ok(Err(e)) => HttpResponse::BadRequest().body(e),
+        Ok(Err(e @ message::LayoutError::NotFound(_))) => {
+            HttpResponse::NotFound().body(e.to_string())
+        }
+        Ok(Err(e @ message::LayoutError::Conflict(_))) => {
+            HttpResponse::Conflict().body(e.to_string())
+        }
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
         Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
     }
 }
 
-/// POST /api/layouts/{name}/activate - Switch to a layout
-#[actix_web::post("/api/layouts/{name}/activate")]
-pub async fn activate_layout(req: HttpRequest, name: web::Path<String>) -> impl Responder {
+/// Query parameters for the activate endpoint
+#[derive(serde::Deserialize)]
+pub struct ActivateQuery {
+    output: Option<String>,
+}
+
+/// POST /api/layouts/{name}/activate?output= - Switch an output (default "overlay") to a
+/// layout. Requires `INGEST_TOKEN` when it is set.
+#[actix_web::post("/layouts/{name}/activate")]
+pub async fn activate_layout(
+    req: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<ActivateQuery>,
+    token: web::Query<IngestQuery>,
+) -> impl Responder {
+    if !webhook_authorized(&req, &token) {
+        return HttpResponse::Unauthorized().body("Invalid ingest token");
+    }
+
     let chat_server = req
         .app_data::<Addr<ChatServer>>()
         .expect("ChatServer missing in app data!")
@@ -264,6 +1033,10 @@ pub async fn activate_layout(req: HttpRequest, name: web::Path<String>) -> impl
 
     match chat_server
         .send(message::SwitchLayout {
+            output: query
+                .into_inner()
+                .output
+                .unwrap_or_else(|| message::DEFAULT_OUTPUT.to_string()),
             name: name.into_inner(),
         })
         .await
@@ -274,6 +1047,78 @@ pub async fn activate_layout(req: HttpRequest, name: web::Path<String>) -> impl
     }
 }
 
+/// GET /api/bans - List banned users, newest first
+#[actix_web::get("/bans")]
+pub async fn list_bans(req: HttpRequest) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    match chat_server.send(message::RequestBans).await {
+        Ok(Ok(bans)) => HttpResponse::Ok().json(bans),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// GET /api/viewers - Current viewer counts and the last hour per platform, a sample a minute
+#[actix_web::get("/viewers")]
+pub async fn list_viewers(req: HttpRequest) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    match chat_server.send(message::RequestViewers).await {
+        Ok(viewers) => HttpResponse::Ok().json(viewers),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// GET /api/messages?platform=&paid=&since_hours=&limit= - Recent messages as JSON, newest
+/// first. Filters combine; paid messages reach back as far as the database goes.
+#[actix_web::get("/messages")]
+pub async fn list_messages(
+    req: HttpRequest,
+    query: web::Query<message::RequestMessages>,
+) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    match chat_server.send(query.into_inner()).await {
+        Ok(messages) => {
+            // to_json adds the rendered `html` next to the message fields
+            let items: Vec<String> = messages.iter().map(|msg| msg.to_json()).collect();
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(format!("[{}]", items.join(",")))
+        }
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
+/// GET /api/messages/{id} - One message as JSON, like a `/api/messages` item. Unpaid
+/// messages are only found while they are in memory.
+#[actix_web::get("/messages/{id}")]
+pub async fn get_message(req: HttpRequest, id: web::Path<uuid::Uuid>) -> impl Responder {
+    let chat_server = req
+        .app_data::<Addr<ChatServer>>()
+        .expect("ChatServer missing in app data!")
+        .clone();
+
+    let id = id.into_inner();
+    match chat_server.send(message::RequestMessageById { id }).await {
+        Ok(Some(msg)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(msg.to_json()),
+        Ok(None) => HttpResponse::NotFound().body(format!("Message '{}' not found", id)),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error: {}", e)),
+    }
+}
+
 /// GET /editor - Serve the React editor SPA
 #[actix_web::get("/editor")]
 pub async fn editor() -> impl Responder {
@@ -289,36 +1134,485 @@ pub async fn editor() -> impl Responder {
 
 /// GET /editor/{filename} - Serve editor static files
 #[actix_web::get("/editor/{filename:.*}")]
-pub async fn editor_static(path: web::Path<String>) -> impl Responder {
+pub async fn editor_static(req: HttpRequest, path: web::Path<String>) -> impl Responder {
     let filename = path.into_inner();
 
-    // Prevent directory traversal
-    if filename.contains("..") || filename.starts_with('/') {
-        return HttpResponse::BadRequest().body("Invalid path");
-    }
-
-    let file_path = format!("public/editor/{}", filename);
-
-    match std::fs::read(&file_path) {
-        Ok(contents) => {
-            let content_type = match std::path::Path::new(&filename)
-                .extension()
-                .and_then(|ext| ext.to_str())
-            {
-                Some("js") => "text/javascript",
-                Some("css") => "text/css",
-                Some("html") => "text/html",
-                Some("png") => "image/png",
-                Some("jpg") | Some("jpeg") => "image/jpeg",
-                Some("svg") => "image/svg+xml",
-                Some("map") => "application/json",
-                _ => "application/octet-stream",
-            };
+    let file_path = match resolve_within(Path::new("public/editor"), &filename) {
+        Ok(file_path) => file_path,
+        Err(response) => return *response,
+    };
 
-            HttpResponse::Ok()
-                .append_header((header::CONTENT_TYPE, content_type))
-                .body(contents)
+    let content_type = match Path::new(&filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        Some("html") => "text/html",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("map") => "application/json",
+        _ => "application/octet-stream",
+    };
+
+    file_response(&req, &file_path, content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::Method, http::StatusCode, test, App};
+
+    async fn preflight(allowed_origins: &[&str], path: &str) -> HttpResponse {
+        let allowed_origins: Vec<String> = allowed_origins.iter().map(|o| o.to_string()).collect();
+        let app = test::init_service(
+            App::new()
+                .service(
+                    web::scope("/api")
+                        .wrap(api_cors(&allowed_origins))
+                        .service(list_layouts),
+                )
+                .service(healthz),
+        )
+        .await;
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri(path)
+            .insert_header((header::ORIGIN, "https://dash.example.com"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        test::call_service(&app, req).await.into()
+    }
+
+    fn allow_origin(res: &HttpResponse) -> Option<&str> {
+        res.headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn test_api_cors_preflight() {
+        let res = preflight(&["https://dash.example.com"], "/api/layouts").await;
+        assert!(res.status().is_success());
+        assert_eq!(allow_origin(&res), Some("https://dash.example.com"));
+
+        let res = preflight(&["*"], "/api/layouts").await;
+        assert_eq!(allow_origin(&res), Some("https://dash.example.com"));
+
+        let res = preflight(&["https://other.example.com"], "/api/layouts").await;
+        assert_eq!(allow_origin(&res), None);
+
+        // Routes outside /api are untouched
+        let res = preflight(&["*"], "/healthz").await;
+        assert_eq!(allow_origin(&res), None);
+    }
+
+    #[actix_web::test]
+    async fn test_parse_webhook() {
+        let update = parse_webhook(br#"{"platform":"Ko-fi","channel":null,"viewers":3}"#).unwrap();
+        assert_eq!(update.platform, "Ko-fi");
+        assert_eq!(update.viewers, Some(3));
+
+        let message = serde_json::json!({
+            "id": uuid::Uuid::new_v4(),
+            "platform": "Ko-fi",
+            "sent_at": 0,
+            "received_at": 0,
+            "is_placeholder": false,
+            "message": "Thanks!",
+            "emojis": [],
+            "username": "Donor",
+            "avatar": "",
+            "amount": 5.0,
+            "currency": "USD",
+            "is_verified": false,
+            "is_sub": false,
+            "is_mod": false,
+            "is_owner": false,
+            "is_staff": false,
+        });
+        let update = parse_webhook(message.to_string().as_bytes()).unwrap();
+        assert_eq!(update.platform, "Ko-fi");
+        assert_eq!(update.messages.unwrap()[0].username, "Donor");
+
+        assert!(parse_webhook(b"not json").unwrap_err()[0].starts_with("Invalid JSON"));
+        assert!(parse_webhook(br#"{"id":"x"}"#).unwrap_err()[0].starts_with("Invalid message"));
+        let errors = parse_webhook(br#"{"platform":" ","channel":null}"#).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_within() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("public");
+        std::fs::create_dir_all(root.join("img")).unwrap();
+        std::fs::write(root.join("app.js"), "").unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "").unwrap();
+
+        let status = |filename: &str| match resolve_within(&root, filename) {
+            Ok(_) => StatusCode::OK,
+            Err(response) => response.status(),
+        };
+        assert_eq!(status("app.js"), StatusCode::OK);
+        assert_eq!(status("img/../app.js"), StatusCode::OK);
+        assert_eq!(status("img\\..\\app.js"), StatusCode::OK);
+        assert_eq!(status("missing.js"), StatusCode::NOT_FOUND);
+        assert_eq!(status("../secret.txt"), StatusCode::BAD_REQUEST);
+        assert_eq!(status("..\\secret.txt"), StatusCode::BAD_REQUEST);
+        assert_eq!(status("img/..\\../secret.txt"), StatusCode::BAD_REQUEST);
+        let absolute = dir.path().join("secret.txt");
+        assert_eq!(status(absolute.to_str().unwrap()), StatusCode::BAD_REQUEST);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("link.txt"))
+                .unwrap();
+            assert_eq!(status("link.txt"), StatusCode::BAD_REQUEST);
         }
-        Err(_) => HttpResponse::NotFound().body("File not found"),
+    }
+
+    #[actix_web::test]
+    async fn test_file_response_revalidates() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file_path = dir.path().join("app.css");
+        std::fs::write(&file_path, "body {}").unwrap();
+
+        let req = test::TestRequest::default()
+            .app_data(StaticMaxAge(60))
+            .to_http_request();
+        let first = file_response(&req, &file_path, "text/css");
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(
+            first.headers().get(header::CACHE_CONTROL).unwrap(),
+            "max-age=60"
+        );
+        assert_eq!(
+            first.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/css"
+        );
+        assert_eq!(
+            actix_web::body::MessageBody::size(first.body()),
+            actix_web::body::BodySize::Sized(7)
+        );
+        let etag = first.headers().get(header::ETAG).unwrap().clone();
+        let last_modified = first.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        let status = |header| {
+            let req = test::TestRequest::default()
+                .insert_header(header)
+                .to_http_request();
+            file_response(&req, &file_path, "text/css").status()
+        };
+        assert_eq!(
+            status((header::IF_NONE_MATCH, etag.clone())),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status((header::IF_MODIFIED_SINCE, last_modified.clone())),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status((header::IF_NONE_MATCH, "\"other\"".parse().unwrap())),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status((
+                header::IF_MODIFIED_SINCE,
+                "Mon, 01 Jan 2001 00:00:00 GMT".parse().unwrap()
+            )),
+            StatusCode::OK
+        );
+
+        // A changed file gets a new tag
+        std::fs::write(&file_path, "body { color: red }").unwrap();
+        assert_eq!(status((header::IF_NONE_MATCH, etag)), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_compresses_text_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let png = dir.path().join("logo.png");
+        let mp3 = dir.path().join("alert.mp3");
+        std::fs::write(&png, [0x89; 256]).unwrap();
+        std::fs::write(&mp3, [0xff; 256]).unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::Compress::default())
+                .service(static_files)
+                .route(
+                    "/logo.png",
+                    web::get().to(move |req: HttpRequest| {
+                        let png = png.clone();
+                        async move { file_response(&req, &png, "image/png") }
+                    }),
+                )
+                .route(
+                    "/alert.mp3",
+                    web::get().to(move |req: HttpRequest| {
+                        let mp3 = mp3.clone();
+                        async move { file_response(&req, &mp3, "audio/mpeg") }
+                    }),
+                ),
+        )
+        .await;
+
+        let encoding = |uri: &'static str| {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::ACCEPT_ENCODING, "gzip"))
+                .to_request();
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req).await;
+                assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+                res.headers()
+                    .get(header::CONTENT_ENCODING)
+                    .map(|value| value.to_str().unwrap().to_string())
+            }
+        };
+        assert_eq!(encoding("/static/style.css").await.as_deref(), Some("gzip"));
+        assert_eq!(encoding("/logo.png").await, None);
+        assert_eq!(encoding("/alert.mp3").await.as_deref(), Some("identity"));
+    }
+
+    #[actix_web::test]
+    async fn test_layout_template_preview() {
+        let layout = Layout::default_layout();
+        let html = LayoutTemplate {
+            layout_name: layout.name.clone(),
+            output: None,
+            preview: Some(preview::elements(&layout)),
+            layout_json: None,
+            fonts: Vec::new(),
+            font_css: String::new(),
+            stylesheet: None,
+        }
+        .to_string();
+        assert!(html.contains(r#"<section id="chat" class="element element--chat""#));
+        assert!(html.contains("Hello from the preview!"));
+        assert!(!html.contains("script.js"));
+
+        let live = LayoutTemplate {
+            layout_name: layout.name,
+            output: None,
+            preview: None,
+            layout_json: None,
+            fonts: Vec::new(),
+            font_css: String::new(),
+            stylesheet: None,
+        }
+        .to_string();
+        assert!(live.contains(r#"<div id="elements-container"></div>"#));
+        assert!(live.contains("script.js"));
+        assert!(!live.contains("layout-fonts"));
+
+        let mut layout = Layout::default_layout();
+        layout.fonts = vec![WebFont {
+            name: "Comic Neue".to_string(),
+            url: "https://fonts.example.com/comic-neue.woff2?v=1&s=2".to_string(),
+        }];
+        let html = LayoutTemplate {
+            layout_name: layout.name.clone(),
+            output: None,
+            preview: None,
+            layout_json: None,
+            fonts: layout.web_fonts(),
+            font_css: layout.font_face_css(),
+            stylesheet: None,
+        }
+        .to_string();
+        assert!(html.contains(
+            r#"<link rel="preload" href="https://fonts.example.com/comic-neue.woff2?v=1&amp;s=2" as="font" crossorigin>"#
+        ), "{}", html);
+        assert!(
+            html.contains(r#"<style id="layout-fonts">@font-face { font-family: "Comic Neue";"#)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_export_overlay_html() {
+        let mut layout = Layout::default_layout();
+        layout.elements.get_mut("chat").unwrap().style.compiled_css =
+            Some("outline: 2px solid red;".to_string());
+
+        let html = export_overlay_html(&layout);
+        assert!(html.contains("Hello from the preview!"));
+        assert!(html.contains("outline: 2px solid red;"));
+        assert!(html.contains("Overlay Styles"));
+        assert!(!html.contains("/static/"));
+        assert!(!html.contains("@import"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[actix_web::test]
+    async fn test_overlay_embeds_layout() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let layout_manager =
+            crate::layout::LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let mut layout = Layout::default_layout();
+        layout.name = "pinned".to_string();
+        layout.elements.get_mut("text").unwrap().options =
+            Some(serde_json::json!({ "content": "</script><b>" }));
+        layout_manager.save(&layout).unwrap();
+        let server = ChatServer::with_database(
+            crate::exchange::ExchangeRates::from_rates(Default::default()),
+            std::sync::Arc::new(std::sync::Mutex::new(layout_manager)),
+            crate::database::Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let app = test::init_service(
+            App::new()
+                .app_data(server)
+                .service(overlay_view)
+                .service(layout_view),
+        )
+        .await;
+
+        let body = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let res =
+                    test::call_service(app, test::TestRequest::get().uri(uri).to_request()).await;
+                assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+                String::from_utf8(test::read_body(res).await.to_vec()).unwrap()
+            }
+        };
+        let json = |html: &str| -> serde_json::Value {
+            let start = html.find(r#"<script type="application/json" id="initial-layout">"#);
+            let rest = &html[start.unwrap()..];
+            let rest = &rest[rest.find('>').unwrap() + 1..];
+            serde_json::from_str(&rest[..rest.find("</script>").unwrap()]).unwrap()
+        };
+
+        let active = body("/overlay").await;
+        assert!(active.contains(r#"window.LAYOUT_OUTPUT = "overlay";"#));
+        assert_eq!(json(&active)["name"], "default");
+
+        for uri in ["/overlay?layout=pinned", "/layout?name=pinned"] {
+            let pinned = body(uri).await;
+            assert!(pinned.contains(r#"window.LAYOUT_NAME = "pinned";"#));
+            assert_eq!(json(&pinned), serde_json::to_value(&layout).unwrap());
+            assert!(!pinned.contains("</script><b>"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_compile_scss() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let layout_manager =
+            crate::layout::LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let server = ChatServer::with_database(
+            crate::exchange::ExchangeRates::from_rates(Default::default()),
+            std::sync::Arc::new(std::sync::Mutex::new(layout_manager)),
+            crate::database::Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let app = test::init_service(
+            App::new()
+                .app_data(server)
+                .service(web::scope("/api").service(compile_scss)),
+        )
+        .await;
+
+        let compile = |body: serde_json::Value| {
+            let app = &app;
+            async move {
+                let req = test::TestRequest::post()
+                    .uri("/api/compile-scss")
+                    .set_json(body)
+                    .to_request();
+                let res = test::call_service(app, req).await;
+                let status = res.status();
+                let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, css) = compile(serde_json::json!({
+            "source": "$gap: 4px; margin: $gap * 2; &:hover { color: red; }",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", css);
+        assert!(css.contains("margin: 8px"), "{}", css);
+
+        let (status, css) = compile(serde_json::json!({
+            "source": "color: $accent;",
+            "prelude": "$accent: #ff8800;",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", css);
+        assert!(css.contains("#ff8800"), "{}", css);
+
+        let (status, error) = compile(serde_json::json!({ "source": "color: red; {{" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.starts_with("SCSS compilation error"), "{}", error);
+
+        let (status, error) = compile(serde_json::json!({
+            "source": "color: red;",
+            "prelude": ".stray { color: red; }",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.contains("prelude"), "{}", error);
+    }
+
+    #[actix_web::test]
+    async fn test_layout_writes_need_ingest_token() {
+        use crate::{database::Database, exchange::ExchangeRates, layout::LayoutManager};
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let server = ChatServer::with_database(
+            ExchangeRates::from_rates(HashMap::new()),
+            Arc::new(Mutex::new(layout_manager)),
+            Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let app = test::init_service(
+            App::new().app_data(server).service(
+                web::scope("/api")
+                    .service(save_layout)
+                    .service(delete_layout)
+                    .service(activate_layout),
+            ),
+        )
+        .await;
+
+        // No other test reads INGEST_TOKEN
+        std::env::set_var("INGEST_TOKEN", "secret");
+        for query in ["", "?token=wrong"] {
+            let requests = [
+                test::TestRequest::put()
+                    .uri(&format!("/api/layouts/alt{query}"))
+                    .set_json(Layout::default_layout()),
+                test::TestRequest::delete().uri(&format!("/api/layouts/missing{query}")),
+                test::TestRequest::post().uri(&format!("/api/layouts/missing/activate{query}")),
+            ];
+            for req in requests {
+                let res = test::call_service(&app, req.to_request()).await;
+                assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{query}");
+            }
+        }
+
+        // With the token the request reaches the layouts (and finds none to delete)
+        let req = test::TestRequest::delete()
+            .uri("/api/layouts/missing")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let req = test::TestRequest::delete()
+            .uri("/api/layouts/missing?token=secret")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        std::env::remove_var("INGEST_TOKEN");
     }
 }
@@ -0,0 +1,195 @@
+use crate::layout::{
+    element_base_type, ChatOptions, ElementOptions, FeaturedOptions, Layout, LiveOptions,
+    MessageStyle, TextOptions,
+};
+use crate::message::{self, Message};
+
+/// Viewer total shown by live badges in previews
+const SAMPLE_VIEWERS: usize = 1337;
+
+/// One layout element rendered as static HTML, shaped like the overlay script builds it
+pub struct PreviewElement {
+    pub id: String,
+    pub class: String,
+    pub style: String,
+    /// Rendered sample messages, or a text element's own content
+    pub html: String,
+}
+
+/// The layout's enabled elements with sample chat in place of live data, in key order.
+/// Types the overlay script doesn't know are left out, as the overlay leaves them out.
+/// Text tokens like `{{date}}` are left as written.
+pub fn elements(layout: &Layout) -> Vec<PreviewElement> {
+    let samples = sample_messages();
+    let mut keys: Vec<&String> = layout.elements.keys().collect();
+    keys.sort();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let config = &layout.elements[key];
+            if !config.enabled {
+                return None;
+            }
+            let base = element_base_type(key);
+            let options = ElementOptions::parse(base, config.options.as_ref())
+                .unwrap_or(ElementOptions::Other);
+            let mut class = format!("element element--{}", base);
+            let mut style = format!("{} {}", config.computed_style(), config.style.to_css());
+
+            let html = match base {
+                "chat" => {
+                    let chat = match options {
+                        ElementOptions::Chat(chat) => chat,
+                        _ => ChatOptions::default(),
+                    };
+                    let message_style = chat.message_style(&layout.message_style);
+                    class.push_str(&chat_classes(&message_style));
+                    format!(
+                        r#"<div class="chat-messages">{}</div><div class="flyout"><div class="poll-ui"></div><div class="superchat-ui"></div></div>"#,
+                        message::render_messages(&samples, &message_style)
+                    )
+                }
+                "live" => {
+                    let live = match options {
+                        ElementOptions::Live(live) => live,
+                        _ => LiveOptions::default(),
+                    };
+                    live_badge(&live)
+                }
+                "text" | "attribution" => match options {
+                    ElementOptions::Text(TextOptions { content }) => content.unwrap_or_default(),
+                    _ => String::new(),
+                },
+                "featured" => {
+                    class.push_str(" show-message");
+                    if let ElementOptions::Featured(FeaturedOptions { scale: Some(scale) }) =
+                        options
+                    {
+                        style.push_str(&format!(" --featured-scale: {};", scale));
+                    }
+                    featured(&samples, &layout.message_style)
+                }
+                "poll" | "superchat" | "goal" => {
+                    class.push_str(&format!(" {}-ui", base));
+                    String::new()
+                }
+                "matter" => {
+                    style.push_str(" overflow: hidden;");
+                    String::new()
+                }
+                _ => return None,
+            };
+
+            Some(PreviewElement {
+                id: key.clone(),
+                class,
+                style: style.trim().to_string(),
+                html,
+            })
+        })
+        .collect()
+}
+
+/// A few messages covering a run from one user, a badge and a donation
+fn sample_messages() -> Vec<Message> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let sample = |index: i64, platform: &str, username: &str, text: &str| Message {
+        platform: platform.to_string(),
+        username: username.to_string(),
+        message: text.to_string(),
+        sent_at: now - (4 - index) * 1000,
+        received_at: now,
+        ..Default::default()
+    };
+    vec![
+        sample(0, "youtube", "Viewer", "Hello from the preview!"),
+        sample(1, "youtube", "Viewer", "Chat messages look like this."),
+        Message {
+            is_mod: true,
+            ..sample(2, "twitch", "Moderator", "Keep it civil, chat.")
+        },
+        Message {
+            amount: 20.0,
+            currency: "USD".to_string(),
+            ..sample(3, "youtube", "Supporter", "Thanks for the stream!")
+        },
+    ]
+}
+
+/// The classes the overlay toggles on a chat element for its effective options
+fn chat_classes(style: &MessageStyle) -> String {
+    [
+        (style.condensed_mode, " chat--condensed"),
+        (!style.show_avatars, " chat--no-avatars"),
+        (!style.show_usernames, " chat--no-usernames"),
+        (style.direction == "top", " chat--top-first"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, class)| *class)
+    .collect()
+}
+
+fn live_badge(options: &LiveOptions) -> String {
+    let mut html = String::new();
+    if options.show_icon == Some(true) {
+        html.push_str(r#"<span class="live-icon live-badge">📺</span>"#);
+    }
+    if options.show_label != Some(false) {
+        html.push_str(r#"<span class="live-label live-badge">LIVE</span>"#);
+    }
+    if options.show_count != Some(false) {
+        html.push_str(&format!(
+            r#"<span class="live-totals live-badge">{}</span>"#,
+            SAMPLE_VIEWERS
+        ));
+    }
+    html
+}
+
+/// The sample donation as featured, with its id prefixed like the overlay does
+fn featured(samples: &[Message], style: &MessageStyle) -> String {
+    let Some(paid) = samples.iter().find(|message| message.is_premium()) else {
+        return String::new();
+    };
+    paid.to_html_with_style(style).replacen(
+        &format!(r#"id="{}""#, paid.id),
+        &format!(r#"id="feature-{}""#, paid.id),
+        1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elements() {
+        let mut layout = Layout::default_layout();
+        layout.elements.get_mut("chat").unwrap().options =
+            Some(serde_json::json!({ "condensedMode": true, "direction": "top" }));
+        layout
+            .elements
+            .insert("custom".to_string(), Default::default());
+        let elements = elements(&layout);
+        let element = |id: &str| elements.iter().find(|element| element.id == id);
+
+        assert!(element("custom").is_none());
+        let chat = element("chat").unwrap();
+        assert_eq!(
+            chat.class,
+            "element element--chat chat--condensed chat--top-first"
+        );
+        assert!(chat.style.starts_with("position: absolute;"));
+        assert!(chat.style.contains("background-color: transparent;"));
+        assert_eq!(chat.html.matches(r#"class="msg "#).count(), 4);
+        // The newest of the two messages from "Viewer" keeps the name when growing from the top
+        assert_eq!(chat.html.matches("msg--grouped").count(), 1);
+
+        assert!(element("live").unwrap().html.contains("1337"));
+        assert_eq!(element("text").unwrap().html, "Mad at the Internet");
+        let featured = element("featured").unwrap();
+        assert!(featured.class.ends_with(" show-message"));
+        assert!(featured.html.contains(r#"id="feature-"#));
+    }
+}
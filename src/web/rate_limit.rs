@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// Token bucket state for a single key.
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// Messages shed since the bucket last had capacity
+    dropped: u64,
+}
+
+/// Per-key token bucket rate limiter.
+/// Each key refills at `rate` tokens per second up to `burst` tokens.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1.0),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token for `key`, returning false if the message should be shed.
+    /// A non-positive rate disables limiting.
+    pub fn check(&mut self, key: &str) -> bool {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&mut self, key: &str, now: Instant) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+
+        let bucket = self
+            .buckets
+            .entry(key.to_owned())
+            .or_insert_with(|| Bucket {
+                tokens: self.burst,
+                updated_at: now,
+                dropped: 0,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            if bucket.dropped > 0 {
+                info!(key = %key, dropped = bucket.dropped, "Rate limit lifted");
+                bucket.dropped = 0;
+            }
+            true
+        } else {
+            if bucket.dropped == 0 {
+                warn!(key = %key, rate = self.rate, "Rate limit exceeded, shedding messages");
+            }
+            bucket.dropped += 1;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_above_limit_is_shed() {
+        let mut limiter = RateLimiter::new(10.0, 5.0);
+        let now = Instant::now();
+
+        let allowed = (0..20).filter(|_| limiter.check_at("Kick", now)).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[test]
+    fn test_steady_traffic_passes() {
+        let mut limiter = RateLimiter::new(10.0, 5.0);
+        let start = Instant::now();
+
+        // 10/s sustained for 5 seconds
+        for i in 0..50 {
+            let now = start + Duration::from_millis(i * 100);
+            assert!(limiter.check_at("YouTube", now));
+        }
+    }
+
+    #[test]
+    fn test_buckets_are_per_key() {
+        let mut limiter = RateLimiter::new(1.0, 1.0);
+        let now = Instant::now();
+
+        assert!(limiter.check_at("Kick", now));
+        assert!(!limiter.check_at("Kick", now));
+        assert!(limiter.check_at("Rumble", now));
+    }
+
+    #[test]
+    fn test_bucket_refills() {
+        let mut limiter = RateLimiter::new(2.0, 2.0);
+        let now = Instant::now();
+
+        assert!(limiter.check_at("Kick", now));
+        assert!(limiter.check_at("Kick", now));
+        assert!(!limiter.check_at("Kick", now));
+        assert!(limiter.check_at("Kick", now + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_zero_rate_disables_limit() {
+        let mut limiter = RateLimiter::new(0.0, 0.0);
+        let now = Instant::now();
+        assert!((0..1000).all(|_| limiter.check_at("Kick", now)));
+    }
+}
@@ -1,108 +1,819 @@
-use actix::{Actor, Context, Handler, MessageResult, Recipient};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use actix::dev::SendError;
+use actix::{Actor, AsyncContext, Context, Handler, MessageResult, Recipient, SpawnHandle};
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use super::message;
+use super::avatar::AvatarConfig;
+use super::client::ClientRole;
+use super::message::{self, BroadcastError};
+use super::message_buffer::MessageBuffer;
+use super::metrics::{Gauges, Metrics};
+use super::rate_limit::RateLimiter;
+use crate::alert_sounds::AlertSounds;
 use crate::database::Database;
 use crate::exchange::ExchangeRates;
-use crate::layout::{Layout, LayoutManager};
-use crate::message::Message as ChatMessage;
+use crate::goal::{Goal, GoalProgress};
+use crate::integrations::PaidMessage;
+use crate::layout::{
+    ImportReport, InvalidLayout, Layout, LayoutManager, LayoutVersion, MAX_MESSAGES,
+};
+use crate::message::{
+    canonical_platform, escape_html, is_valid_avatar, LivestreamUpdate, Message as ChatMessage,
+    BLANK_AVATAR,
+};
+use crate::platforms::replay::ReplayLine;
+use crate::poll::{self, Poll};
+use crate::sneed_env;
+
+/// How far back the database is read to rebuild the current session on startup.
+const SESSION_WINDOW_HOURS: u32 = 24;
+/// How many messages a (re)connecting client is sent by `request_messages` when it isn't
+/// subscribed to a layout with its own `maxMessages`
+const RECENT_MESSAGES: usize = 100;
+/// Messages kept in memory (`chat_messages`) when `CHAT_BUFFER_SIZE` isn't set; paid
+/// messages in the session window are kept beyond it
+const CHAT_BUFFER_SIZE: usize = 5000;
+/// Featured messages remembered for `RequestFeaturedHistory`
+const FEATURED_HISTORY: usize = 10;
+/// Messages /api/messages returns without a `limit`, and the most it returns with one
+const DEFAULT_MESSAGES_LIMIT: usize = 100;
+const MAX_MESSAGES_LIMIT: usize = 1000;
+/// Most messages held for moderation; the oldest are dropped beyond this
+const MAX_PENDING_MESSAGES: usize = 500;
+/// Users tracked by slow mode before those outside the window are forgotten
+const SLOW_MODE_USERS: usize = 1000;
+/// Most recent message texts remembered for duplicate suppression
+const RECENT_TEXTS: usize = 200;
+/// Minimum time between `server_stats` broadcasts
+const SERVER_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Minimum time between `viewers` broadcasts
+const VIEWERS_INTERVAL: Duration = Duration::from_millis(500);
+/// How often connections with a full mailbox are checked for eviction
+const STALLED_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often viewer counts are sampled into `viewer_history`
+const VIEWER_HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+/// Samples kept per platform: an hour at one a minute
+const VIEWER_HISTORY_LEN: usize = 60;
 
 pub struct Connection {
     #[allow(dead_code)] // Stored in HashMap key; field useful for debugging
     pub id: usize,
     pub recipient: Recipient<message::Reply>,
+    pub close: Recipient<message::Close>,
+    pub role: ClientRole,
     /// If set, this client only receives updates for this specific layout.
     /// If None, the client receives updates for any layout (e.g., editor clients).
     pub subscribed_layout: Option<String>,
+    /// If set, `subscribed_layout` tracks whatever layout is active on this output
+    pub subscribed_output: Option<String>,
+    /// Broadcast tags this client wants; empty means all of them
+    pub subscribed_tags: HashSet<String>,
+    /// When a send last found this client's mailbox full, cleared by the next successful send
+    pub stalled_since: Cell<Option<Instant>>,
+}
+
+impl Connection {
+    /// Whether broadcasts tagged `tag` should reach this client
+    fn wants(&self, tag: &str) -> bool {
+        self.subscribed_tags.is_empty() || self.subscribed_tags.contains(tag)
+    }
+
+    /// Queue a reply without blocking the broadcast loop on a client that stopped draining
+    fn send(&self, reply: &str) {
+        match self.recipient.try_send(message::Reply(reply.to_owned())) {
+            Ok(()) => self.stalled_since.set(None),
+            Err(SendError::Full(_)) => {
+                if self.stalled_since.get().is_none() {
+                    self.stalled_since.set(Some(Instant::now()));
+                }
+            }
+            // The client is stopping and will send Disconnect
+            Err(SendError::Closed(_)) => {}
+        }
+    }
+}
+
+/// `DEFAULT_AVATAR`, a URL or image data URI; the blank avatar if unset or unusable
+fn default_avatar() -> String {
+    match dotenvy::var("DEFAULT_AVATAR") {
+        Ok(avatar) if is_valid_avatar(avatar.trim()) => avatar.trim().to_string(),
+        Ok(avatar) if !avatar.trim().is_empty() => {
+            warn!("DEFAULT_AVATAR is not an http(s) URL, image data URI or path; using a blank avatar");
+            BLANK_AVATAR.to_string()
+        }
+        _ => BLANK_AVATAR.to_string(),
+    }
 }
 
 /// Define HTTP actor
 pub struct ChatServer {
     pub clients: HashMap<usize, Connection>,
-    pub chat_messages: HashMap<Uuid, ChatMessage>,
+    /// The session's messages, evicting the oldest unpaid ones beyond `CHAT_BUFFER_SIZE`
+    pub chat_messages: MessageBuffer,
     pub exchange_rates: ExchangeRates,
     pub viewer_counts: HashMap<String, usize>,
+    /// The last `VIEWER_HISTORY_LEN` viewer samples per platform, oldest first
+    pub viewer_history: HashMap<String, VecDeque<message::ViewerSample>>,
     pub layout_manager: Arc<Mutex<LayoutManager>>,
-    pub active_layout: String,
+    /// Active layout per output name. Outputs without an entry show the default output's.
+    pub active_layouts: HashMap<String, String>,
     /// Currently featured message (full data for decoupled rendering)
     pub featured_message: Option<ChatMessage>,
+    /// The last `FEATURED_HISTORY` messages featured, newest first, each listed once
+    pub featured_history: VecDeque<message::FeaturedEntry>,
+    /// Pending auto-unfeature for a message featured with `duration_secs`
+    unfeature_timer: Option<SpawnHandle>,
     /// SQLite database for persistent paid message storage
     pub database: Database,
+    /// Running total of paid messages this session, in USD.
+    /// Rebuilt from the database's session window on startup, so a restart
+    /// mid-stream does not reset the overlay's goal bar.
+    pub session_total_usd: f64,
+    /// Donation goal; progress toward it is `session_total_usd`
+    pub goal: Option<Goal>,
+    /// Keep the goal in the database so it survives restarts (`GOAL_PERSIST`)
+    pub persist_goal: bool,
+    /// Per-platform limit on inbound chat messages
+    pub ingest_limiter: RateLimiter,
+    /// Counters for /metrics
+    pub metrics: Metrics,
+    /// Clients whose mailbox stays full for longer than this are disconnected
+    pub client_timeout: Duration,
+    /// Clients or viewer counts changed since `server_stats` was last broadcast
+    pub stats_changed: bool,
+    /// Viewer counts changed since `viewers` was last broadcast
+    pub viewers_changed: bool,
+    /// Integrations told about every accepted paid message
+    pub paid_message_listeners: Vec<Recipient<PaidMessage>>,
+    /// Substituted on ingest for an empty or unusable avatar
+    pub default_avatar: String,
+    /// Avatars on these hosts are rewritten to the `/avatar` proxy
+    pub avatar_proxy: Option<AvatarConfig>,
+    /// Gets a copy of every inbound message, removal and viewer count (`--record`)
+    pub recorder: Option<Recipient<ReplayLine>>,
+    /// The running poll, or the last one after it ends
+    pub poll: Option<Poll>,
+    /// Hold incoming chat for approval instead of broadcasting it
+    pub moderated: bool,
+    /// Messages waiting for approval, oldest first, escaped but still in their own currency
+    pub pending_messages: VecDeque<ChatMessage>,
+    /// Least time between one user's unpaid messages reaching clients; None is off
+    pub slow_mode: Option<Duration>,
+    /// When each (platform, username) last had a message shown, for slow mode
+    pub last_displayed: HashMap<(String, String), Instant>,
+    /// Drop unpaid chat repeating text seen from anyone this recently; None is off
+    pub duplicate_window: Option<Duration>,
+    /// Normalized texts of recent unpaid chat and when each was seen, oldest first
+    pub recent_texts: VecDeque<(Instant, String)>,
+    /// Canonical names of platforms whose chat is dropped and whose viewers count as zero
+    pub disabled_platforms: HashSet<String>,
+    /// Still save (and count toward the session total) paid messages from disabled platforms
+    pub store_disabled_paid: bool,
+    /// Clip named in the broadcast of each paid message, by tier
+    pub alert_sounds: AlertSounds,
+    /// File `alert_sounds` is (re)loaded from
+    pub alert_sounds_path: String,
+}
+
+/// Message text compared for duplicate suppression: lowercased, whitespace collapsed
+fn normalize_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether a paid message counts toward the session total, as reloaded on startup
+fn in_session_window(chat_msg: &ChatMessage) -> bool {
+    let cutoff =
+        chrono::Utc::now().timestamp_millis() - i64::from(SESSION_WINDOW_HOURS) * 60 * 60 * 1000;
+    chat_msg.received_at >= cutoff
+}
+
+/// Serialize a value as a reply's payload
+fn to_payload<T: Serialize + ?Sized>(value: &T) -> Result<String, BroadcastError> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// The frame sent to clients for a payload, or None (logged) if either failed to build
+fn reply(tag: &str, message: Result<String, BroadcastError>) -> Option<String> {
+    let reply = message.and_then(|message| {
+        to_payload(&message::ReplyInner {
+            tag: tag.to_owned(),
+            message,
+        })
+    });
+    match reply {
+        Ok(reply) => Some(reply),
+        Err(e) => {
+            warn!(tag, error = %e, "Skipping broadcast");
+            None
+        }
+    }
 }
 
 impl ChatServer {
     pub fn new(exchange_rates: ExchangeRates, layout_manager: Arc<Mutex<LayoutManager>>) -> Self {
-        info!("Chat actor starting up.");
-
         // Initialize SQLite database
         let database = Database::new().expect("Failed to initialize database");
+        Self::with_database(exchange_rates, layout_manager, database)
+    }
+
+    pub fn with_database(
+        exchange_rates: ExchangeRates,
+        layout_manager: Arc<Mutex<LayoutManager>>,
+        database: Database,
+    ) -> Self {
+        info!("Chat actor starting up.");
 
         // Clean up messages older than 48 hours on startup
         if let Err(e) = database.cleanup_old_messages(48) {
             warn!("Failed to cleanup old messages: {}", e);
         }
 
-        // Determine active layout (use "default" if it exists)
-        let active_layout = {
-            let lm = layout_manager.lock().unwrap();
-            if lm.exists("default") {
+        // Determine active layout (use "default" if it exists), then restore the layouts
+        // outputs were last switched to, skipping any that have since been deleted
+        let active_layouts = {
+            let lm = lock_layouts(&layout_manager);
+            let fallback = if lm.exists("default") {
                 "default".to_string()
             } else {
                 lm.list().unwrap_or_default().first().cloned().unwrap_or_else(|| "default".to_string())
+            };
+            let mut active_layouts =
+                HashMap::from([(message::DEFAULT_OUTPUT.to_string(), fallback)]);
+            let stored = database.load_active_layouts().unwrap_or_else(|e| {
+                warn!("Failed to load active layouts from database: {}", e);
+                HashMap::new()
+            });
+            for (output, name) in stored {
+                if lm.exists(&name) {
+                    active_layouts.insert(output, name);
+                } else {
+                    warn!(%output, layout = %name, "Stored active layout no longer exists");
+                }
             }
+            active_layouts
         };
 
         // Load paid messages from database into chat_messages for recent message history
-        let chat_messages: HashMap<Uuid, ChatMessage> = database
-            .get_paid_messages_since_hours(24)
-            .unwrap_or_default()
-            .into_iter()
-            .map(|msg| (msg.id, msg))
-            .collect();
+        let mut paid_messages = database
+            .get_paid_messages_above_amount(0.0, SESSION_WINDOW_HOURS)
+            .unwrap_or_default();
+
+        // Paid messages are stored already converted to USD. Folded from 0.0 because
+        // summing no floats gives -0.0, which would serialize as such.
+        let session_total_usd = paid_messages
+            .iter()
+            .fold(0.0, |total, msg| total + msg.amount);
+        info!(
+            "Loaded {} paid messages from database (session total ${:.2})",
+            paid_messages.len(),
+            session_total_usd
+        );
 
-        info!("Loaded {} paid messages from database", chat_messages.len());
+        let mut chat_messages =
+            MessageBuffer::new(sneed_env::var_or("CHAT_BUFFER_SIZE", CHAT_BUFFER_SIZE));
+        paid_messages.sort_by_key(|msg| msg.received_at);
+        for msg in paid_messages {
+            chat_messages.insert(msg);
+        }
+
+        let alert_sounds_path = AlertSounds::path_from_env();
+        let alert_sounds = AlertSounds::load(&alert_sounds_path).unwrap_or_else(|e| {
+            warn!("Alert sounds disabled: {:#}", e);
+            AlertSounds::default()
+        });
+
+        let persist_goal = sneed_env::var_or("GOAL_PERSIST", true);
+        let goal = if persist_goal {
+            database.load_goal().unwrap_or_else(|e| {
+                warn!("Failed to load donation goal from database: {}", e);
+                None
+            })
+        } else {
+            None
+        };
 
         Self {
             clients: HashMap::with_capacity(100),
             chat_messages,
             exchange_rates,
             viewer_counts: HashMap::with_capacity(100),
+            viewer_history: HashMap::new(),
             layout_manager,
-            active_layout,
+            active_layouts,
             featured_message: None,
+            featured_history: VecDeque::with_capacity(FEATURED_HISTORY),
+            unfeature_timer: None,
             database,
+            session_total_usd,
+            goal,
+            persist_goal,
+            ingest_limiter: RateLimiter::new(
+                sneed_env::var_or("INGEST_RATE_LIMIT", 50.0),
+                sneed_env::var_or("INGEST_RATE_BURST", 100.0),
+            ),
+            metrics: Metrics::default(),
+            stats_changed: false,
+            viewers_changed: false,
+            client_timeout: super::CLIENT_TIMEOUT,
+            paid_message_listeners: Vec::new(),
+            default_avatar: default_avatar(),
+            avatar_proxy: None,
+            recorder: None,
+            poll: None,
+            moderated: false,
+            pending_messages: VecDeque::new(),
+            slow_mode: None,
+            last_displayed: HashMap::new(),
+            duplicate_window: Some(Duration::from_secs(sneed_env::var_or(
+                "DUPLICATE_WINDOW_SECS",
+                0,
+            )))
+            .filter(|window| !window.is_zero()),
+            recent_texts: VecDeque::new(),
+            disabled_platforms: HashSet::new(),
+            store_disabled_paid: sneed_env::var_or("DISABLED_PLATFORM_STORE_PAID", true),
+            alert_sounds,
+            alert_sounds_path,
+        }
+    }
+
+    /// Send a tagged reply to every connected client.
+    fn broadcast(&self, tag: &str, message: Result<String, BroadcastError>) {
+        let Some(reply) = reply(tag, message) else {
+            return;
+        };
+
+        for conn in self.clients.values() {
+            if conn.wants(tag) {
+                conn.send(&reply);
+            }
+        }
+    }
+
+    /// Send a tagged reply to ingest clients (dashboard, editor and bridges) only.
+    fn broadcast_ingest(&self, tag: &str, message: Result<String, BroadcastError>) {
+        let Some(reply) = reply(tag, message) else {
+            return;
+        };
+
+        for conn in self.clients.values() {
+            if conn.role == ClientRole::Ingest && conn.wants(tag) {
+                conn.send(&reply);
+            }
+        }
+    }
+
+    /// Disconnect clients whose mailbox has been full for longer than `client_timeout`
+    fn evict_stalled_clients(&mut self) {
+        let timeout = self.client_timeout;
+        let stalled: Vec<usize> = self
+            .clients
+            .iter()
+            .filter(|(_, conn)| {
+                conn.stalled_since
+                    .get()
+                    .is_some_and(|since| since.elapsed() > timeout)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stalled {
+            if let Some(conn) = self.clients.remove(&id) {
+                warn!(client = id, "Evicting client that stopped reading messages");
+                conn.close.do_send(message::Close);
+                self.stats_changed = true;
+            }
+        }
+    }
+
+    fn server_stats(&self) -> message::ServerStats {
+        let viewers = self.visible_viewer_counts();
+        message::ServerStats {
+            clients: self.clients.len(),
+            viewers_total: viewers.values().sum(),
+            viewers_by_platform: viewers,
+        }
+    }
+
+    /// Viewer counts with disabled platforms zeroed, so overlays drop them from totals
+    fn visible_viewer_counts(&self) -> HashMap<String, usize> {
+        self.viewer_counts
+            .iter()
+            .map(|(platform, &viewers)| {
+                let viewers = if self.disabled_platforms.contains(platform) {
+                    0
+                } else {
+                    viewers
+                };
+                (platform.clone(), viewers)
+            })
+            .collect()
+    }
+
+    /// Add the current viewer counts to each platform's history, dropping samples beyond
+    /// `VIEWER_HISTORY_LEN`
+    fn sample_viewers(&mut self, at: i64) {
+        for (platform, viewers) in self.visible_viewer_counts() {
+            let history = self.viewer_history.entry(platform).or_default();
+            history.push_back(message::ViewerSample { at, viewers });
+            if history.len() > VIEWER_HISTORY_LEN {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Broadcast the poll's tally (or final result) to all clients.
+    fn broadcast_poll(&self) {
+        if let Some(poll) = &self.poll {
+            self.broadcast("poll_update", to_payload(poll));
+        }
+    }
+
+    /// Start a poll, ending (and saving) any that is still running
+    fn start_poll(
+        &mut self,
+        question: &str,
+        options: &[String],
+        multi_vote: bool,
+    ) -> Result<(), String> {
+        let poll = Poll::new(question, options, multi_vote)?;
+        self.end_poll();
+        info!(question = %poll.question, options = poll.options.len(), "Poll started");
+        self.poll = Some(poll);
+        self.broadcast_poll();
+        Ok(())
+    }
+
+    /// End the running poll, saving and broadcasting its final result
+    fn end_poll(&mut self) {
+        let Some(poll) = self.poll.as_mut().filter(|poll| poll.is_active()) else {
+            return;
+        };
+        poll.end();
+        info!(question = %poll.question, votes = poll.total_votes, "Poll ended");
+        if let Err(e) = self.database.save_poll(poll) {
+            warn!("Failed to save poll result to database: {}", e);
+        }
+        self.broadcast_poll();
+    }
+
+    fn vote(&mut self, voter: Option<&str>, choices: &[usize]) {
+        if self
+            .poll
+            .as_mut()
+            .is_some_and(|poll| poll.vote(voter, choices))
+        {
+            self.broadcast_poll();
+        }
+    }
+
+    /// Poll commands in chat: the channel owner's `!poll`, `!multipoll` and `!endpoll`,
+    /// and everyone's votes while a poll runs
+    fn handle_poll_chat(&mut self, msg: &ChatMessage) {
+        if msg.is_owner {
+            if msg.message.trim() == "!endpoll" {
+                self.end_poll();
+                return;
+            }
+            if let Some((question, options, multi_vote)) = poll::parse_start_command(&msg.message) {
+                if let Err(e) = self.start_poll(&question, &options, multi_vote) {
+                    warn!("Ignoring poll command: {}", e);
+                }
+                return;
+            }
+        }
+        if self.poll.as_ref().is_some_and(Poll::is_active) {
+            if let Some(choices) = poll::parse_vote(&msg.message) {
+                let voter = format!("{}:{}", msg.platform, msg.username);
+                self.vote(Some(&voter), &choices);
+            }
+        }
+    }
+
+    /// Copy inbound traffic to the recorder, as it arrived
+    fn record(&self, platform: &str, update: impl FnOnce(&mut LivestreamUpdate)) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        let mut line = ReplayLine {
+            recorded_at: Some(chrono::Utc::now().timestamp_millis()),
+            update: LivestreamUpdate {
+                platform: platform.to_string(),
+                channel: None,
+                messages: None,
+                removals: None,
+                viewers: None,
+            },
+        };
+        update(&mut line.update);
+        recorder.do_send(line);
+    }
+
+    /// Whether slow mode holds this message back: unpaid, and from a user who had one
+    /// shown within the window. Messages let through restart the user's window.
+    fn slowed(&mut self, chat_msg: &ChatMessage) -> bool {
+        let Some(window) = self.slow_mode else {
+            return false;
+        };
+        if chat_msg.is_premium() {
+            return false;
+        }
+        let now = Instant::now();
+        let key = (
+            canonical_platform(&chat_msg.platform),
+            chat_msg.username.to_lowercase(),
+        );
+        if self
+            .last_displayed
+            .get(&key)
+            .is_some_and(|shown| now.duration_since(*shown) < window)
+        {
+            return true;
+        }
+        if self.last_displayed.len() >= SLOW_MODE_USERS {
+            self.last_displayed
+                .retain(|_, shown| now.duration_since(*shown) < window);
+        }
+        self.last_displayed.insert(key, now);
+        false
+    }
+
+    /// Whether this unpaid message repeats text anyone sent within the duplicate window.
+    /// Every unpaid message checked is remembered, repeats included, so a flood keeps
+    /// its own text fresh.
+    fn is_copypasta(&mut self, chat_msg: &ChatMessage) -> bool {
+        let Some(window) = self.duplicate_window else {
+            return false;
+        };
+        if chat_msg.is_premium() {
+            return false;
+        }
+        let text = normalize_text(&chat_msg.message);
+        if text.is_empty() {
+            return false;
+        }
+        let now = Instant::now();
+        while self
+            .recent_texts
+            .front()
+            .is_some_and(|(seen, _)| now.duration_since(*seen) >= window)
+        {
+            self.recent_texts.pop_front();
+        }
+        let repeated = self.recent_texts.iter().any(|(_, seen)| *seen == text);
+        if self.recent_texts.len() >= RECENT_TEXTS {
+            self.recent_texts.pop_front();
+        }
+        self.recent_texts.push_back((now, text));
+        repeated
+    }
+
+    /// Queue a message for approval, showing it to ingest clients only. A full queue
+    /// drops its oldest unpaid message; if every message is paid, the oldest is saved and
+    /// counted toward the total instead of being lost.
+    fn hold_for_moderation(&mut self, chat_msg: ChatMessage) {
+        debug!(id = %chat_msg.id, "Holding message for moderation");
+        self.broadcast_ingest(
+            "pending_message",
+            chat_msg.try_to_json_with_alert_sound(None),
+        );
+        self.pending_messages.push_back(chat_msg);
+        if self.pending_messages.len() <= MAX_PENDING_MESSAGES {
+            return;
+        }
+        let index = self
+            .pending_messages
+            .iter()
+            .position(|msg| !msg.is_premium())
+            .unwrap_or(0);
+        let Some(mut dropped) = self.pending_messages.remove(index) else {
+            return;
+        };
+        self.broadcast_ingest("pending_removed", to_payload(&dropped.id));
+        if dropped.is_premium() {
+            warn!(id = %dropped.id, "Moderation queue is full of paid messages; saving its oldest without showing it");
+            self.convert_to_usd(&mut dropped);
+            self.record_paid_message(&dropped);
+        } else {
+            warn!(id = %dropped.id, "Moderation queue is full; dropping its oldest unpaid message");
+        }
+    }
+
+    /// Take a message out of the moderation queue, telling ingest clients it's resolved
+    fn take_pending(&mut self, id: Uuid) -> Option<ChatMessage> {
+        let index = self.pending_messages.iter().position(|msg| msg.id == id)?;
+        self.broadcast_ingest("pending_removed", to_payload(&id));
+        self.pending_messages.remove(index)
+    }
+
+    /// Convert a message's amount to USD in place, returning the amount and currency it
+    /// arrived with
+    fn convert_to_usd(&self, chat_msg: &mut ChatMessage) -> (f64, String) {
+        let original_amount = chat_msg.amount;
+        let original_currency = std::mem::replace(&mut chat_msg.currency, "USD".to_string());
+        chat_msg.amount = if original_amount > 0.0 {
+            self.exchange_rates
+                .get_usd(&original_currency, &original_amount)
+        } else {
+            0.0
+        };
+        (original_amount, original_currency)
+    }
+
+    /// Broadcast an accepted message, keep it in history and, if paid, persist it
+    fn publish_message(&mut self, mut chat_msg: ChatMessage) {
+        let (original_amount, original_currency) = self.convert_to_usd(&mut chat_msg);
+        let usd = chat_msg.amount;
+
+        // Send message to all clients, naming the clip for its tier if it's paid.
+        let alert_sound = if usd > 0.0 {
+            self.alert_sounds.sound_for(chat_msg.get_paid_tier())
+        } else {
+            None
+        };
+        self.broadcast(
+            "chat_message",
+            chat_msg.try_to_json_with_alert_sound(alert_sound),
+        );
+        self.metrics.record_message(&chat_msg.platform, usd);
+
+        self.chat_messages.insert(chat_msg.clone());
+
+        if usd > 0.0 {
+            self.record_paid_message(&chat_msg);
+            for listener in &self.paid_message_listeners {
+                listener.do_send(PaidMessage {
+                    message: chat_msg.clone(),
+                    original_amount,
+                    original_currency: original_currency.clone(),
+                });
+            }
+        }
+    }
+
+    /// A message from memory, else a paid one from the database
+    fn find_message(&self, id: &Uuid) -> Option<ChatMessage> {
+        self.chat_messages
+            .get(id)
+            .cloned()
+            .or_else(|| self.database.get_paid_message(id).ok().flatten())
+    }
+
+    /// The newest `limit` messages, oldest first
+    fn recent_messages(&self, limit: usize) -> Vec<ChatMessage> {
+        let mut last_messages: Vec<ChatMessage> =
+            self.chat_messages.recent(limit).cloned().collect();
+        // Bridges stamp received_at, so arrival order can differ slightly
+        last_messages.sort_by_key(|msg| msg.received_at);
+        last_messages
+    }
+
+    /// Save a paid message to the database and add it to the session total
+    fn record_paid_message(&mut self, chat_msg: &ChatMessage) {
+        if let Err(e) = self.database.upsert_paid_message(chat_msg) {
+            warn!("Failed to save paid message to database: {}", e);
+        }
+
+        self.session_total_usd += chat_msg.amount;
+        self.broadcast_session_total();
+    }
+
+    /// Broadcast the current session total, and the goal it counts toward, to all clients.
+    fn broadcast_session_total(&self) {
+        self.broadcast("session_total", to_payload(&self.session_total_usd));
+        self.broadcast_goal();
+    }
+
+    fn goal_progress(&self) -> Option<GoalProgress> {
+        self.goal
+            .as_ref()
+            .map(|goal| goal.progress(self.session_total_usd))
+    }
+
+    /// Broadcast the goal's progress, or null once it's reset
+    fn broadcast_goal(&self) {
+        self.broadcast("goal_update", to_payload(&self.goal_progress()));
+    }
+
+    /// Replace the goal, or remove it with `None`, and tell every client
+    fn set_goal(&mut self, goal: Option<Goal>) {
+        if self.persist_goal {
+            let saved = match &goal {
+                Some(goal) => self.database.save_goal(goal),
+                None => self.database.delete_goal(),
+            };
+            if let Err(e) = saved {
+                warn!("Failed to save donation goal to database: {}", e);
+            }
+        }
+        match &goal {
+            Some(goal) => info!(label = %goal.label, target = goal.target_usd, "Donation goal set"),
+            None => info!("Donation goal reset"),
+        }
+        self.goal = goal;
+        self.broadcast_goal();
+    }
+
+    /// Remember an output's layout across restarts; a failed write only costs that
+    /// output its selection after the next restart
+    fn save_active_layout(&self, output: &str, name: &str) {
+        if let Err(e) = self.database.save_active_layout(output, name) {
+            warn!(output, layout = name, error = %e, "Failed to save active layout");
+        }
+    }
+
+    /// The layout shown on an output
+    fn active_layout(&self, output: &str) -> &str {
+        output_layout(&self.active_layouts, output)
+    }
+
+    /// Point every output-following client at its output's current layout
+    fn sync_output_subscriptions(&mut self) {
+        for conn in self.clients.values_mut() {
+            if let Some(output) = &conn.subscribed_output {
+                conn.subscribed_layout =
+                    Some(output_layout(&self.active_layouts, output).to_string());
+            }
         }
     }
 
     /// Broadcast a layout update to relevant connected clients.
+    fn broadcast_layout(&self, layout: &Layout) {
+        self.broadcast_for_layout(&layout.name, "layout_update", to_payload(layout));
+    }
+
+    /// Send a layout-specific message to relevant connected clients.
     /// - Clients with no subscription (None) receive all layout updates (e.g., editor)
     /// - Clients subscribed to a specific layout only receive updates for that layout
-    fn broadcast_layout(&self, layout: &Layout) {
-        let reply = serde_json::to_string(&message::ReplyInner {
-            tag: "layout_update".to_owned(),
-            message: serde_json::to_string(layout).expect("Failed to serialize layout"),
-        })
-        .expect("Failed to serialize layout ReplyInner");
+    fn broadcast_for_layout(
+        &self,
+        layout_name: &str,
+        tag: &str,
+        message: Result<String, BroadcastError>,
+    ) {
+        let Some(reply) = reply(tag, message) else {
+            return;
+        };
 
-        for (_, conn) in &self.clients {
+        for conn in self.clients.values() {
             // Send to clients that:
             // 1. Have no subscription (editor clients want all updates)
             // 2. Are subscribed to this specific layout
             let should_send = match &conn.subscribed_layout {
                 None => true, // No subscription = receive all updates
-                Some(subscribed) => subscribed == &layout.name,
+                Some(subscribed) => subscribed == layout_name,
             };
 
-            if should_send {
-                conn.recipient.do_send(message::Reply(reply.clone()));
+            if should_send && conn.wants(tag) {
+                conn.send(&reply);
             }
         }
     }
 }
 
+/// Convert a `LayoutManager::save` result for clients: SCSS errors as "element: error"
+/// on success, every validation problem on failure
+fn save_errors(result: anyhow::Result<Vec<(String, String)>>) -> Result<Vec<String>, Vec<String>> {
+    match result {
+        Ok(scss_errors) => Ok(scss_errors
+            .into_iter()
+            .map(|(id, error)| format!("{}: {}", id, error))
+            .collect()),
+        Err(e) => Err(match e.downcast_ref::<InvalidLayout>() {
+            Some(invalid) => invalid.problems.clone(),
+            None => vec![e.to_string()],
+        }),
+    }
+}
+
+/// Lock the layout manager, recovering it if a panic poisoned the lock. Layouts live on
+/// disk, so a panic mid-operation can't leave the manager itself half-updated, and
+/// overlays should keep working rather than every layout request panicking after it.
+fn lock_layouts(layout_manager: &Mutex<LayoutManager>) -> MutexGuard<'_, LayoutManager> {
+    layout_manager.lock().unwrap_or_else(|poisoned| {
+        warn!("Layout manager lock was poisoned by a panic; recovering");
+        layout_manager.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Outputs that were never switched show the default output's layout
+fn output_layout<'a>(active_layouts: &'a HashMap<String, String>, output: &str) -> &'a str {
+    active_layouts
+        .get(output)
+        .or_else(|| active_layouts.get(message::DEFAULT_OUTPUT))
+        .map(String::as_str)
+        .unwrap_or("default")
+}
+
 // conn.recipient.do_send(message::Reply(message.to_owned()));
 
 /// Make actor from `ChatServer`
@@ -112,6 +823,28 @@ impl Actor for ChatServer {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         ctx.set_mailbox_capacity(256);
+
+        ctx.run_interval(STALLED_CHECK_INTERVAL, |act, _| act.evict_stalled_clients());
+
+        // Platforms report viewers independently, so send one snapshot per interval
+        ctx.run_interval(VIEWERS_INTERVAL, |act, _| {
+            if act.viewers_changed {
+                act.viewers_changed = false;
+                act.broadcast("viewers", to_payload(&act.visible_viewer_counts()));
+            }
+        });
+
+        ctx.run_interval(VIEWER_HISTORY_INTERVAL, |act, _| {
+            act.sample_viewers(chrono::Utc::now().timestamp_millis());
+        });
+
+        // Connects and viewer updates can arrive in bursts, so coalesce them
+        ctx.run_interval(SERVER_STATS_INTERVAL, |act, _| {
+            if act.stats_changed {
+                act.stats_changed = false;
+                act.broadcast("server_stats", to_payload(&act.server_stats()));
+            }
+        });
     }
 }
 
@@ -128,9 +861,15 @@ impl Handler<message::Connect> for ChatServer {
             Connection {
                 id,
                 recipient: msg.recipient,
+                close: msg.close,
+                role: msg.role,
                 subscribed_layout: None,
+                subscribed_output: None,
+                subscribed_tags: HashSet::new(),
+                stalled_since: Cell::new(None),
             },
         );
+        self.stats_changed = true;
         id
     }
 }
@@ -140,51 +879,77 @@ impl Handler<message::Content> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, mut msg: message::Content, _: &mut Context<Self>) -> Self::Result {
-        info!("{}", msg.chat_message.to_console_msg());
+        self.record(&msg.chat_message.platform, |update| {
+            update.messages = Some(vec![msg.chat_message.clone()]);
+        });
+        msg.chat_message.sanitize();
 
-        let usd = if msg.chat_message.amount > 0.0 {
-            self.exchange_rates
-                .get_usd(&msg.chat_message.currency, &msg.chat_message.amount)
-        } else {
-            0.0
-        };
+        // Bridges running in several tabs relay the same message more than once.
+        // Check before anything below mutates the message or stores it.
+        let id = msg.chat_message.id;
+        if self.chat_messages.contains(&id)
+            || self.pending_messages.iter().any(|pending| pending.id == id)
+            || (msg.chat_message.is_premium()
+                && matches!(self.database.get_paid_message(&id), Ok(Some(_))))
+        {
+            debug!(id = %id, "Dropping duplicate message");
+            self.metrics
+                .record_dropped(&msg.chat_message.platform, "duplicate");
+            return;
+        }
+
+        match self
+            .database
+            .is_banned(&msg.chat_message.platform, &msg.chat_message.username)
+        {
+            Ok(true) => {
+                debug!(id = %id, "Dropping message from banned user");
+                self.metrics
+                    .record_dropped(&msg.chat_message.platform, "banned");
+                return;
+            }
+            Ok(false) => {}
+            Err(e) => warn!("Failed to check the ban list: {}", e),
+        }
+
+        // Shed floods from a misbehaving bridge before they reach clients or the database.
+        if !self.ingest_limiter.check(&msg.chat_message.platform) {
+            self.metrics
+                .record_dropped(&msg.chat_message.platform, "rate_limited");
+            return;
+        }
+
+        info!(
+            highlight = msg.chat_message.is_premium(),
+            "{}",
+            msg.chat_message.to_console_msg()
+        );
+        let platform_disabled = self.disabled_platforms.contains(&msg.chat_message.platform);
+        if !platform_disabled {
+            self.handle_poll_chat(&msg.chat_message);
+        }
+
+        if !is_valid_avatar(&msg.chat_message.avatar) {
+            msg.chat_message.avatar = self.default_avatar.clone();
+        }
+        if let Some(proxied) = self
+            .avatar_proxy
+            .as_ref()
+            .and_then(|config| config.proxied(&msg.chat_message.avatar))
+        {
+            msg.chat_message.avatar = proxied;
+        }
+        msg.chat_message.escape_for_display();
 
-        msg.chat_message.username = msg
-            .chat_message
-            .username
-            .replace("&", "&amp;")
-            .replace("\"", "&quot")
-            .replace("'", "&#039;")
-            .replace("<", "&lt;")
-            .replace(">", "&gt;");
-        msg.chat_message.message = msg
-            .chat_message
-            .message
-            .replace("&", "&amp;")
-            .replace("\"", "&quot")
-            .replace("'", "&#039;")
-            .replace("<", "&lt;")
-            .replace(">", "&gt;");
-
-        // emojis = Vec<(String, String, String) where names are (find, replace, name)
         let mut replacements: HashMap<usize, String> =
             HashMap::with_capacity(msg.chat_message.emojis.len());
         let mut replacement_string = msg.chat_message.message.to_owned();
 
         // First, replace all instances with tokens.
-        for (find, replace, name) in &msg.chat_message.emojis {
-            let url = replace
-                .replace("&", "&amp;")
-                .replace("\"", "&quot")
-                .replace("'", "&#039;")
-                .replace("<", "&lt;")
-                .replace(">", "&gt;");
+        for emoji in &msg.chat_message.emojis {
             let key: usize = rand::random();
-            let value: String = format!(
-                "<img class=\"emoji\" src=\"{}\" data-emoji=\"{}\" alt=\"{}\" />",
-                url, name, name
-            );
-            replacement_string = replacement_string.replace(find, &format!("<{}>", key));
+            let value: String = emoji.to_html();
+            replacement_string = replacement_string.replace(&emoji.find, &format!("<{}>", key));
             replacements.insert(key, value);
         }
 
@@ -197,61 +962,149 @@ impl Handler<message::Content> for ChatServer {
         // This stops double replacements.
         msg.chat_message.message = replacement_string;
 
+        // Amounts stay in their own currency until the message is accepted, so listeners
+        // get the original alongside the USD value
         let mut chat_msg = msg.chat_message;
-        let id = chat_msg.id.to_owned();
-        chat_msg.amount = usd;
-        chat_msg.currency = "USD".to_string();
-
-        // Send message to all clients.
-        for (_, conn) in &self.clients {
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "chat_message".to_owned(),
-                    message: chat_msg.to_json(),
-                })
-                .expect("Failed to serialize chat message reply_inner."),
-            ));
+        if platform_disabled {
+            self.metrics
+                .record_dropped(&chat_msg.platform, "platform_disabled");
+            self.convert_to_usd(&mut chat_msg);
+            if chat_msg.amount > 0.0 && self.store_disabled_paid {
+                self.record_paid_message(&chat_msg);
+            }
+        } else if self.slowed(&chat_msg) {
+            debug!(id = %chat_msg.id, "Dropping message held back by slow mode");
+            self.metrics.record_dropped(&chat_msg.platform, "slow_mode");
+        } else if self.is_copypasta(&chat_msg) {
+            debug!(id = %chat_msg.id, "Dropping repeated message text");
+            self.metrics.record_dropped(&chat_msg.platform, "copypasta");
+        } else if self.moderated {
+            self.hold_for_moderation(chat_msg);
+        } else {
+            self.publish_message(chat_msg);
         }
+    }
+}
 
-        if self.chat_messages.len() >= self.chat_messages.capacity() - 1 {
-            self.chat_messages.reserve(100);
+/// Handler for turning moderated mode on or off.
+impl Handler<message::SetModerated> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::SetModerated, _: &mut Context<Self>) -> Self::Result {
+        if self.moderated == msg.enabled {
+            return;
         }
-        self.chat_messages.insert(id.to_owned(), chat_msg.clone());
+        info!(enabled = msg.enabled, "Moderated mode changed");
+        // Messages already queued stay there until approved or rejected
+        self.moderated = msg.enabled;
+        self.broadcast_ingest("moderation", to_payload(&self.moderated));
+    }
+}
 
-        // Save paid messages to SQLite database
-        if usd > 0.0 {
-            if let Err(e) = self.database.upsert_paid_message(&chat_msg) {
-                warn!("Failed to save paid message to database: {}", e);
-            }
+/// Handler for test messages. They are ingested exactly like a bridge's messages, so they
+/// are escaped, converted, broadcast and (if paid) stored for real.
+impl Handler<message::TestMessage> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::TestMessage, ctx: &mut Context<Self>) -> Self::Result {
+        info!(
+            platform = %msg.platform,
+            username = %msg.username,
+            amount = msg.amount,
+            currency = %msg.currency,
+            "Injecting test message"
+        );
+        let chat_message = ChatMessage {
+            platform: msg.platform,
+            username: msg.username,
+            message: msg.message,
+            amount: msg.amount,
+            currency: msg.currency,
+            avatar: String::new(),
+            ..Default::default()
+        };
+        Handler::<message::Content>::handle(self, message::Content { chat_message }, ctx);
+    }
+}
+
+/// Handler for changing slow mode.
+impl Handler<message::SetSlowMode> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::SetSlowMode, _: &mut Context<Self>) -> Self::Result {
+        let slow_mode = (msg.secs > 0).then(|| Duration::from_secs(msg.secs));
+        if self.slow_mode == slow_mode {
+            return;
+        }
+        info!(secs = msg.secs, "Slow mode changed");
+        self.slow_mode = slow_mode;
+        self.last_displayed.clear();
+        self.broadcast_ingest("slow_mode", to_payload(&msg.secs));
+    }
+}
+
+/// Handler for approving a message held for moderation.
+impl Handler<message::ApproveMessage> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::ApproveMessage, _: &mut Context<Self>) -> Self::Result {
+        match self.take_pending(msg.id) {
+            Some(chat_msg) => self.publish_message(chat_msg),
+            None => debug!(id = %msg.id, "No pending message to approve"),
+        }
+    }
+}
+
+/// Handler for rejecting a message held for moderation.
+impl Handler<message::RejectMessage> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::RejectMessage, _: &mut Context<Self>) -> Self::Result {
+        if self.take_pending(msg.id).is_some() {
+            debug!(id = %msg.id, "Rejected pending message");
         }
     }
 }
 
+/// Handler for requesting moderated mode and its queue.
+impl Handler<message::RequestModeration> for ChatServer {
+    type Result = MessageResult<message::RequestModeration>;
+
+    fn handle(&mut self, _: message::RequestModeration, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(message::ModerationState {
+            enabled: self.moderated,
+            pending: self.pending_messages.iter().cloned().collect(),
+            slow_mode_secs: self.slow_mode.map_or(0, |window| window.as_secs()),
+        })
+    }
+}
+
 /// Handler for Disconnect message.
 impl Handler<message::Disconnect> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: message::Disconnect, _: &mut Context<Self>) {
         // Remove Client from HashMap.
-        self.clients.remove(&msg.id);
+        if self.clients.remove(&msg.id).is_some() {
+            self.stats_changed = true;
+        }
     }
 }
 
 /// Handler for feature/unfeature message.
 /// Now looks up full message data and broadcasts it for decoupled rendering.
-impl<'a> Handler<message::FeatureMessage> for ChatServer {
+impl Handler<message::FeatureMessage> for ChatServer {
     type Result = Option<ChatMessage>;
 
-    fn handle(&mut self, msg: message::FeatureMessage, _: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: message::FeatureMessage, ctx: &mut Context<Self>) -> Self::Result {
+        // Any change replaces the previous feature, so its timer no longer applies
+        if let Some(timer) = self.unfeature_timer.take() {
+            ctx.cancel_future(timer);
+        }
+
         // Handle unfeaturing
         let featured_msg = if let Some(id) = msg.id {
-            // Try to find the message in memory first, then database
-            let found_msg = self.chat_messages.get(&id).cloned()
-                .or_else(|| {
-                    self.database.get_paid_message(&id)
-                        .ok()
-                        .flatten()
-                });
+            let found_msg = self.find_message(&id);
 
             if found_msg.is_none() {
                 warn!("[ChatServer] Featured message {} not found in memory or database", id);
@@ -263,30 +1116,79 @@ impl<'a> Handler<message::FeatureMessage> for ChatServer {
 
         // Store the full featured message
         self.featured_message = featured_msg.clone();
+        if let Some(chat_msg) = &featured_msg {
+            self.featured_history
+                .retain(|entry| entry.id != chat_msg.id);
+            self.featured_history.push_front(message::FeaturedEntry {
+                id: chat_msg.id,
+                featured_at: chrono::Utc::now().timestamp_millis(),
+            });
+            self.featured_history.truncate(FEATURED_HISTORY);
+
+            if let Some(secs) = msg.duration_secs.filter(|secs| *secs > 0) {
+                self.unfeature_timer =
+                    Some(ctx.run_later(Duration::from_secs(secs), |act, ctx| {
+                        act.unfeature_timer = None;
+                        act.handle(
+                            message::FeatureMessage {
+                                id: None,
+                                duration_secs: None,
+                            },
+                            ctx,
+                        );
+                    }));
+            }
+        }
         debug!("[ChatServer] Featured message set to: {:?}", self.featured_message.as_ref().map(|m| m.id));
 
         // Broadcast to all clients - send full message JSON if featuring, null if unfeaturing
         let reply_message = match &featured_msg {
-            Some(chat_msg) => chat_msg.to_json(),
-            None => "null".to_string(),
+            Some(chat_msg) => chat_msg.try_to_json_with_alert_sound(None),
+            None => Ok("null".to_string()),
         };
 
-        for (_, conn) in &self.clients {
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "feature_message".to_owned(),
-                    message: reply_message.clone(),
-                })
-                .expect("Failed to serialize feature ReplyInner"),
-            ));
-        }
+        self.broadcast("feature_message", reply_message);
 
         featured_msg
     }
 }
 
-/// Handler for requesting current featured message (returns full message data)
-impl Handler<message::RequestFeaturedMessage> for ChatServer {
+/// Handler for looking up one message by id
+impl Handler<message::RequestMessageById> for ChatServer {
+    type Result = Option<ChatMessage>;
+
+    fn handle(&mut self, msg: message::RequestMessageById, _: &mut Context<Self>) -> Self::Result {
+        self.find_message(&msg.id)
+    }
+}
+
+/// Handler for replaying a message from memory or the database. The message is only
+/// broadcast, so history, the database and the session total are left as they are.
+impl Handler<message::ReplayMessage> for ChatServer {
+    type Result = Option<ChatMessage>;
+
+    fn handle(&mut self, msg: message::ReplayMessage, _: &mut Context<Self>) -> Self::Result {
+        let Some(chat_msg) = self.find_message(&msg.id) else {
+            warn!(id = %msg.id, "Replayed message not found in memory or database");
+            return None;
+        };
+
+        // Amounts are already in USD once stored
+        let alert_sound = if chat_msg.amount > 0.0 {
+            self.alert_sounds.sound_for(chat_msg.get_paid_tier())
+        } else {
+            None
+        };
+        self.broadcast(
+            "replay_message",
+            chat_msg.try_to_json_with_alert_sound(alert_sound),
+        );
+        Some(chat_msg)
+    }
+}
+
+/// Handler for requesting current featured message (returns full message data)
+impl Handler<message::RequestFeaturedMessage> for ChatServer {
     type Result = MessageResult<message::RequestFeaturedMessage>;
 
     fn handle(&mut self, _: message::RequestFeaturedMessage, _: &mut Context<Self>) -> Self::Result {
@@ -294,37 +1196,243 @@ impl Handler<message::RequestFeaturedMessage> for ChatServer {
     }
 }
 
+/// Handler for requesting the recently featured messages.
+impl Handler<message::RequestFeaturedHistory> for ChatServer {
+    type Result = MessageResult<message::RequestFeaturedHistory>;
+
+    fn handle(
+        &mut self,
+        _: message::RequestFeaturedHistory,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        MessageResult(self.featured_history.iter().cloned().collect())
+    }
+}
+
+/// Handler for starting a poll from the dashboard.
+impl Handler<message::StartPoll> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::StartPoll, _: &mut Context<Self>) -> Self::Result {
+        self.start_poll(&msg.question, &msg.options, msg.multi_vote)
+    }
+}
+
+/// Handler for ending the running poll.
+impl Handler<message::EndPoll> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _: message::EndPoll, _: &mut Context<Self>) -> Self::Result {
+        self.end_poll();
+    }
+}
+
+/// Handler for votes sent over the socket rather than typed in chat.
+impl Handler<message::Vote> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Vote, _: &mut Context<Self>) -> Self::Result {
+        self.vote(msg.voter.as_deref(), &msg.choices);
+    }
+}
+
+/// Handler for requesting the running poll, so late overlays can show it.
+impl Handler<message::RequestPoll> for ChatServer {
+    type Result = MessageResult<message::RequestPoll>;
+
+    fn handle(&mut self, _: message::RequestPoll, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.poll.clone().filter(Poll::is_active))
+    }
+}
+
+/// Handler for requesting the running session total.
+impl Handler<message::RequestSessionTotal> for ChatServer {
+    type Result = MessageResult<message::RequestSessionTotal>;
+
+    fn handle(&mut self, _: message::RequestSessionTotal, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.session_total_usd)
+    }
+}
+
+/// Handler for reloading the alert sound mapping.
+impl Handler<message::ReloadAlertSounds> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, _: message::ReloadAlertSounds, _: &mut Context<Self>) -> Self::Result {
+        let sounds = AlertSounds::load(&self.alert_sounds_path).map_err(|e| format!("{:#}", e))?;
+        info!(
+            tiers = sounds.tiers.len(),
+            "Reloaded alert sounds from {}", self.alert_sounds_path
+        );
+        self.alert_sounds = sounds;
+        Ok(())
+    }
+}
+
+/// Handler for setting the donation goal.
+impl Handler<message::SetGoal> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::SetGoal, _: &mut Context<Self>) -> Self::Result {
+        let goal = Goal::new(&msg.label, msg.target_usd)?;
+        self.set_goal(Some(goal));
+        Ok(())
+    }
+}
+
+/// Handler for removing the donation goal.
+impl Handler<message::ResetGoal> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _: message::ResetGoal, _: &mut Context<Self>) -> Self::Result {
+        self.set_goal(None);
+    }
+}
+
+/// Handler for requesting the donation goal's progress.
+impl Handler<message::RequestGoal> for ChatServer {
+    type Result = MessageResult<message::RequestGoal>;
+
+    fn handle(&mut self, _: message::RequestGoal, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.goal_progress())
+    }
+}
+
+/// Handler for rendering /metrics
+impl Handler<message::RequestMetrics> for ChatServer {
+    type Result = String;
+
+    fn handle(&mut self, _: message::RequestMetrics, _: &mut Context<Self>) -> Self::Result {
+        self.metrics.render(&Gauges {
+            connected_clients: self.clients.len(),
+            session_total_usd: self.session_total_usd,
+            viewers: &self.viewer_counts,
+        })
+    }
+}
+
+/// Handler for server shutdown
+impl Handler<message::Shutdown> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _: message::Shutdown, _: &mut Context<Self>) {
+        info!("Shutting down; closing {} client(s)", self.clients.len());
+        self.broadcast("server_shutdown", to_payload("Server is shutting down"));
+        // Replies and the close share each client's mailbox, so the notice lands first
+        for (_, conn) in self.clients.drain() {
+            conn.close.do_send(message::Close);
+        }
+
+        if let Err(e) = self.database.flush() {
+            warn!("Failed to flush database on shutdown: {:?}", e);
+        }
+    }
+}
+
+/// Handler for readiness checks
+impl Handler<message::Ping> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, _: message::Ping, _: &mut Context<Self>) -> Self::Result {
+        self.database.ping().map_err(|e| e.to_string())
+    }
+}
+
 /// Handler for recent chat messages.
-impl<'a> Handler<message::RecentMessages> for ChatServer {
+impl Handler<message::RecentMessages> for ChatServer {
     type Result = MessageResult<message::RecentMessages>;
 
     fn handle(&mut self, _: message::RecentMessages, _: &mut Context<Self>) -> Self::Result {
-        const MAX_MESSAGES: usize = 100;
-
-        let mut last_messages: Vec<ChatMessage> = if self.chat_messages.len() >= MAX_MESSAGES {
-            self.chat_messages
-                .keys()
-                .cloned()
-                .skip(self.chat_messages.len() - MAX_MESSAGES)
-                .filter_map(|id| self.chat_messages.get(&id).cloned())
-                .collect()
-        } else {
-            self.chat_messages.values().cloned().collect()
-        };
-        last_messages.sort_by_key(|msg| msg.received_at);
-
+        let last_messages = self.recent_messages(RECENT_MESSAGES);
         debug!("Sending {} recent messages.", last_messages.len());
         MessageResult(last_messages)
     }
 }
 
+/// Handler for a client's recent chat messages, as many as its layout's `maxMessages`.
+impl Handler<message::RecentMessagesFor> for ChatServer {
+    type Result = MessageResult<message::RecentMessagesFor>;
+
+    fn handle(&mut self, msg: message::RecentMessagesFor, _: &mut Context<Self>) -> Self::Result {
+        let limit = self
+            .clients
+            .get(&msg.client_id)
+            .and_then(|conn| conn.subscribed_layout.as_ref())
+            .and_then(|name| lock_layouts(&self.layout_manager).load(name).ok())
+            .map_or(RECENT_MESSAGES, |layout| {
+                layout.message_style.max_messages.clamp(1, MAX_MESSAGES)
+            });
+        let last_messages = self.recent_messages(limit);
+        debug!(limit, "Sending {} recent messages.", last_messages.len());
+        MessageResult(last_messages)
+    }
+}
+
+/// Handler for searching the session's messages.
+impl Handler<message::SearchRecent> for ChatServer {
+    type Result = MessageResult<message::SearchRecent>;
+
+    fn handle(&mut self, msg: message::SearchRecent, _: &mut Context<Self>) -> Self::Result {
+        let query = msg.query.trim();
+        if query.is_empty() {
+            return MessageResult(Vec::new());
+        }
+        // Stored text is escaped on ingest, so escape the query to match `&`, `<` and quotes
+        let query = escape_html(query).to_lowercase();
+        let mut matches: Vec<ChatMessage> = self
+            .chat_messages
+            .values()
+            .filter(|chat_msg| {
+                chat_msg.username.to_lowercase().contains(&query)
+                    || chat_msg.message.to_lowercase().contains(&query)
+            })
+            .cloned()
+            .collect();
+        matches.sort_by_key(|chat_msg| chat_msg.received_at);
+        MessageResult(matches)
+    }
+}
+
+/// Handler for clearing the session's messages.
+impl Handler<message::ClearMessages> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::ClearMessages, _: &mut Context<Self>) -> Self::Result {
+        info!(purge_db = msg.purge_db, "Clearing all messages");
+        self.chat_messages.clear();
+        self.pending_messages.clear();
+        self.featured_message = None;
+        self.featured_history.clear();
+        self.session_total_usd = 0.0;
+
+        if msg.purge_db {
+            if let Err(e) = self
+                .database
+                .delete_paid_messages_since_hours(SESSION_WINDOW_HOURS)
+            {
+                warn!("Failed to purge paid messages from database: {}", e);
+            }
+        }
+
+        // Overlays wipe their chat and featured message on clear_all
+        self.broadcast("clear_all", Ok("null".to_string()));
+        self.broadcast_session_total();
+    }
+}
+
 /// Handler for remove a message.
 impl Handler<message::RemoveMessage> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: message::RemoveMessage, _: &mut Context<Self>) -> Self::Result {
+        self.record("", |update| update.removals = Some(vec![msg.id]));
         debug!("[ChatServer] Removing message with ID {}", msg.id);
-        self.chat_messages.remove(&msg.id);
+        // Paid messages from disabled platforms or earlier runs are only in the database
+        let paid = self
+            .chat_messages
+            .remove(&msg.id)
+            .filter(ChatMessage::is_premium)
+            .or_else(|| self.database.get_paid_message(&msg.id).ok().flatten());
 
         // Also remove from database
         if let Err(e) = self.database.delete_paid_message(&msg.id) {
@@ -335,73 +1443,272 @@ impl Handler<message::RemoveMessage> for ChatServer {
         if self.featured_message.as_ref().map(|m| m.id) == Some(msg.id) {
             self.featured_message = None;
         }
+        self.featured_history.retain(|entry| entry.id != msg.id);
 
         // Notify all clients to remove the message.
-        for (_, conn) in &self.clients {
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "remove_message".to_owned(),
-                    message: serde_json::to_string(&msg.id)
-                        .expect("Failed to serialize remove string."),
-                })
-                .expect("Failed to serialize remove ReplyInner"),
-            ));
+        self.broadcast("remove_message", to_payload(&msg.id));
+
+        if let Some(paid) = paid.filter(in_session_window) {
+            self.session_total_usd -= paid.amount;
+            self.broadcast_session_total();
         }
     }
 }
 
-/// Handler for all stored Superchats (from database, last 24 hours for dashboard)
-impl<'a> Handler<message::PaidMessages> for ChatServer {
-    type Result = MessageResult<message::PaidMessages>;
+/// Handler for correcting a stored paid message's amount.
+impl Handler<message::EditPaidMessage> for ChatServer {
+    type Result = Result<(), String>;
 
-    fn handle(&mut self, _: message::PaidMessages, _: &mut Context<Self>) -> Self::Result {
-        // Get paid messages from the last 24 hours
-        let super_chats = self.database
-            .get_paid_messages_since_hours(24)
-            .unwrap_or_default();
-        debug!("Sending {} superchats from last 24 hours.", super_chats.len());
-        MessageResult(super_chats)
+    fn handle(&mut self, msg: message::EditPaidMessage, _: &mut Context<Self>) -> Self::Result {
+        if !msg.amount.is_finite() || msg.amount <= 0.0 {
+            return Err(format!("Invalid amount {}", msg.amount));
+        }
+        let mut stored = self
+            .database
+            .get_paid_message(&msg.id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No paid message {}", msg.id))?;
+        let previous_usd = stored.amount;
+
+        stored.amount = msg.amount;
+        stored.currency = msg.currency.trim().to_uppercase();
+        let (amount, currency) = self.convert_to_usd(&mut stored);
+        if stored.amount <= 0.0 {
+            return Err(format!("No exchange rate for {}", currency));
+        }
+        self.database
+            .upsert_paid_message(&stored)
+            .map_err(|e| e.to_string())?;
+        info!(id = %msg.id, amount, currency = %currency, usd = stored.amount, "Paid message corrected");
+
+        // Counted whether or not it is in memory, e.g. from a disabled platform or an earlier run
+        if in_session_window(&stored) {
+            self.session_total_usd += stored.amount - previous_usd;
+        }
+
+        // The in-memory copy keeps its rendered text; only the amount changes
+        let updated = match self.chat_messages.get_mut(&msg.id) {
+            Some(current) => {
+                current.amount = stored.amount;
+                current.currency = stored.currency.clone();
+                current.clone()
+            }
+            None => stored,
+        };
+        if let Some(featured) = self
+            .featured_message
+            .as_mut()
+            .filter(|featured| featured.id == msg.id)
+        {
+            featured.amount = updated.amount;
+            featured.currency = updated.currency.clone();
+        }
+
+        self.broadcast("update_message", updated.try_to_json_with_alert_sound(None));
+        self.broadcast_session_total();
+        Ok(())
     }
 }
 
 /// Handler for paid messages with custom time filter
-impl<'a> Handler<message::PaidMessagesSince> for ChatServer {
+impl Handler<message::PaidMessagesSince> for ChatServer {
     type Result = MessageResult<message::PaidMessagesSince>;
 
     fn handle(&mut self, msg: message::PaidMessagesSince, _: &mut Context<Self>) -> Self::Result {
         let super_chats = self.database
-            .get_paid_messages_since_hours(msg.hours)
+            .get_paid_messages_above_amount(0.0, msg.hours)
             .unwrap_or_default();
         debug!("Sending {} superchats from last {} hours.", super_chats.len(), msg.hours);
         MessageResult(super_chats)
     }
 }
 
+/// Handler for paid messages above a minimum amount (highlight views)
+impl Handler<message::PaidMessagesAboveAmount> for ChatServer {
+    type Result = MessageResult<message::PaidMessagesAboveAmount>;
+
+    fn handle(
+        &mut self,
+        msg: message::PaidMessagesAboveAmount,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        let super_chats = self
+            .database
+            .get_paid_messages_above_amount(msg.min_usd, msg.hours)
+            .unwrap_or_default();
+        debug!(
+            "Sending {} superchats of at least ${} from last {} hours.",
+            super_chats.len(),
+            msg.min_usd,
+            msg.hours
+        );
+        MessageResult(super_chats)
+    }
+}
+
 /// Handler for viewer counts.
 impl Handler<message::ViewCount> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, viewers: message::ViewCount, _: &mut Context<Self>) -> Self::Result {
-        if let Some(old) = self.viewer_counts.insert(viewers.platform, viewers.viewers) {
+        self.record(&viewers.platform, |update| {
+            update.viewers = Some(viewers.viewers)
+        });
+        let platform = canonical_platform(&viewers.platform);
+        if let Some(old) = self.viewer_counts.insert(platform, viewers.viewers) {
             if old == viewers.viewers {
                 return;
             }
         }
 
-        for (_, conn) in &self.clients {
-            let new_viewers = self.viewer_counts.clone();
-            conn.recipient.do_send(message::Reply(
-                serde_json::to_string(&message::ReplyInner {
-                    tag: "viewers".to_owned(),
-                    message: serde_json::to_string(&new_viewers)
-                        .expect("Failed to serialize viewers."),
-                })
-                .expect("Failed to serialize viewers replyinner"),
-            ));
+        // Broadcast by the VIEWERS_INTERVAL timer in started()
+        self.viewers_changed = true;
+        self.stats_changed = true;
+    }
+}
+
+/// Handler for banning a user.
+impl Handler<message::BanUser> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::BanUser, _: &mut Context<Self>) -> Self::Result {
+        let added = self
+            .database
+            .ban_user(&msg.platform, &msg.username)
+            .map_err(|e| e.to_string())?;
+        if added {
+            info!(platform = %msg.platform, username = %msg.username, "User banned");
+        }
+        Ok(())
+    }
+}
+
+/// Handler for lifting a ban.
+impl Handler<message::UnbanUser> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::UnbanUser, _: &mut Context<Self>) -> Self::Result {
+        let removed = self
+            .database
+            .unban_user(&msg.platform, &msg.username)
+            .map_err(|e| e.to_string())?;
+        if removed {
+            info!(platform = %msg.platform, username = %msg.username, "User unbanned");
+        }
+        Ok(())
+    }
+}
+
+/// Handler for listing bans.
+impl Handler<message::RequestBans> for ChatServer {
+    type Result = Result<Vec<crate::database::Ban>, String>;
+
+    fn handle(&mut self, _: message::RequestBans, _: &mut Context<Self>) -> Self::Result {
+        self.database.list_bans().map_err(|e| e.to_string())
+    }
+}
+
+/// Handler for /api/messages. Paid messages come from the database so they reach back past
+/// the in-memory history; everything else comes from recent chat.
+impl Handler<message::RequestMessages> for ChatServer {
+    type Result = MessageResult<message::RequestMessages>;
+
+    fn handle(&mut self, msg: message::RequestMessages, _: &mut Context<Self>) -> Self::Result {
+        let mut messages: Vec<ChatMessage> = Vec::new();
+        if msg.paid != Some(true) {
+            messages.extend(
+                self.chat_messages
+                    .recent(MAX_MESSAGES)
+                    .filter(|chat_msg| msg.paid.is_none() || !chat_msg.is_premium())
+                    .cloned(),
+            );
+        }
+        if msg.paid != Some(false) {
+            let paid = match msg.since_hours {
+                Some(hours) => self.database.get_paid_messages_above_amount(0.0, hours),
+                None => self.database.get_all_paid_messages(),
+            };
+            match paid {
+                Ok(paid) => messages.extend(paid),
+                Err(e) => warn!(error = %e, "Failed to load paid messages"),
+            }
+        }
+
+        let platform = msg.platform.as_deref().map(canonical_platform);
+        let cutoff = msg
+            .since_hours
+            .map(|hours| chrono::Utc::now().timestamp_millis() - hours as i64 * 60 * 60 * 1000);
+        let mut seen = HashSet::new();
+        messages.retain(|chat_msg| {
+            platform
+                .as_ref()
+                .is_none_or(|platform| canonical_platform(&chat_msg.platform) == *platform)
+                && cutoff.is_none_or(|cutoff| chat_msg.received_at >= cutoff)
+                && seen.insert(chat_msg.id)
+        });
+        messages.sort_by_key(|chat_msg| std::cmp::Reverse(chat_msg.received_at));
+        messages.truncate(
+            msg.limit
+                .unwrap_or(DEFAULT_MESSAGES_LIMIT)
+                .min(MAX_MESSAGES_LIMIT),
+        );
+        MessageResult(messages)
+    }
+}
+
+/// Handler for enabling or disabling a platform.
+impl Handler<message::SetPlatformEnabled> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::SetPlatformEnabled, _: &mut Context<Self>) -> Self::Result {
+        let platform = canonical_platform(&msg.platform);
+        let changed = if msg.enabled {
+            self.disabled_platforms.remove(&platform)
+        } else {
+            self.disabled_platforms.insert(platform.clone())
+        };
+        if changed {
+            info!(platform = %platform, enabled = msg.enabled, "Platform toggled");
+            self.viewers_changed = true;
+            self.stats_changed = true;
         }
     }
 }
 
+/// Handler for synthesized TTS audio broadcasts
+impl Handler<message::TtsAudio> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::TtsAudio, _: &mut Context<Self>) -> Self::Result {
+        self.broadcast("tts_audio", to_payload(&msg));
+    }
+}
+
+/// Handler for viewer count and history requests
+impl Handler<message::RequestViewers> for ChatServer {
+    type Result = MessageResult<message::RequestViewers>;
+
+    fn handle(&mut self, _: message::RequestViewers, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(message::Viewers {
+            current: self.visible_viewer_counts(),
+            history: self
+                .viewer_history
+                .iter()
+                .map(|(platform, samples)| (platform.clone(), samples.iter().cloned().collect()))
+                .collect(),
+        })
+    }
+}
+
+/// Handler for server stats requests
+impl Handler<message::RequestServerStats> for ChatServer {
+    type Result = MessageResult<message::RequestServerStats>;
+
+    fn handle(&mut self, _: message::RequestServerStats, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.server_stats())
+    }
+}
+
 // ============================================================================
 // Layout Handlers
 // ============================================================================
@@ -412,7 +1719,35 @@ impl Handler<message::LayoutUpdate> for ChatServer {
 
     fn handle(&mut self, msg: message::LayoutUpdate, _: &mut Context<Self>) -> Self::Result {
         debug!("[ChatServer] Broadcasting layout update: {}", msg.layout.name);
-        self.broadcast_layout(&msg.layout);
+        let mut layout = msg.layout;
+        layout.sanitize_compiled_css(lock_layouts(&self.layout_manager).css_policy());
+        self.broadcast_layout(&layout);
+    }
+}
+
+/// Handler for previewing a layout without persisting it
+impl Handler<message::PreviewLayout> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::PreviewLayout, _: &mut Context<Self>) -> Self::Result {
+        debug!("[ChatServer] Previewing layout: {}", msg.layout.name);
+        let mut layout = msg.layout;
+        let report = layout.compile_scss(lock_layouts(&self.layout_manager).css_policy());
+        for (id, error) in &report.errors {
+            debug!("[ChatServer] Preview SCSS error in {}: {}", id, error);
+        }
+        self.broadcast_layout(&layout);
+    }
+}
+
+/// Handler for compiling an SCSS snippet without saving anything
+impl Handler<message::CompileScss> for ChatServer {
+    type Result = Result<String, String>;
+
+    fn handle(&mut self, msg: message::CompileScss, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        lm.compile_snippet(msg.prelude.as_deref(), &msg.source)
+            .map_err(|e| e.to_string())
     }
 }
 
@@ -421,14 +1756,19 @@ impl Handler<message::SwitchLayout> for ChatServer {
     type Result = Result<(), String>;
 
     fn handle(&mut self, msg: message::SwitchLayout, _: &mut Context<Self>) -> Self::Result {
-        info!("[ChatServer] Switching to layout: {}", msg.name);
+        info!(
+            "[ChatServer] Switching output {} to layout: {}",
+            msg.output, msg.name
+        );
 
         let layout = {
-            let lm = self.layout_manager.lock().map_err(|e| e.to_string())?;
+            let lm = lock_layouts(&self.layout_manager);
             lm.load(&msg.name).map_err(|e| e.to_string())?
         };
 
-        self.active_layout = msg.name;
+        self.save_active_layout(&msg.output, &msg.name);
+        self.active_layouts.insert(msg.output, msg.name);
+        self.sync_output_subscriptions();
         self.broadcast_layout(&layout);
         Ok(())
     }
@@ -436,81 +1776,285 @@ impl Handler<message::SwitchLayout> for ChatServer {
 
 /// Handler for saving a layout
 impl Handler<message::SaveLayout> for ChatServer {
-    type Result = Result<(), String>;
+    type Result = Result<Vec<String>, Vec<String>>;
 
     fn handle(&mut self, msg: message::SaveLayout, _: &mut Context<Self>) -> Self::Result {
         info!("[ChatServer] Saving layout: {}", msg.layout.name);
 
-        let lm = self.layout_manager.lock().map_err(|e| e.to_string())?;
-        lm.save(&msg.layout).map_err(|e| e.to_string())?;
+        let lm = lock_layouts(&self.layout_manager);
+        let scss_errors = save_errors(lm.save(&msg.layout))?;
 
-        // Broadcast to clients subscribed to this layout (and unsubscribed clients like editors)
-        self.broadcast_layout(&msg.layout);
+        // Broadcast the stored copy, with freshly compiled and sanitized CSS, to clients
+        // subscribed to this layout (and unsubscribed clients like editors)
+        let layout = lm.load(&msg.layout.name).map_err(|e| vec![e.to_string()])?;
+        drop(lm);
+        self.broadcast_layout(&layout);
 
-        Ok(())
+        Ok(scss_errors)
     }
 }
 
-/// Handler for deleting a layout
-impl Handler<message::DeleteLayout> for ChatServer {
-    type Result = Result<(), String>;
+/// Handler for updating a single layout element
+impl Handler<message::LayoutElementUpdate> for ChatServer {
+    type Result = Result<Vec<String>, Vec<String>>;
 
-    fn handle(&mut self, msg: message::DeleteLayout, _: &mut Context<Self>) -> Self::Result {
-        info!("[ChatServer] Deleting layout: {}", msg.name);
+    fn handle(&mut self, msg: message::LayoutElementUpdate, _: &mut Context<Self>) -> Self::Result {
+        debug!(
+            "[ChatServer] Updating element {} of layout {}",
+            msg.element_id, msg.layout_name
+        );
 
-        // Don't allow deleting the active layout
-        if msg.name == self.active_layout {
-            return Err("Cannot delete the active layout".to_string());
-        }
+        let lm = lock_layouts(&self.layout_manager);
+        let mut layout = lm.load(&msg.layout_name).map_err(|e| vec![e.to_string()])?;
+        layout.elements.insert(msg.element_id.clone(), msg.config);
+        let scss_errors = save_errors(lm.save(&layout))?;
 
-        let lm = self.layout_manager.lock().map_err(|e| e.to_string())?;
-        lm.delete(&msg.name).map_err(|e| e.to_string())?;
-        Ok(())
+        // Send the stored element so clients get its compiled, sanitized CSS
+        let layout = lm.load(&msg.layout_name).map_err(|e| vec![e.to_string()])?;
+        drop(lm);
+        let delta = message::LayoutElementDelta {
+            layout_name: &layout.name,
+            element_id: &msg.element_id,
+            config: &layout.elements[&msg.element_id],
+        };
+        self.broadcast_for_layout(&layout.name, "layout_element_update", to_payload(&delta));
+
+        Ok(scss_errors)
     }
 }
 
-/// Handler for requesting current layout
-impl Handler<message::RequestLayout> for ChatServer {
-    type Result = MessageResult<message::RequestLayout>;
+/// Handler for listing a layout's history
+impl Handler<message::ListLayoutHistory> for ChatServer {
+    type Result = Result<Vec<LayoutVersion>, String>;
 
-    fn handle(&mut self, _: message::RequestLayout, _: &mut Context<Self>) -> Self::Result {
-        let lm = self.layout_manager.lock().unwrap();
-        match lm.load(&self.active_layout) {
-            Ok(layout) => MessageResult(layout),
-            Err(_) => MessageResult(Layout::default_layout()),
-        }
+    fn handle(&mut self, msg: message::ListLayoutHistory, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        lm.list_history(&msg.name).map_err(|e| e.to_string())
     }
 }
 
-/// Handler for requesting a specific layout by name
-impl Handler<message::RequestLayoutByName> for ChatServer {
-    type Result = MessageResult<message::RequestLayoutByName>;
+/// Handler for restoring a layout version
+impl Handler<message::RestoreLayoutVersion> for ChatServer {
+    type Result = Result<(), String>;
 
-    fn handle(&mut self, msg: message::RequestLayoutByName, _: &mut Context<Self>) -> Self::Result {
-        let lm = self.layout_manager.lock().unwrap();
-        match lm.load(&msg.name) {
-            Ok(layout) => MessageResult(Some(layout)),
-            Err(_) => MessageResult(None),
-        }
+    fn handle(
+        &mut self,
+        msg: message::RestoreLayoutVersion,
+        _: &mut Context<Self>,
+    ) -> Self::Result {
+        info!(
+            "[ChatServer] Restoring layout {} to version {}",
+            msg.name, msg.version
+        );
+
+        let layout = {
+            let lm = lock_layouts(&self.layout_manager);
+            lm.restore_version(&msg.name, msg.version)
+                .map_err(|e| e.to_string())?
+        };
+
+        self.broadcast_layout(&layout);
+        Ok(())
     }
 }
 
-/// Handler for requesting layout list
-impl Handler<message::RequestLayoutList> for ChatServer {
-    type Result = MessageResult<message::RequestLayoutList>;
+/// Handler for deleting a layout
+impl Handler<message::DeleteLayout> for ChatServer {
+    type Result = Result<(), message::LayoutError>;
 
-    fn handle(&mut self, _: message::RequestLayoutList, _: &mut Context<Self>) -> Self::Result {
-        let lm = self.layout_manager.lock().unwrap();
-        let layouts = lm.list().unwrap_or_default();
-        MessageResult(message::LayoutListResponse {
-            layouts,
-            active: self.active_layout.clone(),
-        })
+    fn handle(&mut self, msg: message::DeleteLayout, _: &mut Context<Self>) -> Self::Result {
+        use message::LayoutError;
+        info!("[ChatServer] Deleting layout: {}", msg.name);
+
+        let lm = lock_layouts(&self.layout_manager);
+        if !lm.exists(&msg.name) {
+            return Err(LayoutError::NotFound(msg.name));
+        }
+
+        // Don't allow deleting a layout that an output is showing
+        if let Some((output, _)) = self
+            .active_layouts
+            .iter()
+            .find(|(_, name)| **name == msg.name)
+        {
+            return Err(LayoutError::Conflict(format!(
+                "Cannot delete the active layout of output {}",
+                output
+            )));
+        }
+
+        lm.delete(&msg.name)
+            .map_err(|e| LayoutError::Other(e.to_string()))
     }
 }
 
-/// Handler for subscribing a client to a specific layout
-impl Handler<message::SubscribeLayout> for ChatServer {
+/// Handler for resetting a layout to the factory default
+impl Handler<message::ResetLayout> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::ResetLayout, _: &mut Context<Self>) -> Self::Result {
+        info!("[ChatServer] Resetting layout: {}", msg.name);
+
+        let layout = {
+            let lm = lock_layouts(&self.layout_manager);
+            if !lm.exists(&msg.name) {
+                return Err(format!("Layout '{}' not found", msg.name));
+            }
+            let mut layout = Layout::default_layout();
+            layout.name = msg.name.clone();
+            // Goes through save so the mangled version lands in history
+            lm.save(&layout).map_err(|e| e.to_string())?;
+            lm.load(&msg.name).map_err(|e| e.to_string())?
+        };
+
+        self.broadcast_layout(&layout);
+        Ok(())
+    }
+}
+
+/// Handler for duplicating a layout
+impl Handler<message::DuplicateLayout> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::DuplicateLayout, _: &mut Context<Self>) -> Self::Result {
+        info!("[ChatServer] Duplicating layout {} as {}", msg.from, msg.to);
+
+        let lm = lock_layouts(&self.layout_manager);
+        lm.duplicate(&msg.from, &msg.to).map_err(|e| e.to_string())
+    }
+}
+
+/// Handler for renaming a layout
+impl Handler<message::RenameLayout> for ChatServer {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: message::RenameLayout, _: &mut Context<Self>) -> Self::Result {
+        info!("[ChatServer] Renaming layout {} to {}", msg.from, msg.to);
+
+        let layout = {
+            let lm = lock_layouts(&self.layout_manager);
+            lm.rename(&msg.from, &msg.to).map_err(|e| e.to_string())?;
+            lm.load(&msg.to).map_err(|e| e.to_string())?
+        };
+
+        let renamed: Vec<String> = self
+            .active_layouts
+            .iter()
+            .filter(|(_, name)| **name == msg.from)
+            .map(|(output, _)| output.clone())
+            .collect();
+        for output in renamed {
+            self.save_active_layout(&output, &msg.to);
+            self.active_layouts.insert(output, msg.to.clone());
+        }
+
+        // Move overlays watching the old name over to the new one
+        for conn in self.clients.values_mut() {
+            if conn.subscribed_layout.as_deref() == Some(msg.from.as_str()) {
+                conn.subscribed_layout = Some(msg.to.clone());
+            }
+        }
+
+        self.broadcast_layout(&layout);
+        Ok(())
+    }
+}
+
+/// Handler for requesting current layout
+impl Handler<message::RequestLayout> for ChatServer {
+    type Result = MessageResult<message::RequestLayout>;
+
+    fn handle(&mut self, _: message::RequestLayout, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        match lm.load(self.active_layout(message::DEFAULT_OUTPUT)) {
+            Ok(layout) => MessageResult(layout),
+            Err(_) => MessageResult(Layout::default_layout()),
+        }
+    }
+}
+
+/// Handler for requesting a specific layout by name
+impl Handler<message::RequestLayoutByName> for ChatServer {
+    type Result = MessageResult<message::RequestLayoutByName>;
+
+    fn handle(&mut self, msg: message::RequestLayoutByName, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        match lm.load(&msg.name) {
+            Ok(layout) => MessageResult(Some(layout)),
+            Err(_) => MessageResult(None),
+        }
+    }
+}
+
+/// Handler for requesting layout list
+impl Handler<message::RequestLayoutList> for ChatServer {
+    type Result = MessageResult<message::RequestLayoutList>;
+
+    fn handle(&mut self, _: message::RequestLayoutList, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        let layouts = lm.list().unwrap_or_default();
+        MessageResult(message::LayoutListResponse {
+            layouts,
+            active: self.active_layout(message::DEFAULT_OUTPUT).to_string(),
+            outputs: self.active_layouts.clone(),
+        })
+    }
+}
+
+/// Handler for exporting a layout bundle
+impl Handler<message::ExportLayouts> for ChatServer {
+    type Result = Result<String, String>;
+
+    fn handle(&mut self, msg: message::ExportLayouts, _: &mut Context<Self>) -> Self::Result {
+        let lm = lock_layouts(&self.layout_manager);
+        lm.export_bundle(&msg.names).map_err(|e| format!("{:#}", e))
+    }
+}
+
+/// Handler for exporting a layout as standalone HTML
+impl Handler<message::ExportOverlayHtml> for ChatServer {
+    type Result = Result<String, String>;
+
+    fn handle(&mut self, msg: message::ExportOverlayHtml, _: &mut Context<Self>) -> Self::Result {
+        let layout = {
+            let lm = lock_layouts(&self.layout_manager);
+            lm.load(&msg.layout_name)
+                .map_err(|_| format!("Layout '{}' not found", msg.layout_name))?
+        };
+        Ok(super::export_overlay_html(&layout))
+    }
+}
+
+/// Handler for importing a layout bundle
+impl Handler<message::ImportLayouts> for ChatServer {
+    type Result = Result<ImportReport, String>;
+
+    fn handle(&mut self, msg: message::ImportLayouts, _: &mut Context<Self>) -> Self::Result {
+        info!("[ChatServer] Importing layout bundle");
+
+        let (report, layouts) = {
+            let lm = lock_layouts(&self.layout_manager);
+            let report = lm
+                .import_bundle(&msg.bundle, msg.overwrite)
+                .map_err(|e| format!("{:#}", e))?;
+            // Reload so broadcasts carry the compiled CSS
+            let layouts: Vec<Layout> = report
+                .imported
+                .iter()
+                .filter_map(|name| lm.load(name).ok())
+                .collect();
+            (report, layouts)
+        };
+
+        for layout in &layouts {
+            self.broadcast_layout(layout);
+        }
+        Ok(report)
+    }
+}
+
+/// Handler for subscribing a client to a specific layout
+impl Handler<message::SubscribeLayout> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: message::SubscribeLayout, _: &mut Context<Self>) -> Self::Result {
@@ -521,6 +2065,2256 @@ impl Handler<message::SubscribeLayout> for ChatServer {
         );
         if let Some(conn) = self.clients.get_mut(&msg.client_id) {
             conn.subscribed_layout = Some(msg.layout_name);
+            conn.subscribed_output = None;
+        }
+    }
+}
+
+/// Handler for narrowing the broadcast tags a client receives
+impl Handler<message::SubscribeTags> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::SubscribeTags, _: &mut Context<Self>) -> Self::Result {
+        debug!(client = msg.client_id, tags = ?msg.tags, "Client subscribing to tags");
+        if let Some(conn) = self.clients.get_mut(&msg.client_id) {
+            conn.subscribed_tags = msg.tags.into_iter().collect();
+        }
+    }
+}
+
+/// Handler for subscribing a client to an output
+impl Handler<message::SubscribeOutput> for ChatServer {
+    type Result = Result<Layout, String>;
+
+    fn handle(&mut self, msg: message::SubscribeOutput, _: &mut Context<Self>) -> Self::Result {
+        info!(
+            "[ChatServer] Client {} subscribing to output: {}",
+            msg.client_id, msg.output
+        );
+
+        let name = self.active_layout(&msg.output).to_string();
+        let layout = {
+            let lm = lock_layouts(&self.layout_manager);
+            lm.load(&name).map_err(|e| e.to_string())?
+        };
+        if let Some(conn) = self.clients.get_mut(&msg.client_id) {
+            conn.subscribed_layout = Some(name);
+            conn.subscribed_output = Some(msg.output);
+        }
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Dimension;
+    use crate::message::Emoji;
+    use actix::{Actor, Addr};
+    use tempfile::TempDir;
+
+    /// Records every reply the server sends to it.
+    struct Collector(Arc<Mutex<Vec<message::ReplyInner>>>);
+
+    impl Actor for Collector {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Context<Self>) {
+            // Same room as ChatClient
+            ctx.set_mailbox_capacity(256);
+        }
+    }
+
+    impl Handler<message::Reply> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, msg: message::Reply, _: &mut Context<Self>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(serde_json::from_str(&msg.0).unwrap());
+        }
+    }
+
+    impl Handler<message::Close> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, _: message::Close, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(message::ReplyInner {
+                tag: "close".to_string(),
+                message: String::new(),
+            });
+        }
+    }
+
+    /// Resolves once the collector has processed everything queued before it.
+    struct Flush;
+
+    impl actix::Message for Flush {
+        type Result = ();
+    }
+
+    impl Handler<Flush> for Collector {
+        type Result = ();
+
+        fn handle(&mut self, _: Flush, _: &mut Context<Self>) {}
+    }
+
+    struct TestClient {
+        id: usize,
+        addr: Addr<Collector>,
+        replies: Arc<Mutex<Vec<message::ReplyInner>>>,
+    }
+
+    impl TestClient {
+        async fn connect(server: &Addr<ChatServer>) -> Self {
+            Self::connect_as(server, ClientRole::Ingest).await
+        }
+
+        async fn connect_as(server: &Addr<ChatServer>, role: ClientRole) -> Self {
+            let replies = Arc::new(Mutex::new(Vec::new()));
+            let addr = Collector(replies.clone()).start();
+            let id = server
+                .send(message::Connect {
+                    recipient: addr.clone().recipient(),
+                    close: addr.clone().recipient(),
+                    role,
+                })
+                .await
+                .unwrap();
+            Self { id, addr, replies }
+        }
+
+        /// Tags received so far, after letting pending replies land.
+        async fn tags(&self) -> Vec<String> {
+            self.addr.send(Flush).await.unwrap();
+            self.replies
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| r.tag.clone())
+                .collect()
+        }
+    }
+
+    fn test_server() -> (ChatServer, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let rates = ExchangeRates::from_rates(HashMap::from([
+            ("USD".to_string(), 1.0),
+            ("EUR".to_string(), 1.1),
+        ]));
+        let server = ChatServer::with_database(
+            rates,
+            Arc::new(Mutex::new(layout_manager)),
+            Database::open_in_memory().unwrap(),
+        );
+        (server, dir)
+    }
+
+    fn chat(text: &str) -> ChatMessage {
+        ChatMessage {
+            message: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn paid(amount: f64) -> ChatMessage {
+        ChatMessage {
+            amount,
+            currency: "USD".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn owner_chat(text: &str) -> ChatMessage {
+        ChatMessage {
+            is_owner: true,
+            ..chat(text)
+        }
+    }
+
+    #[actix::test]
+    async fn test_poll_from_chat() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let messages = [
+            chat("!vote 1"), // No poll yet
+            chat("!poll Not the owner; a; b"),
+            owner_chat("!poll Best map?; Dust; Inferno; Nuke"),
+            ChatMessage {
+                username: "alice".to_string(),
+                ..chat("!vote 2")
+            },
+            ChatMessage {
+                username: "alice".to_string(),
+                ..chat("!3")
+            },
+            ChatMessage {
+                username: "bob".to_string(),
+                ..chat("3")
+            },
+            ChatMessage {
+                username: "carol".to_string(),
+                ..chat("3 is the best")
+            },
+            owner_chat("!endpoll"),
+            ChatMessage {
+                username: "dave".to_string(),
+                ..chat("!vote 1")
+            },
+        ];
+        for chat_message in messages {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+        server
+            .send(message::Vote {
+                voter: None,
+                choices: vec![1],
+            })
+            .await
+            .unwrap();
+
+        let tags = client.tags().await;
+        // poll start, two counted votes, end
+        assert_eq!(tags.iter().filter(|t| *t == "poll_update").count(), 4);
+        assert_eq!(tags.iter().filter(|t| *t == "chat_message").count(), 9);
+        assert!(server.send(message::RequestPoll).await.unwrap().is_none());
+
+        // The last update is the final result
+        let replies = client.replies.lock().unwrap();
+        let result = replies
+            .iter()
+            .rev()
+            .find(|r| r.tag == "poll_update")
+            .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result.message).unwrap();
+        assert_eq!(result["question"], "Best map?");
+        assert!(result["ended_at"].is_i64());
+        let votes: Vec<u64> = result["options"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|o| o["votes"].as_u64().unwrap())
+            .collect();
+        assert_eq!(votes, vec![0, 1, 1]);
+        assert_eq!(result["participants"], 2);
+    }
+
+    #[actix::test]
+    async fn test_start_poll_replaces_running_poll() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let options = vec!["a".to_string(), "b".to_string()];
+
+        let invalid = message::StartPoll {
+            question: "Q?".to_string(),
+            options: vec!["a".to_string()],
+            multi_vote: false,
+        };
+        assert!(server.send(invalid).await.unwrap().is_err());
+        assert!(server.send(message::RequestPoll).await.unwrap().is_none());
+
+        for question in ["First?", "Second?"] {
+            server
+                .send(message::StartPoll {
+                    question: question.to_string(),
+                    options: options.clone(),
+                    multi_vote: true,
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        server
+            .send(message::Vote {
+                voter: Some("kick:alice".to_string()),
+                choices: vec![1, 2],
+            })
+            .await
+            .unwrap();
+
+        let poll = server.send(message::RequestPoll).await.unwrap().unwrap();
+        assert_eq!(poll.question, "Second?");
+        assert!(poll.is_active());
+        assert_eq!(poll.total_votes, 2);
+    }
+
+    #[actix::test]
+    async fn test_goal_tracks_session_total() {
+        let (server, dir) = test_server();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let invalid = message::SetGoal {
+            label: "Nothing".to_string(),
+            target_usd: 0.0,
+        };
+        assert!(server.send(invalid).await.unwrap().is_err());
+        assert!(server.send(message::RequestGoal).await.unwrap().is_none());
+
+        server
+            .send(message::SetGoal {
+                label: "New mic".to_string(),
+                target_usd: 100.0,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: paid(25.0),
+            })
+            .await
+            .unwrap();
+
+        let progress = server.send(message::RequestGoal).await.unwrap().unwrap();
+        assert_eq!((progress.target, progress.current), (100.0, 25.0));
+        let updates: Vec<String> = {
+            client.tags().await;
+            let replies = client.replies.lock().unwrap();
+            replies
+                .iter()
+                .filter(|reply| reply.tag == "goal_update")
+                .map(|reply| reply.message.clone())
+                .collect()
+        };
+        assert_eq!(
+            updates,
+            [
+                r#"{"label":"New mic","target":100.0,"current":0.0}"#,
+                r#"{"label":"New mic","target":100.0,"current":25.0}"#,
+            ]
+        );
+
+        // A restart reloads the goal and recomputes progress from the database
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let restarted = ChatServer::with_database(
+            ExchangeRates::from_rates(HashMap::from([("USD".to_string(), 1.0)])),
+            Arc::new(Mutex::new(layout_manager)),
+            database.clone(),
+        )
+        .start();
+        let progress = restarted.send(message::RequestGoal).await.unwrap().unwrap();
+        assert_eq!(progress.label, "New mic");
+        assert_eq!(progress.current, 25.0);
+
+        restarted.send(message::ResetGoal).await.unwrap();
+        assert!(restarted
+            .send(message::RequestGoal)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(database.load_goal().unwrap(), None);
+    }
+
+    #[actix::test]
+    async fn test_moderated_mode_holds_messages() {
+        let (server, _dir) = test_server();
+        let database = server.database.clone();
+        let server = server.start();
+        let overlay = TestClient::connect_as(&server, ClientRole::Viewer).await;
+        let dashboard = TestClient::connect(&server).await;
+
+        server
+            .send(message::SetModerated { enabled: true })
+            .await
+            .unwrap();
+        let rejected = chat("not for the overlay");
+        let approved = paid(10.0);
+        for chat_message in [rejected.clone(), approved.clone(), rejected.clone()] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+
+        let state = server.send(message::RequestModeration).await.unwrap();
+        assert!(state.enabled);
+        assert_eq!(state.pending.len(), 2, "the resent message is a duplicate");
+        assert!(overlay.tags().await.is_empty());
+        assert_eq!(
+            dashboard.tags().await,
+            ["moderation", "pending_message", "pending_message"]
+        );
+        assert!(database.get_paid_message(&approved.id).unwrap().is_none());
+
+        server
+            .send(message::ApproveMessage { id: approved.id })
+            .await
+            .unwrap();
+        server
+            .send(message::RejectMessage { id: rejected.id })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            overlay.tags().await,
+            ["chat_message", "session_total", "goal_update"]
+        );
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].id, approved.id);
+        assert!(database.get_paid_message(&approved.id).unwrap().is_some());
+        assert!(server
+            .send(message::RequestModeration)
+            .await
+            .unwrap()
+            .pending
+            .is_empty());
+
+        // Turning moderation off broadcasts straight away again
+        server
+            .send(message::SetModerated { enabled: false })
+            .await
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: chat("instant"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(overlay.tags().await.last().unwrap(), "chat_message");
+    }
+
+    #[actix::test]
+    async fn test_disabled_platform_is_suppressed() {
+        let (server, _dir) = test_server();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+        let from_kick = |msg: ChatMessage| ChatMessage {
+            platform: "kick".to_string(),
+            ..msg
+        };
+
+        for (platform, viewers) in [("kick", 10), ("youtube", 5)] {
+            server
+                .send(message::ViewCount {
+                    platform: platform.to_string(),
+                    viewers,
+                })
+                .await
+                .unwrap();
+        }
+        server
+            .send(message::SetPlatformEnabled {
+                platform: "Kick".to_string(),
+                enabled: false,
+            })
+            .await
+            .unwrap();
+
+        let donation = from_kick(paid(5.0));
+        for chat_message in [from_kick(chat("hidden")), donation.clone()] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+        assert!(!client.tags().await.contains(&"chat_message".to_string()));
+        assert!(server
+            .send(message::RecentMessages)
+            .await
+            .unwrap()
+            .is_empty());
+        // The paid message is still kept and counted, just not shown
+        assert!(database.get_paid_message(&donation.id).unwrap().is_some());
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            5.0
+        );
+
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.viewers_total, 5);
+        assert_eq!(stats.viewers_by_platform["kick"], 0);
+
+        server
+            .send(message::SetPlatformEnabled {
+                platform: "kick".to_string(),
+                enabled: true,
+            })
+            .await
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: from_kick(chat("visible")),
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.tags().await.last().unwrap(), "chat_message");
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.viewers_total, 15);
+    }
+
+    #[actix::test]
+    async fn test_alert_sound_by_tier() {
+        let (mut server, dir) = test_server();
+        let path = dir.path().join("alert_sounds.json");
+        std::fs::write(
+            &path,
+            r#"{"default": "chime.mp3", "tiers": {"100": "airhorn.mp3"}}"#,
+        )
+        .unwrap();
+        server.alert_sounds_path = path.to_str().unwrap().to_string();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        // Loaded on request, so editing the file needs no restart
+        server
+            .send(message::ReloadAlertSounds)
+            .await
+            .unwrap()
+            .unwrap();
+        std::fs::write(&path, r#"{"default": "../escape.mp3"}"#).unwrap();
+        assert!(server
+            .send(message::ReloadAlertSounds)
+            .await
+            .unwrap()
+            .is_err());
+
+        for chat_message in [paid(150.0), paid(5.0), chat("free")] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+        client.tags().await;
+        let sounds: Vec<Option<String>> = client
+            .replies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|reply| reply.tag == "chat_message")
+            .map(|reply| {
+                let json: serde_json::Value = serde_json::from_str(&reply.message).unwrap();
+                json["alert_sound"].as_str().map(str::to_string)
+            })
+            .collect();
+        assert_eq!(
+            sounds,
+            [
+                Some("airhorn.mp3".to_string()),
+                Some("chime.mp3".to_string()),
+                None
+            ]
+        );
+
+        // Replays to reconnecting overlays stay silent
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        assert!(!recent[0].to_json().contains("alert_sound"));
+    }
+
+    #[actix::test]
+    async fn test_banned_user_is_dropped() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+        let from = |platform: &str, username: &str| ChatMessage {
+            platform: platform.to_string(),
+            username: username.to_string(),
+            ..paid(5.0)
+        };
+
+        server
+            .send(message::BanUser {
+                platform: "Kick".to_string(),
+                username: "Spammer".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let banned = from("kick", "SPAMMER");
+        let namesake = from("youtube", "spammer");
+        for chat_message in [banned.clone(), namesake.clone()] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+
+        // Dropped before broadcast and storage; the same name elsewhere is unaffected
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].id, namesake.id);
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            5.0
+        );
+        let bans = server.send(message::RequestBans).await.unwrap().unwrap();
+        assert_eq!(bans.len(), 1);
+
+        server
+            .send(message::UnbanUser {
+                platform: "kick".to_string(),
+                username: "spammer".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: from("kick", "Spammer"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(server.send(message::RecentMessages).await.unwrap().len(), 2);
+        assert_eq!(
+            client
+                .tags()
+                .await
+                .iter()
+                .filter(|tag| *tag == "chat_message")
+                .count(),
+            2
+        );
+    }
+
+    #[actix::test]
+    async fn test_duplicate_id_is_broadcast_once() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let msg = chat("hello");
+        for _ in 0..2 {
+            server
+                .send(message::Content {
+                    chat_message: msg.clone(),
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(client.tags().await, vec!["chat_message"]);
+        assert_eq!(server.send(message::RecentMessages).await.unwrap().len(), 1);
+    }
+
+    #[actix::test]
+    async fn test_ingest_rate_limit_sheds_burst() {
+        let (mut server, _dir) = test_server();
+        server.ingest_limiter = RateLimiter::new(1.0, 3.0);
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        for _ in 0..10 {
+            server
+                .send(message::Content {
+                    chat_message: chat("spam"),
+                })
+                .await
+                .unwrap();
+        }
+        // Viewer counts are not rate limited.
+        server
+            .send(message::ViewCount {
+                platform: "NONE".to_string(),
+                viewers: 10,
+            })
+            .await
+            .unwrap();
+
+        let tags = client.tags().await;
+        assert_eq!(tags.iter().filter(|t| *t == "chat_message").count(), 3);
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.viewers_by_platform["none"], 10);
+    }
+
+    #[actix::test]
+    async fn test_rename_active_layout() {
+        let (server, _dir) = test_server();
+        assert_eq!(server.active_layout(message::DEFAULT_OUTPUT), "default");
+        let server = server.start();
+        let overlay = TestClient::connect(&server).await;
+        server
+            .send(message::SubscribeLayout {
+                client_id: overlay.id,
+                layout_name: "default".to_string(),
+            })
+            .await
+            .unwrap();
+
+        server
+            .send(message::RenameLayout {
+                from: "default".to_string(),
+                to: "main".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let list = server.send(message::RequestLayoutList).await.unwrap();
+        assert_eq!(list.active, "main");
+        assert_eq!(list.layouts, vec!["main"]);
+        assert_eq!(
+            server.send(message::RequestLayout).await.unwrap().name,
+            "main"
+        );
+        assert_eq!(overlay.tags().await, vec!["layout_update"]);
+
+        // The overlay follows the rename and keeps receiving its layout's updates
+        let mut layout = server.send(message::RequestLayout).await.unwrap();
+        layout.message_style.condensed_mode = true;
+        server
+            .send(message::SaveLayout { layout })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(overlay.tags().await, vec!["layout_update", "layout_update"]);
+    }
+
+    #[actix::test]
+    async fn test_switch_layout_per_output() {
+        let (server, dir) = test_server();
+        let lm = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let mut alt = Layout::default_layout();
+        alt.name = "alt".to_string();
+        lm.save(&alt).unwrap();
+
+        let server = server.start();
+        let main = TestClient::connect(&server).await;
+        let second = TestClient::connect(&server).await;
+        for (client, output) in [(&main, "overlay"), (&second, "alt")] {
+            let layout = server
+                .send(message::SubscribeOutput {
+                    client_id: client.id,
+                    output: output.to_string(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            // Outputs that were never switched show the default output's layout
+            assert_eq!(layout.name, "default");
+        }
+
+        server
+            .send(message::SwitchLayout {
+                output: "alt".to_string(),
+                name: "alt".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(main.tags().await.is_empty());
+        assert_eq!(second.tags().await, vec!["layout_update"]);
+
+        let list = server.send(message::RequestLayoutList).await.unwrap();
+        assert_eq!(list.active, "default");
+        assert_eq!(list.outputs["alt"], "alt");
+        assert_eq!(
+            server.send(message::RequestLayout).await.unwrap().name,
+            "default"
+        );
+
+        // Saving the default layout only reaches the output still showing it
+        let layout = server.send(message::RequestLayout).await.unwrap();
+        server
+            .send(message::SaveLayout { layout })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(main.tags().await, vec!["layout_update"]);
+        assert_eq!(second.tags().await, vec!["layout_update"]);
+
+        let deleted = server
+            .send(message::DeleteLayout {
+                name: "alt".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(deleted.is_err());
+    }
+
+    #[actix::test]
+    async fn test_preview_layout_is_not_saved() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let overlay = TestClient::connect(&server).await;
+        server
+            .send(message::SubscribeLayout {
+                client_id: overlay.id,
+                layout_name: "default".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let mut layout = server.send(message::RequestLayout).await.unwrap();
+        layout.message_style.condensed_mode = true;
+        layout.elements.get_mut("chat").unwrap().style.custom_css =
+            Some("$c: red; color: $c;".to_string());
+        server
+            .send(message::PreviewLayout { layout })
+            .await
+            .unwrap();
+
+        assert_eq!(overlay.tags().await, vec!["layout_update"]);
+        let previewed: Layout =
+            serde_json::from_str(&overlay.replies.lock().unwrap()[0].message).unwrap();
+        assert!(previewed.message_style.condensed_mode);
+        assert!(previewed.elements["chat"]
+            .style
+            .compiled_css
+            .as_deref()
+            .unwrap()
+            .contains("color: red"));
+
+        let saved = server.send(message::RequestLayout).await.unwrap();
+        assert!(!saved.message_style.condensed_mode);
+        assert!(saved.elements["chat"].style.custom_css.is_none());
+    }
+
+    #[actix::test]
+    async fn test_delete_layout_errors() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let delete = |name: &str| {
+            server.send(message::DeleteLayout {
+                name: name.to_string(),
+            })
+        };
+
+        let missing = delete("missing").await.unwrap();
+        assert!(matches!(missing, Err(message::LayoutError::NotFound(_))));
+        let active = delete("default").await.unwrap();
+        assert!(matches!(active, Err(message::LayoutError::Conflict(_))));
+
+        server
+            .send(message::DuplicateLayout {
+                from: "default".to_string(),
+                to: "spare".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        delete("spare").await.unwrap().unwrap();
+        assert!(server
+            .send(message::RequestLayoutByName {
+                name: "spare".to_string()
+            })
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[actix::test]
+    async fn test_reset_layout_restores_defaults() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        server
+            .send(message::DuplicateLayout {
+                from: "default".to_string(),
+                to: "mangled".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut layout = server
+            .send(message::RequestLayoutByName {
+                name: "mangled".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        layout.elements.get_mut("chat").unwrap().position.x = Some(Dimension::Px(999.0));
+        layout.elements.remove("poll");
+        server
+            .send(message::SaveLayout { layout })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let editor = TestClient::connect(&server).await;
+        server
+            .send(message::ResetLayout {
+                name: "mangled".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(editor.tags().await, vec!["layout_update"]);
+
+        let reset = server
+            .send(message::RequestLayoutByName {
+                name: "mangled".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let defaults = Layout::default_layout();
+        assert_eq!(reset.name, "mangled");
+        assert_eq!(reset.elements.len(), defaults.elements.len());
+        for (id, element) in &defaults.elements {
+            assert_eq!(
+                serde_json::to_value(&reset.elements[id].position).unwrap(),
+                serde_json::to_value(&element.position).unwrap(),
+                "{}",
+                id
+            );
+        }
+
+        let missing = server
+            .send(message::ResetLayout {
+                name: "missing".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(missing.is_err());
+    }
+
+    #[actix::test]
+    async fn test_duplicate_paid_id_in_database_is_dropped() {
+        let (server, _dir) = test_server();
+        let msg = paid(5.0);
+        server.database.upsert_paid_message(&msg).unwrap();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        server
+            .send(message::Content { chat_message: msg })
+            .await
+            .unwrap();
+
+        assert!(client.tags().await.is_empty());
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            0.0
+        );
+    }
+
+    #[actix::test]
+    async fn test_save_layout_reports_errors() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let mut layout = server.send(message::RequestLayout).await.unwrap();
+
+        layout.elements.get_mut("poll").unwrap().style.custom_css =
+            Some("color: $undefined;".to_string());
+        let scss_errors = server
+            .send(message::SaveLayout {
+                layout: layout.clone(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(scss_errors.len(), 1);
+        assert!(scss_errors[0].starts_with("poll: "));
+
+        let chat = layout.elements["chat"].clone();
+        layout.elements.insert("chta".to_string(), chat);
+        let errors = server
+            .send(message::SaveLayout { layout })
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(errors, vec!["Unknown element key: \"chta\""]);
+    }
+
+    #[actix::test]
+    async fn test_layout_element_update_sends_delta() {
+        let (server, dir) = test_server();
+        let other = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let mut layout = Layout::default_layout();
+        layout.name = "other".to_string();
+        other.save(&layout).unwrap();
+
+        let server = server.start();
+        let editor = TestClient::connect(&server).await;
+        let overlay = TestClient::connect(&server).await;
+        server
+            .send(message::SubscribeLayout {
+                client_id: overlay.id,
+                layout_name: "other".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let mut config = layout.elements["chat"].clone();
+        config.enabled = false;
+        server
+            .send(message::LayoutElementUpdate {
+                layout_name: "default".to_string(),
+                element_id: "chat".to_string(),
+                config: config.clone(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(editor.tags().await, vec!["layout_element_update"]);
+        assert!(overlay.tags().await.is_empty());
+        let delta: serde_json::Value =
+            serde_json::from_str(&editor.replies.lock().unwrap()[0].message).unwrap();
+        assert_eq!(delta["layoutName"], "default");
+        assert_eq!(delta["elementId"], "chat");
+        assert_eq!(delta["config"]["enabled"], false);
+        let saved = server.send(message::RequestLayout).await.unwrap();
+        assert!(!saved.elements["chat"].enabled);
+
+        // Unknown element keys are rejected like a full save
+        let errors = server
+            .send(message::LayoutElementUpdate {
+                layout_name: "default".to_string(),
+                element_id: "chta".to_string(),
+                config,
+            })
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(errors, vec!["Unknown element key: \"chta\""]);
+        assert_eq!(editor.tags().await.len(), 1);
+    }
+
+    #[actix::test]
+    async fn test_shutdown_closes_clients() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        server.send(message::Shutdown).await.unwrap();
+        assert_eq!(client.tags().await, ["server_shutdown", "close"]);
+
+        // Closed clients no longer receive broadcasts
+        server
+            .send(message::Content {
+                chat_message: chat("after shutdown"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.tags().await, ["server_shutdown", "close"]);
+    }
+
+    #[actix::test]
+    async fn test_server_stats_are_throttled() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+        for (platform, viewers) in [("YouTube", 10), ("Kick", 5), ("YouTube", 12)] {
+            server
+                .send(message::ViewCount {
+                    platform: platform.to_string(),
+                    viewers,
+                })
+                .await
+                .unwrap();
+        }
+
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.clients, 1);
+        assert_eq!(stats.viewers_total, 17);
+        assert_eq!(stats.viewers_by_platform["youtube"], 12);
+
+        // The connect and three updates collapse into a single broadcast
+        actix::clock::sleep(SERVER_STATS_INTERVAL + Duration::from_millis(200)).await;
+        let tags = client.tags().await;
+        assert_eq!(tags.iter().filter(|t| *t == "server_stats").count(), 1);
+        let replies = client.replies.lock().unwrap();
+        let broadcast = replies.iter().find(|r| r.tag == "server_stats").unwrap();
+        let broadcast: serde_json::Value = serde_json::from_str(&broadcast.message).unwrap();
+        assert_eq!(broadcast["viewers_total"], 17);
+    }
+
+    #[actix::test]
+    async fn test_evicts_stalled_client() {
+        let (mut server, _dir) = test_server();
+        server.client_timeout = Duration::from_millis(100);
+        let server = server.start();
+        let healthy = TestClient::connect(&server).await;
+
+        // A mailbox that is never drained, like an overlay whose tab has frozen
+        let stalled_ctx = Context::<Collector>::new();
+        let stalled = stalled_ctx.address();
+        server
+            .send(message::Connect {
+                recipient: stalled.clone().recipient(),
+                close: stalled.recipient(),
+                role: ClientRole::Viewer,
+            })
+            .await
+            .unwrap();
+
+        for i in 0..32 {
+            server
+                .send(message::Content {
+                    chat_message: chat(&format!("message {}", i)),
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            server
+                .send(message::RequestServerStats)
+                .await
+                .unwrap()
+                .clients,
+            2
+        );
+
+        actix::clock::sleep(STALLED_CHECK_INTERVAL + Duration::from_millis(200)).await;
+        assert_eq!(
+            server
+                .send(message::RequestServerStats)
+                .await
+                .unwrap()
+                .clients,
+            1
+        );
+        let tags = healthy.tags().await;
+        assert_eq!(tags.iter().filter(|t| *t == "chat_message").count(), 32);
+    }
+
+    #[actix::test]
+    async fn test_viewer_updates_are_coalesced() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+        for (platform, viewers) in [("YouTube", 10), ("Kick", 5), ("YouTube", 12), ("Kick", 6)] {
+            server
+                .send(message::ViewCount {
+                    platform: platform.to_string(),
+                    viewers,
+                })
+                .await
+                .unwrap();
+        }
+
+        actix::clock::sleep(VIEWERS_INTERVAL + Duration::from_millis(100)).await;
+        client.tags().await;
+        let replies = client.replies.lock().unwrap();
+        let viewers: Vec<_> = replies.iter().filter(|r| r.tag == "viewers").collect();
+        assert_eq!(viewers.len(), 1);
+        let counts: HashMap<String, usize> = serde_json::from_str(&viewers[0].message).unwrap();
+        assert_eq!(
+            counts,
+            HashMap::from([("youtube".to_string(), 12), ("kick".to_string(), 6)])
+        );
+    }
+
+    #[actix::test]
+    async fn test_recent_messages_are_newest_in_order() {
+        let (mut server, _dir) = test_server();
+        server.ingest_limiter = RateLimiter::new(0.0, 0.0);
+        let server = server.start();
+
+        let sent: Vec<ChatMessage> = (0..150)
+            .map(|i| ChatMessage {
+                received_at: 1_000 + i,
+                ..chat(&format!("message {}", i))
+            })
+            .collect();
+        for msg in &sent {
+            server
+                .send(message::Content {
+                    chat_message: msg.clone(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let ids: Vec<Uuid> = recent.iter().map(|msg| msg.id).collect();
+        let newest: Vec<Uuid> = sent[50..].iter().map(|msg| msg.id).collect();
+        assert_eq!(ids, newest);
+
+        // Removed messages drop out and the next oldest takes their place
+        server
+            .send(message::RemoveMessage { id: sent[149].id })
+            .await
+            .unwrap();
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        assert_eq!(recent.len(), 100);
+        assert_eq!(recent.first().unwrap().id, sent[49].id);
+        assert_eq!(recent.last().unwrap().id, sent[148].id);
+    }
+
+    #[actix::test]
+    async fn test_clear_messages() {
+        let (server, _dir) = test_server();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let donation = paid(5.0);
+        for msg in [chat("hello"), donation.clone()] {
+            server
+                .send(message::Content { chat_message: msg })
+                .await
+                .unwrap();
+        }
+        server
+            .send(message::FeatureMessage {
+                id: Some(donation.id),
+                duration_secs: None,
+            })
+            .await
+            .unwrap();
+
+        server
+            .send(message::ClearMessages { purge_db: false })
+            .await
+            .unwrap();
+        assert!(server
+            .send(message::RecentMessages)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(server
+            .send(message::RequestFeaturedMessage)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            0.0
+        );
+        assert!(client.tags().await.contains(&"clear_all".to_string()));
+        // Without purge_db the donation survives a restart
+        assert!(database.get_paid_message(&donation.id).unwrap().is_some());
+
+        server
+            .send(message::ClearMessages { purge_db: true })
+            .await
+            .unwrap();
+        assert!(database.get_paid_message(&donation.id).unwrap().is_none());
+    }
+
+    /// Stands in for the recorder, keeping what it's sent
+    struct LineCollector(Arc<Mutex<Vec<ReplayLine>>>);
+
+    impl Actor for LineCollector {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<ReplayLine> for LineCollector {
+        type Result = ();
+
+        fn handle(&mut self, line: ReplayLine, _: &mut Context<Self>) {
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[actix::test]
+    async fn test_recorder_gets_inbound_traffic() {
+        let (mut server, _dir) = test_server();
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        server.recorder = Some(LineCollector(lines.clone()).start().recipient());
+        let server = server.start();
+
+        let message = ChatMessage {
+            platform: "YT".to_string(),
+            ..chat("<b>raw</b>")
+        };
+        for _ in 0..2 {
+            server
+                .send(message::Content {
+                    chat_message: message.clone(),
+                })
+                .await
+                .unwrap();
+        }
+        server
+            .send(message::RemoveMessage { id: message.id })
+            .await
+            .unwrap();
+        server
+            .send(message::ViewCount {
+                platform: "kick".to_string(),
+                viewers: 7,
+            })
+            .await
+            .unwrap();
+        actix::clock::sleep(Duration::from_millis(50)).await;
+
+        // Recorded as it arrived: duplicates included, before escaping or canonicalizing
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|line| line.recorded_at.is_some()));
+        let recorded = &lines[1].update.messages.as_ref().unwrap()[0];
+        assert_eq!(recorded.platform, "YT");
+        assert_eq!(recorded.message, "<b>raw</b>");
+        assert_eq!(lines[2].update.removals, Some(vec![message.id]));
+        assert_eq!(lines[3].update.platform, "kick");
+        assert_eq!(lines[3].update.viewers, Some(7));
+    }
+
+    #[actix::test]
+    async fn test_avatars_are_proxied() {
+        let (mut server, _dir) = test_server();
+        server.avatar_proxy = Some(AvatarConfig {
+            hosts: vec!["ggpht.com".to_string()],
+            max_bytes: 1024,
+            ttl: Duration::from_secs(60),
+            capacity: 10,
+        });
+        let server = server.start();
+
+        let cdn = ChatMessage {
+            avatar: "https://yt3.ggpht.com/a.jpg".to_string(),
+            ..chat("from the CDN")
+        };
+        let elsewhere = ChatMessage {
+            avatar: "https://example.com/a.jpg".to_string(),
+            ..chat("from elsewhere")
+        };
+        let default = chat("no avatar");
+        for chat_message in [cdn, elsewhere, default.clone()] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+
+        let avatars: Vec<String> = server
+            .send(message::RecentMessages)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|msg| msg.avatar)
+            .collect();
+        assert_eq!(
+            avatars,
+            [
+                "/avatar?url=https%3A%2F%2Fyt3.ggpht.com%2Fa.jpg",
+                "https://example.com/a.jpg",
+                default.avatar.as_str(),
+            ]
+        );
+    }
+
+    #[actix::test]
+    async fn test_empty_avatar_gets_default() {
+        let (mut server, _dir) = test_server();
+        server.default_avatar = "/static/img/default-avatar.png".to_string();
+        let server = server.start();
+
+        for avatar in ["", "not a url", "https://yt3.ggpht.com/a.jpg"] {
+            server
+                .send(message::Content {
+                    chat_message: ChatMessage {
+                        avatar: avatar.to_string(),
+                        ..chat("hi")
+                    },
+                })
+                .await
+                .unwrap();
+        }
+
+        let avatars: Vec<String> = server
+            .send(message::RecentMessages)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|msg| msg.avatar)
+            .collect();
+        assert_eq!(
+            avatars,
+            [
+                "/static/img/default-avatar.png",
+                "/static/img/default-avatar.png",
+                "https://yt3.ggpht.com/a.jpg",
+            ]
+        );
+    }
+
+    #[actix::test]
+    async fn test_request_messages_filters() {
+        let (server, _dir) = test_server();
+        let now = chrono::Utc::now().timestamp_millis();
+        // Paid long ago, so only the database still has it
+        let old = ChatMessage {
+            platform: "youtube".to_string(),
+            message: "old paid".to_string(),
+            received_at: now - 48 * 60 * 60 * 1000,
+            ..paid(20.0)
+        };
+        server.database.upsert_paid_message(&old).unwrap();
+        let server = server.start();
+
+        let sent = [
+            ("youtube", chat("yt chat"), now - 3_000),
+            ("Kick", chat("kick chat"), now - 2_000),
+            (
+                "youtube",
+                ChatMessage {
+                    message: "yt paid".to_string(),
+                    ..paid(5.0)
+                },
+                now - 1_000,
+            ),
+        ];
+        for (platform, chat_message, received_at) in sent {
+            server
+                .send(message::Content {
+                    chat_message: ChatMessage {
+                        platform: platform.to_string(),
+                        received_at,
+                        ..chat_message
+                    },
+                })
+                .await
+                .unwrap();
+        }
+
+        let request = |platform: Option<&str>, paid, since_hours, limit| {
+            server.send(message::RequestMessages {
+                platform: platform.map(String::from),
+                paid,
+                since_hours,
+                limit,
+            })
+        };
+        let texts = |messages: Vec<ChatMessage>| -> Vec<String> {
+            messages.into_iter().map(|msg| msg.message).collect()
+        };
+
+        let all = request(None, None, None, None).await.unwrap();
+        assert_eq!(texts(all), ["yt paid", "kick chat", "yt chat", "old paid"]);
+        let paid_today = request(None, Some(true), Some(24), None).await.unwrap();
+        assert_eq!(texts(paid_today), ["yt paid"]);
+        let youtube_chat = request(Some("YouTube"), Some(false), None, None)
+            .await
+            .unwrap();
+        assert_eq!(texts(youtube_chat), ["yt chat"]);
+        let newest = request(None, None, Some(1), Some(2)).await.unwrap();
+        assert_eq!(texts(newest), ["yt paid", "kick chat"]);
+    }
+
+    #[actix::test]
+    async fn test_featured_history() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let messages: Vec<ChatMessage> = (0..12).map(|i| chat(&format!("msg {}", i))).collect();
+        for msg in &messages {
+            server
+                .send(message::Content {
+                    chat_message: msg.clone(),
+                })
+                .await
+                .unwrap();
+        }
+
+        let feature = |id| {
+            server.send(message::FeatureMessage {
+                id,
+                duration_secs: None,
+            })
+        };
+        for msg in &messages[..3] {
+            feature(Some(msg.id)).await.unwrap();
+        }
+        // Unfeaturing and unknown ids leave the history alone
+        feature(None).await.unwrap();
+        feature(Some(Uuid::new_v4())).await.unwrap();
+        let history = server.send(message::RequestFeaturedHistory).await.unwrap();
+        let ids: Vec<Uuid> = history.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [messages[2].id, messages[1].id, messages[0].id]);
+        assert!(history[0].featured_at >= history[2].featured_at);
+
+        // Featuring again moves a message to the front instead of listing it twice
+        feature(Some(messages[0].id)).await.unwrap();
+        let ids: Vec<Uuid> = server
+            .send(message::RequestFeaturedHistory)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, [messages[0].id, messages[2].id, messages[1].id]);
+
+        // Only the newest ten are kept, and removed messages drop out
+        for msg in &messages[3..] {
+            feature(Some(msg.id)).await.unwrap();
+        }
+        server
+            .send(message::RemoveMessage {
+                id: messages[11].id,
+            })
+            .await
+            .unwrap();
+        let ids: Vec<Uuid> = server
+            .send(message::RequestFeaturedHistory)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        let expected: Vec<Uuid> = messages[3..11]
+            .iter()
+            .rev()
+            .chain([&messages[0]])
+            .map(|msg| msg.id)
+            .collect();
+        assert_eq!(ids, expected);
+    }
+
+    #[actix::test]
+    async fn test_feature_duration_unfeatures() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let (first, second) = (chat("first"), chat("second"));
+        for msg in [first.clone(), second.clone()] {
+            server
+                .send(message::Content { chat_message: msg })
+                .await
+                .unwrap();
+        }
+        let feature = |id, duration_secs| {
+            server.send(message::FeatureMessage {
+                id: Some(id),
+                duration_secs,
+            })
+        };
+        let featured = || async {
+            server
+                .send(message::RequestFeaturedMessage)
+                .await
+                .unwrap()
+                .map(|msg| msg.id)
+        };
+
+        // Featuring another message cancels the earlier timer
+        feature(first.id, Some(1)).await.unwrap();
+        feature(second.id, Some(0)).await.unwrap();
+        actix::clock::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(featured().await, Some(second.id));
+
+        feature(first.id, Some(1)).await.unwrap();
+        assert_eq!(featured().await, Some(first.id));
+        actix::clock::sleep(Duration::from_millis(1200)).await;
+        assert_eq!(featured().await, None);
+    }
+
+    #[actix::test]
+    async fn test_replay_message_does_not_store() {
+        let (server, _dir) = test_server();
+        let database = server.database.clone();
+        // An old donation only the database knows about
+        let archived = paid(20.0);
+        database.upsert_paid_message(&archived).unwrap();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+        let donation = paid(5.0);
+        server
+            .send(message::Content {
+                chat_message: donation.clone(),
+            })
+            .await
+            .unwrap();
+        let total = server.send(message::RequestSessionTotal).await.unwrap();
+
+        for id in [donation.id, archived.id] {
+            let replayed = server.send(message::ReplayMessage { id }).await.unwrap();
+            assert_eq!(replayed.unwrap().id, id);
+        }
+        let missing = server
+            .send(message::ReplayMessage { id: Uuid::new_v4() })
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+
+        let tags = client.tags().await;
+        assert_eq!(tags.iter().filter(|t| *t == "replay_message").count(), 2);
+        assert_eq!(tags.iter().filter(|t| *t == "chat_message").count(), 1);
+        assert_eq!(database.get_all_paid_messages().unwrap().len(), 2);
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            total
+        );
+    }
+
+    /// Fails to serialize, as a message carrying data JSON can't represent would
+    struct Unserializable;
+
+    impl Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("not representable"))
+        }
+    }
+
+    struct BroadcastUnserializable;
+
+    impl actix::Message for BroadcastUnserializable {
+        type Result = ();
+    }
+
+    impl Handler<BroadcastUnserializable> for ChatServer {
+        type Result = ();
+
+        fn handle(&mut self, _: BroadcastUnserializable, _: &mut Context<Self>) {
+            self.broadcast("chat_message", to_payload(&Unserializable));
+            self.broadcast_ingest("pending_message", to_payload(&Unserializable));
+            self.broadcast_for_layout("default", "layout_update", to_payload(&Unserializable));
+        }
+    }
+
+    #[actix::test]
+    async fn test_unserializable_broadcast_is_skipped() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        server.send(BroadcastUnserializable).await.unwrap();
+        // The server is still running and later broadcasts go out as usual
+        server
+            .send(message::Content {
+                chat_message: chat("after"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(client.tags().await, ["chat_message"]);
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.clients, 1);
+    }
+
+    #[actix::test]
+    async fn test_active_layout_survives_restart() {
+        let (server, dir) = test_server();
+        let database = server.database.clone();
+        let lm = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        for name in ["alt", "portrait"] {
+            let mut layout = Layout::default_layout();
+            layout.name = name.to_string();
+            lm.save(&layout).unwrap();
+        }
+
+        let server = server.start();
+        for (output, name) in [("overlay", "alt"), ("vertical", "portrait")] {
+            server
+                .send(message::SwitchLayout {
+                    output: output.to_string(),
+                    name: name.to_string(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        lm.delete("portrait").unwrap();
+
+        let restart = || {
+            ChatServer::with_database(
+                ExchangeRates::from_rates(HashMap::new()),
+                Arc::new(Mutex::new(
+                    LayoutManager::new(dir.path().to_str().unwrap()).unwrap(),
+                )),
+                database.clone(),
+            )
+            .start()
+        };
+        let list = restart().send(message::RequestLayoutList).await.unwrap();
+        assert_eq!(list.active, "alt");
+        // A layout deleted while the server was down falls back to the default output's
+        assert_eq!(
+            list.outputs,
+            HashMap::from([("overlay".to_string(), "alt".to_string())])
+        );
+
+        // Renaming an active layout carries the stored selection along
+        server
+            .send(message::RenameLayout {
+                from: "alt".to_string(),
+                to: "main".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let list = restart().send(message::RequestLayoutList).await.unwrap();
+        assert_eq!(list.active, "main");
+    }
+
+    #[actix::test]
+    async fn test_search_recent() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let sent = [
+            ("Alice", chat("Tom & Jerry tonight?"), 3_000),
+            ("bob", chat("hello there"), 1_000),
+            (
+                "Carol",
+                ChatMessage {
+                    message: "Thanks for the TOM content".to_string(),
+                    ..paid(5.0)
+                },
+                2_000,
+            ),
+            ("tommy", chat("first"), 4_000),
+        ];
+        for (username, chat_message, received_at) in sent {
+            server
+                .send(message::Content {
+                    chat_message: ChatMessage {
+                        username: username.to_string(),
+                        received_at,
+                        ..chat_message
+                    },
+                })
+                .await
+                .unwrap();
+        }
+
+        let search = |query: &str| {
+            let query = query.to_string();
+            let server = server.clone();
+            async move {
+                server
+                    .send(message::SearchRecent { query })
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|msg| msg.username)
+                    .collect::<Vec<_>>()
+            }
+        };
+        // Usernames and text, paid or not, oldest first
+        assert_eq!(search("tom").await, ["Carol", "Alice", "tommy"]);
+        assert_eq!(search(" tom & j ").await, ["Alice"]);
+        assert_eq!(search("BOB").await, ["bob"]);
+        assert!(search("nobody").await.is_empty());
+        assert!(search("  ").await.is_empty());
+    }
+
+    #[actix::test]
+    async fn test_content_expands_sized_emojis() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        // As a bridge sends it: one plain emote, one sticker with its size
+        let chat_message: ChatMessage = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "platform": "kick",
+            "sent_at": 0,
+            "received_at": 0,
+            "is_placeholder": false,
+            "message": "gg :kekw: :sticker:",
+            "emojis": [
+                [":kekw:", "https://cdn.example.com/kekw.png", "kekw"],
+                [":sticker:", "https://cdn.example.com/sticker.png", "sticker", 128, 96]
+            ],
+            "username": "fan",
+            "avatar": "",
+            "amount": 0.0,
+            "currency": "",
+            "is_verified": false,
+            "is_sub": false,
+            "is_mod": false,
+            "is_owner": false,
+            "is_staff": false
+        }))
+        .unwrap();
+        let id = chat_message.id;
+        server
+            .send(message::Content { chat_message })
+            .await
+            .unwrap();
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let stored = recent.iter().find(|msg| msg.id == id).unwrap();
+        assert_eq!(
+            stored.message,
+            concat!(
+                r#"gg <img class="emoji" src="https://cdn.example.com/kekw.png" data-emoji="kekw" alt="kekw" /> "#,
+                r#"<img class="emoji emoji--sized" src="https://cdn.example.com/sticker.png" data-emoji="sticker" alt="sticker" style="width: 128px; height: 96px" />"#
+            )
+        );
+        assert_eq!(stored.to_plaintext(), "gg :kekw: :sticker:");
+    }
+
+    #[actix::test]
+    async fn test_slow_mode_suppresses_repeats() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let overlay = TestClient::connect_as(&server, ClientRole::Viewer).await;
+        let from = |username: &str, chat_message: ChatMessage| ChatMessage {
+            username: username.to_string(),
+            ..chat_message
+        };
+
+        server
+            .send(message::SetSlowMode { secs: 60 })
+            .await
+            .unwrap();
+        for chat_message in [
+            from("alice", chat("first")),
+            from("Alice", chat("too soon")),
+            from("bob", chat("someone else")),
+            from("alice", paid(5.0)),
+        ] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let texts: Vec<_> = recent.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(recent.len(), 3, "{texts:?}");
+        assert!(!texts.contains(&"too soon"));
+        assert_eq!(
+            server
+                .send(message::RequestModeration)
+                .await
+                .unwrap()
+                .slow_mode_secs,
+            60
+        );
+
+        // Turning slow mode off lets repeats straight through again
+        server.send(message::SetSlowMode { secs: 0 }).await.unwrap();
+        overlay.tags().await;
+        server
+            .send(message::Content {
+                chat_message: from("alice", chat("again")),
+            })
+            .await
+            .unwrap();
+        assert_eq!(overlay.tags().await.last().unwrap(), "chat_message");
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(
+            normalize_text("  Buy  CHEAP\tfollowers\n"),
+            "buy cheap followers"
+        );
+        assert_eq!(normalize_text(" \n "), "");
+    }
+
+    #[actix::test]
+    async fn test_copypasta_suppressed() {
+        let (mut server, _dir) = test_server();
+        server.duplicate_window = Some(Duration::from_secs(60));
+        let server = server.start();
+        let from = |username: &str, chat_message: ChatMessage| ChatMessage {
+            username: username.to_string(),
+            ..chat_message
+        };
+
+        for chat_message in [
+            from("alice", chat("buy cheap followers")),
+            from("bob", chat("Buy  CHEAP followers ")),
+            from("carol", chat("buy cheap followers")),
+            from("dave", chat("buy cheap follower")),
+            from(
+                "erin",
+                ChatMessage {
+                    message: "buy cheap followers".to_string(),
+                    ..paid(5.0)
+                },
+            ),
+        ] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let mut senders: Vec<_> = recent.iter().map(|m| m.username.as_str()).collect();
+        senders.sort();
+        assert_eq!(senders, ["alice", "dave", "erin"]);
+    }
+
+    #[actix::test]
+    async fn test_edit_paid_message_corrects_total() {
+        let (mut server, _dir) = test_server();
+        // Counted but only in the database, like a paid message from a disabled platform
+        let stored_only = paid(20.0);
+        server.database.upsert_paid_message(&stored_only).unwrap();
+        server.session_total_usd = 20.0;
+        let expired = ChatMessage {
+            received_at: chrono::Utc::now().timestamp_millis() - 48 * 60 * 60 * 1000,
+            ..paid(3.0)
+        };
+        server.database.upsert_paid_message(&expired).unwrap();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let typo = paid(500.0);
+        server
+            .send(message::Content {
+                chat_message: typo.clone(),
+            })
+            .await
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: paid(10.0),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            server.send(message::RequestSessionTotal).await.unwrap(),
+            530.0
+        );
+
+        server
+            .send(message::EditPaidMessage {
+                id: typo.id,
+                amount: 5.0,
+                currency: "eur".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Re-converted from EUR at 1.1
+        let total = server.send(message::RequestSessionTotal).await.unwrap();
+        assert!((total - 35.5).abs() < 1e-9, "{total}");
+        let stored = database.get_paid_message(&typo.id).unwrap().unwrap();
+        assert!((stored.amount - 5.5).abs() < 1e-9);
+        assert_eq!(stored.currency, "USD");
+        assert_eq!(
+            client.tags().await[6..],
+            ["update_message", "session_total", "goal_update"]
+        );
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        assert!(recent
+            .iter()
+            .any(|m| m.id == typo.id && m.amount == stored.amount));
+
+        assert!(server
+            .send(message::EditPaidMessage {
+                id: typo.id,
+                amount: 0.0,
+                currency: "USD".to_string(),
+            })
+            .await
+            .unwrap()
+            .is_err());
+        assert!(server
+            .send(message::EditPaidMessage {
+                id: Uuid::new_v4(),
+                amount: 5.0,
+                currency: "USD".to_string(),
+            })
+            .await
+            .unwrap()
+            .is_err());
+
+        // Messages only in the database are corrected in the total too, if in the window
+        for (id, amount) in [(stored_only.id, 40.0), (expired.id, 100.0)] {
+            server
+                .send(message::EditPaidMessage {
+                    id,
+                    amount,
+                    currency: "USD".to_string(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let total = server.send(message::RequestSessionTotal).await.unwrap();
+        assert!((total - 55.5).abs() < 1e-9, "{total}");
+        assert_eq!(
+            database
+                .get_paid_message(&expired.id)
+                .unwrap()
+                .unwrap()
+                .amount,
+            100.0
+        );
+    }
+
+    #[actix::test]
+    async fn test_subscribed_tags_filter_broadcasts() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let alerts = TestClient::connect_as(&server, ClientRole::Viewer).await;
+        let everything = TestClient::connect_as(&server, ClientRole::Viewer).await;
+        server
+            .send(message::SubscribeTags {
+                client_id: alerts.id,
+                tags: vec!["chat_message".to_string(), "feature_message".to_string()],
+            })
+            .await
+            .unwrap();
+
+        server
+            .send(message::Content {
+                chat_message: paid(5.0),
+            })
+            .await
+            .unwrap();
+        assert_eq!(alerts.tags().await, ["chat_message"]);
+        assert_eq!(
+            everything.tags().await,
+            ["chat_message", "session_total", "goal_update"]
+        );
+
+        // An empty list goes back to receiving everything
+        server
+            .send(message::SubscribeTags {
+                client_id: alerts.id,
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        server
+            .send(message::Content {
+                chat_message: paid(5.0),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            alerts.tags().await[1..],
+            ["chat_message", "session_total", "goal_update"]
+        );
+    }
+
+    #[actix::test]
+    async fn test_test_message_is_ingested() {
+        let (server, _dir) = test_server();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        server
+            .send(message::TestMessage {
+                platform: "Test".to_string(),
+                username: "<b>Tester</b>".to_string(),
+                message: "Testing 1 2 3".to_string(),
+                amount: 10.0,
+                currency: "EUR".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let stored = database.get_paid_messages_above_amount(0.0, 1).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].username, "&lt;b&gt;Tester&lt;/b&gt;");
+        assert_eq!(stored[0].currency, "USD");
+        assert!((stored[0].amount - 11.0).abs() < 1e-9);
+        assert_eq!(
+            client.tags().await,
+            ["chat_message", "session_total", "goal_update"]
+        );
+    }
+
+    #[actix::test]
+    async fn test_chat_buffer_evicts_oldest() {
+        let (mut server, _dir) = test_server();
+        server.chat_messages = MessageBuffer::new(3);
+        let server = server.start();
+
+        for text in ["one", "two", "three", "four", "five"] {
+            server
+                .send(message::Content {
+                    chat_message: chat(text),
+                })
+                .await
+                .unwrap();
+        }
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let texts: Vec<_> = recent.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(texts, ["three", "four", "five"]);
+    }
+
+    #[actix::test]
+    async fn test_viewer_history_accumulates_and_trims() {
+        let (mut server, _dir) = test_server();
+        server.viewer_counts.insert("YouTube".to_string(), 10);
+        server.sample_viewers(0);
+        server.viewer_counts.insert("Kick".to_string(), 5);
+        for minute in 1..=VIEWER_HISTORY_LEN as i64 {
+            server
+                .viewer_counts
+                .insert("YouTube".to_string(), 10 + minute as usize);
+            server.sample_viewers(minute * 60_000);
+        }
+
+        let server = server.start();
+        let viewers = server.send(message::RequestViewers).await.unwrap();
+        assert_eq!(viewers.current["YouTube"], 70);
+        assert_eq!(viewers.current["Kick"], 5);
+
+        // The first sample fell off the end; the rest are kept oldest first
+        let youtube = &viewers.history["YouTube"];
+        assert_eq!(youtube.len(), VIEWER_HISTORY_LEN);
+        assert_eq!(
+            youtube[0],
+            message::ViewerSample {
+                at: 60_000,
+                viewers: 11
+            }
+        );
+        assert_eq!(youtube.last().unwrap().viewers, 70);
+        assert_eq!(viewers.history["Kick"].len(), VIEWER_HISTORY_LEN);
+    }
+
+    #[actix::test]
+    async fn test_poisoned_layout_lock_still_serves_layouts() {
+        let (server, _dir) = test_server();
+        let layout_manager = server.layout_manager.clone();
+        let server = server.start();
+
+        let poisoner = layout_manager.clone();
+        std::thread::spawn(move || {
+            let _lm = poisoner.lock().unwrap();
+            panic!("panic while holding the layout lock");
+        })
+        .join()
+        .unwrap_err();
+        assert!(layout_manager.is_poisoned());
+
+        let layout = server.send(message::RequestLayout).await.unwrap();
+        assert_eq!(layout.name, Layout::default_layout().name);
+        assert!(!layout_manager.is_poisoned());
+    }
+
+    #[actix::test]
+    async fn test_content_escapes_quotes() {
+        let (server, _dir) = test_server();
+        let server = server.start();
+        let chat_message = ChatMessage {
+            username: r#"Dr "Q""#.to_string(),
+            message: r#"say "hi" :q:"#.to_string(),
+            emojis: vec![Emoji::new(
+                ":q:",
+                r#"https://cdn.example.com/q.png?x="y""#,
+                r#"q""#,
+            )],
+            ..chat("")
+        };
+        let id = chat_message.id;
+        server
+            .send(message::Content { chat_message })
+            .await
+            .unwrap();
+
+        let recent = server.send(message::RecentMessages).await.unwrap();
+        let stored = recent.iter().find(|msg| msg.id == id).unwrap();
+        assert_eq!(stored.username, "Dr &quot;Q&quot;");
+        assert_eq!(
+            stored.message,
+            concat!(
+                "say &quot;hi&quot; ",
+                r#"<img class="emoji" src="https://cdn.example.com/q.png?x=&quot;y&quot;" data-emoji="q&quot;" alt="q&quot;" />"#
+            )
+        );
+        assert_eq!(stored.to_plaintext(), r#"say "hi" :q":"#);
+    }
+
+    #[actix::test]
+    async fn test_request_message_by_id() {
+        let (server, _dir) = test_server();
+        let archived = paid(20.0);
+        server.database.upsert_paid_message(&archived).unwrap();
+        let server = server.start();
+        let recent = chat("still in memory");
+        server
+            .send(message::Content {
+                chat_message: recent.clone(),
+            })
+            .await
+            .unwrap();
+
+        for id in [recent.id, archived.id] {
+            let found = server
+                .send(message::RequestMessageById { id })
+                .await
+                .unwrap();
+            assert_eq!(found.unwrap().id, id);
+        }
+        let missing = server
+            .send(message::RequestMessageById { id: Uuid::new_v4() })
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+    }
+
+    #[actix::test]
+    async fn test_full_moderation_queue_keeps_paid_messages() {
+        let (mut server, _dir) = test_server();
+        let donation = paid(5.0);
+        server.hold_for_moderation(donation.clone());
+        for i in 0..MAX_PENDING_MESSAGES {
+            server.hold_for_moderation(chat(&i.to_string()));
+        }
+
+        // The oldest unpaid message made room, not the older donation
+        assert_eq!(server.pending_messages.len(), MAX_PENDING_MESSAGES);
+        assert_eq!(server.pending_messages[0].id, donation.id);
+        assert_eq!(server.pending_messages[1].message, "1");
+        assert!(server
+            .database
+            .get_paid_message(&donation.id)
+            .unwrap()
+            .is_none());
+
+        // Once only paid messages are held, the oldest is saved rather than lost
+        for _ in 0..MAX_PENDING_MESSAGES {
+            server.hold_for_moderation(paid(1.0));
+        }
+        assert_eq!(server.pending_messages.len(), MAX_PENDING_MESSAGES);
+        assert!(server.pending_messages.iter().all(|msg| msg.is_premium()));
+        assert!(!server
+            .pending_messages
+            .iter()
+            .any(|msg| msg.id == donation.id));
+        assert!(server
+            .database
+            .get_paid_message(&donation.id)
+            .unwrap()
+            .is_some());
+        assert_eq!(server.session_total_usd, 5.0);
+    }
+
+    #[actix::test]
+    async fn test_remove_paid_message_updates_total() {
+        let (mut server, _dir) = test_server();
+        // Counted but not in memory, like a paid message from a disabled platform
+        let stored_only = paid(7.0);
+        server.database.upsert_paid_message(&stored_only).unwrap();
+        server.session_total_usd = 7.0;
+        let expired = ChatMessage {
+            received_at: chrono::Utc::now().timestamp_millis() - 48 * 60 * 60 * 1000,
+            ..paid(3.0)
+        };
+        server.database.upsert_paid_message(&expired).unwrap();
+        let database = server.database.clone();
+        let server = server.start();
+        let client = TestClient::connect(&server).await;
+
+        let donation = paid(10.0);
+        let free = chat("no money");
+        for chat_message in [donation.clone(), free.clone()] {
+            server
+                .send(message::Content { chat_message })
+                .await
+                .unwrap();
+        }
+        let total = || async { server.send(message::RequestSessionTotal).await.unwrap() };
+        assert_eq!(total().await, 17.0);
+
+        server
+            .send(message::RemoveMessage { id: donation.id })
+            .await
+            .unwrap();
+        assert_eq!(total().await, 7.0);
+        let tags = client.tags().await;
+        assert_eq!(
+            tags[tags.len() - 3..],
+            ["remove_message", "session_total", "goal_update"]
+        );
+
+        server
+            .send(message::RemoveMessage { id: stored_only.id })
+            .await
+            .unwrap();
+        assert_eq!(total().await, 0.0);
+        assert!(database.get_paid_message(&stored_only.id).unwrap().is_none());
+
+        // Outside the session window, or unpaid: the total stays
+        for id in [expired.id, free.id] {
+            server.send(message::RemoveMessage { id }).await.unwrap();
+            assert_eq!(total().await, 0.0);
+            assert_eq!(client.tags().await.last().unwrap(), "remove_message");
         }
     }
 }
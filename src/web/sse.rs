@@ -0,0 +1,210 @@
+use actix::{
+    fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner,
+    Handler, Running, WrapFuture,
+};
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use super::client::ClientRole;
+use super::message;
+use super::ChatServer;
+
+/// Events buffered for a slow reader before it is dropped, matching ChatClient's mailbox
+const BUFFER: usize = 256;
+
+/// A receive-only `/chat.sse` connection. Registers with the ChatServer like an overlay
+/// WebSocket and writes each reply to the response body as one SSE event.
+pub struct SseClient {
+    id: usize,
+    server: Addr<ChatServer>,
+    events: mpsc::Sender<Bytes>,
+    heartbeat_interval: Duration,
+}
+
+impl SseClient {
+    /// The client and the body to stream back to the browser
+    pub fn new(
+        server: Addr<ChatServer>,
+        heartbeat_interval: Duration,
+    ) -> (Self, mpsc::Receiver<Bytes>) {
+        let (events, body) = mpsc::channel(BUFFER);
+        let client = Self {
+            id: 0,
+            server,
+            events,
+            heartbeat_interval,
+        };
+        (client, body)
+    }
+
+    /// Queue bytes for the response, stopping once the browser has gone or stopped reading
+    fn write(&mut self, bytes: Bytes, ctx: &mut Context<Self>) {
+        if let Err(e) = self.events.try_send(bytes) {
+            if e.is_full() {
+                warn!(
+                    client = self.id,
+                    "Dropping SSE client that stopped reading events"
+                );
+            }
+            ctx.stop();
+        }
+    }
+}
+
+/// Frame a reply as an unnamed event, so `EventSource.onmessage` sees every tag
+fn event(reply: &str) -> Bytes {
+    let mut event = String::with_capacity(reply.len() + 8);
+    for line in reply.lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    Bytes::from(event)
+}
+
+impl Actor for SseClient {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.set_mailbox_capacity(BUFFER);
+
+        // Comments keep proxies from timing the stream out and notice a closed browser
+        ctx.run_interval(self.heartbeat_interval, |act, ctx| {
+            act.write(Bytes::from_static(b": ping\n\n"), ctx);
+        });
+
+        self.server
+            .send(message::Connect {
+                recipient: ctx.address().recipient(),
+                close: ctx.address().recipient(),
+                role: ClientRole::Viewer,
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(id) => act.id = id,
+                    Err(err) => {
+                        warn!(error = ?err, "Failed to assign connection id");
+                        ctx.stop();
+                    }
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+
+        // Overlays ask for recent chat when they connect; SSE clients can't ask, so send it
+        self.server
+            .send(message::RecentMessages)
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                for msg in res.into_iter().flatten() {
+                    let reply = serde_json::to_string(&message::ReplyInner {
+                        tag: "chat_message".to_owned(),
+                        message: msg.to_json(),
+                    })
+                    .unwrap();
+                    act.write(event(&reply), ctx);
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        debug!(client = self.id, "SSE client disconnected");
+        self.server.do_send(message::Disconnect { id: self.id });
+        Running::Stop
+    }
+}
+
+impl Handler<message::Reply> for SseClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: message::Reply, ctx: &mut Self::Context) {
+        self.write(event(&msg.0), ctx);
+    }
+}
+
+/// Server is shutting down; ending the body lets `EventSource` reconnect on its own
+impl Handler<message::Close> for SseClient {
+    type Result = ();
+
+    fn handle(&mut self, _: message::Close, ctx: &mut Self::Context) {
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::exchange::ExchangeRates;
+    use crate::layout::LayoutManager;
+    use crate::message::Message;
+    use futures::StreamExt;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(
+            event(r#"{"tag":"x"}"#),
+            Bytes::from("data: {\"tag\":\"x\"}\n\n")
+        );
+        assert_eq!(event("a\nb"), Bytes::from("data: a\ndata: b\n\n"));
+    }
+
+    async fn next_event(body: &mut mpsc::Receiver<Bytes>) -> message::ReplyInner {
+        loop {
+            let bytes = body.next().await.unwrap();
+            if let Some(data) = std::str::from_utf8(&bytes).unwrap().strip_prefix("data: ") {
+                return serde_json::from_str(data.trim_end()).unwrap();
+            }
+        }
+    }
+
+    #[actix::test]
+    async fn test_streams_broadcasts() {
+        let dir = TempDir::new().unwrap();
+        let layout_manager = LayoutManager::new(dir.path().to_str().unwrap()).unwrap();
+        let server = ChatServer::with_database(
+            ExchangeRates::from_rates(HashMap::from([("USD".to_string(), 1.0)])),
+            Arc::new(Mutex::new(layout_manager)),
+            Database::open_in_memory().unwrap(),
+        )
+        .start();
+        let earlier = Message {
+            message: "before connecting".to_string(),
+            ..Default::default()
+        };
+        server
+            .send(message::Content {
+                chat_message: earlier.clone(),
+            })
+            .await
+            .unwrap();
+
+        let (client, mut body) = SseClient::new(server.clone(), Duration::from_secs(60));
+        let client = client.start();
+        let replayed = next_event(&mut body).await;
+        assert_eq!(replayed.tag, "chat_message");
+        assert!(replayed.message.contains(&earlier.id.to_string()));
+
+        server
+            .send(message::RemoveMessage { id: earlier.id })
+            .await
+            .unwrap();
+        assert_eq!(next_event(&mut body).await.tag, "remove_message");
+
+        // Closing the response unregisters the client on its next write
+        drop(body);
+        client.do_send(message::Reply("{}".to_string()));
+        actix::clock::sleep(Duration::from_millis(50)).await;
+        let stats = server.send(message::RequestServerStats).await.unwrap();
+        assert_eq!(stats.clients, 0);
+    }
+}